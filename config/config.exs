@@ -6,3 +6,19 @@ config :agentless_monitor,
   monitoring_interval: 30,
   ping_timeout: 5,
   ssh_timeout: 10
+
+# IANA zones for maintenance windows and displayed times (AgentlessMonitor.TimeZones).
+config :elixir, :time_zone_database, Tz.TimeZoneDatabase
+
+# Optional subsystems compiled in (see AgentlessMonitor.Features); e.g.
+# AGENTLESS_FEATURES=none for a headless collector-only build.
+if features = System.get_env("AGENTLESS_FEATURES") do
+  enabled =
+    features
+    |> String.split(",", trim: true)
+    |> Enum.map(&String.trim/1)
+    |> Enum.reject(&(&1 == "none"))
+    |> Enum.map(&String.to_atom/1)
+
+  config :agentless_monitor, features: enabled
+end
@@ -6,6 +6,10 @@ port =
 
 config :agentless_monitor, server_port: port
 
+if bind_address = System.get_env("MONITOR_BIND") do
+  config :agentless_monitor, bind_address: bind_address
+end
+
 if fallback_password = System.get_env("FALLBACK_PASSWORD") do
   config :agentless_monitor, fallback_password: fallback_password
 end
@@ -13,3 +17,7 @@ end
 if ssh_config_path = System.get_env("SSH_CONFIG_PATH") do
   config :agentless_monitor, ssh_config_path: ssh_config_path
 end
+
+if admin_token = System.get_env("ADMIN_TOKEN") do
+  config :agentless_monitor, admin_token: admin_token
+end
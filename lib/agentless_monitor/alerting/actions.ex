@@ -0,0 +1,227 @@
+defmodule AgentlessMonitor.Alerting.Actions do
+  @moduledoc """
+  Runs remediation actions when events match configured rules.
+
+  Rules come from the `actions` list in config.json, e.g.:
+
+      {
+        "id": "restart-nginx",
+        "match": {"kind": "server_down", "server": "web-*"},
+        "type": "remote",
+        "command": "sudo systemctl restart nginx",
+        "cooldown": 300,
+        "max_executions": 5,
+        "risk": "medium",
+        "approval": "manual"
+      }
+
+  `type` is either `"remote"` (run on the event's server) or `"local"` (run on
+  the monitor host, with event details exported as `MONITOR_*` variables).
+  Commands may use `{{server_id}}`, `{{server_name}}`, `{{host}}`, `{{kind}}`
+  and `{{severity}}` placeholders, which are substituted shell-quoted.
+
+  Actions with `"approval": "manual"`, or whose `risk` is at or above the
+  configured `approval_risk_level`, are filed with `AgentlessMonitor.Approvals`
+  and only run once another operator approves them.
+
+  Every execution is recorded as a job of type `action`.
+  """
+
+  use GenServer
+  require Logger
+
+  alias AgentlessMonitor.{Approvals, Config, Settings, State}
+  alias AgentlessMonitor.Models.Event
+  alias AgentlessMonitor.Monitoring.Service
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, nil, name: __MODULE__)
+  end
+
+  def handle_event(%Event{} = event) do
+    GenServer.cast(__MODULE__, {:event, event})
+  end
+
+  def list_rules do
+    GenServer.call(__MODULE__, :list_rules)
+  end
+
+  @doc "Re-reads the action rules from config, keeping execution counters"
+  def reload_rules do
+    GenServer.call(__MODULE__, :reload_rules)
+  end
+
+  # ---- Callbacks ----
+
+  def init(_) do
+    rules = Config.load().actions |> Enum.map(&normalize_rule/1)
+    {:ok, %{rules: rules, runs: %{}, last_run: %{}}}
+  end
+
+  def handle_call(:list_rules, _from, state) do
+    rules =
+      Enum.map(state.rules, fn rule ->
+        Map.put(rule, "executions", Map.get(state.runs, rule["id"], 0))
+      end)
+
+    {:reply, rules, state}
+  end
+
+  def handle_call(:reload_rules, _from, state) do
+    rules = Config.load().actions |> Enum.map(&normalize_rule/1)
+    {:reply, :ok, %{state | rules: rules}}
+  end
+
+  # Approval requests are themselves events; never act on them to avoid loops.
+  def handle_cast({:event, %Event{kind: "approval_requested"}}, state) do
+    {:noreply, state}
+  end
+
+  def handle_cast({:event, event}, state) do
+    state =
+      state.rules
+      |> Enum.filter(&matches?(&1, event))
+      |> Enum.reduce(state, &trigger(&2, &1, event))
+
+    {:noreply, state}
+  end
+
+  def handle_cast({:approved, rule_id, server_id, command, env}, state) do
+    case Enum.find(state.rules, &(&1["id"] == rule_id)) do
+      nil -> {:noreply, state}
+      rule -> {:noreply, dispatch(state, rule, server_id, command, env)}
+    end
+  end
+
+  # ---- Private helpers ----
+
+  defp normalize_rule(rule) do
+    Map.merge(
+      %{
+        "id" => rule["name"],
+        "match" => %{},
+        "type" => "remote",
+        "cooldown" => 300,
+        "max_executions" => nil,
+        "risk" => "low",
+        "approval" => "auto"
+      },
+      rule
+    )
+  end
+
+  defp matches?(rule, event) do
+    Enum.all?(rule["match"], fn
+      {"kind", kind} -> event.kind == kind
+      {"severity", severity} -> event.severity == severity
+      {"group", group} -> event.group == group
+      {"server", pattern} -> Settings.glob_match?(pattern, event.server_name || "")
+      _ -> true
+    end)
+  end
+
+  defp trigger(state, rule, event) do
+    key = {rule["id"], event.server_id}
+    now = System.system_time(:second)
+    runs = Map.get(state.runs, rule["id"], 0)
+
+    cond do
+      now - Map.get(state.last_run, key, 0) < rule["cooldown"] ->
+        Logger.debug("Action #{rule["id"]} skipped for #{event.server_name}: cooling down")
+        state
+
+      rule["max_executions"] && runs >= rule["max_executions"] ->
+        Logger.warning("Action #{rule["id"]} skipped: reached max_executions")
+        state
+
+      true ->
+        command = render(rule["command"], event)
+        env = event_env(event)
+        state = %{state | last_run: Map.put(state.last_run, key, now)}
+
+        if rule["approval"] == "manual" or Approvals.required?(rule["risk"]) do
+          rule_id = rule["id"]
+          server_id = event.server_id
+
+          Approvals.request(
+            %{
+              "summary" => "Action #{rule_id} on #{event.server_name}: #{command}",
+              "risk" => rule["risk"],
+              "server_id" => server_id,
+              "requested_by" => "action:#{rule_id}"
+            },
+            fn -> GenServer.cast(__MODULE__, {:approved, rule_id, server_id, command, env}) end
+          )
+
+          state
+        else
+          dispatch(state, rule, event.server_id, command, env)
+        end
+    end
+  end
+
+  defp dispatch(state, rule, server_id, command, env) do
+    case State.get_server(server_id) do
+      {:ok, server} ->
+        Task.Supervisor.start_child(AgentlessMonitor.TaskSupervisor, fn ->
+          execute(rule, server, command, env)
+        end)
+
+        %{state | runs: Map.update(state.runs, rule["id"], 1, &(&1 + 1))}
+
+      {:error, :not_found} ->
+        state
+    end
+  end
+
+  defp execute(rule, server, command, env) do
+    Logger.info("Running action #{rule["id"]} for #{server.name}: #{command}")
+
+    Service.run_as_job(server, "action", fn ->
+      case rule["type"] do
+        "local" ->
+          case System.cmd("sh", ["-c", command], stderr_to_stdout: true, env: env) do
+            {output, 0} -> {:ok, output}
+            {output, _code} -> {:error, output}
+          end
+
+        _ ->
+          Service.run_command(server, command)
+      end
+    end)
+  end
+
+  defp render(template, event) do
+    Enum.reduce(event_vars(event), template || "", fn {name, value}, acc ->
+      String.replace(acc, "{{#{name}}}", shell_quote(value))
+    end)
+  end
+
+  defp event_env(event) do
+    Enum.map(event_vars(event), fn {name, value} ->
+      {"MONITOR_" <> String.upcase(name), value}
+    end)
+  end
+
+  defp event_vars(event) do
+    host =
+      case State.get_server(event.server_id) do
+        {:ok, server} -> server.host
+        _ -> ""
+      end
+
+    [
+      {"server_id", event.server_id || ""},
+      {"server_name", event.server_name || ""},
+      {"host", host},
+      {"kind", event.kind || ""},
+      {"severity", event.severity || ""}
+    ]
+  end
+
+  defp shell_quote(value) do
+    "'" <> String.replace(to_string(value), "'", "'\\''") <> "'"
+  end
+end
@@ -0,0 +1,279 @@
+defmodule AgentlessMonitor.Alerting.Canary do
+  @moduledoc """
+  Shadow rollout of changed alert thresholds to one group before the whole
+  fleet.
+
+      POST /api/admin/canary
+      {"group": "canary", "thresholds": {"cpu_percent": 75, "disk_percent": 85}}
+
+  While a canary runs, every sample of a server in `group` is also checked
+  against the candidate thresholds (its effective thresholds with the
+  candidate ones over them). Breaches the candidate would start are logged
+  and listed as `would_fire`, without events or notifications; the live
+  thresholds keep alerting as before. The report counts, per threshold,
+  how often the live and the candidate rules fired on the same samples.
+
+  Promoting installs the candidate thresholds fleet-wide as the
+  `canary-thresholds` bundle (see `AgentlessMonitor.Bundles`) and ends the
+  canary. The canary and its counts are kept in `<data_dir>/canary.json`.
+  """
+
+  use GenServer
+  require Logger
+
+  alias AgentlessMonitor.{Bundles, Config, DataFile, Settings}
+  alias AgentlessMonitor.Monitoring.Service
+
+  @bundle "canary-thresholds"
+  @would_fire_kept 50
+  @persist_delay :timer.seconds(60)
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, nil, name: __MODULE__)
+  end
+
+  @doc "The running canary with its comparison report, or nil"
+  def current do
+    GenServer.call(__MODULE__, :current)
+  end
+
+  @doc """
+  Starts a canary of `%{"group" => ..., "thresholds" => ...}`, replacing a
+  running one. Returns `{:ok, canary}` or `{:error, message}`.
+  """
+  def start(attrs) do
+    GenServer.call(__MODULE__, {:start, attrs})
+  end
+
+  @doc "Installs the candidate thresholds fleet-wide and ends the canary"
+  def promote do
+    GenServer.call(__MODULE__, :promote)
+  end
+
+  @doc "Ends the canary without promoting it"
+  def stop do
+    GenServer.call(__MODULE__, :stop)
+  end
+
+  @doc "Checks a sample of `server` against the candidate thresholds"
+  def evaluate(server, data, previous, thresholds) do
+    GenServer.cast(__MODULE__, {:evaluate, server, data, previous, thresholds})
+  end
+
+  # ---- Callbacks ----
+
+  def init(_) do
+    {:ok, %{canary: load(), dirty: false}}
+  end
+
+  def handle_call(:current, _from, state) do
+    {:reply, state.canary && report(state.canary), state}
+  end
+
+  def handle_call({:start, attrs}, _from, state) do
+    case validate(attrs) do
+      :ok ->
+        canary = %{
+          "group" => attrs["group"],
+          "thresholds" => attrs["thresholds"],
+          "started_at" => DateTime.utc_now() |> DateTime.to_iso8601(),
+          "samples" => 0,
+          "live" => %{},
+          "shadow" => %{},
+          "servers" => %{},
+          "would_fire" => []
+        }
+
+        persist(canary)
+        {:reply, {:ok, report(canary)}, %{state | canary: canary, dirty: false}}
+
+      error ->
+        {:reply, error, state}
+    end
+  end
+
+  def handle_call(:promote, _from, %{canary: nil} = state) do
+    {:reply, {:error, :not_found}, state}
+  end
+
+  def handle_call(:promote, _from, state) do
+    bundle = %{
+      Bundles.export(%{
+        "name" => @bundle,
+        "description" => "Thresholds promoted from the #{state.canary["group"]} canary"
+      })
+      | "contents" => %{"thresholds" => state.canary["thresholds"]}
+    }
+
+    case Bundles.install(bundle) do
+      {:ok, _installed} ->
+        persist(nil)
+        {:reply, {:ok, report(state.canary)}, %{state | canary: nil, dirty: false}}
+
+      {:error, reason} ->
+        {:reply, {:error, reason}, state}
+    end
+  end
+
+  def handle_call(:stop, _from, %{canary: nil} = state) do
+    {:reply, {:error, :not_found}, state}
+  end
+
+  def handle_call(:stop, _from, state) do
+    persist(nil)
+    {:reply, {:ok, report(state.canary)}, %{state | canary: nil, dirty: false}}
+  end
+
+  def handle_cast({:evaluate, _server, _data, _previous, _thresholds}, %{canary: nil} = state) do
+    {:noreply, state}
+  end
+
+  def handle_cast({:evaluate, server, data, previous, thresholds}, state) do
+    if Settings.group_for(server) == state.canary["group"] do
+      unless state.dirty, do: Process.send_after(self(), :persist, @persist_delay)
+      live = thresholds || %{}
+      candidate = Map.merge(live, state.canary["thresholds"])
+
+      canary =
+        record(
+          state.canary,
+          server,
+          data.timestamp,
+          fired(data, previous, live),
+          fired(data, previous, candidate)
+        )
+
+      {:noreply, %{state | canary: canary, dirty: true}}
+    else
+      {:noreply, state}
+    end
+  end
+
+  def handle_info(:persist, state) do
+    if state.canary, do: persist(state.canary)
+    {:noreply, %{state | dirty: false}}
+  end
+
+  # ---- Private helpers ----
+
+  defp validate(attrs) do
+    cond do
+      not is_binary(attrs["group"]) ->
+        {:error, "group is required"}
+
+      not Map.has_key?(Config.load().groups, attrs["group"]) ->
+        {:error, "unknown group #{attrs["group"]}"}
+
+      not is_map(attrs["thresholds"]) or attrs["thresholds"] == %{} ->
+        {:error, "thresholds must be a non-empty object"}
+
+      not Enum.all?(Map.values(attrs["thresholds"]), &(is_number(&1) or is_nil(&1))) ->
+        {:error, "threshold values must be numbers (or null to disable one)"}
+
+      true ->
+        :ok
+    end
+  end
+
+  # Breaches that start with this sample, as in `Service.check_thresholds`.
+  defp fired(data, previous, thresholds) do
+    current = Service.threshold_breaches(data, thresholds)
+    before = if previous, do: Service.threshold_breaches(previous, thresholds), else: %{}
+    Map.drop(current, Map.keys(before))
+  end
+
+  defp record(canary, server, timestamp, live, shadow) do
+    would_fire =
+      for {key, message} <- shadow, not Map.has_key?(live, key) do
+        Logger.info("Canary: #{server.name}: would fire: #{message}")
+
+        %{
+          "server_id" => server.id,
+          "server_name" => server.name,
+          "rule" => rule(key),
+          "message" => message,
+          "timestamp" => timestamp
+        }
+      end
+
+    empty = %{"name" => server.name, "samples" => 0, "live" => %{}, "shadow" => %{}}
+    entry = canary["servers"] |> Map.get(server.id, empty) |> count(live, shadow)
+
+    %{
+      count(canary, live, shadow)
+      | "servers" => Map.put(canary["servers"], server.id, entry),
+        "would_fire" => Enum.take(would_fire ++ canary["would_fire"], @would_fire_kept)
+    }
+  end
+
+  defp count(stats, live, shadow) do
+    %{
+      stats
+      | "samples" => stats["samples"] + 1,
+        "live" => tally(stats["live"], live),
+        "shadow" => tally(stats["shadow"], shadow)
+    }
+  end
+
+  defp tally(counts, breaches) do
+    Enum.reduce(breaches, counts, fn {key, _message}, acc ->
+      Map.update(acc, rule(key), 1, &(&1 + 1))
+    end)
+  end
+
+  # Breach keys (`disk:/var`, `custom:queue`) by the threshold behind them.
+  defp rule("cpu"), do: "cpu_percent"
+  defp rule("memory"), do: "memory_percent"
+  defp rule("swap"), do: "swap_out_rate"
+  defp rule("disk:" <> _mount), do: "disk_percent"
+  defp rule("custom:" <> name), do: "custom.#{name}"
+  defp rule(key), do: key
+
+  # Firings per threshold under both rule sets, with the candidate's change.
+  defp report(canary) do
+    rules = Enum.uniq(Map.keys(canary["live"]) ++ Map.keys(canary["shadow"]))
+
+    comparison =
+      rules
+      |> Enum.sort()
+      |> Enum.map(fn rule ->
+        live = Map.get(canary["live"], rule, 0)
+        shadow = Map.get(canary["shadow"], rule, 0)
+        %{"rule" => rule, "live" => live, "candidate" => shadow, "change" => shadow - live}
+      end)
+
+    canary
+    |> Map.take(["group", "thresholds", "started_at", "samples", "would_fire"])
+    |> Map.put("comparison", comparison)
+    |> Map.put(
+      "servers",
+      canary["servers"]
+      |> Enum.map(fn {id, entry} -> Map.put(entry, "id", id) end)
+      |> Enum.sort_by(& &1["name"])
+    )
+  end
+
+  defp path do
+    Path.join(Config.load().data_dir, "canary.json")
+  end
+
+  defp load do
+    with {:ok, content} <- File.read(path()),
+         {:ok, %{"canary" => canary}} <- Jason.decode(content) do
+      canary
+    else
+      _ -> nil
+    end
+  end
+
+  defp persist(canary) do
+    content = Jason.encode!(%{"canary" => canary}, pretty: true)
+
+    case DataFile.write_atomic(path(), content) do
+      :ok -> :ok
+      {:error, reason} -> Logger.error("Failed to persist the canary: #{inspect(reason)}")
+    end
+  end
+end
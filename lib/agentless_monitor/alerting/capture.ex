@@ -0,0 +1,135 @@
+defmodule AgentlessMonitor.Alerting.Capture do
+  @moduledoc """
+  Context captured from a host when a critical alert fires for it, so it
+  survives the host dying shortly after.
+
+  Over the server's usual connection, one command reads the tail of the
+  kernel ring buffer (`dmesg`), the journal's errors of the last hour and
+  the processes using the most CPU. Each section is bounded, the command
+  by `ssh_timeout`, and a server is captured at most once every five
+  minutes however many critical events it raises. The capture is attached
+  to the incident the event belongs to (its `captures`, see
+  `AgentlessMonitor.Alerting.Incidents`); a capture that can't run, for
+  example because the host is already unreachable, is attached with its
+  error. SNMP devices and Windows hosts aren't captured, and
+  `"alert_capture": false` turns captures off.
+
+  With privacy mode on (`privacy_redact`), the processes section, which
+  names commands and their owners, is left out and IP and MAC addresses are
+  masked in the others.
+  """
+
+  use GenServer
+  require Logger
+
+  alias AgentlessMonitor.{Config, Privacy, State}
+  alias AgentlessMonitor.Models.Event
+  alias AgentlessMonitor.Monitoring.Service
+  alias AgentlessMonitor.Alerting.Incidents
+
+  @cooldown 300
+  @max_bytes 16_384
+
+  @command "(dmesg -T 2>&1 || dmesg 2>&1) | tail -n 100; echo '---SEP---'; " <>
+             "(journalctl -p err -n 50 -q --no-pager --since '1 hour ago' 2>&1 || true); " <>
+             "echo '---SEP---'; " <>
+             "ps -eo pid,user,pcpu,pmem,rss,args --sort=-pcpu 2>&1 | head -n 16"
+
+  @sections ["dmesg", "journal", "processes"]
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, nil, name: __MODULE__)
+  end
+
+  @doc "The command a capture runs, for the restricted-mode allowlist"
+  def command, do: @command
+
+  @doc "Captures the event's server in the background if the event is critical"
+  def handle_event(%Event{} = event) do
+    GenServer.cast(__MODULE__, {:event, event})
+  end
+
+  # ---- Callbacks ----
+
+  def init(_) do
+    {:ok, %{captured_at: %{}}}
+  end
+
+  def handle_cast({:event, %Event{severity: "critical", server_id: server_id} = event}, state)
+      when is_binary(server_id) do
+    now = System.monotonic_time(:second)
+
+    with true <- Config.load().alert_capture,
+         true <- now - Map.get(state.captured_at, server_id, now - @cooldown) >= @cooldown,
+         {:ok, server} <- State.get_server(server_id),
+         true <- capturable?(server) do
+      Task.Supervisor.start_child(AgentlessMonitor.TaskSupervisor, fn ->
+        Incidents.attach_capture(event.id, capture(server))
+      end)
+
+      {:noreply, %{state | captured_at: Map.put(state.captured_at, server_id, now)}}
+    else
+      _ -> {:noreply, state}
+    end
+  end
+
+  def handle_cast({:event, _event}, state) do
+    {:noreply, state}
+  end
+
+  # ---- Private helpers ----
+
+  defp capturable?(%{transport: "snmp"}), do: false
+  defp capturable?(%{platform: "windows"}), do: false
+  defp capturable?(_server), do: true
+
+  defp capture(server) do
+    timeout = Config.load().ssh_timeout
+
+    result =
+      case Service.run_command(server, @command, timeout: timeout) do
+        {:ok, output} ->
+          sections = output |> String.split("---SEP---\n") |> Enum.map(&bounded/1)
+          %{"sections" => Map.new(Enum.zip(@sections, sections)), "error" => nil}
+
+        {:error, reason} ->
+          Logger.warning("Alert capture failed for #{server.name}: #{inspect(reason)}")
+          %{"sections" => %{}, "error" => reason |> to_string() |> bounded()}
+      end
+
+    result
+    |> redact(Privacy.enabled())
+    |> Map.merge(%{
+      "server_id" => server.id,
+      "server_name" => server.name,
+      "captured_at" => DateTime.utc_now() |> DateTime.to_iso8601()
+    })
+  end
+
+  defp redact(result, []), do: result
+
+  defp redact(result, fields) do
+    sections =
+      result["sections"]
+      |> Map.delete("processes")
+      |> Map.new(fn {name, text} -> {name, Privacy.redact_text(text, fields)} end)
+
+    %{result | "sections" => sections, "error" => Privacy.redact_text(result["error"], fields)}
+  end
+
+  # The last `@max_bytes` of a section, where the most recent lines are.
+  defp bounded(text) do
+    text = String.trim(text)
+
+    if byte_size(text) > @max_bytes do
+      text
+      |> binary_part(byte_size(text) - @max_bytes, @max_bytes)
+      |> String.split("\n", parts: 2)
+      |> List.last()
+    else
+      text
+    end
+  end
+end
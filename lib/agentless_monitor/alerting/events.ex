@@ -0,0 +1,111 @@
+defmodule AgentlessMonitor.Alerting.Events do
+  @moduledoc "In-memory log of monitoring events such as status changes and alerts"
+
+  use GenServer
+
+  alias AgentlessMonitor.{Models, Notifications, PubSub, Sites, State, Storage, Topology}
+  alias AgentlessMonitor.Syslog
+  alias AgentlessMonitor.Models.Event
+  alias AgentlessMonitor.Alerting.{Incidents, Actions, Capture}
+
+  @max_events 1000
+  @suppressible ["server_down"]
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, %{events: []}, name: __MODULE__)
+  end
+
+  @doc """
+  Records an event and forwards it to incident correlation, actions, alert
+  captures and notification channels.
+  Accepts a map or keyword list with `:server_id`, `:server_name`, `:group`,
+  `:kind`, `:severity` and `:message`. `server_down` events of servers whose
+  dependencies are failing, and every event of a server whose site is in a
+  maintenance window, are recorded as suppressed and not forwarded. Events
+  emitted with `drill: true` only reach incidents and notifications: no
+  action or capture runs against the server for them.
+  """
+  def emit(attrs) do
+    GenServer.call(__MODULE__, {:emit, Map.new(attrs)})
+  end
+
+  def list(opts \\ []) do
+    GenServer.call(__MODULE__, {:list, opts})
+  end
+
+  # ---- Callbacks ----
+
+  def init(state) do
+    {:ok, state}
+  end
+
+  def handle_call({:emit, attrs}, _from, state) do
+    event = %Event{
+      id: Models.generate_id(),
+      server_id: Map.get(attrs, :server_id),
+      server_name: Map.get(attrs, :server_name),
+      group: Map.get(attrs, :group),
+      kind: Map.get(attrs, :kind),
+      severity: Map.get(attrs, :severity, "info"),
+      message: Map.get(attrs, :message),
+      drill: Map.get(attrs, :drill, false),
+      timestamp: DateTime.utc_now() |> DateTime.to_iso8601()
+    }
+
+    event = %{event | suppressed_by: suppressed_by(event)}
+
+    if event.suppressed_by == [] do
+      Incidents.correlate(event)
+
+      unless event.drill do
+        Actions.handle_event(event)
+        Capture.handle_event(event)
+      end
+
+      Notifications.dispatch(event)
+    end
+
+    PubSub.broadcast("event", Event.to_map(event))
+    Storage.write(:event, Event.to_map(event))
+    Syslog.forward(:event, Event.to_map(event))
+
+    {:reply, event, %{state | events: Enum.take([event | state.events], @max_events)}}
+  end
+
+  def handle_call({:list, opts}, _from, state) do
+    server_id = Keyword.get(opts, :server_id)
+    limit = Keyword.get(opts, :limit, 100)
+
+    events =
+      state.events
+      |> Enum.filter(&(is_nil(server_id) or &1.server_id == server_id))
+      |> Enum.take(limit)
+
+    {:reply, events, state}
+  end
+
+  # ---- Private helpers ----
+
+  defp suppressed_by(%Event{server_id: nil}), do: []
+
+  defp suppressed_by(%Event{kind: kind, server_id: server_id}) do
+    case State.get_server(server_id) do
+      {:ok, server} -> maintenance(server) ++ failing_upstream(server, kind)
+      {:error, :not_found} -> []
+    end
+  end
+
+  defp maintenance(server) do
+    case Sites.in_maintenance(server) do
+      nil -> []
+      window -> ["maintenance:#{window["site"]}"]
+    end
+  end
+
+  defp failing_upstream(server, kind) when kind in @suppressible,
+    do: Topology.failing_upstream(server)
+
+  defp failing_upstream(_server, _kind), do: []
+end
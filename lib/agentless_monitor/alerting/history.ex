@@ -0,0 +1,142 @@
+defmodule AgentlessMonitor.Alerting.History do
+  @moduledoc """
+  Durable record of resolved alerts and notification deliveries.
+
+  Incidents are kept here once resolved, and every delivery through a
+  notification channel is logged with its outcome: channel, status,
+  HTTP status or exit code, the number of attempts and the last error.
+  Both survive restarts in `<data_dir>/alert_history.json` and are kept
+  for `alert_history_days` (default 90), up to 10,000 entries each.
+  Writes are batched, so a crash may lose the last couple of seconds.
+  """
+
+  use GenServer
+  require Logger
+
+  alias AgentlessMonitor.{Config, DataFile}
+  alias AgentlessMonitor.Models.Incident
+
+  @max_entries 10_000
+  @flush_delay 2_000
+  @default_limit 100
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, nil, name: __MODULE__)
+  end
+
+  def record_alert(%Incident{} = incident) do
+    GenServer.cast(__MODULE__, {:record, :alerts, Incident.to_map(incident)})
+  end
+
+  @doc """
+  Logs one delivery: `channel`, `type`, `event_id`, `kind`, `severity`,
+  `status` ("delivered" or "failed"), `status_code`, `attempts`, `error`.
+  """
+  def record_delivery(delivery) do
+    entry = Map.put(delivery, "timestamp", DateTime.utc_now() |> DateTime.to_iso8601())
+    GenServer.cast(__MODULE__, {:record, :deliveries, entry})
+  end
+
+  @doc """
+  Resolved alerts, newest first, filtered by `from`/`to` (unix seconds,
+  compared with the resolution time), `server_id`, `severity` and `limit`.
+  """
+  def alerts(opts \\ []) do
+    GenServer.call(__MODULE__, {:search, :alerts, "resolved_at", opts})
+  end
+
+  @doc """
+  Delivery attempts, newest first, filtered by `from`/`to`, `channel`,
+  `event_id`, `status` and `limit`.
+  """
+  def deliveries(opts \\ []) do
+    GenServer.call(__MODULE__, {:search, :deliveries, "timestamp", opts})
+  end
+
+  # ---- Callbacks ----
+
+  def init(_) do
+    {:ok, Map.put(load(), :dirty, false)}
+  end
+
+  def handle_call({:search, kind, time_field, opts}, _from, state) do
+    {filters, opts} = Keyword.split(opts, [:server_id, :severity, :channel, :event_id, :status])
+    from = Keyword.get(opts, :from)
+    to = Keyword.get(opts, :to)
+
+    entries =
+      state
+      |> Map.fetch!(kind)
+      |> Enum.filter(fn entry ->
+        Enum.all?(filters, fn {key, value} ->
+          is_nil(value) or to_string(entry[to_string(key)]) == value
+        end) and within?(unix_time(entry[time_field]), from, to)
+      end)
+      |> Enum.take(Keyword.get(opts, :limit) || @default_limit)
+
+    {:reply, entries, state}
+  end
+
+  def handle_cast({:record, kind, entry}, state) do
+    unless state.dirty, do: Process.send_after(self(), :flush, @flush_delay)
+    entries = Enum.take([entry | Map.fetch!(state, kind)], @max_entries)
+    {:noreply, state |> Map.put(kind, entries) |> Map.put(:dirty, true)}
+  end
+
+  def handle_info(:flush, state) do
+    state = %{state | alerts: prune(state.alerts), deliveries: prune(state.deliveries)}
+    persist(state)
+    {:noreply, %{state | dirty: false}}
+  end
+
+  # ---- Private helpers ----
+
+  defp within?(nil, _from, _to), do: false
+  defp within?(ts, from, _to) when is_integer(from) and ts < from, do: false
+  defp within?(ts, _from, to) when is_integer(to) and ts > to, do: false
+  defp within?(_ts, _from, _to), do: true
+
+  defp unix_time(timestamp) when is_binary(timestamp) do
+    case DateTime.from_iso8601(timestamp) do
+      {:ok, dt, _} -> DateTime.to_unix(dt)
+      _ -> nil
+    end
+  end
+
+  defp unix_time(_timestamp), do: nil
+
+  defp prune(entries) do
+    cutoff = System.system_time(:second) - Config.load().alert_history_days * 86_400
+
+    Enum.filter(entries, fn entry ->
+      case unix_time(entry["resolved_at"] || entry["timestamp"]) do
+        nil -> false
+        ts -> ts >= cutoff
+      end
+    end)
+  end
+
+  defp path do
+    Path.join(Config.load().data_dir, "alert_history.json")
+  end
+
+  defp load do
+    with {:ok, content} <- File.read(path()),
+         {:ok, %{"alerts" => alerts, "deliveries" => deliveries}} <- Jason.decode(content) do
+      %{alerts: prune(alerts), deliveries: prune(deliveries)}
+    else
+      _ -> %{alerts: [], deliveries: []}
+    end
+  end
+
+  defp persist(state) do
+    content = Jason.encode!(%{"alerts" => state.alerts, "deliveries" => state.deliveries})
+
+    case DataFile.write_atomic(path(), content) do
+      :ok -> :ok
+      {:error, reason} -> Logger.error("Failed to persist alert history: #{inspect(reason)}")
+    end
+  end
+end
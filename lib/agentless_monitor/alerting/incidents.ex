@@ -0,0 +1,261 @@
+defmodule AgentlessMonitor.Alerting.Incidents do
+  @moduledoc """
+  Groups related events into incidents.
+
+  Warning and critical events open an incident scoped to the server's group
+  (or the server itself when it has none); later events for the same scope
+  are appended to that incident's timeline until it is resolved. Recovery
+  events move an open incident to "mitigated". Resolved incidents are kept
+  in `AgentlessMonitor.Alerting.History`, along with the host context
+  captured when their critical events fired (see
+  `AgentlessMonitor.Alerting.Capture`).
+  """
+
+  use GenServer
+
+  alias AgentlessMonitor.{Models, Syslog}
+  alias AgentlessMonitor.Models.{Event, Incident}
+  alias AgentlessMonitor.Alerting.History
+
+  @statuses ["open", "mitigated", "resolved"]
+  @severity_rank %{"info" => 0, "warning" => 1, "critical" => 2}
+  @max_incidents 500
+  @max_captures 5
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, %{incidents: []}, name: __MODULE__)
+  end
+
+  def correlate(%Event{} = event) do
+    GenServer.cast(__MODULE__, {:correlate, event})
+  end
+
+  def list(opts \\ []) do
+    GenServer.call(__MODULE__, {:list, opts})
+  end
+
+  def get(id) do
+    GenServer.call(__MODULE__, {:get, id})
+  end
+
+  @doc "Updates status, assignee and/or postmortem notes of an incident"
+  def update(id, attrs) do
+    GenServer.call(__MODULE__, {:update, id, attrs})
+  end
+
+  @doc "Attaches a capture to the incident the event with `event_id` belongs to"
+  def attach_capture(event_id, capture) do
+    GenServer.cast(__MODULE__, {:attach_capture, event_id, capture})
+  end
+
+  # ---- Callbacks ----
+
+  def init(state) do
+    {:ok, state}
+  end
+
+  def handle_call({:list, opts}, _from, state) do
+    status = Keyword.get(opts, :status)
+
+    incidents =
+      Enum.filter(state.incidents, &(is_nil(status) or &1.status == status))
+
+    {:reply, incidents, state}
+  end
+
+  def handle_call({:get, id}, _from, state) do
+    case Enum.find(state.incidents, &(&1.id == id)) do
+      nil -> {:reply, {:error, :not_found}, state}
+      incident -> {:reply, {:ok, incident}, state}
+    end
+  end
+
+  def handle_call({:update, id, attrs}, _from, state) do
+    with {:ok, incident} <- find(state, id),
+         {:ok, updated} <- apply_update(incident, attrs) do
+      if updated.status == "resolved" and incident.status != "resolved",
+        do: History.record_alert(updated)
+
+      if updated.status != incident.status, do: forward(updated)
+
+      {:reply, {:ok, updated}, replace(state, updated)}
+    else
+      error -> {:reply, error, state}
+    end
+  end
+
+  def handle_cast({:correlate, event}, state) do
+    scope = scope_for(event)
+    active = Enum.find(state.incidents, &(&1.scope == scope and &1.status != "resolved"))
+
+    cond do
+      active ->
+        attached = attach(active, event)
+        if attached.status != active.status, do: forward(attached)
+        {:noreply, replace(state, attached)}
+
+      Map.get(@severity_rank, event.severity, 0) > 0 ->
+        incident = open_incident(event, scope)
+        forward(incident)
+        {:noreply, %{state | incidents: Enum.take([incident | state.incidents], @max_incidents)}}
+
+      true ->
+        {:noreply, state}
+    end
+  end
+
+  # Incidents keep their first captures; later ones only add to the timeline.
+  def handle_cast({:attach_capture, event_id, capture}, state) do
+    case Enum.find(state.incidents, &(event_id in &1.event_ids)) do
+      nil ->
+        {:noreply, state}
+
+      incident ->
+        name = capture["server_name"]
+
+        message =
+          if capture["error"],
+            do: "capture of #{name} failed: #{first_line(capture["error"])}",
+            else: "captured kernel log, journal errors and processes of #{name}"
+
+        incident =
+          %{incident | captures: Enum.take(incident.captures ++ [capture], @max_captures)}
+          |> add_timeline("capture", message)
+
+        {:noreply, replace(state, incident)}
+    end
+  end
+
+  # ---- Private helpers ----
+
+  defp scope_for(%Event{group: group}) when is_binary(group) and group != "", do: "group:#{group}"
+  defp scope_for(%Event{server_id: server_id}), do: "server:#{server_id}"
+
+  defp open_incident(event, scope) do
+    %Incident{
+      id: Models.generate_id(),
+      scope: scope,
+      server_id: event.server_id,
+      group: event.group,
+      title: event.message,
+      status: "open",
+      severity: event.severity,
+      opened_at: event.timestamp,
+      updated_at: event.timestamp,
+      event_ids: [event.id],
+      timeline: [timeline_entry("event", event.message, event.id)]
+    }
+  end
+
+  defp attach(incident, event) do
+    severity =
+      if Map.get(@severity_rank, event.severity, 0) > Map.get(@severity_rank, incident.severity, 0),
+        do: event.severity,
+        else: incident.severity
+
+    incident = %{
+      incident
+      | severity: severity,
+        updated_at: event.timestamp,
+        event_ids: incident.event_ids ++ [event.id],
+        timeline: incident.timeline ++ [timeline_entry("event", event.message, event.id)]
+    }
+
+    cond do
+      event.severity == "info" and incident.status == "open" ->
+        transition(incident, "mitigated", "automatically mitigated")
+
+      event.severity != "info" and incident.status == "mitigated" ->
+        transition(incident, "open", "reopened by new event")
+
+      true ->
+        incident
+    end
+  end
+
+  defp apply_update(incident, attrs) do
+    status = Map.get(attrs, "status")
+
+    if status && status not in @statuses do
+      {:error, :invalid_status}
+    else
+      incident =
+        if status && status != incident.status,
+          do: transition(incident, status, "set by operator"),
+          else: incident
+
+      incident =
+        case Map.fetch(attrs, "assignee") do
+          {:ok, assignee} when assignee != incident.assignee ->
+            %{incident | assignee: assignee}
+            |> add_timeline("assignee", "assigned to #{assignee || "nobody"}")
+
+          _ ->
+            incident
+        end
+
+      incident =
+        case Map.fetch(attrs, "postmortem") do
+          {:ok, notes} ->
+            %{incident | postmortem: notes} |> add_timeline("postmortem", "postmortem notes updated")
+
+          :error ->
+            incident
+        end
+
+      {:ok, incident}
+    end
+  end
+
+  defp transition(incident, status, reason) do
+    now = DateTime.utc_now() |> DateTime.to_iso8601()
+
+    incident = %{incident | status: status}
+
+    incident =
+      case status do
+        "mitigated" -> %{incident | mitigated_at: now}
+        "resolved" -> %{incident | resolved_at: now}
+        _ -> incident
+      end
+
+    add_timeline(incident, "status", "status changed to #{status} (#{reason})")
+  end
+
+  defp add_timeline(incident, type, message) do
+    entry = timeline_entry(type, message, nil)
+    %{incident | timeline: incident.timeline ++ [entry], updated_at: entry["timestamp"]}
+  end
+
+  defp timeline_entry(type, message, event_id) do
+    %{
+      "timestamp" => DateTime.utc_now() |> DateTime.to_iso8601(),
+      "type" => type,
+      "message" => message,
+      "event_id" => event_id
+    }
+  end
+
+  # Openings and status changes go to syslog as `alert_<status>`.
+  defp forward(incident), do: Syslog.forward(:alert, Incident.to_map(incident))
+
+  defp first_line(text), do: text |> String.split("\n") |> hd()
+
+  defp find(state, id) do
+    case Enum.find(state.incidents, &(&1.id == id)) do
+      nil -> {:error, :not_found}
+      incident -> {:ok, incident}
+    end
+  end
+
+  defp replace(state, incident) do
+    incidents =
+      Enum.map(state.incidents, fn existing ->
+        if existing.id == incident.id, do: incident, else: existing
+      end)
+
+    %{state | incidents: incidents}
+  end
+end
@@ -0,0 +1,219 @@
+defmodule AgentlessMonitor.Alerting.SLO do
+  @moduledoc """
+  Service level objectives computed from stored ping results.
+
+      "slos": [
+        {"name": "edge-reachability", "sli": "ping_success", "objective": 99.5,
+         "window": "30d", "group": "edge"},
+        {"name": "edge-latency", "sli": "ping_latency", "threshold_ms": 300,
+         "objective": 95, "window": "30d"}
+      ]
+
+  `ping_success` counts successful pings as good events; `ping_latency`
+  counts successful pings at or under `threshold_ms`, so an objective of 95
+  means "p95 latency below the threshold". An SLO covers the servers of
+  `group` (or the names in `servers`), or every server when neither is set.
+
+  The error budget is the share of bad events the objective allows. Burn
+  rates compare the bad ratio of the last hour and six hours with that
+  budget; every minute, a fast burn (1h above 14.4) raises a critical
+  `slo_burn_rate` event and a slow burn (6h above 6) a warning.
+  Objectives are evaluated over the samples still in history, reported as
+  `coverage_seconds`.
+  """
+
+  use GenServer
+  require Logger
+
+  alias AgentlessMonitor.{Config, Settings, State}
+  alias AgentlessMonitor.Alerting.Events
+  alias AgentlessMonitor.Monitoring.Matrix
+
+  @evaluate_interval :timer.seconds(60)
+  @history_samples 1000
+  @burn_windows [{"1h", 3600, 14.4, "critical"}, {"6h", 21_600, 6, "warning"}]
+  @percentiles [50, 95, 99]
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, nil, name: __MODULE__)
+  end
+
+  @doc "Current status, error budget and burn rates of every configured SLO"
+  def report do
+    now = System.system_time(:second)
+    Enum.map(Config.load().slos, &evaluate(&1, now))
+  end
+
+  # ---- Callbacks ----
+
+  def init(_) do
+    schedule_evaluation()
+    {:ok, %{levels: %{}}}
+  end
+
+  def handle_info(:evaluate, state) do
+    levels =
+      Map.new(report(), fn slo ->
+        level = burn_level(slo)
+
+        if level && level != Map.get(state.levels, slo["name"]) do
+          alert(slo, level)
+        end
+
+        {slo["name"], level}
+      end)
+
+    schedule_evaluation()
+    {:noreply, %{state | levels: levels}}
+  end
+
+  # ---- Private helpers ----
+
+  defp schedule_evaluation do
+    Process.send_after(self(), :evaluate, @evaluate_interval)
+  end
+
+  defp evaluate(slo, now) do
+    objective = Map.get(slo, "objective", 99.0)
+    allowed = 1 - objective / 100
+
+    window =
+      case Matrix.parse_duration(Map.get(slo, "window", "30d")) do
+        {:ok, seconds} -> seconds
+        {:error, _} -> 30 * 86_400
+      end
+
+    results = ping_results(slo, now - window)
+    judged = Enum.map(results, fn {ts, test} -> {ts, good?(slo, test)} end)
+    bad_ratio = bad_ratio(judged)
+
+    burn_rates =
+      Map.new(@burn_windows, fn {name, seconds, _threshold, _severity} ->
+        recent = Enum.filter(judged, fn {ts, _good} -> ts >= now - seconds end)
+        {name, burn_rate(bad_ratio(recent), allowed)}
+      end)
+
+    consumed = if allowed > 0, do: bad_ratio / allowed * 100, else: 0.0
+
+    %{
+      "name" => slo["name"],
+      "group" => slo["group"],
+      "sli" => slo["sli"],
+      "objective" => objective,
+      "window" => window,
+      "events" => length(judged),
+      "coverage_seconds" => coverage(judged, now),
+      "sli_value" => Float.round((1 - bad_ratio) * 100, 3),
+      "error_budget" => %{
+        "allowed_ratio" => Float.round(allowed, 6),
+        "consumed_percent" => Float.round(consumed, 1),
+        "remaining_percent" => Float.round(100 - consumed, 1)
+      },
+      "burn_rates" => burn_rates,
+      "percentiles" => latency_percentiles(results),
+      "status" => status(consumed, burn_rates)
+    }
+  end
+
+  defp ping_results(slo, since) do
+    State.get_servers()
+    |> Enum.reject(&(&1.status == "retired"))
+    |> Enum.filter(&covers?(slo, &1))
+    |> Enum.flat_map(fn server ->
+      server.id
+      |> State.get_historical_data(@history_samples)
+      |> Enum.flat_map(fn sample ->
+        case unix_time(sample.timestamp) do
+          ts when is_integer(ts) and ts >= since -> Enum.map(sample.ping_tests, &{ts, &1})
+          _ -> []
+        end
+      end)
+    end)
+  end
+
+  defp covers?(%{"servers" => names}, server) when is_list(names), do: server.name in names
+
+  defp covers?(%{"group" => group}, server) when is_binary(group),
+    do: Settings.group_for(server) == group
+
+  defp covers?(_slo, _server), do: true
+
+  defp good?(%{"sli" => "ping_latency"} = slo, test) do
+    test.success and is_number(test.latency_ms) and
+      test.latency_ms <= Map.get(slo, "threshold_ms", 300)
+  end
+
+  defp good?(_slo, test), do: test.success
+
+  defp bad_ratio([]), do: 0.0
+  defp bad_ratio(judged), do: Enum.count(judged, fn {_ts, good} -> not good end) / length(judged)
+
+  defp burn_rate(_bad_ratio, allowed) when allowed <= 0, do: 0.0
+  defp burn_rate(bad_ratio, allowed), do: Float.round(bad_ratio / allowed, 2)
+
+  defp coverage([], _now), do: 0
+
+  defp coverage(judged, now) do
+    now - (judged |> Enum.map(fn {ts, _good} -> ts end) |> Enum.min())
+  end
+
+  defp latency_percentiles(results) do
+    latencies =
+      results
+      |> Enum.flat_map(fn {_ts, test} ->
+        if test.success and is_number(test.latency_ms), do: [test.latency_ms], else: []
+      end)
+      |> Enum.sort()
+
+    Map.new(@percentiles, fn p -> {"p#{p}", percentile(latencies, p)} end)
+  end
+
+  # Nearest-rank percentile of a sorted list.
+  defp percentile([], _p), do: nil
+
+  defp percentile(sorted, p) do
+    rank = max(ceil(p / 100 * length(sorted)), 1)
+    Enum.at(sorted, rank - 1)
+  end
+
+  defp status(consumed, _burn_rates) when consumed >= 100, do: "exhausted"
+
+  defp status(_consumed, burn_rates) do
+    burning =
+      Enum.any?(@burn_windows, fn {name, _seconds, threshold, _severity} ->
+        burn_rates[name] > threshold
+      end)
+
+    if burning, do: "burning", else: "ok"
+  end
+
+  defp burn_level(slo) do
+    Enum.find_value(@burn_windows, fn {name, _seconds, threshold, severity} ->
+      if slo["burn_rates"][name] > threshold, do: {name, severity}
+    end)
+  end
+
+  defp alert(slo, {window, severity}) do
+    Logger.warning("SLO #{slo["name"]} burning its error budget (#{window} window)")
+
+    Events.emit(
+      group: slo["group"],
+      kind: "slo_burn_rate",
+      severity: severity,
+      message:
+        "SLO #{slo["name"]}: #{window} burn rate #{slo["burn_rates"][window]}x, " <>
+          "#{slo["error_budget"]["remaining_percent"]}% of error budget left"
+    )
+  end
+
+  defp unix_time(timestamp) when is_binary(timestamp) do
+    case DateTime.from_iso8601(timestamp) do
+      {:ok, dt, _} -> DateTime.to_unix(dt)
+      _ -> nil
+    end
+  end
+
+  defp unix_time(_timestamp), do: nil
+end
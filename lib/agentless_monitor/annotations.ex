@@ -0,0 +1,82 @@
+defmodule AgentlessMonitor.Annotations do
+  @moduledoc """
+  Chart markers for a server: its events (alerts, status changes, drift)
+  and the operator-triggered jobs run on it (exec, actions, diagnostics),
+  with timestamps in the same ISO 8601 form as history samples.
+  """
+
+  alias AgentlessMonitor.State
+  alias AgentlessMonitor.Alerting.Events
+
+  @max_events 1000
+
+  @doc """
+  Annotations of `server_id` between the unix times `from` and `to` (either
+  may be nil), oldest first.
+  """
+  def for_server(server_id, from, to) do
+    events =
+      [server_id: server_id, limit: @max_events]
+      |> Events.list()
+      |> Enum.map(fn event ->
+        %{
+          "timestamp" => event.timestamp,
+          "type" => "event",
+          "kind" => event.kind,
+          "severity" => event.severity,
+          "label" => event.message,
+          "suppressed" => event.suppressed_by != []
+        }
+      end)
+
+    jobs =
+      for job <- State.get_jobs(), job.server_id == server_id, job.job_type != "monitoring" do
+        %{
+          "timestamp" => job.started_at || job.created_at,
+          "end" => job.completed_at,
+          "type" => "job",
+          "kind" => job.job_type,
+          "severity" => if(job.status == "failed", do: "warning", else: "info"),
+          "label" => "#{job.job_type} #{job.status}"
+        }
+      end
+
+    (events ++ jobs)
+    |> Enum.map(&{unix_time(&1["timestamp"]), &1})
+    |> Enum.filter(fn {ts, _annotation} -> within?(ts, from, to) end)
+    |> Enum.sort_by(fn {ts, _annotation} -> ts end)
+    |> Enum.map(fn {_ts, annotation} -> annotation end)
+  end
+
+  @doc "Parses a `from`/`to` bound given as unix seconds or ISO 8601"
+  def parse_bound(nil), do: {:ok, nil}
+
+  def parse_bound(value) when is_binary(value) do
+    case Integer.parse(value) do
+      {unix, ""} ->
+        {:ok, unix}
+
+      _ ->
+        case unix_time(value) do
+          nil -> {:error, "invalid time #{inspect(value)}"}
+          unix -> {:ok, unix}
+        end
+    end
+  end
+
+  # ---- Private helpers ----
+
+  defp within?(nil, _from, _to), do: false
+  defp within?(ts, from, _to) when is_integer(from) and ts < from, do: false
+  defp within?(ts, _from, to) when is_integer(to) and ts > to, do: false
+  defp within?(_ts, _from, _to), do: true
+
+  defp unix_time(timestamp) when is_binary(timestamp) do
+    case DateTime.from_iso8601(timestamp) do
+      {:ok, dt, _} -> DateTime.to_unix(dt)
+      _ -> nil
+    end
+  end
+
+  defp unix_time(_timestamp), do: nil
+end
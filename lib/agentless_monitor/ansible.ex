@@ -0,0 +1,251 @@
+defmodule AgentlessMonitor.Ansible do
+  @moduledoc """
+  Converts between Ansible inventories and server entries.
+
+  Both the INI and YAML inventory formats are read. Hosts become server
+  entries in the config.json shape (`name`, `host`, `port`, `username`,
+  `group`, `transport`), taking `ansible_host`, `ansible_port`,
+  `ansible_user` and `ansible_connection` from host or group vars. A host's
+  group is the most specific group it is listed under; `all` and
+  `ungrouped` map to no group. Host ranges such as `web[01:10]` are not
+  expanded.
+  """
+
+  @top_groups ["all", "ungrouped"]
+
+  # ---- Import ----
+
+  @doc "Parses an inventory in `\"ini\"` or `\"yaml\"` format into server entries"
+  def parse(content, "ini"), do: {:ok, parse_ini(content)}
+
+  def parse(content, "yaml") do
+    case YamlElixir.read_from_string(content) do
+      {:ok, inventory} when is_map(inventory) -> {:ok, parse_yaml(inventory)}
+      {:ok, _} -> {:error, "inventory must be a YAML mapping"}
+      {:error, error} -> {:error, Exception.message(error)}
+    end
+  end
+
+  def parse(_content, format), do: {:error, "unsupported format #{inspect(format)}"}
+
+  defp parse_ini(content) do
+    {hosts, group_vars, children, _section} =
+      content
+      |> String.split("\n")
+      |> Enum.map(&String.trim/1)
+      |> Enum.reject(&(&1 == "" or String.starts_with?(&1, ["#", ";"])))
+      |> Enum.reduce({[], %{}, %{}, {"ungrouped", :hosts}}, &parse_ini_line/2)
+
+    hosts
+    |> Enum.reverse()
+    |> Enum.group_by(fn {name, _group, _vars} -> name end)
+    |> Enum.map(fn {name, listings} ->
+      group =
+        listings
+        |> Enum.map(fn {_name, group, _vars} -> group end)
+        |> most_specific(children)
+
+      # all, then ancestors from the outermost in, then the group, then host vars
+      inherited = ["all" | Enum.reverse(ancestors(group, children))] ++ [group]
+
+      vars =
+        Enum.reduce(inherited, %{}, &Map.merge(&2, Map.get(group_vars, &1, %{})))
+        |> then(fn vars ->
+          Enum.reduce(listings, vars, fn {_name, _group, host_vars}, acc ->
+            Map.merge(acc, host_vars)
+          end)
+        end)
+
+      entry(name, group, vars)
+    end)
+    |> Enum.sort_by(& &1["name"])
+  end
+
+  defp parse_ini_line(line, {hosts, vars, children, section}) do
+    case Regex.run(~r/^\[([^\]:]+)(?::(vars|children))?\]$/, line) do
+      [_, group] ->
+        {hosts, vars, children, {group, :hosts}}
+
+      [_, group, "vars"] ->
+        {hosts, vars, children, {group, :vars}}
+
+      [_, group, "children"] ->
+        {hosts, vars, children, {group, :children}}
+
+      nil ->
+        case section do
+          {group, :hosts} ->
+            [name | assignments] = String.split(line)
+            {[{name, group, parse_assignments(assignments)} | hosts], vars, children, section}
+
+          {group, :vars} ->
+            group_vars = Map.merge(Map.get(vars, group, %{}), parse_assignments([line]))
+            {hosts, Map.put(vars, group, group_vars), children, section}
+
+          {group, :children} ->
+            {hosts, vars, Map.update(children, group, [line], &[line | &1]), section}
+        end
+    end
+  end
+
+  defp parse_assignments(assignments) do
+    assignments
+    |> Enum.flat_map(fn assignment ->
+      case String.split(assignment, "=", parts: 2) do
+        [key, value] -> [{String.trim(key), value |> String.trim() |> String.trim("\"")}]
+        _ -> []
+      end
+    end)
+    |> Map.new()
+  end
+
+  # The group listed deepest in the `:children` hierarchy wins.
+  defp most_specific(groups, children) do
+    Enum.max_by(groups, &length(ancestors(&1, children)), fn -> "ungrouped" end)
+  end
+
+  defp ancestors(group, children, seen \\ MapSet.new()) do
+    parents =
+      for {parent, kids} <- children,
+          group in kids,
+          not MapSet.member?(seen, parent),
+          do: parent
+
+    seen = MapSet.union(seen, MapSet.new(parents))
+    parents ++ Enum.flat_map(parents, &ancestors(&1, children, seen))
+  end
+
+  defp parse_yaml(inventory) do
+    inventory
+    |> Enum.flat_map(fn {group, body} -> walk_yaml(group, body, %{}) end)
+    |> Enum.group_by(fn {name, _depth, _group, _vars} -> name end)
+    |> Enum.map(fn {name, listings} ->
+      {_name, _depth, group, vars} = Enum.max_by(listings, fn {_, depth, _, _} -> depth end)
+      entry(name, group, vars)
+    end)
+    |> Enum.sort_by(& &1["name"])
+  end
+
+  defp walk_yaml(group, body, inherited, depth \\ 0)
+
+  defp walk_yaml(group, body, inherited, depth) when is_map(body) do
+    vars = Map.merge(inherited, stringify(body["vars"] || %{}))
+
+    hosts =
+      for {name, host_vars} <- body["hosts"] || %{} do
+        {to_string(name), depth, group, Map.merge(vars, stringify(host_vars || %{}))}
+      end
+
+    children =
+      Enum.flat_map(body["children"] || %{}, fn {child, child_body} ->
+        walk_yaml(child, child_body || %{}, vars, depth + 1)
+      end)
+
+    hosts ++ children
+  end
+
+  defp walk_yaml(_group, _body, _inherited, _depth), do: []
+
+  defp stringify(vars) when is_map(vars) do
+    vars
+    |> Enum.reject(fn {_key, value} -> is_nil(value) end)
+    |> Map.new(fn
+      {key, value} when is_binary(value) or is_number(value) or is_atom(value) ->
+        {to_string(key), to_string(value)}
+
+      {key, value} ->
+        {to_string(key), inspect(value)}
+    end)
+  end
+
+  defp stringify(_), do: %{}
+
+  defp entry(name, group, vars) do
+    base = %{
+      "name" => name,
+      "group" => if(group in @top_groups, do: nil, else: group)
+    }
+
+    if vars["ansible_connection"] == "local" do
+      Map.put(base, "transport", "local")
+    else
+      Map.merge(base, %{
+        "host" => vars["ansible_host"] || name,
+        "port" => parse_port(vars["ansible_port"]),
+        "username" => vars["ansible_user"]
+      })
+      |> Map.reject(fn {_key, value} -> is_nil(value) end)
+    end
+  end
+
+  defp parse_port(nil), do: nil
+
+  defp parse_port(value) do
+    case Integer.parse(value) do
+      {port, ""} -> port
+      _ -> nil
+    end
+  end
+
+  # ---- Export ----
+
+  @doc "Renders servers as an Ansible inventory in `\"ini\"` or `\"yaml\"` format"
+  def export(servers, format) do
+    grouped =
+      servers
+      |> Enum.reject(&(&1.source == "local" or &1.status == "retired"))
+      |> Enum.sort_by(& &1.name)
+      |> Enum.group_by(&(&1.group || "ungrouped"))
+      |> Enum.sort()
+
+    case format do
+      "yaml" -> export_yaml(grouped)
+      _ -> export_ini(grouped)
+    end
+  end
+
+  defp export_ini(grouped) do
+    grouped
+    |> Enum.map_join("\n", fn {group, servers} ->
+      lines =
+        Enum.map(servers, fn server ->
+          vars = Enum.map_join(host_vars(server), " ", fn {key, value} -> "#{key}=#{value}" end)
+          String.trim("#{server.name} #{vars}")
+        end)
+
+      Enum.join(["[#{group}]" | lines], "\n") <> "\n"
+    end)
+  end
+
+  # Scalars are written as JSON strings/numbers, which are valid YAML.
+  defp export_yaml(grouped) do
+    groups =
+      Enum.map(grouped, fn {group, servers} ->
+        hosts =
+          Enum.map(servers, fn server ->
+            vars =
+              Enum.map(host_vars(server), fn {key, value} ->
+                "          #{key}: #{Jason.encode!(value)}\n"
+              end)
+
+            name = "        #{Jason.encode!(server.name)}:"
+            if vars == [], do: [name, " {}\n"], else: [name, "\n", vars]
+          end)
+
+        ["    #{Jason.encode!(group)}:\n", "      hosts:\n", hosts]
+      end)
+
+    IO.iodata_to_binary(["all:\n", "  children:\n", groups])
+  end
+
+  defp host_vars(%{transport: "local"}), do: [{"ansible_connection", "local"}]
+
+  defp host_vars(server) do
+    [
+      {"ansible_host", server.host},
+      {"ansible_port", server.port},
+      {"ansible_user", server.username}
+    ]
+    |> Enum.reject(fn {_key, value} -> is_nil(value) end)
+  end
+end
@@ -0,0 +1,140 @@
+defmodule AgentlessMonitor.API.Auth do
+  @moduledoc """
+  Bearer-token authentication for `/api` routes, enabled with `api_auth`.
+
+  Each route maps to the scope it needs: reads need `read:metrics`, writes
+  `write:servers`, operations that run commands `exec:commands`, and
+  administration (tokens, config, manifest, actions) `admin`. Health and
+  ChatOps endpoints and `POST /api/register` stay open, being verified by
+  signature or registration token instead.
+  With `ldap` configured, HTTP Basic credentials are also accepted and
+  checked against the directory (see `AgentlessMonitor.LDAP`). The
+  authenticated token is stored in `conn.assigns.token`.
+
+  A request carrying a share link token (`X-Share-Token` or `?share=`) is
+  limited to what the link covers, with or without `api_auth`, and the link
+  is stored in `conn.assigns.share_link` (see `AgentlessMonitor.ShareLinks`).
+  """
+
+  import Plug.Conn
+
+  alias AgentlessMonitor.{Config, LDAP, ShareLinks, Tokens}
+  alias AgentlessMonitor.API.Handlers
+
+  # Server operations that run commands on the host or probe it actively.
+  @exec_actions ["collect-now", "connect", "burst", "mtu", "install-key", "port-audit"]
+
+  def init(opts), do: opts
+
+  def call(%Plug.Conn{path_info: ["api" | _]} = conn, _opts) do
+    case {share_token(conn), required_scope(conn)} do
+      {nil, :public} ->
+        conn
+
+      {nil, scope} ->
+        if Config.load().api_auth, do: authorize(conn, scope), else: conn
+
+      {token, _scope} ->
+        authorize_share(conn, token)
+    end
+  end
+
+  def call(conn, _opts), do: conn
+
+  @doc "The scope a request needs, or `:public`"
+  def required_scope(%Plug.Conn{method: method, path_info: path} = conn) do
+    case {method, path} do
+      {_, ["api", "health"]} -> :public
+      {_, ["api", "chatops" | _]} -> :public
+      {"POST", ["api", "register"]} -> :public
+      {_, ["api", "register" | _]} -> "admin"
+      {_, ["api", "tokens" | _]} -> "admin"
+      {_, ["api", "share-links" | _]} -> "admin"
+      {_, ["api", "admin" | _]} -> "admin"
+      {_, ["api", "manifest" | _]} -> "admin"
+      {_, ["api", "actions" | _]} -> "admin"
+      {_, ["api", "bundles" | _]} -> "admin"
+      {_, ["api", "reload-config"]} -> "admin"
+      {"POST", ["api", "servers", _id, "purge"]} -> "admin"
+      {"POST", ["api", "approvals", _id, "approve"]} -> "exec:commands"
+      {"POST", ["api", "servers", _id, action]} when action in @exec_actions -> "exec:commands"
+      {"POST", ["api", "servers", _id, "diagnostics", "du"]} -> "exec:commands"
+      {"GET", ["api", "servers", _id, "details", _]} -> details_scope(conn)
+      {"GET", _} -> "read:metrics"
+      _ -> "write:servers"
+    end
+  end
+
+  # Debug collections run commands on the server and expose raw output.
+  defp details_scope(conn) do
+    if conn.query_params["debug"] == "true", do: "admin", else: "read:metrics"
+  end
+
+  defp authorize(conn, scope) do
+    with {:ok, token} <- authenticate(get_req_header(conn, "authorization")) do
+      if Tokens.authorized?(token, scope) do
+        assign(conn, :token, token)
+      else
+        conn
+        |> Handlers.json_response(403, %{"error" => "token lacks scope #{scope}"})
+        |> halt()
+      end
+    else
+      {:error, :expired} -> unauthorized(conn, "token expired")
+      {:error, :unavailable} -> unauthorized(conn, "directory server unavailable")
+      _ -> unauthorized(conn, "missing or invalid credentials")
+    end
+  end
+
+  defp share_token(conn) do
+    case get_req_header(conn, "x-share-token") do
+      [token | _] -> String.trim(token)
+      [] -> conn.query_params["share"]
+    end
+  end
+
+  defp authorize_share(conn, token) do
+    case ShareLinks.verify(token) do
+      {:ok, link} ->
+        if ShareLinks.permits?(link, conn.method, conn.path_info, conn.query_params) do
+          assign(conn, :share_link, link)
+        else
+          conn
+          |> Handlers.json_response(403, %{"error" => "share link does not cover this view"})
+          |> halt()
+        end
+
+      {:error, :expired} ->
+        unauthorized(conn, "share link expired")
+
+      {:error, :invalid} ->
+        unauthorized(conn, "invalid share link")
+    end
+  end
+
+  defp authenticate(["Bearer " <> secret]), do: Tokens.authenticate(String.trim(secret))
+
+  defp authenticate(["Basic " <> encoded]) do
+    with true <- LDAP.enabled?(),
+         {:ok, decoded} <- Base.decode64(String.trim(encoded)),
+         [username, password] <- String.split(decoded, ":", parts: 2) do
+      LDAP.authenticate(username, password)
+    else
+      _ -> {:error, :invalid}
+    end
+  end
+
+  defp authenticate(_header), do: {:error, :invalid}
+
+  defp unauthorized(conn, message) do
+    conn
+    |> put_resp_header("www-authenticate", challenge())
+    |> Handlers.json_response(401, %{"error" => message})
+    |> halt()
+  end
+
+  # With LDAP the browser's Basic login prompt lets dashboard users sign in.
+  defp challenge do
+    if LDAP.enabled?(), do: ~s(Basic realm="agentless-monitor"), else: "Bearer"
+  end
+end
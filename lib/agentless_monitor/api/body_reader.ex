@@ -0,0 +1,23 @@
+defmodule AgentlessMonitor.API.BodyReader do
+  @moduledoc """
+  Body reader for `Plug.Parsers` that keeps a copy of the raw request body in
+  `conn.assigns.raw_body`, needed to verify signed webhook requests.
+
+  Only ChatOps requests (`/api/chatops/...`) are signed, so other bodies
+  aren't copied.
+  """
+
+  def read_body(conn, opts) do
+    case Plug.Conn.read_body(conn, opts) do
+      {:ok, body, conn} -> {:ok, body, cache(conn, body)}
+      {:more, body, conn} -> {:more, body, cache(conn, body)}
+      other -> other
+    end
+  end
+
+  defp cache(%{path_info: ["api", "chatops" | _]} = conn, body) do
+    Plug.Conn.assign(conn, :raw_body, (conn.assigns[:raw_body] || "") <> body)
+  end
+
+  defp cache(conn, _body), do: conn
+end
@@ -0,0 +1,218 @@
+defmodule AgentlessMonitor.API.ChatOps do
+  @moduledoc """
+  Slack/Mattermost slash-command support.
+
+  Supported commands (the text after the slash command itself):
+
+    * `status [server]` - fleet summary, or status and key metrics of a server
+    * `ack <incident-id>` - acknowledge an incident, assigning it to the caller
+    * `exec <server> <command>` - run a command (requires `chatops_allow_exec`);
+      when `exec_risk` needs approval the command is filed as an approval request
+    * `approve <approval-id>` / `reject <approval-id>` - decide a pending approval
+  """
+
+  require Logger
+
+  alias AgentlessMonitor.{Approvals, Config, ServerStatus, State}
+  alias AgentlessMonitor.Alerting.Incidents
+  alias AgentlessMonitor.Monitoring.Service
+
+  # Slack rejects replays older than five minutes; do the same.
+  @max_request_age 300
+  @max_output 2_500
+
+  # ---- Verification ----
+
+  @doc "Verifies Slack's `X-Slack-Signature` HMAC over the raw request body"
+  def verify_slack(conn) do
+    secret = Config.load().chatops_signing_secret
+    timestamp = header(conn, "x-slack-request-timestamp")
+    signature = header(conn, "x-slack-signature")
+    body = conn.assigns[:raw_body] || ""
+
+    cond do
+      is_nil(secret) ->
+        {:error, :not_configured}
+
+      is_nil(timestamp) or is_nil(signature) or stale?(timestamp) ->
+        {:error, :invalid_signature}
+
+      true ->
+        digest =
+          :crypto.mac(:hmac, :sha256, secret, "v0:#{timestamp}:#{body}")
+          |> Base.encode16(case: :lower)
+
+        if Plug.Crypto.secure_compare("v0=" <> digest, signature),
+          do: :ok,
+          else: {:error, :invalid_signature}
+    end
+  end
+
+  @doc "Verifies the per-command token Mattermost sends with each request"
+  def verify_mattermost(params) do
+    case {Config.load().chatops_token, Map.get(params, "token")} do
+      {nil, _} -> {:error, :not_configured}
+      {_, nil} -> {:error, :invalid_signature}
+      {expected, token} ->
+        if Plug.Crypto.secure_compare(expected, token),
+          do: :ok,
+          else: {:error, :invalid_signature}
+    end
+  end
+
+  # ---- Commands ----
+
+  @doc "Runs a slash command and returns the reply as markdown text"
+  def run(text, user) do
+    case String.split(String.trim(text || ""), ~r/\s+/, parts: 3, trim: true) do
+      ["status"] -> fleet_status()
+      ["status", name] -> server_status(name)
+      ["ack", id] -> ack(id, user)
+      ["exec", name, command] -> exec(name, command, user)
+      ["approve", id] -> decide(id, user, &Approvals.approve/2)
+      ["reject", id] -> decide(id, user, &Approvals.reject/2)
+      _ -> usage()
+    end
+  end
+
+  # ---- Replies ----
+
+  def slack_reply(text) do
+    %{
+      "response_type" => "in_channel",
+      "text" => text,
+      "blocks" => [%{"type" => "section", "text" => %{"type" => "mrkdwn", "text" => text}}]
+    }
+  end
+
+  def mattermost_reply(text) do
+    %{"response_type" => "in_channel", "text" => text}
+  end
+
+  # ---- Private helpers ----
+
+  defp fleet_status do
+    servers = Enum.reject(State.get_servers(), &(&1.status == "retired"))
+
+    counts =
+      servers
+      |> Enum.frequencies_by(&ServerStatus.effective/1)
+      |> Enum.sort()
+      |> Enum.map_join(", ", fn {status, n} -> "#{n} #{status}" end)
+
+    "*#{length(servers)} servers*: #{counts}"
+  end
+
+  defp server_status(name) do
+    case find_server(name) do
+      nil ->
+        "No server named `#{name}`"
+
+      server ->
+        summary = "*#{server.name}* (`#{server.host}`) is *#{ServerStatus.effective(server)}*"
+
+        case State.get_latest_monitoring_data(server.id) do
+          nil ->
+            summary <> "\nNo monitoring data yet"
+
+          data ->
+            summary <>
+              "\nCPU: #{cpu_usage(data)}% · Memory: #{memory_usage(data)}% · Last seen: #{server.last_seen || "never"}"
+        end
+    end
+  end
+
+  defp ack(id, user) do
+    case Incidents.update(id, %{"assignee" => user}) do
+      {:ok, incident} -> "Incident `#{incident.id}` (#{incident.title}) acknowledged by #{user}"
+      {:error, :not_found} -> "No incident `#{id}`"
+    end
+  end
+
+  defp exec(name, command, user) do
+    if Config.load().chatops_allow_exec do
+      case find_server(name) do
+        nil -> "No server named `#{name}`"
+        server -> exec_on(server, command, user)
+      end
+    else
+      "Remote exec is disabled (set `chatops_allow_exec` to enable it)"
+    end
+  end
+
+  defp exec_on(server, command, user) do
+    risk = Config.load().exec_risk
+
+    if Approvals.required?(risk) do
+      {:ok, approval} =
+        Approvals.request(
+          %{
+            "summary" => "exec `#{command}` on #{server.name}",
+            "risk" => risk,
+            "server_id" => server.id,
+            "requested_by" => user
+          },
+          fn ->
+            Service.run_as_job(server, "exec", fn -> Service.run_command(server, command) end)
+          end
+        )
+
+      "Approval `#{approval["id"]}` requested for `#{command}` on *#{server.name}*; " <>
+        "another operator must run `approve #{approval["id"]}` (output will be recorded as a job)"
+    else
+      run_now(server, command, user)
+    end
+  end
+
+  defp run_now(server, command, user) do
+    Logger.info("ChatOps exec by #{user} on #{server.name}: #{command}")
+
+    {label, output} =
+      case Service.run_command(server, command) do
+        {:ok, output} -> {"succeeded", output}
+        {:error, output} -> {"failed", output}
+      end
+
+    "`#{command}` on *#{server.name}* #{label}:\n```\n#{String.slice(output, 0, @max_output)}\n```"
+  end
+
+  defp decide(id, user, fun) do
+    case fun.(id, user) do
+      {:ok, approval} -> "Approval `#{id}` #{approval["status"]} by #{user}"
+      {:error, :not_found} -> "No approval `#{id}`"
+      {:error, :not_pending} -> "Approval `#{id}` is no longer pending"
+      {:error, :self_approval} -> "You cannot approve your own request"
+    end
+  end
+
+  defp usage do
+    "Usage: `status [server]`, `ack <incident-id>`, `exec <server> <command>`, " <>
+      "`approve <approval-id>`, `reject <approval-id>`"
+  end
+
+  defp find_server(name) do
+    Enum.find(State.get_servers(), &(&1.name == name or &1.id == name))
+  end
+
+  defp cpu_usage(%{cpu: %{usage_percent: usage}}), do: usage
+  defp cpu_usage(_), do: "?"
+
+  defp memory_usage(%{memory: %{total: total, used: used}}) when total > 0,
+    do: Float.round(used / total * 100, 1)
+
+  defp memory_usage(_), do: "?"
+
+  defp header(conn, name) do
+    case Plug.Conn.get_req_header(conn, name) do
+      [value | _] -> value
+      [] -> nil
+    end
+  end
+
+  defp stale?(timestamp) do
+    case Integer.parse(timestamp) do
+      {ts, _} -> abs(System.system_time(:second) - ts) > @max_request_age
+      :error -> true
+    end
+  end
+end
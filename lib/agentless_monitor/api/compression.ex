@@ -0,0 +1,63 @@
+defmodule AgentlessMonitor.API.Compression do
+  @moduledoc """
+  Gzip-compresses responses for clients that send `Accept-Encoding: gzip`.
+
+  Only text-like content types are compressed, and bodies below
+  `@min_size` bytes are sent as-is since the savings don't cover the
+  overhead. Chunked responses (streams) are left untouched.
+
+  Brotli isn't offered: OTP has no encoder for it and gzip covers every
+  client. Clients that only accept `br` get uncompressed responses.
+  """
+
+  import Plug.Conn
+
+  @min_size 1024
+  @compressible ["application/json", "application/javascript", "text/"]
+
+  def init(opts), do: opts
+
+  def call(conn, _opts) do
+    if accepts_gzip?(conn) do
+      register_before_send(conn, &compress/1)
+    else
+      conn
+    end
+  end
+
+  defp compress(%Plug.Conn{state: :set, resp_body: body} = conn) when not is_nil(body) do
+    if compressible?(conn) and IO.iodata_length(body) >= @min_size do
+      %{conn | resp_body: :zlib.gzip(body)}
+      |> put_resp_header("content-encoding", "gzip")
+      |> put_resp_header("vary", "accept-encoding")
+    else
+      conn
+    end
+  end
+
+  defp compress(conn), do: conn
+
+  defp accepts_gzip?(conn) do
+    conn
+    |> get_req_header("accept-encoding")
+    |> Enum.flat_map(&String.split(&1, ","))
+    |> Enum.any?(fn coding ->
+      case coding |> String.trim() |> String.split(";") do
+        ["gzip" | params] -> "q=0" not in Enum.map(params, &String.trim/1)
+        _ -> false
+      end
+    end)
+  end
+
+  defp compressible?(conn) do
+    already_encoded = get_resp_header(conn, "content-encoding") != []
+
+    case get_resp_header(conn, "content-type") do
+      [content_type | _] ->
+        not already_encoded and Enum.any?(@compressible, &String.starts_with?(content_type, &1))
+
+      [] ->
+        false
+    end
+  end
+end
@@ -2,7 +2,9 @@ defmodule AgentlessMonitor.API.Handlers do
   @moduledoc "Helper functions for building API responses"
 
   import Plug.Conn
-  alias AgentlessMonitor.Models.{Server, MonitoringData, MonitoringJob}
+  alias AgentlessMonitor.Models.{Server, MonitoringData, MonitoringJob, Event, Incident}
+  alias AgentlessMonitor.Monitoring.{Health, Service}
+  alias AgentlessMonitor.{Privacy, ServerStatus, State}
 
   def json_response(conn, status, body) do
     conn
@@ -15,7 +17,14 @@ defmodule AgentlessMonitor.API.Handlers do
   end
 
   def server_to_map(%Server{} = server) do
-    Server.to_map(server)
+    server
+    |> Server.to_map()
+    |> Privacy.redact_server()
+    |> Map.merge(Service.staleness(server))
+    |> Map.put("enabled", not State.is_paused?(server.id))
+    |> Map.put("status", ServerStatus.effective(server))
+    |> Map.put("collection_status", server.status)
+    |> Map.put("health", Health.server(server))
   end
 
   def server_to_map(server) when is_map(server) do
@@ -25,7 +34,9 @@ defmodule AgentlessMonitor.API.Handlers do
   def monitoring_data_to_map(nil), do: nil
 
   def monitoring_data_to_map(%MonitoringData{} = data) do
-    MonitoringData.to_map(data)
+    data
+    |> Privacy.redact_data()
+    |> MonitoringData.to_map()
   end
 
   def monitoring_data_to_map(data) when is_map(data), do: data
@@ -35,4 +46,8 @@ defmodule AgentlessMonitor.API.Handlers do
   end
 
   def job_to_map(job) when is_map(job), do: job
+
+  def event_to_map(%Event{} = event), do: event |> Event.to_map() |> Privacy.redact_event()
+
+  def incident_to_map(%Incident{} = incident), do: Incident.to_map(incident)
 end
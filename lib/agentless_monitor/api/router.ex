@@ -2,27 +2,109 @@ defmodule AgentlessMonitor.API.Router do
   use Plug.Router
   require Logger
 
-  alias AgentlessMonitor.{State, Config}
-  alias AgentlessMonitor.SSH.{Connection, Manager}
-  alias AgentlessMonitor.Monitoring.Service
-  alias AgentlessMonitor.API.Handlers
-
-  plug(Plug.Static, at: "/static", from: "static")
+  alias AgentlessMonitor.{
+    State,
+    Annotations,
+    Config,
+    Approvals,
+    Bundles,
+    Features,
+    Idempotency,
+    Importer,
+    Settings,
+    ShareLinks,
+    Manifest,
+    Notifications,
+    Tokens,
+    PubSub,
+    Privacy,
+    Ansible,
+    Registrations,
+    Sites,
+    Storage,
+    TimeZones,
+    Topology
+  }
+
+  alias AgentlessMonitor.SSH.{Coalescer, KeyInstall, Manager, Restricted}
+  alias AgentlessMonitor.Monitoring.{
+    Burst,
+    DiskUsage,
+    EOL,
+    Fingerprint,
+    Health,
+    Matrix,
+    Mesh,
+    MTU,
+    Neighbors,
+    Overview,
+    PortAudit,
+    Service,
+    Watchdog
+  }
+  alias AgentlessMonitor.Alerting.{Events, History, Incidents, Actions, SLO, Canary}
+  alias AgentlessMonitor.API.{Handlers, ChatOps, Snapshot}
+
+  plug(AgentlessMonitor.API.Compression)
+
+  if Features.enabled?(:web_ui) do
+    plug(Plug.Static, at: "/static", from: "static")
+  end
 
   plug(Plug.Parsers,
-    parsers: [:json],
-    json_decoder: Jason
+    parsers: [:urlencoded, :json],
+    json_decoder: Jason,
+    body_reader: {AgentlessMonitor.API.BodyReader, :read_body, []}
   )
 
+  plug(AgentlessMonitor.API.Auth)
+
   plug(:match)
   plug(:dispatch)
 
+  # Upper bound on samples read per server for `/api/matrix` and time-range
+  # history queries; matches the in-memory history size.
+  @history_samples 1000
+
+  @detail_sections %{
+    "cpu" => "cpu",
+    "memory" => "memory",
+    "disks" => "disks",
+    "network" => "network",
+    "ports" => "ports",
+    "processes" => "processes",
+    "containers" => "containers",
+    "services" => "services",
+    "ping" => "ping_tests",
+    "system" => "system_info",
+    "http_checks" => "http_checks"
+  }
+
+  # Fields accepted by `POST /api/servers` and `PUT /api/servers/:id`, in
+  # config.json shape.
+  @server_fields ~w(name host port username auth_method group site proxy
+                    monitoring_interval transport command_prefix snmp depends_on
+                    watched_units identity_files)
+
   # ---- Health ----
 
   get "/api/health" do
     Handlers.json_response(conn, 200, %{"status" => "ok", "version" => "1.0.0"})
   end
 
+  # ---- Self-metrics ----
+
+  get "/api/self-metrics" do
+    memory = :erlang.memory()
+
+    Handlers.json_response(conn, 200, %{
+      "uptime_seconds" => div(:erlang.statistics(:wall_clock) |> elem(0), 1000),
+      "processes" => :erlang.system_info(:process_count),
+      "memory" => Map.new(memory, fn {key, bytes} -> {Atom.to_string(key), bytes} end),
+      "history" => State.memory_stats()
+    })
+  end
+
   # ---- Config info ----
 
   get "/api/config-info" do
@@ -30,6 +112,7 @@ defmodule AgentlessMonitor.API.Router do
 
     Handlers.json_response(conn, 200, %{
       "server_port" => config.server_port,
+      "bind_address" => config.bind_address,
       "monitoring_interval" => config.monitoring_interval,
       "ping_timeout" => config.ping_timeout,
       "ssh_timeout" => config.ssh_timeout,
@@ -37,10 +120,527 @@ defmodule AgentlessMonitor.API.Router do
     })
   end
 
+  get "/api/profiles" do
+    Handlers.json_response(conn, 200, Settings.profiles())
+  end
+
+  post "/api/reload-config" do
+    guard_runtime_edit(conn, fn ->
+      {:ok, summary} = State.reload_config()
+      Handlers.json_response(conn, 200, summary)
+    end)
+  end
+
+  # ---- API tokens ----
+
+  get "/api/tokens" do
+    Handlers.json_response(conn, 200, Tokens.list())
+  end
+
+  post "/api/tokens" do
+    case Tokens.create(conn.body_params || %{}, operator(conn)) do
+      {:ok, token, secret} ->
+        Handlers.json_response(conn, 201, Map.put(token, "token", secret))
+
+      {:error, :invalid_name} ->
+        Handlers.json_response(conn, 400, %{"error" => "name is required"})
+
+      {:error, :invalid_scopes} ->
+        Handlers.json_response(conn, 400, %{
+          "error" => "scopes must be a non-empty subset of #{Enum.join(Tokens.scopes(), ", ")}"
+        })
+    end
+  end
+
+  delete "/api/tokens/:id" do
+    case Tokens.revoke(id, operator(conn)) do
+      :ok -> Handlers.json_response(conn, 200, %{"status" => "revoked"})
+      {:error, :not_found} -> Handlers.not_found(conn)
+    end
+  end
+
+  # ---- Share links ----
+
+  get "/api/share-links" do
+    Handlers.json_response(conn, 200, ShareLinks.list())
+  end
+
+  # The returned `url` opens the dashboard with the link's read-only access.
+  post "/api/share-links" do
+    case ShareLinks.create(conn.body_params || %{}) do
+      {:ok, link, token} ->
+        details = Map.take(link, ["id", "view", "server_id"])
+        Storage.audit("share_link_created", operator(conn), details)
+
+        Handlers.json_response(
+          conn,
+          201,
+          Map.merge(link, %{"token" => token, "url" => "/?share=#{token}"})
+        )
+
+      {:error, :unknown_server} ->
+        Handlers.json_response(conn, 400, %{"error" => "unknown server_id"})
+
+      {:error, :invalid_view} ->
+        Handlers.json_response(conn, 400, %{
+          "error" => "server_id or \"view\": \"dashboard\" is required"
+        })
+
+      {:error, :invalid_expiry} ->
+        Handlers.json_response(conn, 400, %{
+          "error" => "expires_in must be between 1 second and 7 days"
+        })
+    end
+  end
+
+  delete "/api/share-links/:id" do
+    case ShareLinks.revoke(id) do
+      :ok ->
+        Storage.audit("share_link_revoked", operator(conn), %{"id" => id})
+        Handlers.json_response(conn, 200, %{"status" => "revoked"})
+
+      {:error, :not_found} ->
+        Handlers.not_found(conn)
+    end
+  end
+
+  # ---- Desired-state manifest ----
+
+  get "/api/manifest" do
+    Handlers.json_response(conn, 200, Manifest.status())
+  end
+
+  get "/api/drift" do
+    case Manifest.drift() do
+      {:ok, drift} -> Handlers.json_response(conn, 200, drift)
+      {:error, :disabled} -> Handlers.json_response(conn, 404, %{"error" => "no manifest configured"})
+    end
+  end
+
+  get "/api/admin/config/export" do
+    include_secrets = Map.get(conn.params, "include_secrets") == "true"
+
+    export =
+      Config.export(Config.load(), State.get_servers(), MapSet.new(State.get_paused_servers()),
+        include_secrets: include_secrets
+      )
+
+    Handlers.json_response(conn, 200, export)
+  end
+
+  # Effective `retention_days` per group and server, with what each holds in
+  # memory and what the retention pass has pruned so far.
+  get "/api/admin/retention" do
+    config = Config.load()
+    stats = State.retention_stats()
+
+    groups =
+      State.get_servers()
+      |> Enum.reject(&(&1.status == "retired"))
+      |> Enum.map(fn server ->
+        effective = Settings.effective(server, config)
+
+        entry =
+          %{
+            "id" => server.id,
+            "name" => server.name,
+            "retention_days" => effective["settings"]["retention_days"],
+            "source" => effective["sources"]["retention_days"]
+          }
+          |> Map.merge(Map.get(stats["servers"], server.id, %{}))
+
+        {effective["group"], entry}
+      end)
+      |> Enum.group_by(&elem(&1, 0), &elem(&1, 1))
+      |> Enum.sort_by(fn {group, _servers} -> {is_nil(group), group} end)
+      |> Enum.map(fn {group, servers} ->
+        %{
+          "group" => group,
+          "retention_days" =>
+            get_in(config.groups, [group, "retention_days"]) || config.retention_days,
+          "samples" => servers |> Enum.map(&(&1["samples"] || 0)) |> Enum.sum(),
+          "pruned_samples" => servers |> Enum.map(&(&1["pruned_samples"] || 0)) |> Enum.sum(),
+          "servers" => Enum.sort_by(servers, & &1["name"])
+        }
+      end)
+
+    Handlers.json_response(conn, 200, %{
+      "default_days" => config.retention_days,
+      "storage" => Storage.persistent?(),
+      "last_run" => stats["last_run"],
+      "groups" => groups
+    })
+  end
+
+  # Backfills a server's stored history from another tool's export. The
+  # import runs as a `history_import` job whose output is the summary; a
+  # retry with the same `Idempotency-Key` gets the first job back.
+  post "/api/admin/import" do
+    body = conn.body_params || %{}
+    format = body["format"]
+    data = body["data"]
+
+    idempotent(conn, "import", fn ->
+      with {:ok, server} <- State.get_server(body["server_id"]),
+           :ok <- Importer.validate(format, data) do
+        import = fn ->
+          case Importer.run(server, format, data) do
+            {:ok, summary} -> {:ok, Jason.encode!(summary)}
+            {:error, message} -> {:error, message}
+          end
+        end
+
+        job = Service.start_job(server, "history_import", import)
+        {202, %{"job_id" => job.id, "status" => job.status}}
+      else
+        {:error, :not_found} -> {404, %{"error" => "not found"}}
+        {:error, message} -> {400, %{"error" => message}}
+      end
+    end)
+  end
+
+  # Alert drills: synthetic failures, samples and events that go through
+  # incidents and the real notification channels, to check them end to end.
+  # Drill events never run actions or captures against the server.
+  post "/api/admin/drills/server-down" do
+    body = conn.body_params || %{}
+
+    case State.get_server(body["server_id"]) do
+      {:ok, server} ->
+        event = Service.simulate_down(server, body["reason"] || "simulated failure (drill)")
+        Storage.audit("drill_server_down", operator(conn), %{"server_id" => server.id})
+        Handlers.json_response(conn, 200, %{"event" => Handlers.event_to_map(event)})
+
+      {:error, :not_found} ->
+        Handlers.not_found(conn)
+    end
+  end
+
+  post "/api/admin/drills/sample" do
+    body = conn.body_params || %{}
+    overrides = Map.take(body, ["cpu_percent", "memory_percent", "disk_percent"])
+
+    with {:ok, server} <- State.get_server(body["server_id"]),
+         :ok <- validate_drill_sample(overrides) do
+      data = Service.inject_sample(server, overrides)
+      Storage.audit("drill_sample", operator(conn), Map.put(overrides, "server_id", server.id))
+      Handlers.json_response(conn, 200, %{"sample" => Handlers.monitoring_data_to_map(data)})
+    else
+      {:error, :not_found} -> Handlers.not_found(conn)
+      {:error, message} -> Handlers.json_response(conn, 400, %{"error" => message})
+    end
+  end
+
+  post "/api/admin/drills/notify" do
+    body = conn.body_params || %{}
+    severity = body["severity"] || "warning"
+
+    server =
+      case State.get_server(body["server_id"]) do
+        {:ok, server} -> server
+        {:error, :not_found} -> nil
+      end
+
+    cond do
+      body["server_id"] && is_nil(server) ->
+        Handlers.not_found(conn)
+
+      severity not in ["info", "warning", "critical"] ->
+        message = "severity must be info, warning or critical"
+        Handlers.json_response(conn, 400, %{"error" => message})
+
+      true ->
+        event =
+          Events.emit(
+            server_id: server && server.id,
+            server_name: server && server.name,
+            group: server && Settings.group_for(server),
+            kind: "drill",
+            severity: severity,
+            message: body["message"] || "Alert drill: this notification is a test",
+            drill: true
+          )
+
+        Storage.audit("drill_notify", operator(conn), %{"event_id" => event.id})
+        Handlers.json_response(conn, 200, %{"event" => Handlers.event_to_map(event)})
+    end
+  end
+
+  # ---- Bundles ----
+
+  get "/api/bundles" do
+    Handlers.json_response(conn, 200, %{"bundles" => Bundles.list()})
+  end
+
+  # `?name=&description=` label the exported bundle.
+  get "/api/bundles/export" do
+    Handlers.json_response(conn, 200, Bundles.export(conn.params))
+  end
+
+  post "/api/bundles" do
+    case Bundles.install(conn.body_params || %{}) do
+      {:ok, installed} ->
+        Storage.audit("bundle_installed", operator(conn), installed)
+        Handlers.json_response(conn, 201, installed)
+
+      {:error, message} ->
+        Handlers.json_response(conn, 400, %{"error" => message})
+    end
+  end
+
+  delete "/api/bundles/:name" do
+    case Bundles.uninstall(name) do
+      :ok ->
+        Storage.audit("bundle_uninstalled", operator(conn), %{"name" => name})
+        Handlers.json_response(conn, 200, %{"status" => "uninstalled"})
+
+      {:error, :not_found} ->
+        Handlers.not_found(conn)
+    end
+  end
+
+  # Shadow rollout of changed thresholds to one group (see Alerting.Canary).
+  get "/api/admin/canary" do
+    case Canary.current() do
+      nil -> Handlers.json_response(conn, 404, %{"error" => "no canary running"})
+      canary -> Handlers.json_response(conn, 200, canary)
+    end
+  end
+
+  post "/api/admin/canary" do
+    body = conn.body_params || %{}
+
+    case Canary.start(body) do
+      {:ok, canary} ->
+        details = Map.take(body, ["group", "thresholds"])
+        Storage.audit("canary_started", operator(conn), details)
+        Handlers.json_response(conn, 201, canary)
+
+      {:error, message} ->
+        Handlers.json_response(conn, 400, %{"error" => message})
+    end
+  end
+
+  post "/api/admin/canary/promote" do
+    case Canary.promote() do
+      {:ok, canary} ->
+        details = Map.take(canary, ["group", "thresholds"])
+        Storage.audit("canary_promoted", operator(conn), details)
+        Handlers.json_response(conn, 200, canary)
+
+      {:error, :not_found} ->
+        Handlers.json_response(conn, 404, %{"error" => "no canary running"})
+
+      {:error, message} ->
+        Handlers.json_response(conn, 500, %{"error" => message})
+    end
+  end
+
+  delete "/api/admin/canary" do
+    case Canary.stop() do
+      {:ok, canary} ->
+        Storage.audit("canary_stopped", operator(conn), %{"group" => canary["group"]})
+        Handlers.json_response(conn, 200, canary)
+
+      {:error, :not_found} ->
+        Handlers.json_response(conn, 404, %{"error" => "no canary running"})
+    end
+  end
+
+  get "/api/dashboards" do
+    Handlers.json_response(conn, 200, %{"dashboards" => Config.load().dashboards})
+  end
+
+  # ---- Ansible inventory ----
+
+  get "/api/inventory/ansible" do
+    format = Map.get(conn.params, "format", "ini")
+
+    conn
+    |> put_resp_content_type(if(format == "yaml", do: "application/yaml", else: "text/plain"))
+    |> send_resp(200, Ansible.export(State.get_servers(), format))
+  end
+
+  post "/api/inventory/ansible" do
+    guard_runtime_edit(conn, fn ->
+      body = conn.body_params || %{}
+
+      with inventory when is_binary(inventory) <- body["inventory"],
+           {:ok, entries} <- Ansible.parse(inventory, Map.get(body, "format", "ini")) do
+        case State.import_servers(entries) do
+          {:ok, summary} ->
+            Handlers.json_response(conn, 200, summary)
+
+          {:error, {:name_taken, names}} ->
+            names = Enum.join(names, ", ")
+            message = "servers not added by an import can't be replaced: #{names}"
+            Handlers.json_response(conn, 409, %{"error" => message})
+        end
+      else
+        {:error, reason} ->
+          Handlers.json_response(conn, 400, %{"error" => reason})
+
+        _ ->
+          Handlers.json_response(conn, 400, %{"error" => "inventory is required"})
+      end
+    end)
+  end
+
+  # ---- Fleet matrix ----
+
+  get "/api/matrix" do
+    params = conn.params
+
+    histories =
+      State.get_servers()
+      |> Enum.reject(&(&1.status == "retired"))
+      |> Enum.filter(&(is_nil(params["group"]) or Settings.group_for(&1) == params["group"]))
+      |> Enum.sort_by(& &1.name)
+      |> Enum.map(&{&1, State.get_historical_data(&1.id, @history_samples)})
+
+    case Matrix.build(
+           histories,
+           Map.get(params, "metric", "cpu.usage_percent"),
+           Map.get(params, "step", "5m"),
+           Map.get(params, "window", "6h"),
+           Map.get(params, "aggregation", "avg")
+         ) do
+      {:ok, matrix} -> Handlers.json_response(conn, 200, matrix)
+      {:error, message} -> Handlers.json_response(conn, 400, %{"error" => message})
+    end
+  end
+
+  # ---- Compliance ----
+
+  get "/api/compliance/eol" do
+    servers =
+      State.get_servers()
+      |> Enum.reject(&(&1.status == "retired"))
+      |> Enum.sort_by(& &1.name)
+      |> Enum.map(&{&1, State.get_latest_monitoring_data(&1.id)})
+
+    case display_zone(conn) do
+      {:ok, zone} ->
+        report = servers |> EOL.report(TimeZones.today(zone)) |> Map.put("timezone", zone)
+        Handlers.json_response(conn, 200, report)
+
+      {:error, message} ->
+        Handlers.json_response(conn, 400, %{"error" => message})
+    end
+  end
+
+  # ---- Topology ----
+
+  get "/api/topology" do
+    Handlers.json_response(conn, 200, Topology.graph())
+  end
+
+  get "/api/mesh" do
+    Handlers.json_response(conn, 200, Privacy.redact_mesh(Mesh.matrix()))
+  end
+
+  get "/api/topology/discovered" do
+    Handlers.json_response(conn, 200, Privacy.redact_neighbors(Neighbors.discovered()))
+  end
+
+  # ---- Sites ----
+
+  get "/api/sites" do
+    Handlers.json_response(conn, 200, %{"sites" => Sites.list()})
+  end
+
+  get "/api/sites/:id/summary" do
+    with {:ok, zone} <- display_zone(conn),
+         {:ok, summary} <- Sites.summary(id, zone) do
+      Handlers.json_response(conn, 200, Map.put(summary, "timezone", zone))
+    else
+      {:error, :not_found} -> Handlers.not_found(conn)
+      {:error, message} -> Handlers.json_response(conn, 400, %{"error" => message})
+    end
+  end
+
+  post "/api/sites/:id/maintenance" do
+    case Sites.add_window(id, conn.body_params || %{}, operator(conn)) do
+      {:ok, window} ->
+        Storage.audit("maintenance_scheduled", operator(conn), window)
+        Handlers.json_response(conn, 201, window)
+
+      {:error, message} ->
+        Handlers.json_response(conn, 400, %{"error" => message})
+    end
+  end
+
+  delete "/api/sites/:id/maintenance/:window_id" do
+    case Sites.remove_window(id, window_id) do
+      :ok ->
+        Storage.audit("maintenance_cancelled", operator(conn), %{"site" => id, "id" => window_id})
+        Handlers.json_response(conn, 200, %{"status" => "cancelled"})
+
+      {:error, :not_found} ->
+        Handlers.not_found(conn)
+    end
+  end
+
+  # ---- Self-registration ----
+
+  post "/api/register" do
+    token = Config.load().registration_token
+
+    case get_req_header(conn, "authorization") do
+      _ when is_nil(token) ->
+        Handlers.json_response(conn, 503, %{"error" => "registration is not configured"})
+
+      ["Bearer " <> presented] ->
+        if Plug.Crypto.secure_compare(String.trim(presented), token) do
+          idempotent(conn, "register", fn ->
+            case Registrations.register(conn.body_params || %{}) do
+              {:ok, registration} -> {202, registration}
+              {:error, :invalid} -> {400, %{"error" => "name and host are required"}}
+              {:error, :name_taken} -> {409, %{"error" => "a server with that name exists"}}
+            end
+          end)
+        else
+          Handlers.json_response(conn, 401, %{"error" => "invalid registration token"})
+        end
+
+      _ ->
+        Handlers.json_response(conn, 401, %{"error" => "invalid registration token"})
+    end
+  end
+
+  get "/api/register/cloud-init" do
+    case Config.load().registration_token do
+      nil ->
+        Handlers.json_response(conn, 503, %{"error" => "registration is not configured"})
+
+      token ->
+        base_url = Map.get(conn.params, "base_url", "#{conn.scheme}://#{conn.host}:#{conn.port}")
+        opts = Enum.map(["group", "username"], &{&1, conn.params[&1]})
+
+        conn
+        |> put_resp_content_type("text/cloud-config")
+        |> send_resp(200, Registrations.cloud_init(base_url, token, opts))
+    end
+  end
+
+  get "/api/registrations" do
+    Handlers.json_response(conn, 200, Registrations.list(status: Map.get(conn.params, "status")))
+  end
+
+  post "/api/registrations/:id/approve" do
+    guard_runtime_edit(conn, fn ->
+      approval_decision(conn, Registrations.approve(id, operator(conn)))
+    end)
+  end
+
+  post "/api/registrations/:id/reject" do
+    approval_decision(conn, Registrations.reject(id, operator(conn)))
+  end
+
   # ---- Connection stats ----
 
   get "/api/connection-stats" do
-    stats = Manager.get_stats()
+    stats = Map.put(Manager.get_stats(), :coalescing, Coalescer.stats())
     Handlers.json_response(conn, 200, stats)
   end
 
@@ -93,27 +693,332 @@ defmodule AgentlessMonitor.API.Router do
     Handlers.json_response(conn, 200, %{"status" => "cleared"})
   end
 
+  # Stops a running job; for diagnostics such as disk usage scans the remote
+  # command is killed as well.
+  delete "/api/jobs/:id" do
+    cancel_job(conn, id)
+  end
+
   post "/api/jobs/:id/cancel" do
-    job_id = id
+    cancel_job(conn, id)
+  end
 
-    case State.get_job(job_id) do
-      {:ok, _job} ->
-        State.update_job(job_id, %{status: "cancelled"})
-        Handlers.json_response(conn, 200, %{"status" => "cancelled"})
+  # ---- Events & incidents ----
+
+  get "/api/events" do
+    limit = conn.params |> Map.get("limit", "100") |> parse_integer(100)
+
+    events =
+      Events.list(server_id: Map.get(conn.params, "server_id"), limit: limit)
+      |> Enum.map(&Handlers.event_to_map/1)
+
+    Handlers.json_response(conn, 200, events)
+  end
+
+  # Server-sent events: `status`, `sample` and `event` messages as they happen,
+  # optionally limited to one server with `?server_id=`.
+  get "/api/events/stream" do
+    PubSub.subscribe()
+
+    conn
+    |> put_resp_content_type("text/event-stream")
+    |> put_resp_header("cache-control", "no-cache")
+    |> put_resp_header("x-accel-buffering", "no")
+    |> send_chunked(200)
+    |> stream_updates(Map.get(conn.params, "server_id"))
+  end
+
+  get "/api/incidents" do
+    incidents =
+      Incidents.list(status: Map.get(conn.params, "status"))
+      |> Enum.map(&Handlers.incident_to_map/1)
+
+    Handlers.json_response(conn, 200, incidents)
+  end
+
+  # Alerts are incidents: `?state=open` lists the active ones (by opening
+  # time), `?state=resolved` searches the durable history (by resolution
+  # time); without `state` both are returned.
+  get "/api/alerts" do
+    state = conn.params["state"]
+    limit = conn.params |> Map.get("limit", "100") |> parse_integer(100)
+
+    with :ok <- validate_alert_state(state),
+         {:ok, from} <- Annotations.parse_bound(conn.params["from"]),
+         {:ok, to} <- Annotations.parse_bound(conn.params["to"]) do
+      open =
+        if state in [nil, "open"] do
+          Incidents.list()
+          |> Enum.filter(&(&1.status != "resolved" and in_range?(&1.opened_at, from, to)))
+          |> Enum.map(&Handlers.incident_to_map/1)
+        else
+          []
+        end
+
+      resolved =
+        if state in [nil, "resolved"] do
+          History.alerts(
+            from: from,
+            to: to,
+            server_id: conn.params["server_id"],
+            severity: conn.params["severity"],
+            limit: limit
+          )
+        else
+          []
+        end
+
+      Handlers.json_response(conn, 200, Enum.take(open ++ resolved, limit))
+    else
+      {:error, message} -> Handlers.json_response(conn, 400, %{"error" => message})
+    end
+  end
+
+  # Includes the burst samples of the incident's server taken while it was
+  # open, for drilling down.
+  get "/api/incidents/:id" do
+    case Incidents.get(id) do
+      {:ok, incident} ->
+        body = Map.put(Handlers.incident_to_map(incident), "bursts", incident_bursts(incident))
+        Handlers.json_response(conn, 200, body)
+
+      {:error, :not_found} ->
+        Handlers.not_found(conn)
+    end
+  end
+
+  put "/api/incidents/:id" do
+    case Incidents.update(id, conn.body_params || %{}) do
+      {:ok, incident} ->
+        Handlers.json_response(conn, 200, Handlers.incident_to_map(incident))
+
+      {:error, :invalid_status} ->
+        Handlers.json_response(conn, 400, %{"error" => "status must be open, mitigated or resolved"})
 
       {:error, :not_found} ->
         Handlers.not_found(conn)
     end
   end
 
+  # ---- Actions ----
+
+  get "/api/actions" do
+    Handlers.json_response(conn, 200, Actions.list_rules())
+  end
+
+  # ---- Alert rules ----
+
+  # Dry run: replays recent history against a threshold rule, optionally with
+  # a candidate `threshold`, and reports when it would have fired.
+  post "/api/alert-rules/:id/test" do
+    body = conn.body_params || %{}
+    limit = parse_integer(Map.get(body, "limit", 100), 100)
+
+    servers =
+      State.get_servers()
+      |> Enum.reject(&(&1.status == "retired"))
+      |> Enum.filter(&(is_nil(body["server_id"]) or &1.id == body["server_id"]))
+
+    cond do
+      id not in Service.threshold_rules() and not String.starts_with?(id, "custom.") ->
+        Handlers.not_found(conn)
+
+      servers == [] ->
+        Handlers.not_found(conn)
+
+      true ->
+        results =
+          Enum.map(servers, fn server ->
+            threshold = Map.get(body, "threshold", Settings.for_server(server)["thresholds"][id])
+            history = State.get_historical_data(server.id, limit)
+
+            %{
+              "server_id" => server.id,
+              "server_name" => server.name,
+              "threshold" => threshold,
+              "samples" => length(history),
+              "firings" => Service.dry_run(id, history, threshold)
+            }
+          end)
+
+        Handlers.json_response(conn, 200, %{
+          "rule" => id,
+          "dry_run" => true,
+          "would_fire" => Enum.sum(Enum.map(results, &length(&1["firings"]))),
+          "servers" => results
+        })
+    end
+  end
+
+  # ---- SLOs ----
+
+  get "/api/slo" do
+    Handlers.json_response(conn, 200, SLO.report())
+  end
+
+  # ---- Notification channels ----
+
+  if Features.enabled?(:notifications) do
+    get "/api/notifications/channels" do
+      Handlers.json_response(conn, 200, Notifications.channels())
+    end
+
+    # Delivery attempts, newest first (`?channel=&event_id=&status=&from=&to=`).
+    get "/api/notifications/history" do
+      limit = conn.params |> Map.get("limit", "100") |> parse_integer(100)
+
+      with {:ok, from} <- Annotations.parse_bound(conn.params["from"]),
+           {:ok, to} <- Annotations.parse_bound(conn.params["to"]) do
+        deliveries =
+          History.deliveries(
+            from: from,
+            to: to,
+            channel: conn.params["channel"],
+            event_id: conn.params["event_id"],
+            status: conn.params["status"],
+            limit: limit
+          )
+
+        Handlers.json_response(conn, 200, deliveries)
+      else
+        {:error, message} -> Handlers.json_response(conn, 400, %{"error" => message})
+      end
+    end
+
+    post "/api/notifications/channels/:id/test" do
+      case Notifications.test_channel(id) do
+        :ok ->
+          Handlers.json_response(conn, 200, %{"status" => "delivered"})
+
+        {:error, :not_found} ->
+          Handlers.not_found(conn)
+
+        {:error, :unknown_type} ->
+          Handlers.json_response(conn, 400, %{"error" => "unknown notifier type"})
+
+        {:error, reason} ->
+          Handlers.json_response(conn, 502, %{"error" => "delivery failed: #{inspect(reason)}"})
+      end
+    end
+  end
+
+  # ---- Approvals ----
+
+  get "/api/approvals" do
+    approvals = Approvals.list(status: Map.get(conn.params, "status"))
+    Handlers.json_response(conn, 200, approvals)
+  end
+
+  get "/api/approvals/:id" do
+    case Approvals.get(id) do
+      {:ok, approval} -> Handlers.json_response(conn, 200, approval)
+      {:error, :not_found} -> Handlers.not_found(conn)
+    end
+  end
+
+  # Deciding needs an identity to tell the approver from the requester, so
+  # only token-authenticated requests may approve or reject.
+  post "/api/approvals/:id/approve" do
+    if conn.assigns[:token],
+      do: approval_decision(conn, Approvals.approve(id, operator(conn))),
+      else: approval_decision(conn, {:error, :no_token})
+  end
+
+  post "/api/approvals/:id/reject" do
+    if conn.assigns[:token],
+      do: approval_decision(conn, Approvals.reject(id, operator(conn))),
+      else: approval_decision(conn, {:error, :no_token})
+  end
+
+  # ---- ChatOps ----
+
+  if Features.enabled?(:chatops) do
+    post "/api/chatops/slack" do
+      case ChatOps.verify_slack(conn) do
+        :ok ->
+          text = ChatOps.run(conn.params["text"], conn.params["user_name"])
+          Handlers.json_response(conn, 200, ChatOps.slack_reply(text))
+
+        {:error, reason} ->
+          chatops_rejected(conn, reason)
+      end
+    end
+
+    post "/api/chatops/mattermost" do
+      case ChatOps.verify_mattermost(conn.params) do
+        :ok ->
+          text = ChatOps.run(conn.params["text"], conn.params["user_name"])
+          Handlers.json_response(conn, 200, ChatOps.mattermost_reply(text))
+
+        {:error, reason} ->
+          chatops_rejected(conn, reason)
+      end
+    end
+  end
+
   # ---- Servers ----
 
-  get "/api/servers" do
-    servers =
-      State.get_servers()
-      |> Enum.map(&Handlers.server_to_map/1)
+  # `?sort=health` lists the servers worst first by health score. A server
+  # share link only sees its own server.
+  get "/api/servers" do
+    servers =
+      case conn.assigns[:share_link] do
+        %{"view" => "server", "server_id" => id} ->
+          Enum.filter(State.get_servers(), &(&1.id == id))
+
+        _ ->
+          State.get_servers()
+      end
+
+    servers = Enum.map(servers, &Handlers.server_to_map/1)
+
+    servers =
+      if conn.params["sort"] == "health",
+        do: Health.worst_first(servers, & &1["health"]),
+        else: servers
+
+    Handlers.json_response(conn, 200, servers)
+  end
+
+  get "/api/overview" do
+    Handlers.json_response(conn, 200, Overview.build())
+  end
+
+  # Manually added servers are saved to the data directory and survive
+  # restarts; hosts from the SSH config stay read-only.
+  post "/api/servers" do
+    guard_runtime_edit(conn, fn ->
+      with {:ok, entry} <- validate_server_entry(conn.body_params || %{}, true),
+           {:ok, server} <- State.create_server(entry) do
+        Handlers.json_response(conn, 201, Handlers.server_to_map(server))
+      else
+        error -> server_edit_failed(conn, error)
+      end
+    end)
+  end
 
-    Handlers.json_response(conn, 200, servers)
+  # Incremental refresh: `?since=<cursor>` returns only the servers changed
+  # after that cursor. Without `since`, or with a cursor from before a
+  # restart, every server is returned with `"full": true`.
+  get "/api/servers/changes" do
+    since = parse_integer(conn.params["since"], -1)
+    {changed, removed, cursor} = State.changes_since(max(since, 0))
+
+    if since < 0 or since > cursor do
+      Handlers.json_response(conn, 200, %{
+        "servers" => Enum.map(State.get_servers(), &Handlers.server_to_map/1),
+        "removed" => [],
+        "cursor" => cursor,
+        "full" => true
+      })
+    else
+      Handlers.json_response(conn, 200, %{
+        "servers" => Enum.map(changed, &Handlers.server_to_map/1),
+        "removed" => removed,
+        "cursor" => cursor,
+        "full" => false
+      })
+    end
   end
 
   get "/api/servers/:id/status" do
@@ -133,46 +1038,270 @@ defmodule AgentlessMonitor.API.Router do
     end
   end
 
+  get "/api/servers/:id/snapshot" do
+    case State.get_server(id) do
+      {:ok, server} -> Handlers.json_response(conn, 200, Snapshot.build(server))
+      {:error, :not_found} -> Handlers.not_found(conn)
+    end
+  end
+
+  # `?from=&to=` (unix or ISO 8601) restrict the time range.
   get "/api/servers/:id/history" do
     server_id = id
     limit = conn.params |> Map.get("limit", "100") |> parse_integer(100)
 
-    case State.get_server(server_id) do
-      {:ok, _server} ->
-        history =
-          State.get_historical_data(server_id, limit)
-          |> Enum.map(&Handlers.monitoring_data_to_map/1)
+    with {:ok, _server} <- State.get_server(server_id),
+         {:ok, from} <- Annotations.parse_bound(conn.params["from"]),
+         {:ok, to} <- Annotations.parse_bound(conn.params["to"]) do
+      history =
+        server_id
+        |> State.get_historical_data(if(from || to, do: @history_samples, else: limit))
+        |> Enum.filter(&in_range?(&1.timestamp, from, to))
+        |> Enum.take(limit)
+        |> Enum.map(&Handlers.monitoring_data_to_map/1)
+
+      # Memory only holds recent samples; older ones come from storage.
+      history =
+        with true <- length(history) < limit,
+             {:ok, stored} <- Storage.history(server_id, limit, from: from, to: to) do
+          stored
+        else
+          _ -> history
+        end
+
+      Handlers.json_response(conn, 200, history)
+    else
+      {:error, :not_found} -> Handlers.not_found(conn)
+      {:error, message} -> Handlers.json_response(conn, 400, %{"error" => message})
+    end
+  end
+
+  # Markers to overlay on history charts (`?from=&to=` as unix or ISO 8601).
+  get "/api/servers/:id/annotations" do
+    with {:ok, _server} <- State.get_server(id),
+         {:ok, from} <- Annotations.parse_bound(conn.params["from"]),
+         {:ok, to} <- Annotations.parse_bound(conn.params["to"]) do
+      Handlers.json_response(conn, 200, Annotations.for_server(id, from, to))
+    else
+      {:error, :not_found} -> Handlers.not_found(conn)
+      {:error, message} -> Handlers.json_response(conn, 400, %{"error" => message})
+    end
+  end
+
+  # Processes of the latest sample, `?sort=cpu|memory|pid` (default cpu) and
+  # `?limit=` (default 20).
+  get "/api/servers/:id/processes" do
+    sort = Map.get(conn.params, "sort", "cpu")
+    limit = conn.params |> Map.get("limit", "20") |> parse_integer(20)
+
+    with {:ok, _server} <- State.get_server(id),
+         {:ok, sorter} <- process_sorter(sort) do
+      sample = id |> State.get_latest_monitoring_data() |> Handlers.monitoring_data_to_map()
+      processes = if sample, do: sample["processes"], else: []
+
+      Handlers.json_response(conn, 200, %{
+        "server_id" => id,
+        "timestamp" => sample && sample["timestamp"],
+        "total" => length(processes),
+        "processes" => processes |> sorter.() |> Enum.take(limit)
+      })
+    else
+      {:error, :not_found} -> Handlers.not_found(conn)
+      {:error, message} -> Handlers.json_response(conn, 400, %{"error" => message})
+    end
+  end
+
+  # Latest results of the server's process rules.
+  get "/api/servers/:id/process-rules" do
+    case State.get_server(id) do
+      {:ok, server} ->
+        rules = Settings.for_server(server)["process_rules"] || []
+        status = Watchdog.status(id) || %{"checked_at" => nil, "rules" => []}
+        Handlers.json_response(conn, 200, Map.put(status, "configured", length(rules)))
+
+      {:error, :not_found} ->
+        Handlers.not_found(conn)
+    end
+  end
+
+  # High-resolution sampling for a few minutes (`{"interval": 1, "minutes": 5}`);
+  # the series shows up in `/api/servers/:id/bursts` once the job finishes.
+  post "/api/servers/:id/burst" do
+    body = conn.body_params || %{}
+    interval = Map.get(body, "interval", 2)
+    minutes = Map.get(body, "minutes", 5)
+
+    with {:ok, server} <- State.get_server(id),
+         :ok <- Burst.validate(interval, minutes),
+         {:ok, burst} <- Burst.start(server, interval, minutes) do
+      Handlers.json_response(conn, 202, Map.delete(burst, "points"))
+    else
+      {:error, :not_found} ->
+        Handlers.not_found(conn)
+
+      {:error, :running} ->
+        Handlers.json_response(conn, 409, %{"error" => "a burst is already running"})
+
+      {:error, message} ->
+        Handlers.json_response(conn, 400, %{"error" => message})
+    end
+  end
+
+  get "/api/servers/:id/bursts" do
+    case State.get_server(id) do
+      {:ok, _server} -> Handlers.json_response(conn, 200, Burst.list(id))
+      {:error, :not_found} -> Handlers.not_found(conn)
+    end
+  end
 
-        Handlers.json_response(conn, 200, history)
+  # The commands restricted mode allows for the server and the forced-command
+  # gate, authorized_keys entry and sudoers rule that enforce them on it.
+  get "/api/servers/:id/restricted-access" do
+    case State.get_server(id) do
+      {:ok, %{transport: transport}} when transport in ["local", "snmp"] ->
+        Handlers.json_response(conn, 400, %{
+          "error" => "#{transport} servers are not reached over SSH"
+        })
+
+      {:ok, server} ->
+        config = Config.load()
+        settings = Settings.for_server(server, config)
+
+        Handlers.json_response(
+          conn,
+          200,
+          Map.merge(Restricted.generate(server, settings, config), %{
+            "enabled" => config.restricted_mode,
+            "commands" => Restricted.manifest(settings, config)
+          })
+        )
 
       {:error, :not_found} ->
         Handlers.not_found(conn)
     end
   end
 
+  # `details/all` (optionally narrowed with `?metrics=cpu,memory`) returns
+  # several sections of the latest sample at once, with an entry in
+  # `errors` for each requested section that is missing.
   get "/api/servers/:id/details/:metric" do
     server_id = id
 
-    case State.get_latest_monitoring_data(server_id) do
-      nil ->
-        Handlers.json_response(conn, 200, nil)
+    if conn.params["debug"] == "true" do
+      debug_details(conn, server_id, metric)
+    else
+      latest_details(conn, server_id, metric)
+    end
+  end
 
-      data ->
-        data_map = Handlers.monitoring_data_to_map(data)
+  get "/api/servers/:id/fingerprint" do
+    case Fingerprint.get(id) do
+      {:ok, fingerprint} -> Handlers.json_response(conn, 200, fingerprint)
+      {:error, :not_found} -> Handlers.not_found(conn)
+    end
+  end
+
+  get "/api/servers/:id/effective-config" do
+    case State.get_server(id) do
+      {:ok, server} ->
+        Handlers.json_response(conn, 200, Settings.effective(server))
+
+      {:error, :not_found} ->
+        Handlers.not_found(conn)
+    end
+  end
+
+  # TCP connect scan from the monitor (`ports`: `22,80,8000-8010`, else the
+  # configured list) compared with the ports the collector saw listening.
+  # An active scan, so it takes a POST and the write scope.
+  post "/api/servers/:id/port-audit" do
+    ports =
+      case conn.params["ports"] do
+        spec when is_binary(spec) and spec != "" -> PortAudit.parse_ports(spec)
+        _ -> {:ok, nil}
+      end
+
+    with {:ok, server} <- State.get_server(id),
+         {:ok, ports} <- ports do
+      Handlers.json_response(conn, 200, PortAudit.run(server, ports))
+    else
+      {:error, :not_found} -> Handlers.not_found(conn)
+      {:error, message} -> Handlers.json_response(conn, 400, %{"error" => message})
+    end
+  end
+
+  # Path MTU sweep from the server toward `destination`, tracked as a job.
+  post "/api/servers/:id/mtu" do
+    body = conn.body_params || %{}
+    destination = body["destination"]
+    max_size = parse_integer(body["max_size"], MTU.max_size())
+
+    with {:ok, server} <- State.get_server(id),
+         true <- is_binary(destination) and destination != "" and max_size > 0 do
+      sweep = fn -> Service.run_command(server, MTU.command(destination, max_size)) end
+
+      with {:ok, output} <- Service.run_as_job(server, "mtu_probe", sweep),
+           {:ok, report} <- MTU.parse(output, destination) do
+        Handlers.json_response(conn, 200, report)
+      else
+        {:error, reason} -> Handlers.json_response(conn, 502, %{"error" => to_string(reason)})
+      end
+    else
+      {:error, :not_found} ->
+        Handlers.not_found(conn)
+
+      false ->
+        Handlers.json_response(conn, 400, %{
+          "error" => "destination is required and max_size must be positive"
+        })
+    end
+  end
+
+  # Largest directories below `?path=` (default `/`), `?depth=` levels deep.
+  # The scan runs as a job; poll the returned `result` URL for the breakdown.
+  post "/api/servers/:id/diagnostics/du" do
+    path = Map.get(conn.params, "path", "/")
+    depth = conn.params |> Map.get("depth", "1") |> to_string() |> parse_integer(1)
+
+    with {:ok, server} <- State.get_server(id),
+         {:ok, path, depth} <- DiskUsage.validate(path, depth) do
+      job = Service.start_command_job(server, "disk_usage", DiskUsage.command(path, depth))
+
+      Handlers.json_response(conn, 202, %{
+        "job_id" => job.id,
+        "status" => job.status,
+        "path" => path,
+        "result" => "/api/servers/#{id}/diagnostics/du/#{job.id}"
+      })
+    else
+      {:error, :not_found} -> Handlers.not_found(conn)
+      {:error, message} -> Handlers.json_response(conn, 400, %{"error" => message})
+    end
+  end
+
+  get "/api/servers/:id/diagnostics/du/:job_id" do
+    limit = conn.params |> Map.get("limit", "20") |> parse_integer(20)
+
+    case State.get_job(job_id) do
+      {:ok, %{server_id: ^id, job_type: "disk_usage"} = job} ->
+        body = %{"job_id" => job.id, "status" => job.status}
 
-        result =
-          case metric do
-            "cpu" -> Map.get(data_map, "cpu")
-            "memory" -> Map.get(data_map, "memory")
-            "disks" -> Map.get(data_map, "disks")
-            "network" -> Map.get(data_map, "network")
-            "ports" -> Map.get(data_map, "ports")
-            "ping" -> Map.get(data_map, "ping_tests")
-            "system" -> Map.get(data_map, "system_info")
-            _ -> nil
+        body =
+          case job.status do
+            "completed" ->
+              Map.merge(body, DiskUsage.parse(job.output, limit))
+
+            "failed" ->
+              Map.put(body, "error", job.output)
+
+            _ ->
+              body
           end
 
-        Handlers.json_response(conn, 200, result)
+        Handlers.json_response(conn, 200, body)
+
+      _ ->
+        Handlers.not_found(conn)
     end
   end
 
@@ -185,19 +1314,23 @@ defmodule AgentlessMonitor.API.Router do
       {:error, :not_found} ->
         Handlers.not_found(conn)
 
+      {:ok, %{transport: transport}} when transport in ["local", "snmp"] ->
+        Handlers.json_response(conn, 200, %{"status" => "connected", "connection" => nil})
+
       {:ok, server} ->
         opts = if password, do: [password: password], else: []
 
-        case Connection.test_connection(server.host, server.port, server.username, opts) do
-          {:ok, _} ->
-            Manager.record_connection(server_id, server.host, server.port, server.username)
-
+        case Manager.connect(server, opts) do
+          {:ok, connection} ->
             State.update_server(server_id, %{
               status: "online",
               last_seen: DateTime.utc_now() |> DateTime.to_iso8601()
             })
 
-            Handlers.json_response(conn, 200, %{"status" => "connected"})
+            Handlers.json_response(conn, 200, %{
+              "status" => "connected",
+              "connection" => connection
+            })
 
           {:error, reason} ->
             State.update_server(server_id, %{status: "error"})
@@ -206,6 +1339,85 @@ defmodule AgentlessMonitor.API.Router do
     end
   end
 
+  # Appends the monitor's public key to the server's authorized_keys using a
+  # `password` given once, then switches the server to key-only logins.
+  post "/api/servers/:id/install-key" do
+    guard_runtime_edit(conn, fn ->
+      password = Map.get(conn.body_params || %{}, "password")
+
+      with {:ok, server} <- State.get_server(id),
+           :ok <- validate_key_install(server, password) do
+        install = fn -> KeyInstall.install(server, password, Service.connection_opts(server)) end
+
+        case Service.run_as_job(server, "key_install", install) do
+          {:ok, message} ->
+            {:ok, updated} = State.get_server(id)
+
+            Handlers.json_response(conn, 200, %{
+              "status" => "installed",
+              "message" => message,
+              "server" => Handlers.server_to_map(updated)
+            })
+
+          {:error, reason} ->
+            Handlers.json_response(conn, 502, %{"error" => to_string(reason)})
+        end
+      else
+        {:error, :not_found} -> Handlers.not_found(conn)
+        {:error, message} -> Handlers.json_response(conn, 400, %{"error" => message})
+      end
+    end)
+  end
+
+  put "/api/servers/:id/dependencies" do
+    guard_runtime_edit(conn, fn ->
+      depends_on = Map.get(conn.body_params || %{}, "depends_on")
+
+      with {:ok, server} <- State.get_server(id),
+           true <- is_list(depends_on) and Enum.all?(depends_on, &is_binary/1),
+           :ok <- Topology.validate(server, depends_on) do
+        {:ok, updated} = State.update_server(id, %{depends_on: Enum.uniq(depends_on)})
+        Handlers.json_response(conn, 200, Handlers.server_to_map(updated))
+      else
+        {:error, :not_found} ->
+          Handlers.not_found(conn)
+
+        false ->
+          Handlers.json_response(conn, 400, %{"error" => "depends_on must be a list of names"})
+
+        {:error, reason} ->
+          Handlers.json_response(conn, 400, %{"error" => reason})
+      end
+    end)
+  end
+
+  post "/api/servers/:id/restore" do
+    guard_runtime_edit(conn, fn -> restore_server(conn, id) end)
+  end
+
+  # Alias of `restore`, kept for clients written before deletion existed.
+  post "/api/servers/:id/unretire" do
+    guard_runtime_edit(conn, fn -> restore_server(conn, id) end)
+  end
+
+  post "/api/servers/:id/purge" do
+    guard_runtime_edit(conn, fn ->
+      case State.purge_server(id) do
+        :ok ->
+          Storage.audit("server_purge", operator(conn), %{"server_id" => id})
+          Handlers.json_response(conn, 200, %{"status" => "purged"})
+
+        {:error, :not_retired} ->
+          Handlers.json_response(conn, 409, %{
+            "error" => "only deleted or retired servers can be purged"
+          })
+
+        {:error, :not_found} ->
+          Handlers.not_found(conn)
+      end
+    end)
+  end
+
   post "/api/servers/:id/start-monitoring" do
     server_id = id
 
@@ -213,9 +1425,12 @@ defmodule AgentlessMonitor.API.Router do
       {:error, :not_found} ->
         Handlers.not_found(conn)
 
+      {:ok, %{status: "retired"}} ->
+        Handlers.json_response(conn, 409, %{"error" => "server is retired"})
+
       {:ok, _server} ->
         State.resume_server(server_id)
-        State.update_server(server_id, %{next_monitoring: 0})
+        State.update_server(server_id, %{due_at: nil})
         Service.collect_data(server_id)
         Handlers.json_response(conn, 200, %{"status" => "monitoring started"})
     end
@@ -234,6 +1449,29 @@ defmodule AgentlessMonitor.API.Router do
     end
   end
 
+  # A one-off collection outside the schedule; paused servers stay paused.
+  post "/api/servers/:id/collect-now" do
+    case State.get_server(id) do
+      {:error, :not_found} ->
+        Handlers.not_found(conn)
+
+      {:ok, %{status: "retired"}} ->
+        Handlers.json_response(conn, 409, %{"error" => "server is retired"})
+
+      {:ok, _server} ->
+        {:ok, job} = Service.collect_now(id)
+
+        latest =
+          if job.status == "completed",
+            do: Handlers.monitoring_data_to_map(State.get_latest_monitoring_data(id))
+
+        Handlers.json_response(conn, 200, %{
+          "job" => Handlers.job_to_map(job),
+          "latest_data" => latest
+        })
+    end
+  end
+
   get "/api/servers/:id" do
     server_id = id
 
@@ -243,7 +1481,8 @@ defmodule AgentlessMonitor.API.Router do
 
         Handlers.json_response(conn, 200, %{
           "server" => Handlers.server_to_map(server),
-          "latest_data" => Handlers.monitoring_data_to_map(latest)
+          "latest_data" => Handlers.monitoring_data_to_map(latest),
+          "ssh_probe" => Manager.get_probe(server.host, server.port)
         })
 
       {:error, :not_found} ->
@@ -251,10 +1490,40 @@ defmodule AgentlessMonitor.API.Router do
     end
   end
 
+  put "/api/servers/:id" do
+    guard_runtime_edit(conn, fn ->
+      with {:ok, entry} <- validate_server_entry(conn.body_params || %{}, false),
+           {:ok, server} <- State.edit_server(id, entry) do
+        Handlers.json_response(conn, 200, Handlers.server_to_map(server))
+      else
+        error -> server_edit_failed(conn, error)
+      end
+    end)
+  end
+
+  # Soft delete: the server is retired with its history archived and can be
+  # restored until the retention window passes or it is purged.
+  delete "/api/servers/:id" do
+    guard_runtime_edit(conn, fn ->
+      case State.delete_server(id) do
+        {:ok, server} ->
+          Handlers.json_response(conn, 200, Handlers.server_to_map(server))
+
+        {:error, :already_retired} ->
+          Handlers.json_response(conn, 409, %{"error" => "server is already deleted"})
+
+        error ->
+          server_edit_failed(conn, error)
+      end
+    end)
+  end
+
   # ---- Static / SPA ----
 
-  get "/" do
-    serve_index(conn)
+  if Features.enabled?(:web_ui) do
+    get "/" do
+      serve_index(conn)
+    end
   end
 
   match _ do
@@ -263,19 +1532,376 @@ defmodule AgentlessMonitor.API.Router do
 
   # ---- Private ----
 
-  defp serve_index(conn) do
-    path = Path.join([File.cwd!(), "templates", "index.html"])
+  if Features.enabled?(:web_ui) do
+    defp serve_index(conn) do
+      path = Path.join([File.cwd!(), "templates", "index.html"])
+
+      case File.read(path) do
+        {:ok, content} ->
+          conn
+          |> Plug.Conn.put_resp_content_type("text/html")
+          |> Plug.Conn.send_resp(200, content)
+
+        {:error, _} ->
+          conn
+          |> Plug.Conn.put_resp_content_type("text/html")
+          |> Plug.Conn.send_resp(200, "<html><body><h1>Agentless Monitor</h1></body></html>")
+      end
+    end
+  end
+
+  # With token auth the operator is the token's name, otherwise `api`; it is
+  # never taken from the request.
+  defp operator(conn) do
+    case conn.assigns[:token] do
+      %{"name" => name} -> name
+      nil -> "api"
+    end
+  end
 
-    case File.read(path) do
-      {:ok, content} ->
-        conn
-        |> Plug.Conn.put_resp_content_type("text/html")
-        |> Plug.Conn.send_resp(200, content)
+  @stream_keepalive :timer.seconds(15)
+
+  defp stream_updates(conn, server_id) do
+    receive do
+      {:live_update, type, payload} ->
+        if is_nil(server_id) or payload["server_id"] == server_id do
+          payload = if type == "event", do: Privacy.redact_event(payload), else: payload
+          data = "event: #{type}\ndata: #{Jason.encode!(payload)}\n\n"
+
+          case chunk(conn, data) do
+            {:ok, conn} -> stream_updates(conn, server_id)
+            {:error, _closed} -> conn
+          end
+        else
+          stream_updates(conn, server_id)
+        end
+    after
+      @stream_keepalive ->
+        case chunk(conn, ": keepalive\n\n") do
+          {:ok, conn} -> stream_updates(conn, server_id)
+          {:error, _closed} -> conn
+        end
+    end
+  end
+
+  defp cancel_job(conn, job_id) do
+    case Service.cancel_job(job_id) do
+      {:ok, job} ->
+        Handlers.json_response(conn, 200, Handlers.job_to_map(job))
+
+      {:error, :finished} ->
+        Handlers.json_response(conn, 409, %{"error" => "job is not running"})
+
+      {:error, :not_found} ->
+        Handlers.not_found(conn)
+    end
+  end
+
+  defp guard_runtime_edit(conn, fun) do
+    if Manifest.runtime_edits_allowed?() do
+      fun.()
+    else
+      Handlers.json_response(conn, 409, %{
+        "error" => "servers are managed by the manifest; edit the manifest instead"
+      })
+    end
+  end
+
+  defp server_edit_failed(conn, {:error, :not_found}), do: Handlers.not_found(conn)
+
+  defp server_edit_failed(conn, {:error, :local}) do
+    Handlers.json_response(conn, 409, %{"error" => "the local server cannot be changed"})
+  end
+
+  defp server_edit_failed(conn, {:error, :read_only}) do
+    Handlers.json_response(conn, 409, %{
+      "error" => "servers from the SSH config are read-only; edit the SSH config instead"
+    })
+  end
+
+  defp server_edit_failed(conn, {:error, :name_taken}) do
+    Handlers.json_response(conn, 409, %{"error" => "a server with that name already exists"})
+  end
+
+  defp server_edit_failed(conn, {:error, message}) do
+    Handlers.json_response(conn, 400, %{"error" => message})
+  end
+
+  defp incident_bursts(%{server_id: server_id} = incident) when is_binary(server_id) do
+    case DateTime.from_iso8601(incident.opened_at || "") do
+      {:ok, opened_at, _} ->
+        resolved_at =
+          case DateTime.from_iso8601(incident.resolved_at || "") do
+            {:ok, resolved_at, _} -> resolved_at
+            {:error, _} -> nil
+          end
+
+        Burst.during(server_id, opened_at, resolved_at)
 
       {:error, _} ->
-        conn
-        |> Plug.Conn.put_resp_content_type("text/html")
-        |> Plug.Conn.send_resp(200, "<html><body><h1>Agentless Monitor</h1></body></html>")
+        []
+    end
+  end
+
+  defp incident_bursts(_incident), do: []
+
+  defp restore_server(conn, id) do
+    case State.unretire_server(id) do
+      {:ok, server} ->
+        Handlers.json_response(conn, 200, Handlers.server_to_map(server))
+
+      {:error, :not_retired} ->
+        Handlers.json_response(conn, 409, %{"error" => "server is not retired"})
+
+      {:error, :not_found} ->
+        Handlers.not_found(conn)
+    end
+  end
+
+  defp approval_decision(conn, {:ok, approval}), do: Handlers.json_response(conn, 200, approval)
+  defp approval_decision(conn, {:error, :not_found}), do: Handlers.not_found(conn)
+
+  defp approval_decision(conn, {:error, :not_pending}) do
+    Handlers.json_response(conn, 409, %{"error" => "approval is no longer pending"})
+  end
+
+  defp approval_decision(conn, {:error, :self_approval}) do
+    Handlers.json_response(conn, 403, %{"error" => "a different operator must approve"})
+  end
+
+  defp approval_decision(conn, {:error, :name_taken}) do
+    Handlers.json_response(conn, 409, %{"error" => "a server with that name already exists"})
+  end
+
+  defp approval_decision(conn, {:error, :no_token}) do
+    Handlers.json_response(conn, 403, %{"error" => "approvals are decided with an API token"})
+  end
+
+  if Features.enabled?(:chatops) do
+    defp chatops_rejected(conn, :not_configured) do
+      Handlers.json_response(conn, 503, %{"error" => "chatops is not configured"})
+    end
+
+    defp chatops_rejected(conn, :invalid_signature) do
+      Handlers.json_response(conn, 401, %{"error" => "invalid signature"})
+    end
+  end
+
+  defp latest_details(conn, server_id, metric) do
+    case State.get_latest_monitoring_data(server_id) do
+      nil when metric == "all" ->
+        Handlers.json_response(conn, 200, %{"errors" => %{"all" => "no data collected yet"}})
+
+      nil ->
+        Handlers.json_response(conn, 200, nil)
+
+      data ->
+        data_map = Handlers.monitoring_data_to_map(data)
+
+        if metric == "all" do
+          metrics =
+            case conn.params["metrics"] do
+              list when is_binary(list) and list != "" -> String.split(list, ",", trim: true)
+              _ -> Map.keys(@detail_sections)
+            end
+
+          {sections, errors} =
+            Enum.reduce(metrics, {%{}, %{}}, fn name, {sections, errors} ->
+              case detail_section(data_map, name) do
+                {:ok, value} -> {Map.put(sections, name, value), errors}
+                {:error, reason} -> {sections, Map.put(errors, name, reason)}
+              end
+            end)
+
+          result = Map.merge(sections, %{"timestamp" => data_map["timestamp"], "errors" => errors})
+          Handlers.json_response(conn, 200, result)
+        else
+          result =
+            case detail_section(data_map, metric) do
+              {:ok, value} -> value
+              {:error, _reason} -> nil
+            end
+
+          Handlers.json_response(conn, 200, result)
+        end
+    end
+  end
+
+  # Fresh collection with the raw command output, for reporting parser
+  # issues. Admin-only (see `AgentlessMonitor.API.Auth`).
+  defp debug_details(conn, server_id, metric) do
+    with {:ok, server} <- State.get_server(server_id),
+         {:ok, raw, data} <- Service.debug_collect(server, metric) do
+      parsed =
+        case detail_section(Handlers.monitoring_data_to_map(data), metric) do
+          {:ok, value} -> value
+          {:error, _reason} -> nil
+        end
+
+      Handlers.json_response(conn, 200, %{"metric" => metric, "parsed" => parsed, "raw" => raw})
+    else
+      {:error, :not_found} ->
+        Handlers.not_found(conn)
+
+      {:error, :unknown_metric} ->
+        Handlers.json_response(conn, 400, %{"error" => "debug is not available for #{metric}"})
+
+      {:error, reason} ->
+        Handlers.json_response(conn, 502, %{"error" => "collection failed", "output" => reason})
+    end
+  end
+
+  defp detail_section(data_map, name) do
+    case Map.fetch(@detail_sections, name) do
+      {:ok, key} ->
+        case data_map[key] do
+          nil -> {:error, "not collected"}
+          value -> {:ok, value}
+        end
+
+      :error ->
+        {:error, "unknown metric"}
+    end
+  end
+
+  defp process_sorter("cpu"), do: {:ok, &Enum.sort_by(&1, fn p -> p["cpu_percent"] end, :desc)}
+  defp process_sorter("memory"), do: {:ok, &Enum.sort_by(&1, fn p -> p["rss_kb"] end, :desc)}
+  defp process_sorter("pid"), do: {:ok, &Enum.sort_by(&1, fn p -> p["pid"] end)}
+  defp process_sorter(_sort), do: {:error, "sort must be cpu, memory or pid"}
+
+  defp validate_alert_state(state) when state in [nil, "open", "resolved"], do: :ok
+  defp validate_alert_state(_state), do: {:error, "state must be open or resolved"}
+
+  defp validate_drill_sample(overrides) when overrides == %{},
+    do: {:error, "set at least one of cpu_percent, memory_percent or disk_percent"}
+
+  defp validate_drill_sample(overrides) do
+    if Enum.all?(Map.values(overrides), &(is_number(&1) and &1 >= 0 and &1 <= 100)),
+      do: :ok,
+      else: {:error, "percentages must be numbers between 0 and 100"}
+  end
+
+  # Runs `fun` (returning `{status, body}`) once per `Idempotency-Key`
+  # header within `scope`; retries get the stored response back.
+  defp idempotent(conn, scope, fun) do
+    case get_req_header(conn, "idempotency-key") do
+      [key | _] ->
+        case Idempotency.claim({scope, key}) do
+          :new ->
+            {status, body} = fun.()
+            Idempotency.complete({scope, key}, status, body)
+            Handlers.json_response(conn, status, body)
+
+          {:done, status, body} ->
+            conn
+            |> put_resp_header("idempotent-replayed", "true")
+            |> Handlers.json_response(status, body)
+
+          :pending ->
+            message = "a request with this Idempotency-Key is still in progress"
+            Handlers.json_response(conn, 409, %{"error" => message})
+        end
+
+      [] ->
+        {status, body} = fun.()
+        Handlers.json_response(conn, status, body)
+    end
+  end
+
+  # The zone reports show times in: `?tz=` or else `display_timezone`.
+  defp display_zone(conn) do
+    zone = conn.params["tz"] || Config.load().display_timezone
+
+    with :ok <- TimeZones.validate(zone), do: {:ok, zone}
+  end
+
+  defp validate_key_install(%{transport: transport}, _password)
+       when transport in ["local", "snmp"],
+       do: {:error, "#{transport} servers are not reached over SSH"}
+
+  defp validate_key_install(_server, password) when is_binary(password) and password != "",
+    do: :ok
+
+  defp validate_key_install(_server, _password), do: {:error, "password is required"}
+
+  defp validate_server_entry(params, creating) do
+    entry = Map.take(params, @server_fields)
+
+    if creating and not Map.has_key?(entry, "name") do
+      {:error, "name is required"}
+    else
+      Enum.reduce_while(entry, {:ok, entry}, fn {field, value}, acc ->
+        case validate_server_field(field, value) do
+          :ok -> {:cont, acc}
+          {:error, message} -> {:halt, {:error, message}}
+        end
+      end)
+    end
+  end
+
+  defp validate_server_field(field, value) when field in ~w(name host username) do
+    if is_binary(value) and String.trim(value) != "",
+      do: :ok,
+      else: {:error, "#{field} must be a non-empty string"}
+  end
+
+  defp validate_server_field("port", port) when port in 1..65_535, do: :ok
+  defp validate_server_field("port", _port), do: {:error, "port must be between 1 and 65535"}
+
+  # `null` drops the server's own interval, so it inherits one again.
+  defp validate_server_field("monitoring_interval", nil), do: :ok
+
+  defp validate_server_field("monitoring_interval", seconds)
+       when is_integer(seconds) and seconds > 0,
+       do: :ok
+
+  defp validate_server_field("monitoring_interval", _seconds),
+    do: {:error, "monitoring_interval must be a positive number of seconds"}
+
+  defp validate_server_field("auth_method", method) when method in ~w(key key_only password),
+    do: :ok
+
+  defp validate_server_field("auth_method", _method),
+    do: {:error, "auth_method must be key, key_only or password"}
+
+  defp validate_server_field("transport", transport) when transport in ~w(ssh local snmp),
+    do: :ok
+
+  defp validate_server_field("transport", _transport),
+    do: {:error, "transport must be ssh, local or snmp"}
+
+  defp validate_server_field("snmp", snmp) when is_nil(snmp) or is_map(snmp), do: :ok
+  defp validate_server_field("snmp", _snmp), do: {:error, "snmp must be an object"}
+
+  defp validate_server_field("proxy", proxy) when is_nil(proxy) or is_map(proxy), do: :ok
+  defp validate_server_field("proxy", _proxy), do: {:error, "proxy must be an object"}
+
+  defp validate_server_field(field, names) when field in ~w(depends_on watched_units) do
+    if is_list(names) and Enum.all?(names, &is_binary/1),
+      do: :ok,
+      else: {:error, "#{field} must be a list of names"}
+  end
+
+  defp validate_server_field("identity_files", paths) do
+    if is_list(paths) and Enum.all?(paths, &is_binary/1),
+      do: :ok,
+      else: {:error, "identity_files must be a list of paths"}
+  end
+
+  defp validate_server_field(field, value) do
+    if is_nil(value) or is_binary(value), do: :ok, else: {:error, "#{field} must be a string"}
+  end
+
+  defp in_range?(_timestamp, nil, nil), do: true
+
+  defp in_range?(timestamp, from, to) do
+    case DateTime.from_iso8601(timestamp || "") do
+      {:ok, dt, _} ->
+        unix = DateTime.to_unix(dt)
+        (is_nil(from) or unix >= from) and (is_nil(to) or unix <= to)
+
+      _ ->
+        false
     end
   end
 
@@ -0,0 +1,58 @@
+defmodule AgentlessMonitor.API.Snapshot do
+  @moduledoc """
+  Everything known about one server as a single JSON document, to attach to
+  a ticket or a support request: its definition and status, the effective
+  settings, the latest sample, recent events, open and recently resolved
+  alerts, its inventory (fingerprint and system information), process rule
+  results and its SSH connection.
+
+  Privacy mode applies as it does to the individual endpoints.
+  """
+
+  alias AgentlessMonitor.{Settings, State}
+  alias AgentlessMonitor.Alerting.{Events, History, Incidents}
+  alias AgentlessMonitor.API.Handlers
+  alias AgentlessMonitor.Monitoring.{Fingerprint, Watchdog}
+  alias AgentlessMonitor.SSH.Manager
+
+  @recent 50
+
+  def build(server) do
+    latest = Handlers.monitoring_data_to_map(State.get_latest_monitoring_data(server.id))
+
+    fingerprint =
+      case Fingerprint.get(server.id) do
+        {:ok, fingerprint} -> fingerprint
+        {:error, :not_found} -> nil
+      end
+
+    open_alerts =
+      for incident <- Incidents.list(),
+          incident.server_id == server.id and incident.status != "resolved",
+          do: Handlers.incident_to_map(incident)
+
+    %{
+      "generated_at" => DateTime.utc_now() |> DateTime.to_iso8601(),
+      "server" => Handlers.server_to_map(server),
+      "effective_config" => Settings.effective(server),
+      "latest_data" => latest,
+      "events" =>
+        [server_id: server.id, limit: @recent]
+        |> Events.list()
+        |> Enum.map(&Handlers.event_to_map/1),
+      "alerts" => %{
+        "open" => open_alerts,
+        "resolved" => History.alerts(server_id: server.id, limit: @recent)
+      },
+      "inventory" => %{
+        "fingerprint" => fingerprint,
+        "system_info" => latest && latest["system_info"]
+      },
+      "process_rules" => Watchdog.status(server.id),
+      "connection" => %{
+        "pooled" => Map.get(Manager.get_connections(), server.id),
+        "ssh_probe" => Manager.get_probe(server.host, server.port)
+      }
+    }
+  end
+end
@@ -3,20 +3,114 @@ defmodule AgentlessMonitor.Application do
   require Logger
 
   def start(_type, _args) do
+    if Application.get_env(:agentless_monitor, :embedded, false) do
+      start_embedded()
+    else
+      case AgentlessMonitor.CLI.main(Burrito.Util.Args.argv()) do
+        :server -> start_server()
+        exit_code -> System.halt(exit_code)
+      end
+    end
+  end
+
+  # Used as a library (see AgentlessMonitor.Collector): no web server,
+  # monitoring loop or alerting, only what collection needs.
+  defp start_embedded do
+    children = [{Task.Supervisor, name: AgentlessMonitor.TaskSupervisor}]
+    Supervisor.start_link(children, strategy: :one_for_one, name: AgentlessMonitor.Supervisor)
+  end
+
+  defp start_server do
     config = AgentlessMonitor.Config.load()
-    port = config.server_port
+    Logger.info("Starting AgentlessMonitor on #{config.bind_address}:#{config.server_port}")
+
+    children =
+      [
+        {AgentlessMonitor.PubSub, []},
+        {AgentlessMonitor.State, []},
+        {Task.Supervisor, name: AgentlessMonitor.TaskSupervisor}
+      ] ++ AgentlessMonitor.Storage.children() ++ services(config)
 
-    Logger.info("Starting AgentlessMonitor on port #{port}")
+    opts = [strategy: :one_for_one, name: AgentlessMonitor.Supervisor]
+    Supervisor.start_link(children, opts)
+  end
 
-    children = [
-      {AgentlessMonitor.State, []},
+  defp services(config) do
+    [
+      {AgentlessMonitor.Tokens, []},
+      {AgentlessMonitor.ShareLinks, []},
+      feature(:ldap, {AgentlessMonitor.LDAP, []}),
+      {AgentlessMonitor.Approvals, []},
+      {AgentlessMonitor.Registrations, []},
+      {AgentlessMonitor.Idempotency, []},
+      feature(:notifications, {AgentlessMonitor.Notifications, []}),
+      {AgentlessMonitor.Syslog, []},
+      {AgentlessMonitor.Sites, []},
+      {AgentlessMonitor.Alerting.History, []},
+      {AgentlessMonitor.Alerting.Incidents, []},
+      {AgentlessMonitor.Alerting.Actions, []},
+      {AgentlessMonitor.Alerting.Capture, []},
+      {AgentlessMonitor.Alerting.Events, []},
+      {AgentlessMonitor.Alerting.SLO, []},
+      {AgentlessMonitor.Alerting.Canary, []},
       {AgentlessMonitor.SSH.Manager, []},
-      {Task.Supervisor, name: AgentlessMonitor.TaskSupervisor},
+      {AgentlessMonitor.SSH.Coalescer, []},
+      {AgentlessMonitor.SSH.Native, []},
+      {AgentlessMonitor.Monitoring.Fingerprint, []},
+      {AgentlessMonitor.Monitoring.Watchdog, []},
+      {AgentlessMonitor.Monitoring.Neighbors, []},
+      {AgentlessMonitor.Monitoring.Mesh, []},
+      {AgentlessMonitor.Monitoring.Burst, []},
+      {AgentlessMonitor.Bundles, []},
+      {AgentlessMonitor.Manifest, []},
       {AgentlessMonitor.Monitoring.Service, []},
-      {Plug.Cowboy, scheme: :http, plug: AgentlessMonitor.API.Router, options: [port: port]}
+      listener(config)
     ]
+    |> Enum.reject(&is_nil/1)
+  end
 
-    opts = [strategy: :one_for_one, name: AgentlessMonitor.Supervisor]
-    Supervisor.start_link(children, opts)
+  defp feature(name, child) do
+    if AgentlessMonitor.Features.enabled?(name), do: child
+  end
+
+  # Cowboy negotiates HTTP/2 via ALPN on the TLS listener; the plain listener
+  # still accepts HTTP/2 with prior knowledge (h2c). A half-configured or
+  # unreadable certificate stops startup instead of falling back to HTTP.
+  defp listener(%{tls_certfile: nil, tls_keyfile: nil} = config) do
+    {Plug.Cowboy,
+     scheme: :http, plug: AgentlessMonitor.API.Router, options: listen_options(config)}
+  end
+
+  defp listener(config) do
+    Logger.info("Serving HTTPS (HTTP/2 enabled)")
+
+    {Plug.Cowboy,
+     scheme: :https,
+     plug: AgentlessMonitor.API.Router,
+     options: listen_options(config) ++ tls_options(config)}
+  end
+
+  defp tls_options(config) do
+    files =
+      [certfile: config.tls_certfile, keyfile: config.tls_keyfile]
+      |> Enum.concat(if config.tls_cacertfile, do: [cacertfile: config.tls_cacertfile], else: [])
+
+    Enum.each(files, fn
+      {key, path} when is_binary(path) ->
+        unless File.regular?(path), do: raise(ArgumentError, "tls_#{key} not found: #{path}")
+
+      {key, _path} ->
+        raise ArgumentError, "tls_#{key} is required when serving HTTPS"
+    end)
+
+    files ++ [versions: [:"tlsv1.2", :"tlsv1.3"]]
+  end
+
+  defp listen_options(config) do
+    case :inet.parse_address(String.to_charlist(config.bind_address)) do
+      {:ok, ip} when tuple_size(ip) == 8 -> [port: config.server_port, ip: ip, net: :inet6]
+      {:ok, ip} -> [port: config.server_port, ip: ip]
+      {:error, _} -> raise ArgumentError, "invalid bind_address: #{config.bind_address}"
+    end
   end
 end
@@ -0,0 +1,203 @@
+defmodule AgentlessMonitor.Approvals do
+  @moduledoc """
+  Two-person approval for risky operations.
+
+  Remote exec and actions whose risk is at or above `approval_risk_level`
+  are parked here as approval requests. A different operator than the
+  requester must approve them before `approval_ttl` seconds elapse, after
+  which the request expires and is never executed.
+  """
+
+  use GenServer
+  require Logger
+
+  alias AgentlessMonitor.{Config, Models, Storage}
+  alias AgentlessMonitor.Alerting.Events
+
+  @risk_levels ["low", "medium", "high", "critical"]
+  @sweep_interval :timer.seconds(30)
+  @max_history 200
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, %{approvals: [], callbacks: %{}}, name: __MODULE__)
+  end
+
+  def risk_levels, do: @risk_levels
+
+  @doc "Returns true when an operation of the given risk needs approval"
+  def required?(risk) do
+    risk_rank(risk) >= risk_rank(Config.load().approval_risk_level)
+  end
+
+  @doc """
+  Files an approval request. `attrs` is a map with `"summary"`, `"risk"`,
+  `"requested_by"` and optionally `"server_id"`; `on_approve` runs once the
+  request is approved.
+  """
+  def request(attrs, on_approve) when is_function(on_approve, 0) do
+    GenServer.call(__MODULE__, {:request, attrs, on_approve})
+  end
+
+  def list(opts \\ []) do
+    GenServer.call(__MODULE__, {:list, opts})
+  end
+
+  def get(id) do
+    GenServer.call(__MODULE__, {:get, id})
+  end
+
+  def approve(id, approver) do
+    GenServer.call(__MODULE__, {:decide, id, approver, "approved"})
+  end
+
+  def reject(id, approver) do
+    GenServer.call(__MODULE__, {:decide, id, approver, "rejected"})
+  end
+
+  # ---- Callbacks ----
+
+  def init(state) do
+    schedule_sweep()
+    {:ok, state}
+  end
+
+  def handle_call({:request, attrs, on_approve}, _from, state) do
+    now = DateTime.utc_now()
+    ttl = Config.load().approval_ttl
+
+    approval = %{
+      "id" => Models.generate_id(),
+      "summary" => attrs["summary"],
+      "risk" => attrs["risk"],
+      "server_id" => attrs["server_id"],
+      "requested_by" => attrs["requested_by"],
+      "status" => "pending",
+      "created_at" => DateTime.to_iso8601(now),
+      "expires_at" => now |> DateTime.add(ttl, :second) |> DateTime.to_iso8601(),
+      "decided_by" => nil,
+      "decided_at" => nil
+    }
+
+    notify_approvers(approval)
+
+    new_state = %{
+      state
+      | approvals: Enum.take([approval | state.approvals], @max_history),
+        callbacks: Map.put(state.callbacks, approval["id"], on_approve)
+    }
+
+    {:reply, {:ok, approval}, new_state}
+  end
+
+  def handle_call({:list, opts}, _from, state) do
+    status = Keyword.get(opts, :status)
+    approvals = Enum.filter(state.approvals, &(is_nil(status) or &1["status"] == status))
+    {:reply, approvals, state}
+  end
+
+  def handle_call({:get, id}, _from, state) do
+    case Enum.find(state.approvals, &(&1["id"] == id)) do
+      nil -> {:reply, {:error, :not_found}, state}
+      approval -> {:reply, {:ok, approval}, state}
+    end
+  end
+
+  def handle_call({:decide, id, approver, decision}, _from, state) do
+    case Enum.find(state.approvals, &(&1["id"] == id)) do
+      nil ->
+        {:reply, {:error, :not_found}, state}
+
+      %{"status" => status} when status != "pending" ->
+        {:reply, {:error, :not_pending}, state}
+
+      %{"requested_by" => ^approver} when decision == "approved" ->
+        {:reply, {:error, :self_approval}, state}
+
+      approval ->
+        if expired?(approval, DateTime.utc_now()) do
+          {:reply, {:error, :not_pending}, expire(state, approval)}
+        else
+          decided = %{
+            approval
+            | "status" => decision,
+              "decided_by" => approver,
+              "decided_at" => DateTime.utc_now() |> DateTime.to_iso8601()
+          }
+
+          Logger.info("Approval #{id} #{decision} by #{approver}: #{approval["summary"]}")
+          Storage.audit("approval_#{decision}", approver, Map.take(approval, ["id", "summary"]))
+
+          if decision == "approved" do
+            Task.Supervisor.start_child(AgentlessMonitor.TaskSupervisor, state.callbacks[id])
+          end
+
+          {:reply, {:ok, decided}, replace(state, decided)}
+        end
+    end
+  end
+
+  def handle_info(:sweep, state) do
+    now = DateTime.utc_now()
+
+    state =
+      state.approvals
+      |> Enum.filter(&(&1["status"] == "pending" and expired?(&1, now)))
+      |> Enum.reduce(state, &expire(&2, &1))
+
+    schedule_sweep()
+    {:noreply, state}
+  end
+
+  # ---- Private helpers ----
+
+  defp risk_rank(risk) do
+    Enum.find_index(@risk_levels, &(&1 == risk)) || 0
+  end
+
+  defp notify_approvers(approval) do
+    Logger.warning(
+      "Approval required (#{approval["risk"]} risk) for: #{approval["summary"]} " <>
+        "- approve via POST /api/approvals/#{approval["id"]}/approve"
+    )
+
+    Events.emit(
+      server_id: approval["server_id"],
+      kind: "approval_requested",
+      severity: "info",
+      message: "Approval requested by #{approval["requested_by"]}: #{approval["summary"]}"
+    )
+  end
+
+  defp expired?(approval, now) do
+    case DateTime.from_iso8601(approval["expires_at"]) do
+      {:ok, expires_at, _} -> DateTime.compare(now, expires_at) == :gt
+      _ -> false
+    end
+  end
+
+  defp expire(state, approval) do
+    Logger.info("Approval #{approval["id"]} expired: #{approval["summary"]}")
+    state = replace(state, %{approval | "status" => "expired"})
+    %{state | callbacks: Map.delete(state.callbacks, approval["id"])}
+  end
+
+  defp replace(state, approval) do
+    approvals =
+      Enum.map(state.approvals, fn existing ->
+        if existing["id"] == approval["id"], do: approval, else: existing
+      end)
+
+    callbacks =
+      if approval["status"] == "pending",
+        do: state.callbacks,
+        else: Map.delete(state.callbacks, approval["id"])
+
+    %{state | approvals: approvals, callbacks: callbacks}
+  end
+
+  defp schedule_sweep do
+    Process.send_after(self(), :sweep, @sweep_interval)
+  end
+end
@@ -0,0 +1,212 @@
+defmodule AgentlessMonitor.Bundles do
+  @moduledoc """
+  Shareable monitoring setups: alert thresholds, actions (with their
+  command templates), group definitions, SLOs and dashboard layouts packed
+  into one versioned JSON document.
+
+      {"format": "agentless-monitor-bundle", "version": 1,
+       "name": "linux-baseline", "description": "...",
+       "contents": {"thresholds": {...}, "actions": [...], "groups": {...},
+                    "slos": [...], "dashboards": [...]}}
+
+  `export/1` builds a bundle from the running configuration. Imported
+  bundles are installed under `<data_dir>/bundles/<name>.json` and overlaid
+  on the configuration in name order: threshold and group maps are merged,
+  and list entries replace same-named ones. The manifest, when used, still
+  has the last word. Uninstalling a bundle removes its overlay.
+  """
+
+  use GenServer
+  require Logger
+
+  alias AgentlessMonitor.{Config, DataFile}
+  alias AgentlessMonitor.Alerting.Actions
+
+  @format "agentless-monitor-bundle"
+  @version 1
+  @sections ["thresholds", "actions", "groups", "slos", "dashboards"]
+  @installed_key {__MODULE__, :installed}
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, nil, name: __MODULE__)
+  end
+
+  @doc "Overlays the installed bundles on a loaded configuration"
+  def overlay_installed(config) do
+    @installed_key
+    |> :persistent_term.get([])
+    |> Enum.reduce(config, fn bundle, acc -> overlay(acc, bundle["contents"]) end)
+  end
+
+  @doc "Bundle of the running configuration's shareable sections"
+  def export(attrs \\ %{}) do
+    config = Config.load()
+
+    %{
+      "format" => @format,
+      "version" => @version,
+      "name" => Map.get(attrs, "name", "export"),
+      "description" => Map.get(attrs, "description"),
+      "exported_at" => DateTime.utc_now() |> DateTime.to_iso8601(),
+      "contents" => %{
+        "thresholds" => config.thresholds,
+        "actions" => config.actions,
+        "groups" => config.groups,
+        "slos" => config.slos,
+        "dashboards" => config.dashboards
+      }
+    }
+  end
+
+  @doc "Installed bundles without their contents"
+  def list do
+    @installed_key
+    |> :persistent_term.get([])
+    |> Enum.map(&summary/1)
+  end
+
+  @doc "Validates and installs a bundle, replacing one with the same name"
+  def install(bundle) do
+    with :ok <- validate(bundle) do
+      GenServer.call(__MODULE__, {:install, Map.put(bundle, "installed_at", now())})
+    end
+  end
+
+  def uninstall(name) do
+    GenServer.call(__MODULE__, {:uninstall, name})
+  end
+
+  # ---- Callbacks ----
+
+  def init(_) do
+    :persistent_term.put(@installed_key, load())
+    {:ok, nil}
+  end
+
+  def handle_call({:install, bundle}, _from, state) do
+    path = path(bundle["name"])
+
+    case DataFile.write_atomic(path, Jason.encode!(bundle, pretty: true)) do
+      :ok ->
+        refresh()
+        {:reply, {:ok, summary(bundle)}, state}
+
+      {:error, reason} ->
+        {:reply, {:error, "failed to store bundle: #{inspect(reason)}"}, state}
+    end
+  end
+
+  def handle_call({:uninstall, name}, _from, state) do
+    with true <- valid_name?(name),
+         :ok <- File.rm(path(name)) do
+      refresh()
+      {:reply, :ok, state}
+    else
+      _ -> {:reply, {:error, :not_found}, state}
+    end
+  end
+
+  # ---- Private helpers ----
+
+  defp refresh do
+    :persistent_term.put(@installed_key, load())
+    Actions.reload_rules()
+  end
+
+  defp validate(bundle) when not is_map(bundle), do: {:error, "a bundle must be an object"}
+
+  defp validate(bundle) do
+    contents = bundle["contents"]
+
+    cond do
+      bundle["format"] != @format ->
+        {:error, "not a bundle (format must be #{@format})"}
+
+      not is_integer(bundle["version"]) or bundle["version"] > @version ->
+        {:error, "unsupported bundle version #{inspect(bundle["version"])}"}
+
+      not valid_name?(bundle["name"]) ->
+        {:error, "name must consist of letters, digits, '-', '_' and '.'"}
+
+      not is_map(contents) or Map.keys(contents) -- @sections != [] ->
+        {:error, "contents may only hold #{Enum.join(@sections, ", ")}"}
+
+      not (is_nil(contents["thresholds"]) or is_map(contents["thresholds"])) or
+          not (is_nil(contents["groups"]) or is_map(contents["groups"])) ->
+        {:error, "thresholds and groups must be objects"}
+
+      not Enum.all?(["actions", "slos", "dashboards"], &named_list?(contents[&1])) ->
+        {:error, "actions, slos and dashboards must be lists of objects with a name"}
+
+      true ->
+        :ok
+    end
+  end
+
+  defp valid_name?(name), do: is_binary(name) and name =~ ~r/^[A-Za-z0-9][A-Za-z0-9._-]*$/
+
+  defp named_list?(nil), do: true
+
+  defp named_list?(list) when is_list(list),
+    do: Enum.all?(list, &(is_map(&1) and is_binary(&1["name"])))
+
+  defp named_list?(_value), do: false
+
+  defp overlay(config, contents) do
+    %{
+      config
+      | thresholds: Map.merge(config.thresholds, contents["thresholds"] || %{}),
+        groups: Map.merge(config.groups, contents["groups"] || %{}),
+        actions: merge_named(config.actions, contents["actions"]),
+        slos: merge_named(config.slos, contents["slos"]),
+        dashboards: merge_named(config.dashboards, contents["dashboards"])
+    }
+  end
+
+  defp merge_named(existing, nil), do: existing
+
+  defp merge_named(existing, entries) do
+    names = MapSet.new(entries, & &1["name"])
+    Enum.reject(existing, &MapSet.member?(names, &1["name"])) ++ entries
+  end
+
+  defp summary(bundle) do
+    counts =
+      Map.new(@sections, fn section ->
+        {section, bundle |> get_in(["contents", section]) |> count()}
+      end)
+
+    bundle
+    |> Map.take(["name", "description", "version", "exported_at", "installed_at"])
+    |> Map.put("counts", counts)
+  end
+
+  defp count(nil), do: 0
+  defp count(section), do: Enum.count(section)
+
+  defp now, do: DateTime.utc_now() |> DateTime.to_iso8601()
+
+  defp dir, do: Path.join(Config.load().data_dir, "bundles")
+
+  defp path(name), do: Path.join(dir(), name <> ".json")
+
+  defp load do
+    dir()
+    |> Path.join("*.json")
+    |> Path.wildcard()
+    |> Enum.sort()
+    |> Enum.flat_map(fn path ->
+      with {:ok, content} <- File.read(path),
+           {:ok, bundle} <- Jason.decode(content),
+           :ok <- validate(bundle) do
+        [bundle]
+      else
+        _ ->
+          Logger.warning("Ignoring invalid bundle #{path}")
+          []
+      end
+    end)
+  end
+end
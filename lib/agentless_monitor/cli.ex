@@ -0,0 +1,271 @@
+defmodule AgentlessMonitor.CLI do
+  @moduledoc """
+  Command-line entry point of the release binary.
+
+      agentless-monitor [server [--port PORT] [--bind ADDRESS]]
+      agentless-monitor config export [--output FILE] [--include-secrets]
+      agentless-monitor fsck [--repair]
+      agentless-monitor check HOST [--user USER] [--port PORT] [--collectors LIST] [--json]
+      agentless-monitor hosts [--json]
+
+  `hosts` lists the SSH config's entries as the servers they become, and
+  the ones that are skipped or look wrong; it exits 1 when there are any.
+
+  `check` collects one sample from a host (or a configured server, by
+  name) over SSH and prints it, without starting the web server. Its exit
+  code is 0 when the sample is within thresholds, 1 when one is exceeded
+  and 2 when the collection fails.
+
+  `--port` and `--bind` take precedence over `server_port` and
+  `bind_address` from the environment and `config.json`.
+  """
+
+  alias AgentlessMonitor.{Collector, Config, DataFile, Settings, State}
+  alias AgentlessMonitor.Monitoring.Service
+
+  @doc """
+  Returns `:server` when the web server should start, or runs a one-shot
+  command and returns its exit code.
+  """
+  def main(argv) do
+    case argv do
+      [] -> :server
+      ["server" | rest] -> server(rest)
+      ["config", "export" | rest] -> config_export(rest)
+      ["fsck" | rest] -> fsck(rest)
+      ["check" | rest] -> check(rest)
+      ["hosts" | rest] -> hosts(rest)
+      _ -> usage()
+    end
+  end
+
+  defp server(args) do
+    case OptionParser.parse(args, strict: [port: :integer, bind: :string]) do
+      {opts, [], []} ->
+        overrides =
+          Enum.reduce(opts, %{}, fn
+            {:port, port}, acc -> Map.put(acc, :server_port, port)
+            {:bind, address}, acc -> Map.put(acc, :bind_address, address)
+          end)
+
+        Application.put_env(:agentless_monitor, :cli_overrides, overrides)
+        :server
+
+      _ ->
+        usage()
+    end
+  end
+
+  defp config_export(args) do
+    {opts, _rest, _invalid} =
+      OptionParser.parse(args, strict: [output: :string, include_secrets: :boolean])
+
+    export =
+      Config.export(Config.load(), State.initial_servers(), MapSet.new(),
+        include_secrets: Keyword.get(opts, :include_secrets, false)
+      )
+
+    json = Jason.encode!(export, pretty: true)
+
+    case Keyword.get(opts, :output) do
+      nil ->
+        IO.puts(json)
+        0
+
+      path ->
+        File.write!(path, json <> "\n")
+        IO.puts(:stderr, "Configuration written to #{path}")
+        0
+    end
+  end
+
+  # Exits non-zero when problems were found and left in place.
+  defp fsck(args) do
+    {opts, _rest, _invalid} = OptionParser.parse(args, strict: [repair: :boolean])
+    repair = Keyword.get(opts, :repair, false)
+    data_dir = Config.load().data_dir
+
+    case DataFile.fsck(data_dir, repair: repair) do
+      [] ->
+        IO.puts("#{data_dir}: no problems found")
+        0
+
+      reports ->
+        Enum.each(reports, fn report ->
+          status = if report["repaired"], do: "repaired", else: "NOT repaired"
+          IO.puts("#{report["path"]}: #{report["problem"]} (#{status})")
+        end)
+
+        if Enum.all?(reports, & &1["repaired"]), do: 0, else: 1
+    end
+  end
+
+  defp check(args) do
+    strict = [user: :string, port: :integer, collectors: :string, json: :boolean]
+
+    case OptionParser.parse(args, strict: strict) do
+      {opts, [host], []} ->
+        {:ok, _pid} = Task.Supervisor.start_link(name: AgentlessMonitor.TaskSupervisor)
+        server = check_server(host, opts)
+        settings = Settings.for_server(server)
+
+        overrides =
+          case Keyword.get(opts, :collectors) do
+            nil -> %{}
+            list -> %{"collectors" => String.split(list, ",", trim: true)}
+          end
+
+        case Collector.collect(server, overrides) do
+          {:ok, sample} ->
+            breaches = Service.threshold_breaches(sample, settings["thresholds"] || %{})
+
+            if opts[:json],
+              do: IO.puts(Jason.encode!(check_json(server, sample, breaches), pretty: true)),
+              else: print_check(server, sample, breaches)
+
+            if breaches == %{} and not sample.degraded, do: 0, else: 1
+
+          {:error, reason} ->
+            error = if is_binary(reason), do: reason, else: inspect(reason)
+
+            if opts[:json] do
+              json = %{"server" => server.name, "host" => server.host, "error" => error}
+              IO.puts(Jason.encode!(json, pretty: true))
+            else
+              IO.puts("#{server.name} (#{server.host}): CRITICAL - #{error}")
+            end
+
+            2
+        end
+
+      _ ->
+        usage()
+    end
+  end
+
+  # A configured server of that name keeps its user, port and proxy.
+  defp check_server(host, opts) do
+    configured = Enum.find(State.initial_servers(), &(&1.name == host))
+    server = configured || Collector.server(host: host)
+
+    %{
+      server
+      | username: Keyword.get(opts, :user, server.username),
+        port: Keyword.get(opts, :port, server.port)
+    }
+  end
+
+  defp check_json(server, sample, breaches) do
+    %{
+      "server" => server.name,
+      "host" => server.host,
+      "status" =>
+        cond do
+          sample.degraded -> "degraded"
+          breaches == %{} -> "ok"
+          true -> "warning"
+        end,
+      "breaches" => Enum.map(breaches, fn {_key, message} -> message end),
+      "sample" => Collector.to_map(sample)
+    }
+  end
+
+  defp print_check(server, sample, breaches) do
+    status =
+      cond do
+        sample.degraded -> "WARNING - degraded collection"
+        breaches == %{} -> "OK"
+        true -> "WARNING"
+      end
+
+    IO.puts("#{server.name} (#{server.host}): #{status}")
+
+    if info = sample.system_info do
+      IO.puts("  system   #{info.hostname}, #{info.os_release}, up #{duration(info.uptime)}")
+    end
+
+    if cpu = sample.cpu do
+      load = Enum.map_join(cpu.load_average, " ", &to_string/1)
+
+      if cpu.usage_percent,
+        do: IO.puts("  cpu      #{cpu.usage_percent}% of #{cpu.cores} cores, load #{load}"),
+        else: IO.puts("  cpu      load #{load}")
+    end
+
+    if (memory = sample.memory) && memory.total > 0 do
+      percent = Float.round(memory.used / memory.total * 100, 1)
+      IO.puts("  memory   #{percent}% of #{gib(memory.total)}")
+    end
+
+    Enum.each(sample.disks, fn disk ->
+      IO.puts("  disk     #{disk.mount_point} #{disk.usage_percent}% of #{gib(disk.total)}")
+    end)
+
+    Enum.each(sample.ping_tests, fn ping ->
+      result = if ping.success, do: "#{ping.latency_ms} ms", else: ping.error || "failed"
+      IO.puts("  ping     #{ping.target} #{result}")
+    end)
+
+    unless sample.failed_collectors == [] do
+      IO.puts("  failed   #{Enum.join(sample.failed_collectors, ", ")}")
+    end
+
+    Enum.each(breaches, fn {_key, message} -> IO.puts("  ! #{message}") end)
+  end
+
+  defp gib(bytes), do: "#{Float.round(bytes / 1_073_741_824, 1)} GiB"
+
+  defp duration(seconds) do
+    days = div(seconds, 86_400)
+    hours = div(rem(seconds, 86_400), 3600)
+    if days > 0, do: "#{days}d #{hours}h", else: "#{hours}h #{div(rem(seconds, 3600), 60)}m"
+  end
+
+  defp hosts(args) do
+    {opts, _rest, _invalid} = OptionParser.parse(args, strict: [json: :boolean])
+    report = State.ssh_config_report()
+
+    if opts[:json] do
+      IO.puts(Jason.encode!(report, pretty: true))
+    else
+      IO.puts(if report["exists"], do: report["path"], else: "#{report["path"]} (not found)")
+      width = report["hosts"] |> Enum.map(&String.length(&1["name"])) |> Enum.max(fn -> 0 end)
+
+      Enum.each(report["hosts"], fn host ->
+        name = String.pad_trailing(host["name"], width)
+
+        case host["status"] do
+          "pattern" ->
+            IO.puts("  #{name}  (pattern, supplies defaults)")
+
+          "skipped" ->
+            IO.puts("  #{name}  SKIPPED: #{Enum.join(host["problems"], "; ")}")
+
+          _ ->
+            target = "#{host["user"]}@#{host["host"]}:#{host["port"]}"
+            IO.puts("  #{name}  #{target}#{via(host["proxy"])}")
+            Enum.each(host["problems"], &IO.puts("  #{String.duplicate(" ", width)}  ! #{&1}"))
+        end
+      end)
+    end
+
+    if Enum.any?(report["hosts"], &(&1["status"] in ["skipped", "warning"])), do: 1, else: 0
+  end
+
+  defp via(%{"type" => "jump", "host" => hops}), do: " via #{hops}"
+  defp via(%{"type" => "command", "command" => command}), do: " via `#{command}`"
+  defp via(_proxy), do: ""
+
+  defp usage do
+    IO.puts(:stderr, """
+    Usage:
+      agentless-monitor [server [--port PORT] [--bind ADDRESS]]
+      agentless-monitor config export [--output FILE] [--include-secrets]
+      agentless-monitor fsck [--repair]
+      agentless-monitor check HOST [--user USER] [--port PORT] [--collectors LIST] [--json]
+      agentless-monitor hosts [--json]
+    """)
+
+    1
+  end
+end
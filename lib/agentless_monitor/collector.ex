@@ -0,0 +1,75 @@
+defmodule AgentlessMonitor.Collector do
+  @moduledoc """
+  Stable entry point for embedding the collection engine in another
+  application, without the web server or the monitoring loop.
+
+  Add the dependency and set `config :agentless_monitor, embedded: true`,
+  then:
+
+      server = AgentlessMonitor.Collector.server(host: "10.0.0.5", username: "deploy")
+      {:ok, sample} = AgentlessMonitor.Collector.collect(server)
+      sample.cpu.usage_percent
+
+  Samples are `AgentlessMonitor.Models.MonitoringData` structs. Settings
+  (collectors, ping targets, SSH options, ...) are resolved from the usual
+  configuration and can be overridden per call. SSH connections go through
+  the `ssh` client and reuse ControlMaster sockets.
+  """
+
+  alias AgentlessMonitor.{Models, Settings}
+  alias AgentlessMonitor.Models.Server
+  alias AgentlessMonitor.Monitoring.Service
+
+  @fields [
+    :id,
+    :name,
+    :host,
+    :port,
+    :username,
+    :auth_method,
+    :group,
+    :transport,
+    :command_prefix,
+    :snmp
+  ]
+
+  @doc """
+  Builds a server from a keyword list or map with `host` and optionally
+  `id`, `name`, `port`, `username`, `auth_method`, `group`, `transport`
+  (`"ssh"`, `"local"` or `"snmp"`), `command_prefix` and `snmp`.
+  """
+  def server(attrs) do
+    attrs = Map.new(attrs, fn {key, value} -> {to_existing_atom(key), value} end)
+    host = Map.get(attrs, :host, "localhost")
+
+    struct(
+      %Server{id: host, name: host, username: System.get_env("USER", "root")},
+      Map.take(attrs, @fields)
+    )
+  end
+
+  @doc """
+  Collects one sample. `overrides` replaces resolved settings, e.g.
+  `%{"collectors" => ["cpu", "memory"], "ping_targets" => ["10.0.0.1"]}`.
+  """
+  def collect(%Server{} = server, overrides \\ %{}) do
+    Service.collect(server, Map.merge(Settings.for_server(server), overrides))
+  end
+
+  @doc "Runs a shell command on the server and returns its output"
+  def run(%Server{} = server, command) do
+    Service.run_command(server, command)
+  end
+
+  @doc "Checks that commands can be run on the server"
+  def test_connection(%Server{} = server), do: run(server, "echo ok")
+
+  @doc "Collectors that can be enabled in the `collectors` setting"
+  def collectors, do: Settings.collectors()
+
+  @doc "Converts a sample to the string-keyed map served by the API"
+  def to_map(sample), do: Models.MonitoringData.to_map(sample)
+
+  defp to_existing_atom(key) when is_atom(key), do: key
+  defp to_existing_atom(key), do: String.to_existing_atom(key)
+end
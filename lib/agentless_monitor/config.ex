@@ -6,14 +6,90 @@ defmodule AgentlessMonitor.Config do
 
     base = %{
       server_port: Keyword.get(app_config, :server_port, 8080),
+      bind_address: Keyword.get(app_config, :bind_address, "0.0.0.0"),
       monitoring_interval: Keyword.get(app_config, :monitoring_interval, 30),
       ping_timeout: Keyword.get(app_config, :ping_timeout, 5),
+      ping_targets: Keyword.get(app_config, :ping_targets, ["8.8.8.8", "1.1.1.1"]),
+      ping_interval: Keyword.get(app_config, :ping_interval, nil),
       ssh_timeout: Keyword.get(app_config, :ssh_timeout, 10),
+      collector_timeout: Keyword.get(app_config, :collector_timeout, nil),
+      single_shot: Keyword.get(app_config, :single_shot, false),
+      custom_metrics: Keyword.get(app_config, :custom_metrics, []),
+      collection_hooks: Keyword.get(app_config, :collection_hooks, %{}),
+      collection_budget: Keyword.get(app_config, :collection_budget, %{}),
+      http_checks: Keyword.get(app_config, :http_checks, []),
+      port_audit_ports:
+        Keyword.get(app_config, :port_audit_ports, [
+          21, 22, 23, 25, 53, 80, 110, 143, 443, 445, 465, 587, 993, 995,
+          1433, 3306, 3389, 5432, 5900, 6379, 8080, 8443, 9200, 27017
+        ]),
+      ssh_backend: Keyword.get(app_config, :ssh_backend, "openssh"),
+      ssh_pool: Keyword.get(app_config, :ssh_pool, %{}),
+      restricted_mode: Keyword.get(app_config, :restricted_mode, false),
+      restricted_commands: Keyword.get(app_config, :restricted_commands, []),
       fallback_password: Keyword.get(app_config, :fallback_password, nil),
-      ssh_config_path: Keyword.get(app_config, :ssh_config_path, nil)
+      ssh_config_path: Keyword.get(app_config, :ssh_config_path, nil),
+      data_dir: Keyword.get(app_config, :data_dir, "data"),
+      retired_retention_days: Keyword.get(app_config, :retired_retention_days, 30),
+      chatops_signing_secret: Keyword.get(app_config, :chatops_signing_secret, nil),
+      chatops_token: Keyword.get(app_config, :chatops_token, nil),
+      chatops_allow_exec: Keyword.get(app_config, :chatops_allow_exec, false),
+      actions: Keyword.get(app_config, :actions, []),
+      alert_capture: Keyword.get(app_config, :alert_capture, true),
+      approval_risk_level: Keyword.get(app_config, :approval_risk_level, "high"),
+      approval_ttl: Keyword.get(app_config, :approval_ttl, 900),
+      exec_risk: Keyword.get(app_config, :exec_risk, "high"),
+      thresholds:
+        Keyword.get(app_config, :thresholds, %{
+          "cpu_percent" => 90,
+          "memory_percent" => 90,
+          "disk_percent" => 90
+        }),
+      groups: Keyword.get(app_config, :groups, %{}),
+      health_weights: Keyword.get(app_config, :health_weights, %{}),
+      sites: Keyword.get(app_config, :sites, %{}),
+      display_timezone: Keyword.get(app_config, :display_timezone, "Etc/UTC"),
+      server_overrides: Keyword.get(app_config, :server_overrides, %{}),
+      profile: Keyword.get(app_config, :profile, nil),
+      profiles: Keyword.get(app_config, :profiles, %{}),
+      servers: Keyword.get(app_config, :servers, []),
+      manifest_path: Keyword.get(app_config, :manifest_path, nil),
+      config_reload_interval: Keyword.get(app_config, :config_reload_interval, 30),
+      api_auth: Keyword.get(app_config, :api_auth, false),
+      admin_token: Keyword.get(app_config, :admin_token, nil),
+      share_link_secret: Keyword.get(app_config, :share_link_secret, nil),
+      tls_certfile: Keyword.get(app_config, :tls_certfile, nil),
+      tls_keyfile: Keyword.get(app_config, :tls_keyfile, nil),
+      tls_cacertfile: Keyword.get(app_config, :tls_cacertfile, nil),
+      registration_token: Keyword.get(app_config, :registration_token, nil),
+      ldap: Keyword.get(app_config, :ldap, nil),
+      notifiers: Keyword.get(app_config, :notifiers, []),
+      syslog: Keyword.get(app_config, :syslog, nil),
+      storage: Keyword.get(app_config, :storage, nil),
+      slos: Keyword.get(app_config, :slos, []),
+      warmup: Keyword.get(app_config, :warmup, nil),
+      stale_factor: Keyword.get(app_config, :stale_factor, 3),
+      memory_budget_mb: Keyword.get(app_config, :memory_budget_mb, nil),
+      retention_days: Keyword.get(app_config, :retention_days, nil),
+      neighbor_interval: Keyword.get(app_config, :neighbor_interval, 300),
+      mesh_interval: Keyword.get(app_config, :mesh_interval, 60),
+      privacy_redact: Keyword.get(app_config, :privacy_redact, []),
+      alert_history_days: Keyword.get(app_config, :alert_history_days, 90),
+      process_rules: Keyword.get(app_config, :process_rules, []),
+      identification: Keyword.get(app_config, :identification, nil),
+      dashboards: Keyword.get(app_config, :dashboards, []),
+      burst_retention_hours: Keyword.get(app_config, :burst_retention_hours, 24)
     }
 
-    # Optionally merge from config.json if present
+    base
+    |> merge_config_json()
+    |> AgentlessMonitor.Bundles.overlay_installed()
+    |> Map.merge(AgentlessMonitor.Manifest.config_overrides())
+    |> Map.merge(Keyword.get(app_config, :cli_overrides, %{}))
+  end
+
+  # Optionally merge from config.json if present
+  defp merge_config_json(base) do
     case File.read("config.json") do
       {:ok, content} ->
         case Jason.decode(content) do
@@ -21,14 +97,79 @@ defmodule AgentlessMonitor.Config do
             %{
               base
               | server_port: Map.get(json, "server_port", base.server_port),
+                bind_address: Map.get(json, "bind_address", base.bind_address),
                 monitoring_interval:
                   Map.get(json, "monitoring_interval", base.monitoring_interval),
                 ping_timeout: Map.get(json, "ping_timeout", base.ping_timeout),
+                ping_targets: Map.get(json, "ping_targets", base.ping_targets),
+                ping_interval: Map.get(json, "ping_interval", base.ping_interval),
                 ssh_timeout: Map.get(json, "ssh_timeout", base.ssh_timeout),
+                collector_timeout: Map.get(json, "collector_timeout", base.collector_timeout),
+                single_shot: Map.get(json, "single_shot", base.single_shot),
+                custom_metrics: Map.get(json, "custom_metrics", base.custom_metrics),
+                collection_hooks: Map.get(json, "collection_hooks", base.collection_hooks),
+                collection_budget: Map.get(json, "collection_budget", base.collection_budget),
+                http_checks: Map.get(json, "http_checks", base.http_checks),
+                port_audit_ports: Map.get(json, "port_audit_ports", base.port_audit_ports),
+                ssh_backend: Map.get(json, "ssh_backend", base.ssh_backend),
+                ssh_pool: Map.get(json, "ssh_pool", base.ssh_pool),
+                restricted_mode: Map.get(json, "restricted_mode", base.restricted_mode),
+                restricted_commands:
+                  Map.get(json, "restricted_commands", base.restricted_commands),
                 fallback_password:
                   Map.get(json, "fallback_password", base.fallback_password),
                 ssh_config_path:
-                  Map.get(json, "ssh_config_path", base.ssh_config_path)
+                  Map.get(json, "ssh_config_path", base.ssh_config_path),
+                data_dir: Map.get(json, "data_dir", base.data_dir),
+                retired_retention_days:
+                  Map.get(json, "retired_retention_days", base.retired_retention_days),
+                chatops_signing_secret:
+                  Map.get(json, "chatops_signing_secret", base.chatops_signing_secret),
+                chatops_token: Map.get(json, "chatops_token", base.chatops_token),
+                chatops_allow_exec: Map.get(json, "chatops_allow_exec", base.chatops_allow_exec),
+                actions: Map.get(json, "actions", base.actions),
+                alert_capture: Map.get(json, "alert_capture", base.alert_capture),
+                approval_risk_level:
+                  Map.get(json, "approval_risk_level", base.approval_risk_level),
+                approval_ttl: Map.get(json, "approval_ttl", base.approval_ttl),
+                exec_risk: Map.get(json, "exec_risk", base.exec_risk),
+                thresholds: Map.merge(base.thresholds, Map.get(json, "thresholds", %{})),
+                groups: Map.get(json, "groups", base.groups),
+                health_weights: Map.get(json, "health_weights", base.health_weights),
+                sites: Map.get(json, "sites", base.sites),
+                display_timezone: Map.get(json, "display_timezone", base.display_timezone),
+                server_overrides: Map.get(json, "server_overrides", base.server_overrides),
+                profile: Map.get(json, "profile", base.profile),
+                profiles: Map.get(json, "profiles", base.profiles),
+                servers: Map.get(json, "servers", base.servers),
+                manifest_path: Map.get(json, "manifest_path", base.manifest_path),
+                config_reload_interval:
+                  Map.get(json, "config_reload_interval", base.config_reload_interval),
+                api_auth: Map.get(json, "api_auth", base.api_auth),
+                admin_token: Map.get(json, "admin_token", base.admin_token),
+                share_link_secret: Map.get(json, "share_link_secret", base.share_link_secret),
+                tls_certfile: Map.get(json, "tls_certfile", base.tls_certfile),
+                tls_keyfile: Map.get(json, "tls_keyfile", base.tls_keyfile),
+                tls_cacertfile: Map.get(json, "tls_cacertfile", base.tls_cacertfile),
+                registration_token: Map.get(json, "registration_token", base.registration_token),
+                ldap: Map.get(json, "ldap", base.ldap),
+                notifiers: Map.get(json, "notifiers", base.notifiers),
+                syslog: Map.get(json, "syslog", base.syslog),
+                storage: Map.get(json, "storage", base.storage),
+                slos: Map.get(json, "slos", base.slos),
+                warmup: Map.get(json, "warmup", base.warmup),
+                stale_factor: Map.get(json, "stale_factor", base.stale_factor),
+                memory_budget_mb: Map.get(json, "memory_budget_mb", base.memory_budget_mb),
+                retention_days: Map.get(json, "retention_days", base.retention_days),
+                neighbor_interval: Map.get(json, "neighbor_interval", base.neighbor_interval),
+                mesh_interval: Map.get(json, "mesh_interval", base.mesh_interval),
+                privacy_redact: Map.get(json, "privacy_redact", base.privacy_redact),
+                alert_history_days: Map.get(json, "alert_history_days", base.alert_history_days),
+                process_rules: Map.get(json, "process_rules", base.process_rules),
+                identification: Map.get(json, "identification", base.identification),
+                dashboards: Map.get(json, "dashboards", base.dashboards),
+                burst_retention_hours:
+                  Map.get(json, "burst_retention_hours", base.burst_retention_hours)
             }
 
           _ ->
@@ -39,4 +180,66 @@ defmodule AgentlessMonitor.Config do
         base
     end
   end
+
+  @secret_keys [
+    :fallback_password,
+    :chatops_signing_secret,
+    :chatops_token,
+    :admin_token,
+    :share_link_secret,
+    :registration_token,
+    :notifiers,
+    :storage
+  ]
+
+  @doc """
+  Renders the running configuration as a config.json document. Servers
+  (including those synthesized from the SSH config) are emitted as explicit
+  `servers` entries so the result can be used as-is; secrets are left out
+  unless `include_secrets` is set.
+  """
+  def export(config, servers, paused_ids, opts \\ []) do
+    include_secrets = Keyword.get(opts, :include_secrets, false)
+
+    settings =
+      config
+      |> Map.drop([:servers | if(include_secrets, do: [], else: @secret_keys)])
+      |> Map.new(fn {key, value} -> {Atom.to_string(key), value} end)
+
+    exported_servers =
+      servers
+      |> Enum.reject(&(&1.source == "local" or &1.status == "retired"))
+      |> Enum.sort_by(& &1.name)
+      |> Enum.map(fn server ->
+        %{
+          "id" => server.id,
+          "name" => server.name,
+          "host" => server.host,
+          "port" => server.port,
+          "username" => server.username,
+          "auth_method" => server.auth_method,
+          "group" => server.group,
+          "site" => server.site,
+          "monitoring_interval" => server.monitoring_interval,
+          "transport" => server.transport,
+          "command_prefix" => server.command_prefix,
+          "snmp" => export_snmp(server.snmp, include_secrets),
+          "proxy" => export_proxy(server.proxy_config, include_secrets),
+          "depends_on" => server.depends_on,
+          "watched_units" => server.watched_units,
+          "identity_files" => server.identity_files,
+          "paused" => MapSet.member?(paused_ids, server.id)
+        }
+      end)
+
+    Map.put(settings, "servers", exported_servers)
+  end
+
+  defp export_proxy(%{} = proxy, false), do: Map.delete(proxy, "password")
+  defp export_proxy(proxy, _include_secrets), do: proxy
+
+  defp export_snmp(%{} = snmp, false),
+    do: Map.drop(snmp, ["community", "auth_password", "priv_password"])
+
+  defp export_snmp(snmp, _include_secrets), do: snmp
 end
@@ -0,0 +1,185 @@
+defmodule AgentlessMonitor.DataFile do
+  @moduledoc """
+  Crash-safe files in the data directory.
+
+  Files are written to a temporary path and renamed into place, so a crash
+  leaves either the old or the new version. History archives are split into
+  segments that each carry a SHA-256 checksum:
+
+      "AMH1" <> (<<size::32, sha256::binary-32>> <> payload)*
+
+  On load, segments that fail their checksum (or are cut short) are moved to
+  `<data_dir>/quarantine` and skipped, so one bad write loses a batch of
+  samples instead of poisoning the whole history. `fsck/2` verifies every
+  file in the data directory and can repair what it finds.
+  """
+
+  require Logger
+
+  alias AgentlessMonitor.Config
+
+  @magic "AMH1"
+
+  @doc "Writes `content` atomically"
+  def write_atomic(path, content) do
+    tmp = path <> ".tmp"
+
+    with :ok <- File.mkdir_p(Path.dirname(path)),
+         :ok <- File.write(tmp, content, [:sync]) do
+      File.rename(tmp, path)
+    end
+  end
+
+  @doc "Writes each batch of terms as a checksummed segment"
+  def write_segments(path, batches) do
+    segments =
+      Enum.map(batches, fn batch ->
+        payload = :erlang.term_to_binary(batch)
+        <<byte_size(payload)::32, :crypto.hash(:sha256, payload)::binary, payload::binary>>
+      end)
+
+    write_atomic(path, [@magic | segments])
+  end
+
+  @doc """
+  Reads a segmented file, returning `{:ok, batches, bad}` where `bad` counts
+  the segments that were quarantined. Files written before checksums were
+  introduced are read as a single unchecked term.
+  """
+  def read_segments(path) do
+    with {:ok, binary} <- File.read(path) do
+      {batches, bad} = decode(binary)
+      Enum.each(bad, fn {label, data} -> quarantine(path, label, data) end)
+      {:ok, batches, length(bad)}
+    end
+  end
+
+  @doc """
+  Verifies the data directory. Returns one report per file with problems;
+  with `repair: true`, corrupt segments are quarantined and the file is
+  rewritten with the intact ones, unreadable JSON files are quarantined and
+  stale temporary files are removed.
+  """
+  def fsck(data_dir \\ Config.load().data_dir, opts \\ []) do
+    repair = Keyword.get(opts, :repair, false)
+
+    data_dir
+    |> Path.join("**/*")
+    |> Path.wildcard()
+    |> Enum.reject(&(File.dir?(&1) or String.contains?(&1, "/quarantine/")))
+    |> Enum.flat_map(&check(&1, repair))
+  end
+
+  # ---- Private helpers ----
+
+  defp check(path, repair) do
+    case Path.extname(path) do
+      ".tmp" ->
+        if repair, do: File.rm(path)
+        [report(path, "stale temporary file", repair)]
+
+      ".json" ->
+        check_json(path, repair)
+
+      ".etf" ->
+        check_segments(path, repair)
+
+      _ ->
+        []
+    end
+  end
+
+  defp check_json(path, repair) do
+    with {:ok, content} <- File.read(path),
+         {:ok, _} <- Jason.decode(content) do
+      []
+    else
+      _ ->
+        if repair do
+          quarantine(path, "json", File.read!(path))
+          File.rm(path)
+        end
+
+        [report(path, "not valid JSON", repair)]
+    end
+  end
+
+  defp check_segments(path, repair) do
+    case File.read(path) do
+      {:ok, binary} ->
+        case decode(binary) do
+          {_batches, []} ->
+            []
+
+          {batches, bad} ->
+            if repair do
+              Enum.each(bad, fn {label, data} -> quarantine(path, label, data) end)
+              write_segments(path, batches)
+            end
+
+            [report(path, "#{length(bad)} corrupt segment(s)", repair)]
+        end
+
+      {:error, reason} ->
+        [report(path, "unreadable: #{inspect(reason)}", false)]
+    end
+  end
+
+  defp report(path, problem, repaired) do
+    %{"path" => path, "problem" => problem, "repaired" => repaired}
+  end
+
+  defp decode(<<@magic, rest::binary>>), do: decode_segments(rest, 0, [], [])
+
+  defp decode(binary) do
+    case term(binary) do
+      {:ok, term} -> {[term], []}
+      :error -> {[], [{"legacy", binary}]}
+    end
+  end
+
+  defp decode_segments(<<>>, _index, batches, bad) do
+    {Enum.reverse(batches), Enum.reverse(bad)}
+  end
+
+  defp decode_segments(
+         <<size::32, checksum::binary-32, payload::binary-size(size), rest::binary>>,
+         index,
+         batches,
+         bad
+       ) do
+    with true <- :crypto.hash(:sha256, payload) == checksum,
+         {:ok, batch} <- term(payload) do
+      decode_segments(rest, index + 1, [batch | batches], bad)
+    else
+      _ -> decode_segments(rest, index + 1, batches, [{"segment#{index}", payload} | bad])
+    end
+  end
+
+  # A truncated tail: everything from here on is unusable.
+  defp decode_segments(rest, index, batches, bad) do
+    {Enum.reverse(batches), Enum.reverse([{"segment#{index}-truncated", rest} | bad])}
+  end
+
+  # `:safe` refuses terms that would create atoms or funs, so a tampered
+  # file in the data directory can't exhaust the atom table.
+  defp term(binary) do
+    {:ok, :erlang.binary_to_term(binary, [:safe])}
+  rescue
+    ArgumentError -> :error
+  end
+
+  defp quarantine(path, label, data) do
+    dir = Path.join(Config.load().data_dir, "quarantine")
+    stamp = System.system_time(:second)
+    target = Path.join(dir, "#{Path.basename(path)}.#{stamp}.#{label}")
+
+    with :ok <- File.mkdir_p(dir),
+         :ok <- File.write(target, data) do
+      Logger.warning("Quarantined corrupt data from #{path} to #{target}")
+    else
+      {:error, reason} ->
+        Logger.error("Failed to quarantine corrupt data from #{path}: #{inspect(reason)}")
+    end
+  end
+end
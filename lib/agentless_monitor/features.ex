@@ -0,0 +1,25 @@
+defmodule AgentlessMonitor.Features do
+  @moduledoc """
+  Optional subsystems, chosen at build time with `AGENTLESS_FEATURES`:
+
+    * `web_ui` - the dashboard and its static assets
+    * `notifications` - notification channels (`notifiers`)
+    * `storage` - the PostgreSQL/TimescaleDB and SQLite backends (and the Postgrex
+      and Exqlite dependencies)
+    * `chatops` - Slack and Mattermost slash commands
+    * `ldap` - LDAP/Active Directory authentication
+
+  All are enabled by default. `AGENTLESS_FEATURES=none` builds a headless
+  collector that only serves the JSON API; a comma-separated list such as
+  `notifications,storage` enables just those.
+  """
+
+  @all [:web_ui, :notifications, :storage, :chatops, :ldap]
+  @enabled Application.compile_env(:agentless_monitor, :features, @all)
+
+  def all, do: @all
+
+  def enabled, do: @enabled
+
+  def enabled?(feature), do: feature in @enabled
+end
@@ -0,0 +1,87 @@
+defmodule AgentlessMonitor.Idempotency do
+  @moduledoc """
+  Remembers the responses to requests sent with an `Idempotency-Key`
+  header, so a client retrying over a flaky network gets the first response
+  back instead of repeating the work (registering a machine twice, starting
+  a second import of the same history).
+
+  A key is claimed when its request starts and its response kept for
+  24 hours once it succeeds. A retry arriving while the first request is
+  still running is told to wait (for up to five minutes, in case it never
+  finishes); a failed request releases the key so it can be retried.
+  """
+
+  use GenServer
+
+  @ttl_seconds 24 * 3600
+  @pending_seconds 300
+  @sweep_interval :timer.minutes(10)
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, nil, name: __MODULE__)
+  end
+
+  @doc """
+  Claims `key`. Returns `:new` for a first request, `{:done, status, body}`
+  with the stored response of a finished one, or `:pending`.
+  """
+  def claim(key) do
+    GenServer.call(__MODULE__, {:claim, key})
+  end
+
+  @doc "Stores the response of a claimed key; responses other than 2xx release it"
+  def complete(key, status, body) do
+    GenServer.call(__MODULE__, {:complete, key, status, body})
+  end
+
+  # ---- Callbacks ----
+
+  def init(_) do
+    schedule_sweep()
+    {:ok, %{entries: %{}}}
+  end
+
+  def handle_call({:claim, key}, _from, state) do
+    now = System.monotonic_time(:second)
+
+    case Map.get(state.entries, key) do
+      {:done, status, body, expires_at} when expires_at > now ->
+        {:reply, {:done, status, body}, state}
+
+      {:pending, expires_at} when expires_at > now ->
+        {:reply, :pending, state}
+
+      _ ->
+        {:reply, :new, put_in(state.entries[key], {:pending, now + @pending_seconds})}
+    end
+  end
+
+  def handle_call({:complete, key, status, body}, _from, state) when status in 200..299 do
+    expires_at = System.monotonic_time(:second) + @ttl_seconds
+    {:reply, :ok, put_in(state.entries[key], {:done, status, body, expires_at})}
+  end
+
+  def handle_call({:complete, key, _status, _body}, _from, state) do
+    {:reply, :ok, %{state | entries: Map.delete(state.entries, key)}}
+  end
+
+  def handle_info(:sweep, state) do
+    now = System.monotonic_time(:second)
+
+    entries =
+      state.entries
+      |> Enum.filter(fn {_key, entry} -> elem(entry, tuple_size(entry) - 1) > now end)
+      |> Map.new()
+
+    schedule_sweep()
+    {:noreply, %{state | entries: entries}}
+  end
+
+  # ---- Private helpers ----
+
+  defp schedule_sweep do
+    Process.send_after(self(), :sweep, @sweep_interval)
+  end
+end
@@ -0,0 +1,78 @@
+defmodule AgentlessMonitor.Importer do
+  @moduledoc """
+  Backfills a server's history in the storage backend from another tool's
+  data, so charts don't start from zero after switching.
+
+  A format is a module implementing `parse/1`, which turns the uploaded
+  `data` into samples: `{unix_seconds, fields}` where `fields` holds the
+  `MonitoringData` sections it could map (`cpu`, `memory`, `disks`,
+  `network`). The built-in formats are `prometheus`
+  (`AgentlessMonitor.Importers.Prometheus`) and `collectd`
+  (`AgentlessMonitor.Importers.Collectd`).
+
+  Imported samples are marked with `"imported_from"` and only go to the
+  storage backend; the in-memory history and alerting are left alone.
+  """
+
+  alias AgentlessMonitor.Storage
+  alias AgentlessMonitor.Models.MonitoringData
+
+  @callback parse(data :: term()) ::
+              {:ok, [{integer(), map()}]} | {:error, String.t()}
+
+  @formats %{
+    "prometheus" => AgentlessMonitor.Importers.Prometheus,
+    "collectd" => AgentlessMonitor.Importers.Collectd
+  }
+
+  def formats, do: @formats |> Map.keys() |> Enum.sort()
+
+  @doc "Checks an import request before it is started"
+  def validate(format, data) do
+    cond do
+      not Map.has_key?(@formats, format) ->
+        {:error, "format must be one of: #{Enum.join(formats(), ", ")}"}
+
+      is_nil(data) ->
+        {:error, "data is required"}
+
+      not Storage.persistent?() ->
+        {:error, "importing needs a storage backend (storage.type sqlite or postgres)"}
+
+      true ->
+        :ok
+    end
+  end
+
+  @doc """
+  Parses `data` and writes the samples for `server`. Returns `{:ok, summary}`
+  with the number of samples and the time range they cover.
+  """
+  def run(server, format, data) do
+    with {:ok, points} <- Map.fetch!(@formats, format).parse(data),
+         samples = Enum.map(points, &to_sample(server, format, &1)),
+         :ok <- Storage.write_all(:sample, samples) do
+      timestamps = Enum.map(points, &elem(&1, 0))
+
+      {:ok,
+       %{
+         "samples" => length(samples),
+         "from" => timestamps |> Enum.min(fn -> nil end) |> iso8601(),
+         "to" => timestamps |> Enum.max(fn -> nil end) |> iso8601()
+       }}
+    end
+  end
+
+  # ---- Private helpers ----
+
+  defp to_sample(server, format, {timestamp, fields}) do
+    MonitoringData
+    |> struct(fields)
+    |> Map.merge(%{server_id: server.id, timestamp: iso8601(timestamp)})
+    |> MonitoringData.to_map()
+    |> Map.put("imported_from", format)
+  end
+
+  defp iso8601(nil), do: nil
+  defp iso8601(unix), do: unix |> DateTime.from_unix!() |> DateTime.to_iso8601()
+end
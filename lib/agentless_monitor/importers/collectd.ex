@@ -0,0 +1,250 @@
+defmodule AgentlessMonitor.Importers.Collectd do
+  @moduledoc """
+  Imports collectd data: files written by the `csv` plugin, or the output
+  of `rrdtool fetch <file>.rrd AVERAGE` for the `rrdtool` plugin, keyed by
+  their `<plugin>/<type>` path below the host directory:
+
+      {"step": 60,
+       "files": {"load/load": "epoch,shortterm,midterm,longterm\\n...",
+                 "memory/memory-used": "...",
+                 "df-root/df_complex-free": "..."}}
+
+  Mapped files: `load/load`, `cpu[-N]/percent-idle` (the cpu plugin with
+  `ValuesPercentage`), `memory/memory-*`, `swap/swap-*`,
+  `df-<mount>/df_complex-*` and `interface-<dev>/if_{octets,packets,errors}`.
+  Points are merged into `step`-second buckets (default 60). Interface
+  rates are summed up into counters that start at zero with the import.
+  """
+
+  @behaviour AgentlessMonitor.Importer
+
+  alias AgentlessMonitor.Models.{CpuInfo, DiskInfo, MemoryInfo, NetworkInfo}
+
+  @interface_fields %{
+    "if_octets" => {:rx_bytes, :tx_bytes},
+    "if_packets" => {:rx_packets, :tx_packets},
+    "if_errors" => {:rx_errors, :tx_errors}
+  }
+
+  @impl true
+  def parse(%{"files" => files} = data) when is_map(files) do
+    step = Map.get(data, "step", 60)
+
+    with :ok <- validate_step(step),
+         {:ok, tables} <- tables(files) do
+      buckets =
+        tables
+        |> Enum.flat_map(fn {key, rows} ->
+          Enum.map(rows, fn {timestamp, values} -> {div(timestamp, step) * step, key, values} end)
+        end)
+        |> Enum.group_by(&elem(&1, 0), fn {_t, key, values} -> {key, values} end)
+        |> Enum.sort_by(&elem(&1, 0))
+
+      {samples, _state} =
+        Enum.map_reduce(buckets, {nil, %{}}, fn {timestamp, observations}, {previous, counters} ->
+          observations = Map.new(observations)
+          elapsed = if previous, do: timestamp - previous, else: 0
+          {network, counters} = network(observations, counters, elapsed)
+
+          fields =
+            %{}
+            |> put_cpu(observations)
+            |> put_memory(observations)
+            |> put_disks(observations)
+
+          fields = if network == [], do: fields, else: Map.put(fields, :network, network)
+
+          {{timestamp, fields}, {timestamp, counters}}
+        end)
+
+      {:ok, Enum.reject(samples, fn {_timestamp, fields} -> fields == %{} end)}
+    end
+  end
+
+  def parse(_data), do: {:error, "expected {\"files\": {\"<plugin>/<type>\": contents}}"}
+
+  # ---- Private helpers ----
+
+  defp validate_step(step) when is_integer(step) and step > 0, do: :ok
+  defp validate_step(_step), do: {:error, "step must be a positive number of seconds"}
+
+  defp tables(files) do
+    Enum.reduce_while(files, {:ok, []}, fn {key, content}, {:ok, acc} ->
+      case table(content) do
+        {:ok, rows} -> {:cont, {:ok, [{key, rows} | acc]}}
+        {:error, message} -> {:halt, {:error, "#{key}: #{message}"}}
+      end
+    end)
+  end
+
+  # Rows as `{unix_seconds, %{column => value}}`; unknown values are dropped.
+  defp table(content) when is_binary(content) do
+    case String.split(content, ~r/\r?\n/, trim: true) do
+      ["epoch," <> columns | rows] ->
+        {:ok, rows(String.split(columns, ","), rows, &String.split(&1, ",", parts: 2))}
+
+      [header | rows] ->
+        {:ok, rows(String.split(header), rows, &String.split(&1, ":", parts: 2))}
+
+      [] ->
+        {:ok, []}
+    end
+  end
+
+  defp table(_content), do: {:error, "contents must be a string"}
+
+  defp rows(columns, lines, split) do
+    Enum.flat_map(lines, fn line ->
+      with [time, rest] <- split.(line),
+           {timestamp, _} <- Float.parse(String.trim(time)) do
+        values =
+          rest
+          |> String.split([",", " "], trim: true)
+          |> Enum.map(&number/1)
+          |> then(&Enum.zip(columns, &1))
+          |> Enum.reject(fn {_column, value} -> is_nil(value) end)
+          |> Map.new()
+
+        [{trunc(timestamp), values}]
+      else
+        _ -> []
+      end
+    end)
+  end
+
+  defp number(text) do
+    case Float.parse(text) do
+      {value, ""} -> value
+      _ -> nil
+    end
+  end
+
+  defp put_cpu(fields, observations) do
+    load = observations["load/load"]
+
+    idle =
+      for {key, %{"value" => value}} <- observations,
+          Regex.match?(~r{^cpu(-\d+)?/percent-idle$}, key),
+          do: value
+
+    if load || idle != [] do
+      cpu = %CpuInfo{
+        usage_percent: if(idle == [], do: 0.0, else: Float.round(100 - average(idle), 1)),
+        load_average: Enum.map(["shortterm", "midterm", "longterm"], &((load || %{})[&1] || 0.0)),
+        cores: max(length(idle), 1)
+      }
+
+      Map.put(fields, :cpu, cpu)
+    else
+      fields
+    end
+  end
+
+  defp put_memory(fields, observations) do
+    memory = instances(observations, ~r{^memory/memory-(.+)$})
+    swap = instances(observations, ~r{^swap/swap-(.+)$})
+
+    if memory == %{} do
+      fields
+    else
+      bytes = &round(Map.get(&1, &2, 0))
+      free = bytes.(memory, "free")
+      swap_used = bytes.(swap, "used")
+      swap_free = bytes.(swap, "free")
+
+      info = %MemoryInfo{
+        total: memory |> Map.values() |> Enum.sum() |> round(),
+        used: bytes.(memory, "used"),
+        free: free,
+        available: free + bytes.(memory, "buffered") + bytes.(memory, "cached"),
+        swap_total: swap_used + swap_free + bytes.(swap, "cached"),
+        swap_used: swap_used,
+        swap_free: swap_free
+      }
+
+      Map.put(fields, :memory, info)
+    end
+  end
+
+  defp put_disks(fields, observations) do
+    disks =
+      observations
+      |> Enum.flat_map(fn {key, values} ->
+        case Regex.run(~r{^df-(.+)/df_complex-(.+)$}, key) do
+          [_, mount, kind] -> [{mount, kind, Map.get(values, "value", 0)}]
+          nil -> []
+        end
+      end)
+      |> Enum.group_by(&elem(&1, 0), &{elem(&1, 1), elem(&1, 2)})
+      |> Enum.sort()
+      |> Enum.map(fn {mount, kinds} ->
+        kinds = Map.new(kinds)
+        used = round(Map.get(kinds, "used", 0))
+        free = round(Map.get(kinds, "free", 0))
+
+        %DiskInfo{
+          device: mount,
+          mount_point: mount_point(mount),
+          total: used + free + round(Map.get(kinds, "reserved", 0)),
+          used: used,
+          free: free,
+          usage_percent: percent(used, used + free)
+        }
+      end)
+
+    if disks == [], do: fields, else: Map.put(fields, :disks, disks)
+  end
+
+  # collectd names df instances after the mount point with `/` as `-`.
+  defp mount_point("root"), do: "/"
+  defp mount_point(name), do: "/" <> String.replace(name, "-", "/")
+
+  defp network(observations, counters, elapsed) do
+    rates =
+      Enum.flat_map(observations, fn {key, values} ->
+        with [_, device, type] <- Regex.run(~r{^interface-(.+)/(if_\w+)$}, key),
+             {rx_field, tx_field} <- Map.get(@interface_fields, type) do
+          [{device, rx_field, values["rx"]}, {device, tx_field, values["tx"]}]
+        else
+          _ -> []
+        end
+      end)
+      |> Enum.reject(fn {device, _field, rate} -> is_nil(rate) or device == "lo" end)
+
+    counters =
+      Enum.reduce(rates, counters, fn {device, field, rate}, acc ->
+        Map.update(acc, {device, field}, 0, &(&1 + rate * elapsed))
+      end)
+
+    network =
+      rates
+      |> Enum.map(&elem(&1, 0))
+      |> Enum.uniq()
+      |> Enum.sort()
+      |> Enum.map(fn device ->
+        @interface_fields
+        |> Map.values()
+        |> Enum.reduce(%NetworkInfo{interface: device}, fn {rx, tx}, acc ->
+          acc
+          |> Map.put(rx, round(Map.get(counters, {device, rx}, 0)))
+          |> Map.put(tx, round(Map.get(counters, {device, tx}, 0)))
+        end)
+      end)
+
+    {network, counters}
+  end
+
+  defp instances(observations, pattern) do
+    Enum.reduce(observations, %{}, fn {key, values}, acc ->
+      case Regex.run(pattern, key) do
+        [_, instance] -> Map.put(acc, instance, Map.get(values, "value", 0))
+        nil -> acc
+      end
+    end)
+  end
+
+  defp average(values), do: Enum.sum(values) / length(values)
+
+  defp percent(_part, 0), do: 0.0
+  defp percent(part, whole), do: Float.round(part / whole * 100, 1)
+end
@@ -0,0 +1,209 @@
+defmodule AgentlessMonitor.Importers.Prometheus do
+  @moduledoc """
+  Imports node_exporter metrics from Prometheus range query results
+  (`/api/v1/query_range` responses, a list of them, or just their
+  `result` arrays), for example:
+
+      curl -G http://prometheus:9090/api/v1/query_range \\
+        --data-urlencode 'query={instance="web-01:9100",__name__=~"node_.+"}' \\
+        --data-urlencode start=2024-01-01T00:00:00Z \\
+        --data-urlencode end=2024-01-31T00:00:00Z --data-urlencode step=300
+
+  Mapped series: `node_load1/5/15`, `node_cpu_seconds_total` (usage from
+  the idle share between consecutive points), `node_memory_*_bytes`,
+  `node_filesystem_{size,free,avail}_bytes` and
+  `node_network_{receive,transmit}_{bytes,packets,errs}_total`. Other
+  series are ignored.
+  """
+
+  @behaviour AgentlessMonitor.Importer
+
+  alias AgentlessMonitor.Models.{CpuInfo, DiskInfo, MemoryInfo, NetworkInfo}
+
+  @network %{
+    "node_network_receive_bytes_total" => :rx_bytes,
+    "node_network_transmit_bytes_total" => :tx_bytes,
+    "node_network_receive_packets_total" => :rx_packets,
+    "node_network_transmit_packets_total" => :tx_packets,
+    "node_network_receive_errs_total" => :rx_errors,
+    "node_network_transmit_errs_total" => :tx_errors
+  }
+
+  @impl true
+  def parse(data) do
+    with {:ok, series} <- series(data) do
+      points =
+        Enum.flat_map(series, fn %{"metric" => metric, "values" => values} ->
+          {name, labels} = Map.pop(metric, "__name__")
+
+          Enum.flat_map(values, fn [timestamp, value] ->
+            case Float.parse(to_string(value)) do
+              {number, ""} -> [{trunc(timestamp), name, labels, number}]
+              _ -> []
+            end
+          end)
+        end)
+
+      by_time =
+        Enum.group_by(points, &elem(&1, 0), fn {_t, name, labels, value} ->
+          {name, labels, value}
+        end)
+
+      cpu = cpu_usage(by_time)
+
+      samples =
+        by_time
+        |> Enum.sort_by(&elem(&1, 0))
+        |> Enum.map(fn {timestamp, points} ->
+          {timestamp, sample(points, Map.get(cpu, timestamp))}
+        end)
+        |> Enum.reject(fn {_timestamp, fields} -> fields == %{} end)
+
+      {:ok, samples}
+    end
+  end
+
+  # ---- Private helpers ----
+
+  defp series(%{"data" => %{"resultType" => "matrix", "result" => result}}), do: series(result)
+
+  defp series(%{"data" => %{"resultType" => type}}),
+    do: {:error, "expected a range query (matrix) result, got #{type}"}
+
+  defp series(list) when is_list(list) do
+    Enum.reduce_while(list, {:ok, []}, fn
+      %{"metric" => %{}, "values" => values} = entry, {:ok, acc} when is_list(values) ->
+        {:cont, {:ok, [entry | acc]}}
+
+      %{"data" => _} = response, {:ok, acc} ->
+        case series(response) do
+          {:ok, more} -> {:cont, {:ok, more ++ acc}}
+          error -> {:halt, error}
+        end
+
+      _other, _acc ->
+        {:halt, {:error, "unrecognized Prometheus series"}}
+    end)
+  end
+
+  defp series(_data), do: {:error, "expected a Prometheus query_range response"}
+
+  defp sample(points, cpu_usage) do
+    values = Map.new(points, fn {name, labels, value} -> {{name, labels}, value} end)
+    plain = fn name -> Enum.find_value(points, fn {n, _l, v} -> if n == name, do: v end) end
+
+    %{}
+    |> put_cpu(plain, points, cpu_usage)
+    |> put_memory(plain)
+    |> put_disks(points, values)
+    |> put_network(points)
+  end
+
+  defp put_cpu(fields, plain, points, usage) do
+    loads = Enum.map(["node_load1", "node_load5", "node_load15"], plain)
+    cores = points |> cpu_labels() |> Enum.uniq() |> length()
+
+    if usage || Enum.any?(loads) do
+      cpu = %CpuInfo{
+        usage_percent: usage || 0.0,
+        load_average: Enum.map(loads, &(&1 || 0.0)),
+        cores: max(cores, 1)
+      }
+
+      Map.put(fields, :cpu, cpu)
+    else
+      fields
+    end
+  end
+
+  defp cpu_labels(points) do
+    for {"node_cpu_seconds_total", %{"cpu" => cpu}, _value} <- points, do: cpu
+  end
+
+  defp put_memory(fields, plain) do
+    case plain.("node_memory_MemTotal_bytes") do
+      nil ->
+        fields
+
+      total ->
+        bytes = &round(plain.(&1) || 0)
+        free = bytes.("node_memory_MemFree_bytes")
+        cached = bytes.("node_memory_Cached_bytes") + bytes.("node_memory_Buffers_bytes")
+        swap_total = bytes.("node_memory_SwapTotal_bytes")
+        swap_free = bytes.("node_memory_SwapFree_bytes")
+
+        memory = %MemoryInfo{
+          total: round(total),
+          used: max(0, round(total) - free - cached),
+          free: free,
+          available: bytes.("node_memory_MemAvailable_bytes"),
+          swap_total: swap_total,
+          swap_used: max(0, swap_total - swap_free),
+          swap_free: swap_free
+        }
+
+        Map.put(fields, :memory, memory)
+    end
+  end
+
+  # Like df: used = size - free, and the percentage is of what a user can
+  # fill (used + avail).
+  defp put_disks(fields, points, values) do
+    disks =
+      for {"node_filesystem_size_bytes", labels, size} <- points do
+        free = Map.get(values, {"node_filesystem_free_bytes", labels}, size)
+        avail = Map.get(values, {"node_filesystem_avail_bytes", labels}, free)
+        used = round(size - free)
+
+        %DiskInfo{
+          device: labels["device"] || "",
+          mount_point: labels["mountpoint"] || "",
+          filesystem: labels["fstype"] || "",
+          total: round(size),
+          used: used,
+          free: round(avail),
+          usage_percent: percent(used, used + avail)
+        }
+      end
+
+    if disks == [], do: fields, else: Map.put(fields, :disks, disks)
+  end
+
+  defp put_network(fields, points) do
+    network =
+      points
+      |> Enum.filter(fn {name, labels, _v} ->
+        Map.has_key?(@network, name) and labels["device"] not in [nil, "lo"]
+      end)
+      |> Enum.group_by(fn {_name, labels, _v} -> labels["device"] end)
+      |> Enum.sort()
+      |> Enum.map(fn {device, counters} ->
+        Enum.reduce(counters, %NetworkInfo{interface: device}, fn {name, _l, value}, acc ->
+          Map.put(acc, @network[name], round(value))
+        end)
+      end)
+
+    if network == [], do: fields, else: Map.put(fields, :network, network)
+  end
+
+  # Usage at each point from the CPU seconds spent since the previous one.
+  defp cpu_usage(by_time) do
+    by_time
+    |> Enum.sort_by(&elem(&1, 0))
+    |> Enum.map(fn {timestamp, points} ->
+      seconds =
+        for {"node_cpu_seconds_total", %{"mode" => mode}, value} <- points, do: {mode, value}
+
+      idle = for {"idle", value} <- seconds, reduce: 0, do: (sum -> sum + value)
+      {timestamp, idle, seconds |> Enum.map(&elem(&1, 1)) |> Enum.sum()}
+    end)
+    |> Enum.chunk_every(2, 1, :discard)
+    |> Map.new(fn [{_t0, idle0, total0}, {t1, idle1, total1}] ->
+      busy = total1 - total0 - (idle1 - idle0)
+      {t1, if(total1 > total0, do: percent(busy, total1 - total0))}
+    end)
+  end
+
+  defp percent(_part, whole) when whole <= 0, do: 0.0
+  defp percent(part, whole), do: Float.round(part / whole * 100, 1)
+end
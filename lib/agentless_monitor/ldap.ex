@@ -0,0 +1,161 @@
+defmodule AgentlessMonitor.LDAP do
+  @moduledoc """
+  LDAP / Active Directory authentication for the API.
+
+  Enabled by an `ldap` config map. Users authenticate with HTTP Basic
+  credentials: the password is verified by binding as `user_dn` (with `%s`
+  replaced by the username), then the user's `group_attribute` values are
+  mapped to token scopes through `role_mapping`:
+
+      "ldap": {
+        "host": "dc01.corp.example.com", "port": 636, "ssl": true,
+        "user_dn": "%s@corp.example.com",
+        "base_dn": "DC=corp,DC=example,DC=com",
+        "user_attribute": "sAMAccountName",
+        "group_attribute": "memberOf",
+        "role_mapping": {
+          "CN=Ops,OU=Groups,DC=corp,DC=example,DC=com": ["admin"],
+          "CN=Dev,OU=Groups,DC=corp,DC=example,DC=com": ["read:metrics"]
+        }
+      }
+
+  With `ssl` the server's certificate is verified against the system CAs;
+  `tls` takes the same entry as the HTTP notification channels (`ca_file`
+  for a private CA, a client certificate, `"verify": false`; see
+  `AgentlessMonitor.Notifier`).
+
+  Successful logins are cached for `@cache_ttl` so dashboards polling the API
+  don't bind on every request.
+  """
+
+  use GenServer
+  require Logger
+
+  alias AgentlessMonitor.{Config, Features, Notifier}
+
+  @cache_table :ldap_auth_cache
+  @cache_ttl 60
+  @timeout 5_000
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, nil, name: __MODULE__)
+  end
+
+  def enabled?, do: Features.enabled?(:ldap) and is_map(Config.load().ldap)
+
+  @doc """
+  Verifies the credentials and returns `{:ok, token}` where `token` has the
+  same shape as an API token (`"id"`, `"name"`, `"scopes"`).
+  """
+  def authenticate(username, password) do
+    config = Config.load().ldap
+    key = :crypto.hash(:sha256, [username, 0, password])
+    now = System.system_time(:second)
+
+    case :ets.lookup(@cache_table, key) do
+      [{^key, token, expires_at}] when expires_at > now ->
+        {:ok, token}
+
+      _ ->
+        with :ok <- validate_credentials(username, password),
+             {:ok, groups} <- bind_and_fetch_groups(config, username, password) do
+          token = %{
+            "id" => "ldap:#{username}",
+            "name" => username,
+            "scopes" => scopes_for(config, groups)
+          }
+
+          :ets.insert(@cache_table, {key, token, now + @cache_ttl})
+          {:ok, token}
+        end
+    end
+  end
+
+  # ---- Callbacks ----
+
+  def init(_) do
+    :ets.new(@cache_table, [:named_table, :public, :set])
+    {:ok, nil}
+  end
+
+  # ---- Private helpers ----
+
+  # An empty password would be an unauthenticated bind, which most servers
+  # accept; DN metacharacters would let the username change the bind DN.
+  defp validate_credentials(username, password) do
+    cond do
+      password == "" -> {:error, :invalid}
+      username == "" or String.match?(username, ~r/[,=+<>#;\\"\x00]/) -> {:error, :invalid}
+      true -> :ok
+    end
+  end
+
+  defp bind_and_fetch_groups(config, username, password) do
+    host = config |> Map.fetch!("host") |> String.to_charlist()
+    ssl = Map.get(config, "ssl", false)
+    port = Map.get(config, "port", if(ssl, do: 636, else: 389))
+
+    sslopts = Notifier.ssl_options(config["tls"] || %{})
+
+    case :eldap.open([host], port: port, ssl: ssl, sslopts: sslopts, timeout: @timeout) do
+      {:ok, handle} ->
+        try do
+          bind_dn =
+            config |> Map.fetch!("user_dn") |> String.replace("%s", username) |> String.to_charlist()
+
+          case :eldap.simple_bind(handle, bind_dn, String.to_charlist(password)) do
+            :ok ->
+              {:ok, fetch_groups(handle, config, username)}
+
+            {:error, reason} ->
+              Logger.info("LDAP bind failed for #{username}: #{inspect(reason)}")
+              {:error, :invalid}
+          end
+        after
+          :eldap.close(handle)
+        end
+
+      {:error, reason} ->
+        Logger.error("Cannot reach LDAP server #{config["host"]}: #{inspect(reason)}")
+        {:error, :unavailable}
+    end
+  end
+
+  defp fetch_groups(handle, config, username) do
+    group_attribute = config |> Map.get("group_attribute", "memberOf") |> String.to_charlist()
+    user_attribute = config |> Map.get("user_attribute", "uid") |> String.to_charlist()
+
+    search = [
+      base: config |> Map.get("base_dn", "") |> String.to_charlist(),
+      scope: :eldap.wholeSubtree(),
+      filter: :eldap.equalityMatch(user_attribute, String.to_charlist(username)),
+      attributes: [group_attribute]
+    ]
+
+    # The search result record gained a controls field in newer OTP releases,
+    # so only its entries element is matched.
+    with {:ok, result} <- :eldap.search(handle, search),
+         [{:eldap_entry, _dn, attributes} | _] <- elem(result, 1) do
+      attributes
+      |> Enum.flat_map(fn {_name, values} -> values end)
+      |> Enum.map(&to_string/1)
+    else
+      other ->
+        Logger.warning("LDAP group lookup for #{username} returned #{inspect(other)}")
+        []
+    end
+  end
+
+  # Group DNs are compared case-insensitively, as LDAP does.
+  defp scopes_for(config, groups) do
+    groups = MapSet.new(groups, &String.downcase/1)
+
+    config
+    |> Map.get("role_mapping", %{})
+    |> Enum.filter(fn {group, _scopes} -> MapSet.member?(groups, String.downcase(group)) end)
+    |> Enum.flat_map(fn {_group, scopes} -> List.wrap(scopes) end)
+    |> Enum.uniq()
+  end
+end
@@ -0,0 +1,217 @@
+defmodule AgentlessMonitor.Manifest do
+  @moduledoc """
+  Declarative desired-state mode.
+
+  When `manifest_path` points at a YAML manifest, it becomes the source of
+  truth for servers, groups, actions and thresholds:
+
+      allow_runtime_edits: false
+      servers:
+        - name: web-01
+          host: 10.0.0.5
+          username: deploy
+          group: prod
+      groups:
+        prod:
+          monitoring_interval: 15
+      thresholds:
+        cpu_percent: 80
+
+  The manifest is reconciled into `AgentlessMonitor.State` on startup and
+  whenever the file changes. Groups, actions and thresholds are overlaid on
+  `AgentlessMonitor.Config.load/0`. Differences between the manifest and the
+  running state are reported by `drift/0`.
+  """
+
+  use GenServer
+  require Logger
+
+  alias AgentlessMonitor.{Config, State}
+  alias AgentlessMonitor.Alerting.Actions
+
+  @check_interval :timer.seconds(10)
+  @overrides_key {__MODULE__, :config}
+  @edits_key {__MODULE__, :allow_runtime_edits}
+  @compared_fields [
+    "host",
+    "port",
+    "username",
+    "auth_method",
+    "group",
+    "monitoring_interval",
+    "transport",
+    "command_prefix",
+    "depends_on",
+    "watched_units"
+  ]
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, nil, name: __MODULE__)
+  end
+
+  @doc "Config values declared by the manifest, merged over the regular config"
+  def config_overrides do
+    :persistent_term.get(@overrides_key, %{})
+  end
+
+  @doc "Whether API edits to servers are allowed (always true without a manifest)"
+  def runtime_edits_allowed? do
+    :persistent_term.get(@edits_key, true)
+  end
+
+  def status do
+    GenServer.call(__MODULE__, :status)
+  end
+
+  def drift do
+    GenServer.call(__MODULE__, :drift)
+  end
+
+  # ---- Callbacks ----
+
+  def init(_) do
+    case Config.load().manifest_path do
+      nil ->
+        {:ok, %{path: nil, mtime: nil, servers: [], loaded_at: nil, last_error: nil}}
+
+      path ->
+        state = %{path: path, mtime: nil, servers: [], loaded_at: nil, last_error: nil}
+        {:ok, state, {:continue, :reconcile}}
+    end
+  end
+
+  def handle_continue(:reconcile, state) do
+    schedule_check()
+    {:noreply, reconcile(state)}
+  end
+
+  def handle_call(:status, _from, state) do
+    status = %{
+      "enabled" => not is_nil(state.path),
+      "path" => state.path,
+      "loaded_at" => state.loaded_at,
+      "last_error" => state.last_error,
+      "allow_runtime_edits" => runtime_edits_allowed?()
+    }
+
+    {:reply, status, state}
+  end
+
+  def handle_call(:drift, _from, %{path: nil} = state) do
+    {:reply, {:error, :disabled}, state}
+  end
+
+  def handle_call(:drift, _from, state) do
+    {:reply, {:ok, compute_drift(state.servers)}, state}
+  end
+
+  def handle_info(:check, state) do
+    schedule_check()
+
+    case File.stat(state.path) do
+      {:ok, %File.Stat{mtime: mtime}} when mtime != state.mtime -> {:noreply, reconcile(state)}
+      _ -> {:noreply, state}
+    end
+  end
+
+  # ---- Private helpers ----
+
+  defp reconcile(state) do
+    with {:ok, %File.Stat{mtime: mtime}} <- File.stat(state.path),
+         {:ok, manifest} when is_map(manifest) <- YamlElixir.read_from_file(state.path) do
+      servers = Map.get(manifest, "servers") || []
+
+      :persistent_term.put(@overrides_key, overrides(manifest))
+      :persistent_term.put(@edits_key, Map.get(manifest, "allow_runtime_edits", false))
+
+      {:ok, summary} = State.apply_manifest(servers)
+      Actions.reload_rules()
+
+      Logger.info("Reconciled manifest #{state.path}: #{inspect(summary)}")
+
+      %{
+        state
+        | mtime: mtime,
+          servers: servers,
+          loaded_at: DateTime.utc_now() |> DateTime.to_iso8601(),
+          last_error: nil
+      }
+    else
+      error ->
+        Logger.error("Failed to load manifest #{state.path}: #{inspect(error)}")
+        # Remember the mtime anyway so a broken file isn't re-parsed every check.
+        mtime =
+          case File.stat(state.path) do
+            {:ok, stat} -> stat.mtime
+            _ -> state.mtime
+          end
+
+        %{state | mtime: mtime, last_error: inspect(error)}
+    end
+  end
+
+  defp overrides(manifest) do
+    defaults = Config.load()
+
+    [
+      {"groups", :groups, & &1},
+      {"actions", :actions, & &1},
+      {"thresholds", :thresholds, &Map.merge(defaults.thresholds, &1)}
+    ]
+    |> Enum.filter(fn {key, _field, _fun} -> Map.has_key?(manifest, key) end)
+    |> Map.new(fn {key, field, fun} -> {field, fun.(manifest[key])} end)
+  end
+
+  defp compute_drift(desired) do
+    actual =
+      State.get_servers()
+      |> Enum.reject(&(&1.source == "local" or &1.status == "retired"))
+      |> Map.new(&{&1.name, &1})
+
+    desired_names = MapSet.new(desired, & &1["name"])
+
+    missing = for entry <- desired, not Map.has_key?(actual, entry["name"]), do: entry["name"]
+
+    unmanaged =
+      for {name, _server} <- actual, not MapSet.member?(desired_names, name), do: name
+
+    changed =
+      Enum.flat_map(desired, fn entry ->
+        case Map.fetch(actual, entry["name"]) do
+          {:ok, server} ->
+            case field_diffs(entry, server) do
+              [] -> []
+              diffs -> [%{"name" => entry["name"], "fields" => diffs}]
+            end
+
+          :error ->
+            []
+        end
+      end)
+
+    %{
+      "in_sync" => missing == [] and unmanaged == [] and changed == [],
+      "missing" => missing,
+      "unmanaged" => Enum.sort(unmanaged),
+      "changed" => changed
+    }
+  end
+
+  defp field_diffs(entry, server) do
+    @compared_fields
+    |> Enum.filter(&Map.has_key?(entry, &1))
+    |> Enum.flat_map(fn field ->
+      actual = Map.get(server, String.to_existing_atom(field))
+
+      if entry[field] == actual,
+        do: [],
+        else: [%{"field" => field, "desired" => entry[field], "actual" => actual}]
+    end)
+  end
+
+  defp schedule_check do
+    Process.send_after(self(), :check, @check_interval)
+  end
+end
@@ -10,13 +10,24 @@ defmodule AgentlessMonitor.Models do
       :username,
       :proxy_config,
       :last_seen,
+      :retired_at,
+      :group,
+      :site,
+      :command_prefix,
+      :platform,
+      :snmp,
       port: 22,
       auth_method: "key",
       created_at: nil,
       updated_at: nil,
-      status: "offline",
-      monitoring_interval: 30,
-      next_monitoring: 0
+      status: "unknown",
+      monitoring_interval: nil,
+      due_at: nil,
+      source: "manual",
+      transport: "ssh",
+      depends_on: [],
+      watched_units: [],
+      identity_files: []
     ]
 
     def to_map(%__MODULE__{} = s) do
@@ -27,15 +38,37 @@ defmodule AgentlessMonitor.Models do
         "port" => s.port,
         "username" => s.username,
         "auth_method" => s.auth_method,
-        "proxy_config" => s.proxy_config,
+        "proxy_config" => if(s.proxy_config, do: Map.delete(s.proxy_config, "password")),
         "created_at" => s.created_at,
         "updated_at" => s.updated_at,
         "last_seen" => s.last_seen,
         "status" => s.status,
         "monitoring_interval" => s.monitoring_interval,
-        "next_monitoring" => s.next_monitoring
+        "next_monitoring" => next_monitoring(s),
+        "source" => s.source,
+        "retired_at" => s.retired_at,
+        "group" => s.group,
+        "site" => s.site,
+        "transport" => s.transport,
+        "command_prefix" => s.command_prefix,
+        "platform" => s.platform,
+        "snmp" => if(s.snmp, do: Map.drop(s.snmp, ~w(community auth_password priv_password))),
+        "depends_on" => s.depends_on,
+        "watched_units" => s.watched_units,
+        "identity_files" => s.identity_files
       }
     end
+
+    @doc """
+    Wall-clock unix time of the next collection, or 0 when it is due now.
+    Scheduling itself runs on the monotonic clock (`due_at`) so clock jumps
+    don't cause bursts of re-monitoring or long stalls.
+    """
+    def next_monitoring(%__MODULE__{due_at: nil}), do: 0
+
+    def next_monitoring(%__MODULE__{due_at: due_at}) do
+      System.system_time(:second) + due_at - System.monotonic_time(:second)
+    end
   end
 
   defmodule CpuInfo do
@@ -52,8 +85,12 @@ defmodule AgentlessMonitor.Models do
   end
 
   defmodule MemoryInfo do
+    # `swap_in_pages`/`swap_out_pages` are the cumulative counters from
+    # /proc/vmstat; the rates (pages per second) need a previous sample.
     defstruct total: 0, used: 0, free: 0, available: 0,
-              swap_total: 0, swap_used: 0, swap_free: 0
+              swap_total: 0, swap_used: 0, swap_free: 0,
+              swap_in_pages: nil, swap_out_pages: nil,
+              swap_in_rate: nil, swap_out_rate: nil
 
     def to_map(%__MODULE__{} = m) do
       %{
@@ -63,11 +100,21 @@ defmodule AgentlessMonitor.Models do
         "available" => m.available,
         "swap_total" => m.swap_total,
         "swap_used" => m.swap_used,
-        "swap_free" => m.swap_free
+        "swap_free" => m.swap_free,
+        "swap_in_rate" => Map.get(m, :swap_in_rate),
+        "swap_out_rate" => Map.get(m, :swap_out_rate)
       }
     end
   end
 
+  defmodule OomKill do
+    defstruct pid: nil, process: "", time: nil, message: ""
+
+    def to_map(%__MODULE__{} = o) do
+      %{"pid" => o.pid, "process" => o.process, "time" => o.time, "message" => o.message}
+    end
+  end
+
   defmodule DiskInfo do
     defstruct device: "", mount_point: "", filesystem: "",
               total: 0, used: 0, free: 0, usage_percent: 0.0
@@ -118,6 +165,54 @@ defmodule AgentlessMonitor.Models do
     end
   end
 
+  defmodule ProcessInfo do
+    defstruct pid: nil, user: "", cpu_percent: 0.0, memory_percent: 0.0, rss_kb: 0, command: ""
+
+    def to_map(%__MODULE__{} = p) do
+      %{
+        "pid" => p.pid,
+        "user" => p.user,
+        "cpu_percent" => p.cpu_percent,
+        "memory_percent" => p.memory_percent,
+        "rss_kb" => p.rss_kb,
+        "command" => p.command
+      }
+    end
+  end
+
+  defmodule ContainerInfo do
+    defstruct id: "", name: "", image: "", state: "", status: "",
+              cpu_percent: nil, memory_usage: nil, memory_limit: nil, memory_percent: nil
+
+    def to_map(%__MODULE__{} = c) do
+      %{
+        "id" => c.id,
+        "name" => c.name,
+        "image" => c.image,
+        "state" => c.state,
+        "status" => c.status,
+        "cpu_percent" => c.cpu_percent,
+        "memory_usage" => c.memory_usage,
+        "memory_limit" => c.memory_limit,
+        "memory_percent" => c.memory_percent
+      }
+    end
+  end
+
+  defmodule ServiceUnit do
+    defstruct name: "", load: "", active: "", sub: "", description: ""
+
+    def to_map(%__MODULE__{} = u) do
+      %{
+        "name" => u.name,
+        "load" => u.load,
+        "active" => u.active,
+        "sub" => u.sub,
+        "description" => u.description
+      }
+    end
+  end
+
   defmodule PingTest do
     defstruct target: "", latency_ms: nil, success: false, error: nil
 
@@ -131,16 +226,39 @@ defmodule AgentlessMonitor.Models do
     end
   end
 
+  defmodule HttpCheck do
+    defstruct name: "", url: "", from: "monitor", status_code: nil, latency_ms: nil,
+              success: false, error: nil
+
+    def to_map(%__MODULE__{} = h) do
+      %{
+        "name" => h.name,
+        "url" => h.url,
+        "from" => h.from,
+        "status_code" => h.status_code,
+        "latency_ms" => h.latency_ms,
+        "success" => h.success,
+        "error" => h.error
+      }
+    end
+  end
+
   defmodule SystemInfo do
-    defstruct hostname: "", os: "", kernel: "", architecture: "", uptime: 0
+    # `os_id` and `os_version` are `ID` and `VERSION_ID` from /etc/os-release.
+    defstruct hostname: "", os: "", os_release: "", os_id: "", os_version: "", kernel: "",
+              architecture: "", uptime: 0, ip_addresses: []
 
     def to_map(%__MODULE__{} = s) do
       %{
         "hostname" => s.hostname,
         "os" => s.os,
+        "os_release" => s.os_release,
+        "os_id" => Map.get(s, :os_id, ""),
+        "os_version" => Map.get(s, :os_version, ""),
         "kernel" => s.kernel,
         "architecture" => s.architecture,
-        "uptime" => s.uptime
+        "uptime" => s.uptime,
+        "ip_addresses" => s.ip_addresses
       }
     end
   end
@@ -154,8 +272,16 @@ defmodule AgentlessMonitor.Models do
       disks: [],
       network: [],
       ports: [],
+      processes: [],
+      oom_kills: [],
+      containers: [],
+      services: [],
       ping_tests: [],
-      system_info: nil
+      http_checks: [],
+      system_info: nil,
+      custom: %{},
+      failed_collectors: [],
+      degraded: false
     ]
 
     def to_map(%__MODULE__{} = d) do
@@ -167,8 +293,17 @@ defmodule AgentlessMonitor.Models do
         "disks" => Enum.map(d.disks, &DiskInfo.to_map/1),
         "network" => Enum.map(d.network, &NetworkInfo.to_map/1),
         "ports" => Enum.map(d.ports, &PortInfo.to_map/1),
+        # Samples recorded before these collectors existed lack the keys.
+        "processes" => d |> Map.get(:processes, []) |> Enum.map(&ProcessInfo.to_map/1),
+        "oom_kills" => d |> Map.get(:oom_kills, []) |> Enum.map(&OomKill.to_map/1),
+        "containers" => d |> Map.get(:containers, []) |> Enum.map(&ContainerInfo.to_map/1),
+        "services" => d |> Map.get(:services, []) |> Enum.map(&ServiceUnit.to_map/1),
         "ping_tests" => Enum.map(d.ping_tests, &PingTest.to_map/1),
-        "system_info" => if(d.system_info, do: SystemInfo.to_map(d.system_info), else: nil)
+        "http_checks" => d |> Map.get(:http_checks, []) |> Enum.map(&HttpCheck.to_map/1),
+        "system_info" => if(d.system_info, do: SystemInfo.to_map(d.system_info), else: nil),
+        "custom" => Map.get(d, :custom, %{}),
+        "failed_collectors" => Map.get(d, :failed_collectors, []),
+        "degraded" => Map.get(d, :degraded, false)
       }
     end
   end
@@ -185,6 +320,8 @@ defmodule AgentlessMonitor.Models do
       :completed_at,
       :duration_ms,
       :error,
+      :output,
+      hook_errors: [],
       metrics_collected: 0,
       retry_count: 0,
       priority: 0
@@ -202,10 +339,101 @@ defmodule AgentlessMonitor.Models do
         "completed_at" => j.completed_at,
         "duration_ms" => j.duration_ms,
         "error" => j.error,
+        "output" => j.output,
+        "hook_errors" => j.hook_errors,
         "metrics_collected" => j.metrics_collected,
         "retry_count" => j.retry_count,
         "priority" => j.priority
       }
     end
   end
+
+  defmodule Event do
+    defstruct [
+      :id,
+      :server_id,
+      :server_name,
+      :group,
+      :kind,
+      :message,
+      :timestamp,
+      severity: "info",
+      suppressed_by: [],
+      drill: false
+    ]
+
+    def to_map(%__MODULE__{} = e) do
+      %{
+        "id" => e.id,
+        "server_id" => e.server_id,
+        "server_name" => e.server_name,
+        "group" => e.group,
+        "kind" => e.kind,
+        "severity" => e.severity,
+        "message" => e.message,
+        "timestamp" => e.timestamp,
+        "suppressed_by" => e.suppressed_by,
+        "drill" => e.drill
+      }
+    end
+  end
+
+  defmodule Incident do
+    defstruct [
+      :id,
+      :scope,
+      :server_id,
+      :group,
+      :title,
+      :assignee,
+      :postmortem,
+      :opened_at,
+      :updated_at,
+      :mitigated_at,
+      :resolved_at,
+      status: "open",
+      severity: "warning",
+      event_ids: [],
+      timeline: [],
+      captures: []
+    ]
+
+    def to_map(%__MODULE__{} = i) do
+      %{
+        "id" => i.id,
+        "scope" => i.scope,
+        "server_id" => i.server_id,
+        "group" => i.group,
+        "title" => i.title,
+        "status" => i.status,
+        "severity" => i.severity,
+        "assignee" => i.assignee,
+        "postmortem" => i.postmortem,
+        "opened_at" => i.opened_at,
+        "updated_at" => i.updated_at,
+        "mitigated_at" => i.mitigated_at,
+        "resolved_at" => i.resolved_at,
+        "event_ids" => i.event_ids,
+        "timeline" => i.timeline,
+        "captures" => i.captures
+      }
+    end
+  end
+
+  @doc "Generates a random UUID-formatted identifier"
+  def generate_id do
+    :crypto.strong_rand_bytes(16)
+    |> Base.encode16(case: :lower)
+    |> then(fn hex ->
+      String.slice(hex, 0, 8) <>
+        "-" <>
+        String.slice(hex, 8, 4) <>
+        "-" <>
+        String.slice(hex, 12, 4) <>
+        "-" <>
+        String.slice(hex, 16, 4) <>
+        "-" <>
+        String.slice(hex, 20, 12)
+    end)
+  end
 end
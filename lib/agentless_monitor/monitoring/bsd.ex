@@ -0,0 +1,348 @@
+defmodule AgentlessMonitor.Monitoring.BSD do
+  @moduledoc """
+  Collection from macOS and the BSDs, which have no /proc.
+
+  The platform is the lowercased `uname -s` detected on the first
+  collection. A collection is one shell command whose sections, separated
+  like the mega command's, read:
+
+    * CPU usage from `iostat` (its idle column), load averages, core count
+      and model from `sysctl`
+    * memory from `sysctl` and `vm_stat` on macOS, from the
+      `vm.stats.vm` counters on the BSDs; swap from `vm.swapusage` or
+      `swapinfo`
+    * disks from `df -kP` (devices under /dev only), interfaces from
+      `netstat -ibn` and their addresses from `ifconfig`
+    * listening TCP ports from `netstat -an -p tcp`, the heaviest processes
+      from `ps -r` and `ps -m`
+    * host name, `uname`, the release from `sw_vers` or `freebsd-version`
+      and the uptime from `kern.boottime`
+
+  Containers, services, OOM kills and pings aren't collected.
+  """
+
+  alias AgentlessMonitor.Monitoring.Parser
+
+  alias AgentlessMonitor.Models.{
+    CpuInfo,
+    DiskInfo,
+    MemoryInfo,
+    MonitoringData,
+    NetworkInfo,
+    PortInfo,
+    SystemInfo
+  }
+
+  @platforms ["darwin", "freebsd", "openbsd", "netbsd", "dragonfly"]
+
+  @process_command "(ps -axo pid=,user=,pcpu=,pmem=,rss=,args= -r | head -n 50; " <>
+                     "ps -axo pid=,user=,pcpu=,pmem=,rss=,args= -m | head -n 50)"
+
+  @darwin_memory "sysctl hw.memsize hw.pagesize vm.swapusage; vm_stat"
+  @bsd_memory "(sysctl hw.physmem hw.pagesize vm.stats.vm.v_free_count " <>
+                "vm.stats.vm.v_inactive_count 2>/dev/null; " <>
+                "echo \"swapinfo: $(swapinfo -k 2>/dev/null | tail -n 1)\")"
+
+  @darwin_release "sw_vers -productName; sw_vers -productVersion"
+  @bsd_release "uname -s; (freebsd-version 2>/dev/null || uname -r)"
+
+  def platforms, do: @platforms
+
+  @doc "The collection command for `platform`"
+  def command(platform) do
+    darwin = platform == "darwin"
+
+    [
+      "sysctl -n kern.boottime; date +%s",
+      "sysctl -n vm.loadavg",
+      "sysctl -n hw.ncpu",
+      "(sysctl -n machdep.cpu.brand_string 2>/dev/null || sysctl -n hw.model)",
+      "(iostat -n 0 -c 2 -w 1 2>/dev/null || iostat -c 2 -w 1)",
+      if(darwin, do: @darwin_memory, else: @bsd_memory),
+      "df -kP",
+      "netstat -ibn",
+      "ifconfig",
+      "hostname",
+      "uname -s; uname -r; uname -m",
+      if(darwin, do: @darwin_release, else: @bsd_release),
+      "(netstat -an -p tcp 2>/dev/null | grep LISTEN || true)",
+      @process_command
+    ]
+    |> Enum.join("; echo '---SEP---'; ")
+  end
+
+  @doc "Parses the output of `command/1` into a sample of `server_id`"
+  def parse(output, platform, server_id) do
+    case output |> String.split("---SEP---\n") |> Enum.map(&String.trim/1) do
+      [boot, loadavg, ncpu, model, iostat, memory, df, netstat, ifconfig, hostname, uname,
+       release, ports, processes] ->
+        [os, kernel, arch] = uname |> String.split("\n") |> Enum.map(&String.trim/1) |> pad(3)
+        addresses = addresses(ifconfig)
+        {os_release, os_version} = release(release)
+
+        {:ok,
+         %MonitoringData{
+           server_id: server_id,
+           timestamp: DateTime.utc_now() |> DateTime.to_iso8601(),
+           cpu: %CpuInfo{
+             usage_percent: cpu_usage(iostat),
+             load_average: load_average(loadavg),
+             cores: max(1, integer(ncpu)),
+             model: model
+           },
+           memory: memory(memory),
+           disks: disks(df),
+           network: network(netstat, addresses),
+           ports: ports(ports),
+           processes: Parser.parse_processes(processes),
+           system_info: %SystemInfo{
+             hostname: hostname,
+             os: os,
+             os_release: os_release,
+             os_id: if(platform == "darwin", do: "macos", else: platform),
+             os_version: os_version,
+             kernel: kernel,
+             architecture: arch,
+             uptime: uptime(boot),
+             ip_addresses: addresses |> Map.values() |> List.flatten() |> Enum.uniq()
+           }
+         }}
+
+      _ ->
+        {:error, "unexpected output from #{platform} collection"}
+    end
+  end
+
+  # ---- Private helpers ----
+
+  # `iostat` prints a header naming the columns and one line per report; the
+  # second report covers the last second. Columns are aligned from the right
+  # since the leading ones (tty, disks) vary.
+  defp cpu_usage(output) do
+    lines = output |> String.split("\n", trim: true) |> Enum.map(&String.split/1)
+
+    with header when is_list(header) <- Enum.find(lines, &("id" in &1)),
+         [_ | _] = last <- List.last(lines),
+         index when is_integer(index) <- Enum.find_index(header, &(&1 == "id")),
+         {idle, _} <- Float.parse(Enum.at(last, length(last) - length(header) + index, "")) do
+      Float.round(max(0.0, 100.0 - idle), 1)
+    else
+      _ -> 0.0
+    end
+  end
+
+  # `{ 1.23 1.10 0.98 }`
+  defp load_average(output) do
+    output
+    |> String.replace(["{", "}"], "")
+    |> String.split()
+    |> Enum.map(&float/1)
+    |> pad(3, 0.0)
+  end
+
+  # `key: value` lines from `sysctl` and `vm_stat`; counts are in pages.
+  defp memory(output) do
+    fields =
+      for line <- String.split(output, "\n"),
+          [key, value] <- [String.split(line, ":", parts: 2)],
+          into: %{},
+          do: {String.trim(key), String.trim(value)}
+
+    page = integer(fields["hw.pagesize"] || "4096")
+    pages = &(integer(fields[&1] || "0") * page)
+
+    {total, free, available} =
+      if fields["hw.memsize"] do
+        free = pages.("Pages free") + pages.("Pages speculative")
+        {integer(fields["hw.memsize"]), free, free + pages.("Pages inactive")}
+      else
+        free = pages.("vm.stats.vm.v_free_count")
+        {integer(fields["hw.physmem"]), free, free + pages.("vm.stats.vm.v_inactive_count")}
+      end
+
+    {swap_total, swap_used} = swap(fields)
+
+    %MemoryInfo{
+      total: total,
+      used: max(0, total - available),
+      free: free,
+      available: available,
+      swap_total: swap_total,
+      swap_used: swap_used,
+      swap_free: max(0, swap_total - swap_used)
+    }
+  end
+
+  # macOS: `total = 2048.00M  used = 1047.50M  free = 1000.50M  (encrypted)`;
+  # the BSDs: the last `swapinfo -k` line, `device 1K-blocks used avail cap`.
+  defp swap(%{"vm.swapusage" => usage}) do
+    sizes =
+      for [_, key, value, unit] <- Regex.scan(~r/(total|used) = ([\d.]+)([KMG])/, usage),
+          into: %{},
+          do: {key, round(float(value) * unit_bytes(unit))}
+
+    {Map.get(sizes, "total", 0), Map.get(sizes, "used", 0)}
+  end
+
+  defp swap(%{"swapinfo" => line}) do
+    case String.split(line) do
+      [_device, total, used | _] -> {integer(total) * 1024, integer(used) * 1024}
+      _ -> {0, 0}
+    end
+  end
+
+  defp swap(_fields), do: {0, 0}
+
+  defp unit_bytes("K"), do: 1024
+  defp unit_bytes("M"), do: 1_048_576
+  defp unit_bytes("G"), do: 1_073_741_824
+
+  # `Filesystem 1024-blocks Used Available Capacity Mounted on`. APFS
+  # system volumes other than the data volume are left out.
+  defp disks(output) do
+    output
+    |> String.split("\n", trim: true)
+    |> Enum.drop(1)
+    |> Enum.flat_map(fn line ->
+      case String.split(line, ~r/\s+/, parts: 6) do
+        ["/dev/" <> _ = device, total, used, free, capacity, mount] ->
+          if system_volume?(mount) do
+            []
+          else
+            [
+              %DiskInfo{
+                device: device,
+                mount_point: mount,
+                filesystem: device,
+                total: integer(total) * 1024,
+                used: integer(used) * 1024,
+                free: integer(free) * 1024,
+                usage_percent: float(String.trim_trailing(capacity, "%"))
+              }
+            ]
+          end
+
+        _ ->
+          []
+      end
+    end)
+  end
+
+  defp system_volume?("/System/Volumes/Data"), do: false
+  defp system_volume?(mount), do: String.starts_with?(mount, "/System/Volumes/")
+
+  # Link-level rows of `netstat -ibn`. Their address is missing for
+  # loopback and tunnels, so columns are aligned from the right.
+  defp network(output, addresses) do
+    [header | rows] =
+      case output |> String.split("\n", trim: true) |> Enum.map(&String.split/1) do
+        [] -> [[]]
+        lines -> lines
+      end
+
+    column = fn row, name ->
+      case Enum.find_index(header, &(&1 == name)) do
+        nil -> 0
+        index -> integer(Enum.at(row, length(row) - length(header) + index, "0"))
+      end
+    end
+
+    rows
+    |> Enum.filter(&match?([_name, _mtu, "<Link" <> _ | _], &1))
+    |> Enum.uniq_by(&hd/1)
+    |> Enum.map(fn [name | _] = row ->
+      name = String.trim_trailing(name, "*")
+
+      %NetworkInfo{
+        interface: name,
+        rx_bytes: column.(row, "Ibytes"),
+        tx_bytes: column.(row, "Obytes"),
+        rx_packets: column.(row, "Ipkts"),
+        tx_packets: column.(row, "Opkts"),
+        rx_errors: column.(row, "Ierrs"),
+        tx_errors: column.(row, "Oerrs"),
+        ip_addresses: Map.get(addresses, name, [])
+      }
+    end)
+  end
+
+  # Non-loopback, non-link-local addresses per interface from `ifconfig`.
+  defp addresses(output) do
+    output
+    |> String.split("\n")
+    |> Enum.reduce({nil, %{}}, fn line, {interface, acc} ->
+      case Regex.run(~r/^([^\s:]+): flags=/, line) do
+        [_, name] ->
+          {name, acc}
+
+        nil ->
+          case String.split(line) do
+            [family, address | _] when family in ["inet", "inet6"] and interface != nil ->
+              if local_address?(address),
+                do: {interface, acc},
+                else: {interface, Map.update(acc, interface, [address], &(&1 ++ [address]))}
+
+            _ ->
+              {interface, acc}
+          end
+      end
+    end)
+    |> elem(1)
+  end
+
+  defp local_address?(address) do
+    String.starts_with?(address, "127.") or address == "::1" or String.contains?(address, "%")
+  end
+
+  # `tcp4  0  0  *.22  *.*  LISTEN`; the port follows the last dot.
+  defp ports(output) do
+    output
+    |> String.split("\n", trim: true)
+    |> Enum.flat_map(fn line ->
+      case String.split(line) do
+        [protocol, _recvq, _sendq, local, _foreign, state | _] ->
+          port = local |> String.split(".") |> List.last() |> integer()
+          if port > 0, do: [%PortInfo{port: port, protocol: protocol, state: state}], else: []
+
+        _ ->
+          []
+      end
+    end)
+    |> Enum.uniq_by(&{&1.protocol, &1.port})
+  end
+
+  # `sw_vers` or `uname -s` followed by the version.
+  defp release(output) do
+    case output |> String.split("\n") |> Enum.map(&String.trim/1) do
+      [name, version | _] -> {"#{name} #{version}", version}
+      _ -> {output, ""}
+    end
+  end
+
+  # `{ sec = 1700000000, usec = 0 } ...` followed by the current epoch.
+  defp uptime(output) do
+    with [_, boot] <- Regex.run(~r/sec = (\d+)/, output),
+         [now | _] <- output |> String.split("\n") |> Enum.reverse() do
+      max(0, integer(now) - integer(boot))
+    else
+      _ -> 0
+    end
+  end
+
+  defp pad(list, count, default \\ ""),
+    do: Enum.take(list ++ List.duplicate(default, count), count)
+
+  defp integer(value) do
+    case Integer.parse(String.trim(to_string(value))) do
+      {n, _} -> n
+      :error -> 0
+    end
+  end
+
+  defp float(value) do
+    case Float.parse(String.trim(to_string(value))) do
+      {f, _} -> f
+      :error -> 0.0
+    end
+  end
+end
@@ -0,0 +1,258 @@
+defmodule AgentlessMonitor.Monitoring.Burst do
+  @moduledoc """
+  Burst sampling: CPU, memory and network of one server every 1 or 2
+  seconds for a few minutes, to drill into an incident while it unfolds.
+
+  The whole burst is one remote command, a shell loop printing `/proc/stat`,
+  `/proc/meminfo` and `/proc/net/dev` after an `@ <epoch> <uptime>` marker,
+  so sampling doesn't pay for a connection per point. Rates come from the
+  deltas between consecutive snapshots, timed with `/proc/uptime`. It runs
+  as a `burst_sampling` job. The series is kept apart from the regular
+  history and dropped `burst_retention_hours` (default 24) after the burst
+  ends.
+  """
+
+  use GenServer
+
+  alias AgentlessMonitor.{Config, Models}
+  alias AgentlessMonitor.Monitoring.Service
+
+  @max_minutes 15
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, nil, name: __MODULE__)
+  end
+
+  @doc "Checks burst parameters: an `interval` of 1 or 2 seconds and 1-15 `minutes`"
+  def validate(interval, minutes) do
+    cond do
+      interval not in [1, 2] -> {:error, "interval must be 1 or 2 seconds"}
+      minutes not in 1..@max_minutes -> {:error, "minutes must be between 1 and #{@max_minutes}"}
+      true -> :ok
+    end
+  end
+
+  @doc """
+  Starts a burst on `server`. Returns `{:ok, burst}`, or `{:error, :running}`
+  while the server already has one in progress.
+  """
+  def start(server, interval, minutes) do
+    GenServer.call(__MODULE__, {:start, server, interval, minutes})
+  end
+
+  @doc "Bursts of a server, newest first, with their points"
+  def list(server_id) do
+    GenServer.call(__MODULE__, {:list, server_id})
+  end
+
+  @doc "Bursts of a server that overlap the time between `from` and `to` (nil: now)"
+  def during(server_id, %DateTime{} = from, to) do
+    to = to || DateTime.utc_now()
+
+    server_id
+    |> list()
+    |> Enum.filter(fn burst ->
+      {:ok, started_at, _} = DateTime.from_iso8601(burst["started_at"])
+
+      finished_at =
+        case burst["finished_at"] do
+          nil -> DateTime.utc_now()
+          finished -> finished |> DateTime.from_iso8601() |> elem(1)
+        end
+
+      DateTime.compare(started_at, to) != :gt and DateTime.compare(finished_at, from) != :lt
+    end)
+  end
+
+  @doc "Shell loop printing `count` snapshots `interval` seconds apart"
+  def command(interval, count) do
+    "i=0; while [ $i -le #{count} ]; do " <>
+      "echo \"@ $(date +%s) $(cut -d' ' -f1 /proc/uptime)\"; head -n 1 /proc/stat; " <>
+      "grep -E '^(MemTotal|MemAvailable):' /proc/meminfo; tail -n +3 /proc/net/dev; " <>
+      "i=$((i + 1)); sleep #{interval}; done"
+  end
+
+  @doc """
+  Points from the loop's output: `%{"timestamp", "cpu_percent",
+  "memory_percent", "rx_bytes_per_sec", "tx_bytes_per_sec"}`, one per
+  snapshot after the first. Loopback traffic is left out.
+  """
+  def parse(output) do
+    output
+    |> String.split(~r/^@ /m)
+    |> Enum.flat_map(&snapshot/1)
+    |> Enum.chunk_every(2, 1, :discard)
+    |> Enum.map(fn [previous, current] -> point(previous, current) end)
+  end
+
+  # ---- Callbacks ----
+
+  def init(_) do
+    {:ok, %{bursts: %{}}}
+  end
+
+  def handle_call({:start, server, interval, minutes}, _from, state) do
+    if Enum.any?(Map.values(state.bursts), &(&1["server_id"] == server.id and running?(&1))) do
+      {:reply, {:error, :running}, state}
+    else
+      id = Models.generate_id()
+      count = div(minutes * 60, interval)
+
+      sample = fn ->
+        opts = [timeout: minutes * 60 + 30]
+
+        case Service.run_command(server, command(interval, count), opts) do
+          {:ok, output} ->
+            points = parse(output)
+            GenServer.cast(__MODULE__, {:finish, id, "completed", points})
+            {:ok, "#{length(points)} points at #{interval}s"}
+
+          {:error, output} ->
+            GenServer.cast(__MODULE__, {:finish, id, "failed", []})
+            {:error, output}
+        end
+      end
+
+      job = Service.start_job(server, "burst_sampling", sample)
+
+      burst = %{
+        "id" => id,
+        "server_id" => server.id,
+        "job_id" => job.id,
+        "interval" => interval,
+        "minutes" => minutes,
+        "status" => "running",
+        "started_at" => DateTime.utc_now() |> DateTime.to_iso8601(),
+        "finished_at" => nil,
+        "expires_at" => nil,
+        "points" => []
+      }
+
+      {:reply, {:ok, burst}, put_in(state.bursts[id], burst)}
+    end
+  end
+
+  def handle_call({:list, server_id}, _from, state) do
+    bursts =
+      state.bursts
+      |> Map.values()
+      |> Enum.filter(&(&1["server_id"] == server_id))
+      |> Enum.sort_by(& &1["started_at"], :desc)
+
+    {:reply, bursts, state}
+  end
+
+  def handle_cast({:finish, id, status, points}, state) do
+    case Map.fetch(state.bursts, id) do
+      {:ok, burst} ->
+        retention = Config.load().burst_retention_hours * 3600
+        now = DateTime.utc_now()
+        Process.send_after(self(), {:expire, id}, retention * 1000)
+
+        finished = %{
+          burst
+          | "status" => status,
+            "points" => points,
+            "finished_at" => DateTime.to_iso8601(now),
+            "expires_at" => now |> DateTime.add(retention, :second) |> DateTime.to_iso8601()
+        }
+
+        {:noreply, put_in(state.bursts[id], finished)}
+
+      :error ->
+        {:noreply, state}
+    end
+  end
+
+  def handle_info({:expire, id}, state) do
+    {:noreply, %{state | bursts: Map.delete(state.bursts, id)}}
+  end
+
+  # ---- Private helpers ----
+
+  defp running?(burst), do: burst["status"] == "running"
+
+  defp snapshot(block) do
+    with [marker | lines] <- String.split(block, "\n", trim: true),
+         [epoch, uptime] <- String.split(marker),
+         {timestamp, ""} <- Integer.parse(epoch),
+         {seconds, ""} <- Float.parse(uptime) do
+      [
+        %{
+          timestamp: timestamp,
+          uptime: seconds,
+          cpu: cpu_times(lines),
+          memory: memory_percent(lines),
+          network: network_bytes(lines)
+        }
+      ]
+    else
+      _ -> []
+    end
+  end
+
+  # `{idle, total}` jiffies from the aggregate `cpu` line; iowait counts as idle.
+  defp cpu_times(lines) do
+    Enum.find_value(lines, fn line ->
+      case String.split(line) do
+        ["cpu" | fields] ->
+          jiffies = for {value, ""} <- Enum.map(fields, &Integer.parse/1), do: value
+          {Enum.at(jiffies, 3, 0) + Enum.at(jiffies, 4, 0), Enum.sum(jiffies)}
+
+        _ ->
+          nil
+      end
+    end)
+  end
+
+  defp memory_percent(lines) do
+    kb =
+      for line <- lines, [key, value, "kB"] <- [String.split(line)], into: %{}, do: {key, value}
+
+    case {Integer.parse(kb["MemTotal:"] || ""), Integer.parse(kb["MemAvailable:"] || "")} do
+      {{total, ""}, {available, ""}} when total > 0 ->
+        Float.round((total - available) / total * 100, 1)
+
+      _ ->
+        nil
+    end
+  end
+
+  defp network_bytes(lines) do
+    lines
+    |> Enum.flat_map(fn line ->
+      case Regex.run(~r/^\s*([^\s:]+):\s*(\d+)(?:\s+\d+){7}\s+(\d+)/, line) do
+        [_, "lo", _rx, _tx] -> []
+        [_, _device, rx, tx] -> [{String.to_integer(rx), String.to_integer(tx)}]
+        nil -> []
+      end
+    end)
+    |> Enum.reduce({0, 0}, fn {rx, tx}, {rx_sum, tx_sum} -> {rx_sum + rx, tx_sum + tx} end)
+  end
+
+  defp point(previous, current) do
+    elapsed = current.uptime - previous.uptime
+    {rx0, tx0} = previous.network
+    {rx1, tx1} = current.network
+
+    %{
+      "timestamp" => current.timestamp,
+      "cpu_percent" => cpu_percent(previous.cpu, current.cpu),
+      "memory_percent" => current.memory,
+      "rx_bytes_per_sec" => rate(rx1 - rx0, elapsed),
+      "tx_bytes_per_sec" => rate(tx1 - tx0, elapsed)
+    }
+  end
+
+  defp cpu_percent({idle0, total0}, {idle1, total1}) when total1 > total0 do
+    Float.round((1 - (idle1 - idle0) / (total1 - total0)) * 100, 1)
+  end
+
+  defp cpu_percent(_previous, _current), do: nil
+
+  # Counters that went backwards (a reset or a vanished interface) give no rate.
+  defp rate(delta, elapsed) when delta >= 0 and elapsed > 0, do: round(delta / elapsed)
+  defp rate(_delta, _elapsed), do: nil
+end
@@ -0,0 +1,79 @@
+defmodule AgentlessMonitor.Monitoring.CustomMetrics do
+  @moduledoc """
+  Metrics of your own, read by a command on every collection and declared
+  with a name, a type and a unit in the `custom_metrics` setting (global,
+  per group or per server):
+
+      "custom_metrics": [
+        {"name": "queue_depth", "type": "number", "unit": "jobs",
+         "command": "redis-cli llen jobs"},
+        {"name": "app_version", "type": "string", "command": "cat /srv/app/VERSION"}
+      ]
+
+  The commands of a server run as one remote command alongside the
+  collection. Each one's trimmed output is its value: a `number` (the
+  default type) is an integer or a float, or nil when the output isn't one;
+  a `string` is kept up to 256 characters. Samples carry the values in
+  `custom`, keyed by name.
+
+  Numeric metrics can be charted as `custom.<name>` in `/api/matrix` and
+  alerted on with a `custom.<name>` key in `thresholds`.
+  """
+
+  @types ["number", "string"]
+  @max_string 256
+
+  @doc "The usable declarations in `settings`; incomplete ones are skipped"
+  def declarations(settings) do
+    for %{"name" => name, "command" => command} = metric <- settings["custom_metrics"] || [],
+        is_binary(name) and name != "" and is_binary(command),
+        Map.get(metric, "type", "number") in @types do
+      Map.put_new(metric, "type", "number")
+    end
+  end
+
+  @doc """
+  One shell command printing every metric's output, separated like the mega
+  command. It ends in `true` so a failing metric command only leaves its
+  value empty.
+  """
+  def command(declarations) do
+    Enum.map_join(declarations, "; echo '---SEP---'; ", &"(#{&1["command"]}) 2>/dev/null") <>
+      "; true"
+  end
+
+  @doc "Values from the output of `command/1`, as `%{name => value}`"
+  def parse(output, declarations) do
+    output
+    |> String.split("---SEP---\n")
+    |> Enum.zip(declarations)
+    |> Map.new(fn {text, metric} ->
+      {metric["name"], value(metric["type"], String.trim(text))}
+    end)
+  end
+
+  @doc "The numeric value of metric `name` in a sample, or nil"
+  def value(data, name) do
+    case Map.get(data, :custom, %{})[name] do
+      number when is_number(number) -> number
+      _ -> nil
+    end
+  end
+
+  # ---- Private helpers ----
+
+  defp value("string", text), do: String.slice(text, 0, @max_string)
+
+  defp value("number", text) do
+    case Integer.parse(text) do
+      {integer, ""} ->
+        integer
+
+      _ ->
+        case Float.parse(text) do
+          {float, ""} -> float
+          _ -> nil
+        end
+    end
+  end
+end
@@ -0,0 +1,78 @@
+defmodule AgentlessMonitor.Monitoring.DiskUsage do
+  @moduledoc """
+  On-demand "what is filling the disk" breakdown.
+
+  `command/2` runs `du` limited to `depth` levels below `path` and to the
+  filesystem `path` lives on (`-x`), so a scan of `/` does not wander into
+  network mounts; `parse/2` turns its output into the largest directories.
+  Sizes are in KiB. Unreadable directories are skipped silently.
+  """
+
+  @max_depth 3
+  @default_limit 20
+
+  def max_depth, do: @max_depth
+
+  @doc "Validates a scan request, returning `{:ok, path, depth}`"
+  def validate(path, depth) do
+    cond do
+      not is_binary(path) or not String.starts_with?(path, "/") ->
+        {:error, "path must be an absolute path"}
+
+      String.contains?(path, ["\n", "\r", <<0>>]) ->
+        {:error, "path contains invalid characters"}
+
+      not is_integer(depth) or depth < 1 or depth > @max_depth ->
+        {:error, "depth must be between 1 and #{@max_depth}"}
+
+      true ->
+        {:ok, path, depth}
+    end
+  end
+
+  def command(path, depth) do
+    # du exits non-zero on any unreadable directory; only a missing path fails.
+    "du -x -k -d #{depth} #{shell_quote(path)} 2>/dev/null; test -d #{shell_quote(path)}"
+  end
+
+  @doc """
+  `%{"path", "total_kb", "entries"}` with the `limit` largest directories
+  below the scanned path, largest first, each as `%{"path", "size_kb",
+  "percent"}`. `du` prints the scanned path itself last.
+  """
+  def parse(output, limit \\ @default_limit) do
+    sizes =
+      output
+      |> String.split("\n", trim: true)
+      |> Enum.flat_map(fn line ->
+        with [size, dir] <- String.split(line, "\t", parts: 2),
+             {kb, ""} <- Integer.parse(size) do
+          [{dir, kb}]
+        else
+          _ -> []
+        end
+      end)
+
+    {root, total} = List.last(sizes, {nil, nil})
+
+    entries =
+      sizes
+      |> Enum.reject(fn {dir, _kb} -> dir == root end)
+      |> Enum.sort_by(&elem(&1, 1), :desc)
+      |> Enum.take(limit)
+      |> Enum.map(fn {dir, kb} ->
+        %{"path" => dir, "size_kb" => kb, "percent" => percent(kb, total)}
+      end)
+
+    %{"path" => root, "total_kb" => total, "entries" => entries}
+  end
+
+  # ---- Private helpers ----
+
+  defp percent(_kb, total) when total in [nil, 0], do: nil
+  defp percent(kb, total), do: Float.round(kb / total * 100, 1)
+
+  defp shell_quote(value) do
+    "'" <> String.replace(value, "'", "'\\''") <> "'"
+  end
+end
@@ -0,0 +1,194 @@
+defmodule AgentlessMonitor.Monitoring.EOL do
+  @moduledoc """
+  End-of-life awareness for the distributions and kernels hosts run.
+
+  Releases are identified by `ID` and `VERSION_ID` from `/etc/os-release`
+  and looked up in a bundled table of end-of-support dates; a version is
+  tried in full and then with trailing components dropped, so RHEL `8.9`
+  matches `8` and Alpine `3.19.1` matches `3.19`. Kernels older than
+  `min_kernel` are flagged separately, since enterprise distributions keep
+  old kernel versions alive with backports.
+
+  The table can be updated without a release by placing
+  `<data_dir>/eol.json` next to the other state files; its entries are
+  merged over the bundled ones:
+
+      {"min_kernel": "5.4",
+       "releases": {"ubuntu": {"20.04": "2025-05-31"}, "sles": {"15.5": "2024-12-31"}}}
+  """
+
+  alias AgentlessMonitor.Config
+
+  # Releases within this many days of their end of support are `expiring`.
+  @warning_days 90
+
+  @min_kernel "4.18"
+
+  # End of standard (or, for Debian, LTS) support.
+  @releases %{
+    "ubuntu" => %{
+      "16.04" => "2021-04-30",
+      "18.04" => "2023-05-31",
+      "20.04" => "2025-05-31",
+      "22.04" => "2027-04-30",
+      "23.04" => "2024-01-25",
+      "23.10" => "2024-07-11",
+      "24.04" => "2029-04-30",
+      "24.10" => "2025-07-10"
+    },
+    "debian" => %{
+      "9" => "2022-06-30",
+      "10" => "2024-06-30",
+      "11" => "2026-08-31",
+      "12" => "2028-06-30"
+    },
+    "centos" => %{
+      "6" => "2020-11-30",
+      "7" => "2024-06-30",
+      "8" => "2024-05-31",
+      "9" => "2027-05-31"
+    },
+    "rhel" => %{
+      "6" => "2020-11-30",
+      "7" => "2024-06-30",
+      "8" => "2029-05-31",
+      "9" => "2032-05-31"
+    },
+    "rocky" => %{"8" => "2029-05-31", "9" => "2032-05-31"},
+    "almalinux" => %{"8" => "2029-03-01", "9" => "2032-05-31"},
+    "amzn" => %{"2" => "2026-06-30", "2023" => "2029-06-30"},
+    "fedora" => %{"38" => "2024-05-21", "39" => "2024-11-26", "40" => "2025-05-13"},
+    "alpine" => %{
+      "3.16" => "2024-05-23",
+      "3.17" => "2024-11-22",
+      "3.18" => "2025-05-09",
+      "3.19" => "2025-11-01",
+      "3.20" => "2026-04-01"
+    }
+  }
+
+  @doc """
+  The compliance view of `servers`, a list of `{server, latest_sample}`:
+  one entry per server and a count per status (`eol`, `expiring`,
+  `supported`, `unknown`) plus `kernel_outdated`.
+  """
+  def report(servers, today \\ Date.utc_today()) do
+    %{"releases" => releases, "min_kernel" => min_kernel} = dataset()
+
+    entries =
+      Enum.map(servers, fn {server, data} ->
+        data
+        |> check(releases, min_kernel, today)
+        |> Map.merge(%{"server_id" => server.id, "name" => server.name})
+      end)
+
+    summary =
+      entries
+      |> Enum.frequencies_by(& &1["status"])
+      |> Map.put("kernel_outdated", Enum.count(entries, & &1["kernel_outdated"]))
+
+    %{
+      "min_kernel" => min_kernel,
+      "warning_days" => @warning_days,
+      "summary" => summary,
+      "servers" => entries
+    }
+  end
+
+  @doc "The bundled table merged with `<data_dir>/eol.json`, if present"
+  def dataset do
+    overrides =
+      with {:ok, content} <- File.read(path()),
+           {:ok, json} when is_map(json) <- Jason.decode(content) do
+        json
+      else
+        _ -> %{}
+      end
+
+    releases =
+      Map.merge(@releases, Map.get(overrides, "releases", %{}), fn _id, bundled, override ->
+        Map.merge(bundled, override)
+      end)
+
+    %{"releases" => releases, "min_kernel" => Map.get(overrides, "min_kernel", @min_kernel)}
+  end
+
+  # ---- Private helpers ----
+
+  defp check(nil, _releases, _min_kernel, _today) do
+    %{"status" => "unknown", "kernel_outdated" => false, "error" => "no data collected yet"}
+  end
+
+  defp check(data, releases, min_kernel, today) do
+    info = data.system_info || %{}
+    os_id = Map.get(info, :os_id, "")
+    os_version = Map.get(info, :os_version, "")
+    kernel = Map.get(info, :kernel, "")
+    eol_date = lookup(releases, os_id, os_version)
+
+    %{
+      "os_release" => Map.get(info, :os_release, ""),
+      "os_id" => os_id,
+      "os_version" => os_version,
+      "kernel" => kernel,
+      "eol_date" => eol_date && Date.to_iso8601(eol_date),
+      "days_left" => eol_date && Date.diff(eol_date, today),
+      "status" => status(eol_date, today),
+      "kernel_outdated" => kernel_outdated?(kernel, min_kernel)
+    }
+  end
+
+  defp lookup(_releases, id, version) when id in [nil, ""] or version in [nil, ""], do: nil
+
+  defp lookup(releases, id, version) do
+    versions = Map.get(releases, id, %{})
+    parts = String.split(version, ".")
+
+    length(parts)..1//-1
+    |> Enum.find_value(fn n -> Map.get(versions, parts |> Enum.take(n) |> Enum.join(".")) end)
+    |> parse_date()
+  end
+
+  defp parse_date(value) when is_binary(value) do
+    case Date.from_iso8601(value) do
+      {:ok, date} -> date
+      {:error, _} -> nil
+    end
+  end
+
+  defp parse_date(_value), do: nil
+
+  defp status(nil, _today), do: "unknown"
+
+  defp status(eol_date, today) do
+    days_left = Date.diff(eol_date, today)
+
+    cond do
+      days_left < 0 -> "eol"
+      days_left <= @warning_days -> "expiring"
+      true -> "supported"
+    end
+  end
+
+  defp kernel_outdated?(kernel, min_kernel) do
+    case {kernel_version(kernel), kernel_version(min_kernel)} do
+      {nil, _} -> false
+      {_, nil} -> false
+      {version, minimum} -> version < minimum
+    end
+  end
+
+  # `5.15.0-91-generic` -> [5, 15]
+  defp kernel_version(value) when is_binary(value) do
+    case Regex.run(~r/^(\d+)\.(\d+)/, value) do
+      [_, major, minor] -> [String.to_integer(major), String.to_integer(minor)]
+      nil -> nil
+    end
+  end
+
+  defp kernel_version(_value), do: nil
+
+  defp path do
+    Path.join(Config.load().data_dir, "eol.json")
+  end
+end
@@ -0,0 +1,119 @@
+defmodule AgentlessMonitor.Monitoring.Fingerprint do
+  @moduledoc """
+  Tracks a fingerprint of each host's environment: OS release, kernel, CPU
+  model and core count, RAM size, block-device layout and IP addresses.
+
+  Fingerprints are kept in `<data_dir>/fingerprints.json` so changes are
+  still detected across restarts. Fields that could not be collected (for
+  example because a collector is disabled) are not compared.
+  """
+
+  use GenServer
+  require Logger
+
+  alias AgentlessMonitor.{Config, DataFile}
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, nil, name: __MODULE__)
+  end
+
+  def get(server_id) do
+    GenServer.call(__MODULE__, {:get, server_id})
+  end
+
+  @doc """
+  Fingerprints a sample and stores it, returning the fields that changed
+  since the previous fingerprint as `%{"field", "previous", "current"}` maps.
+  """
+  def check(server_id, data) do
+    GenServer.call(__MODULE__, {:check, server_id, build(data)})
+  end
+
+  def build(data) do
+    %{
+      "os_release" => data.system_info && blank_to_nil(data.system_info.os_release),
+      "kernel" => data.system_info && blank_to_nil(data.system_info.kernel),
+      "cpu_model" => data.cpu && blank_to_nil(data.cpu.model),
+      "cpu_cores" => data.cpu && data.cpu.cores,
+      "memory_total" => data.memory && data.memory.total,
+      "disks" => disk_layout(data.disks),
+      "ip_addresses" => data.system_info && empty_to_nil(Enum.sort(data.system_info.ip_addresses))
+    }
+  end
+
+  # ---- Callbacks ----
+
+  def init(_) do
+    {:ok, %{fingerprints: load()}}
+  end
+
+  def handle_call({:get, server_id}, _from, state) do
+    case Map.fetch(state.fingerprints, server_id) do
+      {:ok, fingerprint} -> {:reply, {:ok, fingerprint}, state}
+      :error -> {:reply, {:error, :not_found}, state}
+    end
+  end
+
+  def handle_call({:check, server_id, current}, _from, state) do
+    previous = Map.get(state.fingerprints, server_id, %{})
+
+    changes =
+      current
+      |> Enum.sort()
+      |> Enum.flat_map(fn {field, value} ->
+        case previous[field] do
+          old when is_nil(old) or is_nil(value) or old == value -> []
+          old -> [%{"field" => field, "previous" => old, "current" => value}]
+        end
+      end)
+
+    # Keep previously known values for fields missing from this sample.
+    merged = Map.merge(previous, current, fn _field, old, new -> new || old end)
+
+    if merged == previous do
+      {:reply, changes, state}
+    else
+      fingerprints = Map.put(state.fingerprints, server_id, merged)
+      persist(fingerprints)
+      {:reply, changes, %{state | fingerprints: fingerprints}}
+    end
+  end
+
+  # ---- Private helpers ----
+
+  defp disk_layout(disks) do
+    disks
+    |> Enum.filter(&String.starts_with?(&1.device, "/dev/"))
+    |> Enum.map(&"#{&1.device} #{&1.mount_point} #{&1.total}")
+    |> Enum.sort()
+    |> empty_to_nil()
+  end
+
+  defp blank_to_nil(""), do: nil
+  defp blank_to_nil(value), do: value
+
+  defp empty_to_nil([]), do: nil
+  defp empty_to_nil(list), do: list
+
+  defp path do
+    Path.join(Config.load().data_dir, "fingerprints.json")
+  end
+
+  defp load do
+    with {:ok, content} <- File.read(path()),
+         {:ok, fingerprints} when is_map(fingerprints) <- Jason.decode(content) do
+      fingerprints
+    else
+      _ -> %{}
+    end
+  end
+
+  defp persist(fingerprints) do
+    case DataFile.write_atomic(path(), Jason.encode!(fingerprints, pretty: true)) do
+      :ok -> :ok
+      {:error, reason} -> Logger.error("Failed to persist fingerprints: #{inspect(reason)}")
+    end
+  end
+end
@@ -0,0 +1,179 @@
+defmodule AgentlessMonitor.Monitoring.Health do
+  @moduledoc """
+  A 0-100 health score per server, rolled up per group, for sorting the
+  fleet worst first.
+
+  A server's score is the weighted average of three components, each
+  0-100:
+
+    * `metrics` - headroom of the busiest of CPU, memory and disk in the
+      latest sample: 100 up to 70% usage, falling to 0 at 100%
+    * `alerts` - 100 without open incidents for the server, 50 with a
+      warning one, 0 with a critical one; mitigated incidents count as
+      half their severity
+    * `collection` - the share of collectors that succeeded in the latest
+      collection, 0 when it failed
+
+  Weights come from `health_weights` (default `{"metrics": 40, "alerts":
+  40, "collection": 20}`). Components without data are left out and the
+  remaining weights rescaled; a server not collected yet has no score. A
+  group's score is the average of its servers'.
+  """
+
+  alias AgentlessMonitor.{Config, Settings, State}
+  alias AgentlessMonitor.Alerting.Incidents
+  alias AgentlessMonitor.Monitoring.Matrix
+
+  @default_weights %{"metrics" => 40, "alerts" => 40, "collection" => 20}
+  # Usage up to which a resource counts as fully healthy.
+  @comfortable_percent 70
+  @alert_penalty %{"info" => 0, "warning" => 50, "critical" => 100}
+
+  @doc """
+  Score of `server` as `%{"score", "components"}`. `incidents` are the
+  unresolved incidents, looked up when not given.
+  """
+  def server(server, config \\ Config.load(), incidents \\ nil) do
+    incidents = incidents || unresolved_incidents()
+    data = State.get_latest_monitoring_data(server.id)
+
+    components = %{
+      "metrics" => metrics(data),
+      "alerts" => if(data || server.status == "error", do: alerts(server, incidents)),
+      "collection" => collection(server, data, config)
+    }
+
+    %{"score" => score(components, weights(config)), "components" => components}
+  end
+
+  @doc """
+  Fleet overview: every monitored server and every group with its score,
+  worst first, and the fleet's average score.
+  """
+  def overview(servers \\ State.get_servers()) do
+    config = Config.load()
+    incidents = unresolved_incidents()
+
+    scored =
+      servers
+      |> Enum.reject(&(&1.status == "retired"))
+      |> Enum.map(&{&1, Settings.group_for(&1, config), server(&1, config, incidents)})
+
+    server_rows =
+      Enum.map(scored, fn {server, group, health} ->
+        Map.merge(%{"id" => server.id, "name" => server.name, "group" => group}, health)
+      end)
+
+    groups =
+      scored
+      |> Enum.reject(fn {_server, group, _health} -> is_nil(group) end)
+      |> Enum.group_by(fn {_server, group, _health} -> group end)
+      |> Enum.map(fn {group, members} ->
+        scores = for {_server, _group, %{"score" => score}} <- members, score, do: score
+        {worst, _group, _health} = Enum.min_by(members, &sort_key(elem(&1, 2)))
+
+        %{
+          "name" => group,
+          "server_count" => length(members),
+          "score" => average(scores),
+          "worst_server" => worst.name
+        }
+      end)
+
+    fleet = for %{"score" => score} <- server_rows, score, do: score
+
+    %{
+      "score" => average(fleet),
+      "servers" => worst_first(server_rows),
+      "groups" => worst_first(groups)
+    }
+  end
+
+  @doc """
+  Sorts rows worst first by the `score` of `health.(row)` (the row itself
+  by default); rows without a score go last
+  """
+  def worst_first(rows, health \\ & &1), do: Enum.sort_by(rows, &sort_key(health.(&1)))
+
+  # ---- Private helpers ----
+
+  defp sort_key(%{"score" => nil}), do: {1, 0}
+  defp sort_key(%{"score" => score}), do: {0, score}
+
+  defp unresolved_incidents do
+    Enum.reject(Incidents.list(), &(&1.status == "resolved"))
+  end
+
+  defp metrics(nil), do: nil
+
+  defp metrics(data) do
+    usages = [Matrix.cpu_usage(data), Matrix.memory_usage(data), Matrix.disk_usage(data)]
+
+    case Enum.reject(usages, &is_nil/1) do
+      [] -> nil
+      usages -> headroom(Enum.max(usages))
+    end
+  end
+
+  defp headroom(usage) when usage <= @comfortable_percent, do: 100
+
+  defp headroom(usage) do
+    round(max(0, (100 - usage) / (100 - @comfortable_percent) * 100))
+  end
+
+  defp alerts(server, incidents) do
+    penalty =
+      incidents
+      |> Enum.filter(&(&1.server_id == server.id))
+      |> Enum.map(fn incident ->
+        penalty = Map.get(@alert_penalty, incident.severity, 0)
+        if incident.status == "mitigated", do: div(penalty, 2), else: penalty
+      end)
+      |> Enum.max(fn -> 0 end)
+
+    100 - penalty
+  end
+
+  defp collection(%{status: "error"}, _data, _config), do: 0
+  defp collection(_server, nil, _config), do: nil
+
+  defp collection(server, data, config) do
+    collectors = Settings.for_server(server, config)["collectors"] || []
+    failed = data |> Map.get(:failed_collectors, []) |> length()
+
+    case length(collectors) do
+      0 -> 100
+      total -> round(max(0, total - failed) / total * 100)
+    end
+  end
+
+  defp score(components, weights) do
+    weighted =
+      for {name, value} <- components, is_number(value), Map.get(weights, name, 0) > 0 do
+        {value, weights[name]}
+      end
+
+    case Enum.sum(Enum.map(weighted, &elem(&1, 1))) do
+      total when total > 0 ->
+        round(Enum.sum(Enum.map(weighted, fn {value, weight} -> value * weight end)) / total)
+
+      _ ->
+        nil
+    end
+  end
+
+  # Weights that aren't non-negative numbers fall back to the defaults.
+  defp weights(config) do
+    configured = config.health_weights || %{}
+
+    Map.new(@default_weights, fn {name, default} ->
+      case Map.get(configured, name) do
+        weight when is_number(weight) and weight >= 0 -> {name, weight}
+        _ -> {name, default}
+      end
+    end)
+  end
+
+  defp average([]), do: nil
+  defp average(scores), do: round(Enum.sum(scores) / length(scores))
+end
@@ -0,0 +1,108 @@
+defmodule AgentlessMonitor.Monitoring.Hooks do
+  @moduledoc """
+  Commands run before and after every collection of a server, declared in
+  the `collection_hooks` setting (global, per group or per server):
+
+      "collection_hooks": {
+        "before": ["sync"],
+        "after": ["touch /var/run/monitored",
+                  {"command": "/usr/local/bin/notify-cmdb", "local": true}]
+      }
+
+  A hook is a command run on the server, or with `"local": true` a command
+  run on the monitor's machine, with `AGENTLESS_SERVER`, `AGENTLESS_HOST`,
+  `AGENTLESS_PHASE` (`before` or `after`) and, after a collection,
+  `AGENTLESS_RESULT` (`ok` or `error`) in its environment. Hooks run in
+  order and are bounded by `ssh_timeout`.
+
+  A failing hook doesn't stop the collection. Failures are listed in the
+  collection job's `hook_errors`, apart from collector failures, and raise a
+  `collection_hook_failed` warning when a hook starts failing.
+  """
+
+  alias AgentlessMonitor.Config
+  alias AgentlessMonitor.Monitoring.Service
+
+  @phases ["before", "after"]
+
+  @doc "The hooks of `phase` in `settings`, as `%{\"command\", \"local\"}`"
+  def declarations(settings, phase) when phase in @phases do
+    hooks = settings["collection_hooks"] || %{}
+
+    for hook <- List.wrap(hooks[phase]), declaration <- [normalize(hook)], declaration do
+      declaration
+    end
+  end
+
+  @doc "Remote hook commands of `settings`, as `{name, command}`"
+  def remote_commands(settings) do
+    for phase <- @phases,
+        {hook, index} <- Enum.with_index(declarations(settings, phase), 1),
+        not hook["local"],
+        do: {"hook #{phase} #{index}", hook["command"]}
+  end
+
+  @doc """
+  Runs the hooks of `phase` for `server`; `result` is the collection's
+  outcome (`"ok"` or `"error"`) for `after` hooks. Returns the failures as
+  `%{"phase", "index", "command", "error"}`.
+  """
+  def run(server, settings, phase, result \\ nil) do
+    timeout = Config.load().ssh_timeout
+
+    settings
+    |> declarations(phase)
+    |> Enum.with_index(1)
+    |> Enum.flat_map(fn {hook, index} ->
+      case execute(server, hook, phase, result, timeout) do
+        :ok ->
+          []
+
+        {:error, output} ->
+          error = output |> message() |> String.trim() |> String.slice(0, 200)
+          [
+            %{"phase" => phase, "index" => index, "command" => hook["command"], "error" => error}
+          ]
+      end
+    end)
+  end
+
+  # ---- Private helpers ----
+
+  defp normalize(command) when is_binary(command) and command != "",
+    do: %{"command" => command, "local" => false}
+
+  defp normalize(%{"command" => command} = hook) when is_binary(command) and command != "",
+    do: %{"command" => command, "local" => hook["local"] == true}
+
+  defp normalize(_hook), do: nil
+
+  defp message(output) when is_binary(output), do: output
+  defp message(output) when is_atom(output), do: Atom.to_string(output)
+  defp message(output), do: inspect(output)
+
+  defp execute(server, %{"local" => true, "command" => command}, phase, result, timeout) do
+    env =
+      [
+        {"AGENTLESS_SERVER", server.name},
+        {"AGENTLESS_HOST", server.host || ""},
+        {"AGENTLESS_PHASE", phase}
+      ] ++ if(result, do: [{"AGENTLESS_RESULT", result}], else: [])
+
+    task =
+      Task.async(fn -> System.cmd("sh", ["-c", command], env: env, stderr_to_stdout: true) end)
+
+    case Task.yield(task, timeout * 1000) || Task.shutdown(task, :brutal_kill) do
+      {:ok, {_output, 0}} -> :ok
+      {:ok, {output, _code}} -> {:error, output}
+      nil -> {:error, "timeout"}
+    end
+  end
+
+  defp execute(server, %{"command" => command}, _phase, _result, timeout) do
+    case Service.run_command(server, command, timeout: timeout) do
+      {:ok, _output} -> :ok
+      {:error, output} -> {:error, output}
+    end
+  end
+end
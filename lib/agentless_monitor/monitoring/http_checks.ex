@@ -0,0 +1,177 @@
+defmodule AgentlessMonitor.Monitoring.HttpChecks do
+  @moduledoc """
+  HTTP(S) checks of the services a server runs, declared in the
+  `http_checks` setting (global, per group or per server) and run with
+  every collection:
+
+      "http_checks": [
+        {"name": "api", "url": "https://api.example.com/health",
+         "expect_status": 200, "keyword": "\\"status\\":\\"ok\\"", "timeout": 5},
+        {"name": "admin", "url": "http://127.0.0.1:8081/", "from": "target",
+         "expect_status": [200, 302]}
+      ]
+
+  A check runs `from` the monitor (the default) or from the target, with
+  `curl` over the collection's SSH connection, which reaches services bound
+  to loopback or a private network. It passes when the response status is
+  one of `expect_status` (default 200) and, with a `keyword`, the body
+  contains it. `timeout` (default 10 seconds) bounds the whole request;
+  `verify_tls: false` accepts any certificate. Samples carry the results
+  in `http_checks`.
+  """
+
+  alias AgentlessMonitor.Models.HttpCheck
+
+  @default_timeout 10
+  # Bodies are searched for the keyword up to this size.
+  @max_body 1_048_576
+
+  @doc "The usable declarations in `settings`; incomplete ones are skipped"
+  def declarations(settings) do
+    for %{"name" => name, "url" => url} = check <- settings["http_checks"] || [],
+        is_binary(name) and name != "" and is_binary(url),
+        String.starts_with?(url, ["http://", "https://"]),
+        Map.get(check, "from", "monitor") in ["monitor", "target"] do
+      check
+      |> Map.put_new("from", "monitor")
+      |> Map.put_new("timeout", @default_timeout)
+      |> Map.update("expect_status", [200], &List.wrap/1)
+    end
+  end
+
+  @doc "Runs a check from the monitor"
+  def run(check) do
+    started = System.monotonic_time(:millisecond)
+    timeout = check["timeout"] * 1000
+
+    request = {String.to_charlist(check["url"]), [{~c"user-agent", ~c"agentless-monitor"}]}
+    http_options = [timeout: timeout, connect_timeout: timeout, ssl: ssl_options(check)]
+    options = [body_format: :binary]
+
+    case :httpc.request(:get, request, http_options, options) do
+      {:ok, {{_, status, _}, _headers, body}} ->
+        latency = System.monotonic_time(:millisecond) - started
+        result(check, status, latency, keyword_found?(check, binary_part_max(body)), nil)
+
+      {:error, reason} ->
+        result(check, nil, nil, false, format_error(reason))
+    end
+  end
+
+  @doc """
+  One shell command running the target-side checks with `curl` one after
+  the other, their outputs separated like the mega command.
+  """
+  def command(checks) do
+    Enum.map_join(checks, "; echo '---SEP---'; ", &curl/1)
+  end
+
+  @doc "Results from the output of `command/1`"
+  def parse(output, checks) do
+    sections = String.split(output, "---SEP---\n")
+
+    checks
+    |> Enum.with_index()
+    |> Enum.map(fn {check, index} -> parse_section(Enum.at(sections, index, ""), check) end)
+  end
+
+  @doc "Every check failed with `error`, when the remote command couldn't run"
+  def failed(checks, error) do
+    Enum.map(checks, &result(&1, nil, nil, false, error))
+  end
+
+  # ---- Private helpers ----
+
+  # `HTTP <status> <seconds>` from curl's write-out, then the keyword's
+  # match count; curl's own error, if any, comes first.
+  defp curl(check) do
+    insecure = if check["verify_tls"] == false, do: " -k", else: ""
+
+    keyword =
+      case check["keyword"] do
+        keyword when is_binary(keyword) and keyword != "" ->
+          "head -c #{@max_body} \"$f\" | grep -cF -- #{shell_quote(keyword)}"
+
+        _ ->
+          "true"
+      end
+
+    "(f=$(mktemp); curl -sS#{insecure} -m #{check["timeout"]} -o \"$f\" " <>
+      "-w 'HTTP %{http_code} %{time_total}\\n' #{shell_quote(check["url"])} 2>&1; " <>
+      "#{keyword}; rm -f \"$f\")"
+  end
+
+  defp parse_section(text, check) do
+    case Regex.run(~r/HTTP (\d{3}) ([\d.]+)/, text) do
+      [_, "000", _seconds] ->
+        result(check, nil, nil, false, curl_error(text))
+
+      [_, status, seconds] ->
+        count = text |> String.split("\n", trim: true) |> List.last() |> to_string()
+        found = match?({n, ""} when n > 0, Integer.parse(String.trim(count)))
+        {seconds, _} = Float.parse(seconds)
+        latency = round(seconds * 1000)
+        result(check, String.to_integer(status), latency, found, nil)
+
+      nil ->
+        result(check, nil, nil, false, curl_error(text))
+    end
+  end
+
+  defp curl_error(text) do
+    case Regex.run(~r/curl: \(\d+\) (.+)/, text) do
+      [_, message] -> String.trim(message)
+      nil -> if String.trim(text) == "", do: "no output", else: String.trim(text)
+    end
+  end
+
+  defp result(check, status, latency, keyword_found, error) do
+    keyword = check["keyword"] not in [nil, ""]
+
+    error =
+      cond do
+        error -> error
+        status not in check["expect_status"] -> "unexpected status #{status}"
+        keyword and not keyword_found -> "keyword not found"
+        true -> nil
+      end
+
+    %HttpCheck{
+      name: check["name"],
+      url: check["url"],
+      from: check["from"],
+      status_code: status,
+      latency_ms: latency,
+      success: is_nil(error),
+      error: error
+    }
+  end
+
+  defp keyword_found?(%{"keyword" => keyword}, body) when is_binary(keyword) and keyword != "",
+    do: String.contains?(body, keyword)
+
+  defp keyword_found?(_check, _body), do: false
+
+  defp binary_part_max(body) when byte_size(body) > @max_body,
+    do: binary_part(body, 0, @max_body)
+
+  defp binary_part_max(body), do: body
+
+  defp ssl_options(%{"verify_tls" => false}), do: [verify: :verify_none]
+
+  defp ssl_options(_check) do
+    [
+      verify: :verify_peer,
+      cacerts: :public_key.cacerts_get(),
+      customize_hostname_check: [match_fun: :public_key.pkix_verify_hostname_match_fun(:https)]
+    ]
+  end
+
+  defp format_error({:failed_connect, [{:to_address, _}, {_family, _, reason} | _]}),
+    do: "connection failed: #{inspect(reason)}"
+
+  defp format_error(:timeout), do: "timed out"
+  defp format_error(reason), do: inspect(reason)
+
+  defp shell_quote(value), do: "'" <> String.replace(value, "'", "'\\''") <> "'"
+end
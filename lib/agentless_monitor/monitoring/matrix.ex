@@ -0,0 +1,177 @@
+defmodule AgentlessMonitor.Monitoring.Matrix do
+  @moduledoc """
+  Builds servers × time-bucket matrices of a single metric for heatmaps.
+
+  Each server's history is walked once, newest first, stopping at the start
+  of the window; samples are grouped into `step`-sized buckets and reduced
+  with `avg`, `min` or `max`. Buckets without samples are `nil`.
+  """
+
+  alias AgentlessMonitor.Models.MonitoringData
+  alias AgentlessMonitor.Monitoring.CustomMetrics
+
+  @max_buckets 1000
+  @aggregations ["avg", "min", "max"]
+
+  @metrics %{
+    "cpu.usage_percent" => &__MODULE__.cpu_usage/1,
+    "cpu.load_average" => &__MODULE__.load_average/1,
+    "memory.usage_percent" => &__MODULE__.memory_usage/1,
+    "swap.usage_percent" => &__MODULE__.swap_usage/1,
+    "swap.out_rate" => &__MODULE__.swap_out_rate/1,
+    "disk.max_usage_percent" => &__MODULE__.disk_usage/1,
+    "ping.latency_ms" => &__MODULE__.ping_latency/1,
+    "ping.success_percent" => &__MODULE__.ping_success/1
+  }
+
+  def metrics, do: @metrics |> Map.keys() |> Enum.sort()
+
+  @doc """
+  `histories` is a list of `{server, samples}` with samples newest first.
+  Returns `{:ok, matrix}` or `{:error, message}`.
+  """
+  def build(histories, metric, step, window, aggregation \\ "avg") do
+    with {:ok, extract} <- fetch_metric(metric),
+         {:ok, step_s} <- parse_duration(step),
+         {:ok, window_s} <- parse_duration(window),
+         :ok <- validate(step_s, window_s, aggregation) do
+      count = div(window_s + step_s - 1, step_s)
+      now = System.system_time(:second)
+      start = now - count * step_s
+
+      rows =
+        Enum.map(histories, fn {server, samples} ->
+          %{
+            "id" => server.id,
+            "name" => server.name,
+            "values" => row(samples, extract, start, step_s, count, aggregation)
+          }
+        end)
+
+      {:ok,
+       %{
+         "metric" => metric,
+         "aggregation" => aggregation,
+         "step" => step_s,
+         "window" => window_s,
+         "buckets" => Enum.map(0..(count - 1), &iso8601(start + &1 * step_s)),
+         "servers" => rows
+       }}
+    end
+  end
+
+  @doc "Parses durations such as `30s`, `5m`, `6h` or `7d` into seconds"
+  def parse_duration(value) when is_binary(value) do
+    case Integer.parse(value) do
+      {n, unit} when n > 0 and unit in ["", "s"] -> {:ok, n}
+      {n, "m"} when n > 0 -> {:ok, n * 60}
+      {n, "h"} when n > 0 -> {:ok, n * 3600}
+      {n, "d"} when n > 0 -> {:ok, n * 86_400}
+      _ -> {:error, "invalid duration #{inspect(value)}"}
+    end
+  end
+
+  def parse_duration(_value), do: {:error, "invalid duration"}
+
+  # ---- Metric extractors ----
+
+  def cpu_usage(%MonitoringData{cpu: %{usage_percent: value}}), do: value
+  def cpu_usage(_data), do: nil
+
+  def load_average(%MonitoringData{cpu: %{load_average: [one | _]}}), do: one
+  def load_average(_data), do: nil
+
+  def memory_usage(%MonitoringData{memory: %{total: total, used: used}}) when total > 0,
+    do: Float.round(used / total * 100, 1)
+
+  def memory_usage(_data), do: nil
+
+  def swap_usage(%MonitoringData{memory: %{swap_total: total, swap_used: used}}) when total > 0,
+    do: Float.round(used / total * 100, 1)
+
+  def swap_usage(_data), do: nil
+
+  def swap_out_rate(%MonitoringData{memory: %{swap_out_rate: rate}}), do: rate
+  def swap_out_rate(_data), do: nil
+
+  def disk_usage(%MonitoringData{disks: [_ | _] = disks}),
+    do: disks |> Enum.map(& &1.usage_percent) |> Enum.max()
+
+  def disk_usage(_data), do: nil
+
+  def ping_latency(%MonitoringData{ping_tests: tests}) do
+    case for(%{success: true, latency_ms: ms} <- tests, is_number(ms), do: ms) do
+      [] -> nil
+      latencies -> Float.round(Enum.sum(latencies) / length(latencies), 2)
+    end
+  end
+
+  def ping_success(%MonitoringData{ping_tests: []}), do: nil
+
+  def ping_success(%MonitoringData{ping_tests: tests}),
+    do: Float.round(Enum.count(tests, & &1.success) / length(tests) * 100, 1)
+
+  # ---- Private helpers ----
+
+  defp fetch_metric("custom." <> name), do: {:ok, &CustomMetrics.value(&1, name)}
+
+  defp fetch_metric(metric) do
+    case Map.fetch(@metrics, metric) do
+      {:ok, extract} ->
+        {:ok, extract}
+
+      :error ->
+        {:error, "unknown metric; expected custom.<name> or one of #{Enum.join(metrics(), ", ")}"}
+    end
+  end
+
+  defp validate(step, window, aggregation) do
+    cond do
+      aggregation not in @aggregations ->
+        {:error, "aggregation must be one of #{Enum.join(@aggregations, ", ")}"}
+
+      div(window, step) > @max_buckets ->
+        {:error, "window / step must not exceed #{@max_buckets} buckets"}
+
+      true ->
+        :ok
+    end
+  end
+
+  defp row(samples, extract, start, step, count, aggregation) do
+    buckets =
+      samples
+      |> Stream.map(&{unix_time(&1.timestamp), extract.(&1)})
+      |> Stream.take_while(fn {ts, _value} -> is_nil(ts) or ts >= start end)
+      |> Enum.reduce(%{}, fn
+        {ts, value}, acc when is_integer(ts) and is_number(value) ->
+          index = min(div(ts - start, step), count - 1)
+          Map.update(acc, index, [value], &[value | &1])
+
+        _sample, acc ->
+          acc
+      end)
+
+    Enum.map(0..(count - 1), fn index ->
+      case Map.get(buckets, index) do
+        nil -> nil
+        values -> aggregate(values, aggregation)
+      end
+    end)
+  end
+
+  defp aggregate(values, "avg"), do: Float.round(Enum.sum(values) / length(values), 2)
+  defp aggregate(values, "min"), do: Enum.min(values)
+  defp aggregate(values, "max"), do: Enum.max(values)
+
+  defp unix_time(timestamp) when is_binary(timestamp) do
+    case DateTime.from_iso8601(timestamp) do
+      {:ok, dt, _} -> DateTime.to_unix(dt)
+      _ -> nil
+    end
+  end
+
+  defp unix_time(_timestamp), do: nil
+
+  defp iso8601(unix), do: unix |> DateTime.from_unix!() |> DateTime.to_iso8601()
+end
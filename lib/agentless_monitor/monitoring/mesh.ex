@@ -0,0 +1,219 @@
+defmodule AgentlessMonitor.Monitoring.Mesh do
+  @moduledoc """
+  Mesh pings: monitored servers pinging each other, for east-west latency
+  and loss between the nodes of a cluster.
+
+  A server takes part when its `mesh_peers` setting (global, per group or
+  per server, like `ping_targets`) lists other monitored servers, by name
+  pattern (`*` matches any run of characters) or as `group:<name>`:
+
+      "groups": {"k8s": {"servers": ["node-*"], "mesh_peers": ["group:k8s"]}}
+
+  Every `mesh_interval` seconds (default 60, `0` disables) each of them
+  sends 4 pings to every peer's host, concurrently. `matrix/0` reports the
+  latest latency and loss per pair. Peers with the `local` transport are
+  skipped, since they have no address of their own, and SNMP devices are
+  pinged but don't ping.
+  """
+
+  use GenServer
+  require Logger
+
+  alias AgentlessMonitor.{Config, Settings, State}
+  alias AgentlessMonitor.Monitoring.Service
+
+  @count 4
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, nil, name: __MODULE__)
+  end
+
+  @doc "Shell command pinging `host`, waiting up to `wait` seconds per reply"
+  def command(host, wait) do
+    "ping -c #{@count} -W #{wait} '#{String.replace(host, "'", "'\\''")}' 2>&1"
+  end
+
+  @doc "Mesh commands for servers with `settings`, as `{name, command}`"
+  def commands(settings, config \\ Config.load()) do
+    servers = State.get_servers()
+
+    for peer <- peers(settings, servers, config) do
+      {"mesh #{peer.name}", command(peer.host, config.ping_timeout)}
+    end
+  end
+
+  @doc """
+  Latest results as `%{"servers", "matrix"}`: `servers` lists the names of
+  every server taking part and `matrix[from][to]` holds `%{"latency_ms",
+  "loss_percent", "checked_at", "error"}`.
+  """
+  def matrix do
+    results = GenServer.call(__MODULE__, :results)
+    names = State.get_servers() |> Map.new(&{&1.id, &1.name})
+
+    matrix =
+      for {from_id, peers} <- results, Map.has_key?(names, from_id), into: %{} do
+        row =
+          for {to_id, cell} <- peers, Map.has_key?(names, to_id), into: %{} do
+            {names[to_id], cell}
+          end
+
+        {names[from_id], row}
+      end
+
+    servers =
+      matrix
+      |> Enum.flat_map(fn {from, row} -> [from | Map.keys(row)] end)
+      |> Enum.uniq()
+      |> Enum.sort()
+
+    %{"servers" => servers, "matrix" => matrix}
+  end
+
+  @doc """
+  Parses ping output (iputils or BusyBox) into `%{"latency_ms",
+  "loss_percent", "error"}`; latency is the average round trip.
+  """
+  def parse(output) do
+    sent_received =
+      Regex.run(~r/(\d+) packets transmitted, (\d+) (?:packets )?received/, output)
+
+    latency =
+      case Regex.run(~r{= [\d.]+/([\d.]+)/}, output) do
+        [_, avg] -> avg |> Float.parse() |> elem(0)
+        nil -> nil
+      end
+
+    case sent_received do
+      [_, sent, received] when sent != "0" ->
+        sent = String.to_integer(sent)
+        lost = sent - String.to_integer(received)
+        loss = Float.round(lost / sent * 100, 1)
+        error = if loss == 100.0, do: "host unreachable"
+
+        %{"latency_ms" => latency, "loss_percent" => loss, "error" => error}
+
+      _ ->
+        error = output |> String.trim() |> String.slice(0, 100)
+        %{"latency_ms" => nil, "loss_percent" => 100.0, "error" => error}
+    end
+  end
+
+  # ---- Callbacks ----
+
+  def init(_) do
+    schedule_refresh(Config.load().mesh_interval)
+    {:ok, %{results: %{}}}
+  end
+
+  def handle_call(:results, _from, state) do
+    {:reply, state.results, state}
+  end
+
+  # Pings run outside the server so `matrix/0` stays responsive.
+  def handle_info(:refresh, state) do
+    interval = Config.load().mesh_interval
+
+    if enabled?(interval) do
+      parent = self()
+
+      Task.Supervisor.start_child(AgentlessMonitor.TaskSupervisor, fn ->
+        send(parent, {:refreshed, refresh()})
+      end)
+    end
+
+    schedule_refresh(interval)
+    {:noreply, state}
+  end
+
+  def handle_info({:refreshed, collected}, state) do
+    known = Enum.map(State.get_servers(), & &1.id)
+    {:noreply, %{state | results: state.results |> Map.merge(collected) |> Map.take(known)}}
+  end
+
+  # ---- Private helpers ----
+
+  defp enabled?(interval), do: is_integer(interval) and interval > 0
+
+  # When disabled, check again later in case the configuration is reloaded.
+  defp schedule_refresh(interval) do
+    delay = if enabled?(interval), do: :timer.seconds(interval), else: :timer.minutes(5)
+    Process.send_after(self(), :refresh, delay)
+  end
+
+  defp refresh do
+    config = Config.load()
+    all = State.get_servers()
+
+    servers =
+      all
+      |> Enum.reject(&(&1.status == "retired"))
+      |> Enum.reject(&State.is_paused?(&1.id))
+      |> Enum.reject(&(&1.transport == "snmp"))
+
+    sweeps =
+      Enum.flat_map(servers, fn server ->
+        case peers(Settings.for_server(server, config), all, config) do
+          [] -> []
+          peers -> [{server, Enum.reject(peers, &(&1.id == server.id))}]
+        end
+      end)
+
+    AgentlessMonitor.TaskSupervisor
+    |> Task.Supervisor.async_stream_nolink(sweeps, &sweep(&1, config),
+      timeout: (@count + config.ping_timeout + config.ssh_timeout + 5) * 1000,
+      on_timeout: :kill_task
+    )
+    |> Enum.zip(sweeps)
+    |> Enum.reduce(%{}, fn
+      {{:ok, cells}, {server, _peers}}, acc ->
+        Map.put(acc, server.id, cells)
+
+      {_failed, {server, _peers}}, acc ->
+        Logger.debug("Mesh pings timed out for #{server.name}")
+        acc
+    end)
+  end
+
+  defp sweep({server, peers}, config) do
+    checked_at = DateTime.utc_now() |> DateTime.to_iso8601()
+
+    peers
+    |> Task.async_stream(
+      fn peer ->
+        case Service.run_command(server, command(peer.host, config.ping_timeout)) do
+          {:ok, output} -> parse(output)
+          {:error, output} -> parse(to_string(output))
+        end
+      end,
+      timeout: (@count + config.ping_timeout + config.ssh_timeout) * 1000,
+      on_timeout: :kill_task
+    )
+    |> Enum.zip(peers)
+    |> Map.new(fn
+      {{:ok, cell}, peer} ->
+        {peer.id, Map.put(cell, "checked_at", checked_at)}
+
+      {{:exit, _reason}, peer} ->
+        cell = %{"latency_ms" => nil, "loss_percent" => 100.0, "error" => "timed out"}
+        {peer.id, Map.put(cell, "checked_at", checked_at)}
+    end)
+  end
+
+  defp peers(settings, servers, config) do
+    patterns = settings["mesh_peers"] || []
+
+    Enum.filter(servers, fn server ->
+      server.status != "retired" and server.transport != "local" and
+        Enum.any?(patterns, &peer_match?(&1, server, config))
+    end)
+  end
+
+  defp peer_match?("group:" <> group, server, config) do
+    Settings.group_for(server, config) == group
+  end
+
+  defp peer_match?(pattern, server, _config), do: Settings.glob_match?(pattern, server.name)
+end
@@ -0,0 +1,81 @@
+defmodule AgentlessMonitor.Monitoring.MTU do
+  @moduledoc """
+  Path MTU diagnostic run from a monitored server.
+
+  `command/2` builds a shell script that binary-searches the largest ICMP
+  payload reaching `destination` with the Don't Fragment bit set
+  (`ping -M do -s <size>`), printing one `<size> ok|fail` line per probe;
+  `parse/2` turns that output into a report. The path MTU is the payload
+  plus 28 bytes of IPv4 and ICMP headers; anything below 1500 usually means
+  a tunnel or PPPoE hop, and a path that silently drops large packets
+  instead of answering "fragmentation needed" shows up the same way.
+  """
+
+  @headers 28
+  @ethernet_mtu 1500
+  @min_size 0
+  @max_size @ethernet_mtu - @headers
+
+  def max_size, do: @max_size
+
+  @doc "Shell script probing `destination` with payloads up to `max_size`"
+  def command(destination, max_size \\ @max_size) do
+    dest = shell_quote(destination)
+
+    """
+    probe() { ping -M do -c 1 -W 1 -s "$1" #{dest} >/dev/null 2>&1; }
+    if probe #{@min_size}; then echo "#{@min_size} ok"; else echo "#{@min_size} fail"; exit 0; fi
+    lo=#{@min_size + 1}; hi=#{max_size}
+    while [ "$lo" -le "$hi" ]; do
+      mid=$(( (lo + hi) / 2 ))
+      if probe "$mid"; then echo "$mid ok"; lo=$((mid + 1)); else echo "$mid fail"; hi=$((mid - 1)); fi
+    done
+    """
+  end
+
+  @doc """
+  Parses the output of `command/2` into `{:ok, report}`, or `{:error, message}`
+  when not even an empty payload got through.
+  """
+  def parse(output, destination) do
+    probes =
+      output
+      |> String.split("\n", trim: true)
+      |> Enum.flat_map(fn line ->
+        case String.split(line) do
+          [size, result] when result in ["ok", "fail"] ->
+            case Integer.parse(size) do
+              {size, ""} -> [%{"size" => size, "ok" => result == "ok"}]
+              _ -> []
+            end
+
+          _ ->
+            []
+        end
+      end)
+
+    case for(%{"ok" => true, "size" => size} <- probes, do: size) do
+      [] ->
+        {:error,
+         "#{destination} did not answer a #{@min_size}-byte ping with DF set " <>
+           "(unreachable, or ping lacks -M)"}
+
+      sizes ->
+        payload = Enum.max(sizes)
+        mtu = payload + @headers
+
+        {:ok,
+         %{
+           "destination" => destination,
+           "max_payload" => payload,
+           "path_mtu" => mtu,
+           "fragmentation_issue" => mtu < @ethernet_mtu,
+           "probes" => probes
+         }}
+    end
+  end
+
+  defp shell_quote(value) do
+    "'" <> String.replace(value, "'", "'\\''") <> "'"
+  end
+end
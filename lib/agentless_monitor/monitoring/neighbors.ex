@@ -0,0 +1,282 @@
+defmodule AgentlessMonitor.Monitoring.Neighbors do
+  @moduledoc """
+  Layer 2/3 adjacency discovered from each host's neighbor table (`ip neigh`)
+  and, where lldpd is installed, its LLDP neighbors (`lldpctl`).
+
+  Every `neighbor_interval` seconds (default 300, `0` disables) the tables
+  of all active servers are refreshed. `discovered/0` links servers whose
+  neighbor entries point at another monitored server's host or addresses
+  (ARP/NDP) or whose LLDP neighbor reports its name; other neighbors are
+  listed as unknown endpoints. This is a hint, not a source of truth: ARP
+  entries expire and only hosts on the same segment show up.
+
+  Servers are matched on the addresses of their latest collection as
+  recorded by `record_addresses/2`, before privacy mode strips them from
+  the stored sample.
+  """
+
+  use GenServer
+  require Logger
+
+  alias AgentlessMonitor.{Config, State}
+  alias AgentlessMonitor.Monitoring.Service
+
+  @command """
+  ip neigh show 2>/dev/null
+  echo ---SEP---
+  if command -v lldpctl >/dev/null 2>&1; then lldpctl -f keyvalue 2>/dev/null; fi
+  """
+
+  @unusable_states ["FAILED", "INCOMPLETE"]
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, nil, name: __MODULE__)
+  end
+
+  def command, do: @command
+
+  @doc "Latest neighbor tables, keyed by server id"
+  def tables do
+    GenServer.call(__MODULE__, :tables)
+  end
+
+  @doc "Records the addresses a sample found on a server, before it is redacted"
+  def record_addresses(server_id, %{system_info: %{ip_addresses: ips}}) when is_list(ips) do
+    GenServer.cast(__MODULE__, {:addresses, server_id, ips})
+  end
+
+  def record_addresses(_server_id, _data), do: :ok
+
+  @doc """
+  Adjacency between monitored servers: `nodes` for every server with a
+  neighbor table, `edges` as `%{"from", "to", "via", "interface", "known"}`
+  where `to` is a server name when `known`, and an address or LLDP system
+  name otherwise.
+  """
+  def discovered do
+    servers = Enum.reject(State.get_servers(), &(&1.status == "retired"))
+    tables = tables()
+    by_address = address_index(servers, GenServer.call(__MODULE__, :addresses))
+    by_name = Map.new(servers, &{&1.name, &1})
+
+    nodes =
+      Enum.flat_map(servers, fn server ->
+        case tables[server.id] do
+          nil ->
+            []
+
+          table ->
+            [
+              %{
+                "id" => server.id,
+                "name" => server.name,
+                "collected_at" => table["collected_at"],
+                "lldp" => table["lldp"] != []
+              }
+            ]
+        end
+      end)
+
+    edges =
+      Enum.flat_map(servers, fn server ->
+        case tables[server.id] do
+          nil -> []
+          table -> edges(server, table, by_address, by_name)
+        end
+      end)
+
+    %{"nodes" => Enum.sort_by(nodes, & &1["name"]), "edges" => Enum.uniq(edges)}
+  end
+
+  @doc "Parses the output of the neighbor command into `arp` and `lldp` entries"
+  def parse(output) do
+    [neigh, lldp] =
+      case String.split(output, "---SEP---\n", parts: 2) do
+        [neigh, lldp] -> [neigh, lldp]
+        [neigh] -> [neigh, ""]
+      end
+
+    %{"arp" => parse_neigh(neigh), "lldp" => parse_lldp(lldp)}
+  end
+
+  # ---- Callbacks ----
+
+  def init(_) do
+    schedule_refresh(Config.load().neighbor_interval)
+    {:ok, %{tables: %{}, addresses: %{}}}
+  end
+
+  def handle_call(:tables, _from, state) do
+    {:reply, state.tables, state}
+  end
+
+  def handle_call(:addresses, _from, state) do
+    {:reply, state.addresses, state}
+  end
+
+  def handle_cast({:addresses, server_id, ips}, state) do
+    {:noreply, %{state | addresses: Map.put(state.addresses, server_id, ips)}}
+  end
+
+  # Collection runs outside the server so `tables/0` stays responsive.
+  def handle_info(:refresh, state) do
+    interval = Config.load().neighbor_interval
+
+    if enabled?(interval) do
+      parent = self()
+
+      Task.Supervisor.start_child(AgentlessMonitor.TaskSupervisor, fn ->
+        send(parent, {:refreshed, refresh()})
+      end)
+    end
+
+    schedule_refresh(interval)
+    {:noreply, state}
+  end
+
+  def handle_info({:refreshed, collected}, state) do
+    known = Enum.map(State.get_servers(), & &1.id)
+
+    {:noreply,
+     %{
+       state
+       | tables: state.tables |> Map.merge(collected) |> Map.take(known),
+         addresses: Map.take(state.addresses, known)
+     }}
+  end
+
+  # ---- Private helpers ----
+
+  defp enabled?(interval), do: is_integer(interval) and interval > 0
+
+  # When disabled, check again later in case the configuration is reloaded.
+  defp schedule_refresh(interval) do
+    delay = if enabled?(interval), do: :timer.seconds(interval), else: :timer.minutes(5)
+    Process.send_after(self(), :refresh, delay)
+  end
+
+  defp refresh do
+    servers =
+      State.get_servers()
+      |> Enum.reject(&(&1.status == "retired"))
+      |> Enum.reject(&State.is_paused?(&1.id))
+
+    AgentlessMonitor.TaskSupervisor
+    |> Task.Supervisor.async_stream_nolink(servers, &collect/1,
+      timeout: 30_000,
+      on_timeout: :kill_task
+    )
+    |> Enum.zip(servers)
+    |> Enum.reduce(%{}, fn
+      {{:ok, {:ok, table}}, server}, acc ->
+        Map.put(acc, server.id, table)
+
+      {_failed, server}, acc ->
+        Logger.debug("Neighbor collection failed for #{server.name}")
+        acc
+    end)
+  end
+
+  defp collect(server) do
+    case Service.run_command(server, @command) do
+      {:ok, output} ->
+        collected_at = DateTime.utc_now() |> DateTime.to_iso8601()
+        {:ok, Map.put(parse(output), "collected_at", collected_at)}
+
+      {:error, reason} ->
+        {:error, reason}
+    end
+  end
+
+  # `10.0.0.1 dev eth0 lladdr 52:54:00:12:34:56 REACHABLE`
+  defp parse_neigh(output) do
+    output
+    |> String.split("\n", trim: true)
+    |> Enum.flat_map(fn line ->
+      case String.split(line) do
+        [address, "dev", interface | rest] ->
+          state = List.last(rest)
+
+          if "lladdr" in rest and state not in @unusable_states do
+            mac = rest |> Enum.drop_while(&(&1 != "lladdr")) |> Enum.at(1)
+            [%{"address" => address, "interface" => interface, "mac" => mac, "state" => state}]
+          else
+            []
+          end
+
+        _ ->
+          []
+      end
+    end)
+  end
+
+  # `lldp.eth0.chassis.name=switch-01`, `lldp.eth0.port.ifname=Gi0/1`, ...
+  defp parse_lldp(output) do
+    output
+    |> String.split("\n", trim: true)
+    |> Enum.reduce(%{}, fn line, acc ->
+      with "lldp." <> rest <- line,
+           [key, value] <- String.split(rest, "=", parts: 2),
+           [interface, field] <- String.split(key, ".", parts: 2) do
+        Map.update(acc, interface, %{field => value}, &Map.put_new(&1, field, value))
+      else
+        _ -> acc
+      end
+    end)
+    |> Enum.map(fn {interface, fields} ->
+      %{
+        "interface" => interface,
+        "system_name" => fields["chassis.name"],
+        "management_ip" => fields["chassis.mgmt-ip"],
+        "port" => fields["port.ifname"] || fields["port.descr"]
+      }
+    end)
+    |> Enum.sort_by(& &1["interface"])
+  end
+
+  # Until a server's first collection since startup, the stored sample's
+  # addresses are used.
+  defp address_index(servers, recorded) do
+    Enum.reduce(servers, %{}, fn server, acc ->
+      addresses =
+        Map.get_lazy(recorded, server.id, fn ->
+          case State.get_latest_monitoring_data(server.id) do
+            %{system_info: %{ip_addresses: ips}} when is_list(ips) -> ips
+            _ -> []
+          end
+        end)
+
+      Enum.reduce([server.host | addresses], acc, &Map.put_new(&2, &1, server.name))
+    end)
+  end
+
+  defp edges(server, table, by_address, by_name) do
+    arp =
+      for entry <- table["arp"], by_address[entry["address"]] != server.name do
+        edge(server, by_address[entry["address"]] || entry["address"], "arp", entry["interface"])
+      end
+
+    lldp =
+      for entry <- table["lldp"], entry["system_name"] || entry["management_ip"] do
+        name = entry["system_name"]
+        address = entry["management_ip"]
+
+        target =
+          cond do
+            Map.has_key?(by_name, name) -> name
+            Map.has_key?(by_address, address) -> by_address[address]
+            true -> name || address
+          end
+
+        edge(server, target, "lldp", entry["interface"])
+      end
+
+    Enum.map(arp ++ lldp, &Map.put(&1, "known", Map.has_key?(by_name, &1["to"])))
+  end
+
+  defp edge(server, to, via, interface) do
+    %{"from" => server.name, "to" => to, "via" => via, "interface" => interface}
+  end
+end
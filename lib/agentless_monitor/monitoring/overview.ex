@@ -0,0 +1,69 @@
+defmodule AgentlessMonitor.Monitoring.Overview do
+  @moduledoc """
+  Fleet-wide aggregates from the latest sample of every monitored server,
+  so a dashboard gets the whole picture in one request: server counts per
+  status, average CPU and memory usage, the five busiest servers by CPU,
+  memory and disk, the number of active alerts and the health scores of
+  `AgentlessMonitor.Monitoring.Health`.
+  """
+
+  alias AgentlessMonitor.{ServerStatus, State}
+  alias AgentlessMonitor.Alerting.Incidents
+  alias AgentlessMonitor.Monitoring.{Health, Matrix}
+
+  @top 5
+  @statuses ~w(online degraded error unknown maintenance paused)
+
+  @doc "The overview of `servers` (retired ones are left out)"
+  def build(servers \\ State.get_servers()) do
+    servers = Enum.reject(servers, &(&1.status == "retired"))
+    latest = Enum.map(servers, &{&1, State.get_latest_monitoring_data(&1.id)})
+
+    counts =
+      @statuses
+      |> Map.new(&{&1, 0})
+      |> Map.merge(Enum.frequencies_by(servers, &ServerStatus.effective/1))
+      |> Map.put("total", length(servers))
+
+    servers
+    |> Health.overview()
+    |> Map.merge(%{
+      "counts" => counts,
+      "averages" => %{
+        "cpu_usage_percent" => average(latest, &Matrix.cpu_usage/1),
+        "memory_usage_percent" => average(latest, &Matrix.memory_usage/1)
+      },
+      "top" => %{
+        "cpu_usage_percent" => top(latest, &Matrix.cpu_usage/1),
+        "memory_usage_percent" => top(latest, &Matrix.memory_usage/1),
+        "disk_usage_percent" => top(latest, &Matrix.disk_usage/1)
+      },
+      "active_alerts" => Enum.count(Incidents.list(), &(&1.status != "resolved"))
+    })
+  end
+
+  # ---- Private helpers ----
+
+  defp values(latest, extract) do
+    for {server, data} <- latest, data, value <- [extract.(data)], is_number(value) do
+      {server, value}
+    end
+  end
+
+  defp average(latest, extract) do
+    case values(latest, extract) do
+      [] -> nil
+      values -> Float.round(Enum.sum(Enum.map(values, &elem(&1, 1))) / length(values), 1)
+    end
+  end
+
+  defp top(latest, extract) do
+    latest
+    |> values(extract)
+    |> Enum.sort_by(&elem(&1, 1), :desc)
+    |> Enum.take(@top)
+    |> Enum.map(fn {server, value} ->
+      %{"id" => server.id, "name" => server.name, "value" => value}
+    end)
+  end
+end
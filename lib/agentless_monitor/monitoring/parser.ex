@@ -2,12 +2,16 @@ defmodule AgentlessMonitor.Monitoring.Parser do
   @moduledoc "Parses output from the monitoring mega-command"
 
   alias AgentlessMonitor.Models.{
+    ContainerInfo,
     CpuInfo,
     MemoryInfo,
+    OomKill,
     DiskInfo,
     NetworkInfo,
     PortInfo,
+    ProcessInfo,
     PingTest,
+    ServiceUnit,
     SystemInfo
   }
 
@@ -31,13 +35,23 @@ defmodule AgentlessMonitor.Monitoring.Parser do
     uptime = parse_uptime(Enum.at(sections, 10, ""))
     arch = String.trim(Enum.at(sections, 11, ""))
     ports = parse_ports(Enum.at(sections, 12, ""))
+    {os_release, os_id, os_version} = parse_os_release(Enum.at(sections, 13, ""))
+    ip_addresses = String.split(Enum.at(sections, 14, ""))
+    processes = parse_processes(Enum.at(sections, 15, ""))
+    oom_kills = parse_oom_kills(Enum.at(sections, 16, ""))
+    containers = parse_containers(Enum.at(sections, 17, ""))
+    services = parse_services(Enum.at(sections, 18, ""))
 
     system_info = %SystemInfo{
       hostname: hostname,
       os: os,
+      os_release: os_release,
+      os_id: os_id,
+      os_version: os_version,
       kernel: kernel,
       architecture: arch,
-      uptime: uptime
+      uptime: uptime,
+      ip_addresses: ip_addresses
     }
 
     %{
@@ -46,24 +60,49 @@ defmodule AgentlessMonitor.Monitoring.Parser do
       disks: disks,
       network: network,
       ports: ports,
+      processes: processes,
+      oom_kills: oom_kills,
+      containers: containers,
+      services: services,
       system_info: system_info
     }
   end
 
-  @doc "Parse ping output (two pings separated by ---SEP---\\n)"
-  def parse_ping_output(output) do
+  @doc "Parse ping output (one ping per target, separated by ---SEP---\\n)"
+  def parse_ping_output(output, targets \\ ["8.8.8.8", "1.1.1.1"]) do
     sections =
       output
       |> String.split("---SEP---\n")
       |> Enum.map(&String.trim/1)
 
-    targets = ["8.8.8.8", "1.1.1.1"]
-
     sections
     |> Enum.zip(targets)
     |> Enum.map(fn {section, target} -> parse_single_ping(section, target) end)
   end
 
+  @doc "Parse the output of a single `ping -c 1` run against `target`"
+  def parse_single_ping(output, target) do
+    output = String.trim(output)
+
+    cond do
+      Regex.match?(~r/0 received/, output) or Regex.match?(~r/100% packet loss/, output) ->
+        %PingTest{target: target, latency_ms: nil, success: false, error: "host unreachable"}
+
+      Regex.match?(~r/time=[\d.]+\s*ms/, output) ->
+        latency =
+          Regex.run(~r/time=([\d.]+)\s*ms/, output)
+          |> case do
+            [_, ms] -> parse_float(ms)
+            _ -> nil
+          end
+
+        %PingTest{target: target, latency_ms: latency, success: true, error: nil}
+
+      true ->
+        %PingTest{target: target, latency_ms: nil, success: false, error: String.slice(output, 0, 100)}
+    end
+  end
+
   # ---- CPU ----
 
   defp parse_cpu(stat_line, loadavg_line, nproc_line, model_line) do
@@ -128,6 +167,7 @@ defmodule AgentlessMonitor.Monitoring.Parser do
 
     used = max(0, total - free - buffers - cached)
     swap_used = max(0, swap_total - swap_free)
+    vmstat = parse_vmstat(meminfo)
 
     %MemoryInfo{
       total: total,
@@ -136,10 +176,18 @@ defmodule AgentlessMonitor.Monitoring.Parser do
       available: available,
       swap_total: swap_total,
       swap_used: swap_used,
-      swap_free: swap_free
+      swap_free: swap_free,
+      swap_in_pages: vmstat["pswpin"],
+      swap_out_pages: vmstat["pswpout"]
     }
   end
 
+  # `pswpin 1234` lines from /proc/vmstat, appended to the meminfo section.
+  defp parse_vmstat(content) do
+    Regex.scan(~r/^(pswpin|pswpout) (\d+)$/m, content)
+    |> Map.new(fn [_, key, value] -> {key, String.to_integer(value)} end)
+  end
+
   defp parse_meminfo_fields(content) do
     content
     |> String.split("\n")
@@ -369,28 +417,193 @@ defmodule AgentlessMonitor.Monitoring.Parser do
     end
   end
 
-  # ---- Ping ----
+  # ---- OS release ----
 
-  defp parse_single_ping(output, target) do
-    cond do
-      Regex.match?(~r/0 received/, output) or Regex.match?(~r/100% packet loss/, output) ->
-        %PingTest{target: target, latency_ms: nil, success: false, error: "host unreachable"}
+  # PRETTY_NAME, ID and VERSION_ID on separate lines; empty on hosts
+  # without /etc/os-release.
+  defp parse_os_release(output) do
+    case output |> String.split("\n") |> Enum.map(&String.trim/1) do
+      [name, id, version | _] -> {name, id, version}
+      [name | _] -> {name, "", ""}
+    end
+  end
 
-      Regex.match?(~r/time=[\d.]+\s*ms/, output) ->
-        latency =
-          Regex.run(~r/time=([\d.]+)\s*ms/, output)
-          |> case do
-            [_, ms] -> parse_float(ms)
-            _ -> nil
+  # `ps -eo pid=,user=,pcpu=,pmem=,rss=,args=` lines; the same process may
+  # appear twice (heaviest by CPU and by memory).
+  @doc "Parses `ps -o pid=,user=,pcpu=,pmem=,rss=,args=` lines, first occurrence of a pid wins"
+  def parse_processes(output) do
+    output
+    |> String.split("\n", trim: true)
+    |> Enum.flat_map(fn line ->
+      case line |> String.trim_leading() |> String.split(~r/\s+/, parts: 6) do
+        [pid, user, cpu, mem, rss, command] ->
+          [
+            %ProcessInfo{
+              pid: parse_integer(pid),
+              user: user,
+              cpu_percent: parse_float(cpu),
+              memory_percent: parse_float(mem),
+              rss_kb: parse_integer(rss),
+              command: String.trim(command)
+            }
+          ]
+
+        _ ->
+          []
+      end
+    end)
+    |> Enum.uniq_by(& &1.pid)
+  end
+
+  # Kernel log lines such as `[Tue Oct 14 10:20:01 2026] Out of memory:
+  # Killed process 1234 (java) ...` (dmesg --ctime) or the journalctl
+  # short-iso equivalent.
+  defp parse_oom_kills(output) do
+    output
+    |> String.split("\n", trim: true)
+    |> Enum.flat_map(fn line ->
+      case Regex.run(~r/Killed process (\d+) \(([^)]*)\)/, line) do
+        [_, pid, process] ->
+          [
+            %OomKill{
+              pid: parse_integer(pid),
+              process: process,
+              time: oom_time(line),
+              message: String.trim(line)
+            }
+          ]
+
+        nil ->
+          []
+      end
+    end)
+  end
+
+  defp oom_time(line) do
+    case Regex.run(~r/^\[([^\]]+)\]|^(\S+T\S+)/, line) do
+      [_, ctime] -> ctime
+      [_, "", iso] -> iso
+      nil -> nil
+    end
+  end
+
+  # ---- Containers ----
+
+  # `docker ps -a` lines (id, name, image, state, status), then after
+  # `---STATS---` the `docker stats --no-stream` lines of running containers
+  # (id, CPU %, "used / limit", memory %).
+  defp parse_containers(output) do
+    [ps, stats] =
+      case String.split(output, "---STATS---", parts: 2) do
+        [ps, stats] -> [ps, stats]
+        [ps] -> [ps, ""]
+      end
+
+    stats =
+      stats
+      |> String.split("\n", trim: true)
+      |> Enum.flat_map(fn line ->
+        case String.split(line, "\t") do
+          [id, cpu, usage, mem] -> [{id, {cpu, usage, mem}}]
+          _ -> []
+        end
+      end)
+      |> Map.new()
+
+    ps
+    |> String.split("\n", trim: true)
+    |> Enum.flat_map(fn line ->
+      case String.split(line, "\t") do
+        [id, name, image, state, status] ->
+          container = %ContainerInfo{
+            id: id,
+            name: name,
+            image: image,
+            state: state,
+            status: status
+          }
+
+          [with_container_stats(container, Map.get(stats, id))]
+
+        _ ->
+          []
+      end
+    end)
+  end
+
+  defp with_container_stats(container, nil), do: container
+
+  defp with_container_stats(container, {cpu, usage, mem}) do
+    {used, limit} =
+      case String.split(usage, "/") do
+        [used, limit] -> {parse_docker_size(used), parse_docker_size(limit)}
+        _ -> {nil, nil}
+      end
+
+    %ContainerInfo{
+      container
+      | cpu_percent: parse_usage_pct(cpu),
+        memory_usage: used,
+        memory_limit: limit,
+        memory_percent: parse_usage_pct(mem)
+    }
+  end
+
+  # Docker prints sizes like `12.5MiB`, `1.944GiB` or `512kB`.
+  defp parse_docker_size(str) do
+    case Regex.run(~r/^([\d.]+)\s*([A-Za-z]*)$/, String.trim(str)) do
+      [_, number, unit] ->
+        multiplier =
+          case String.downcase(unit) do
+            "kib" -> 1024
+            "kb" -> 1000
+            "mib" -> 1_048_576
+            "mb" -> 1_000_000
+            "gib" -> 1_073_741_824
+            "gb" -> 1_000_000_000
+            "tib" -> 1_099_511_627_776
+            "tb" -> 1_000_000_000_000
+            _ -> 1
           end
 
-        %PingTest{target: target, latency_ms: latency, success: true, error: nil}
+        round(parse_float(number) * multiplier)
 
-      true ->
-        %PingTest{target: target, latency_ms: nil, success: false, error: String.slice(output, 0, 100)}
+      nil ->
+        nil
     end
   end
 
+  # ---- Services ----
+
+  # `systemctl list-units --plain --no-legend` lines: unit, load, active, sub
+  # and a description that may contain spaces. Failed units can carry a
+  # leading bullet on older systemd versions.
+  defp parse_services(output) do
+    output
+    |> String.split("\n", trim: true)
+    |> Enum.flat_map(fn line ->
+      case line |> String.trim_leading("●") |> String.split(~r/\s+/, parts: 5, trim: true) do
+        [name, load, active, sub | rest] ->
+          if String.ends_with?(name, ".service") do
+            [
+              %ServiceUnit{
+                name: name,
+                load: load,
+                active: active,
+                sub: sub,
+                description: rest |> List.first("") |> String.trim()
+              }
+            ]
+          else
+            []
+          end
+
+        _ ->
+          []
+      end
+    end)
+  end
+
   # ---- Utilities ----
 
   defp parse_integer(str) do
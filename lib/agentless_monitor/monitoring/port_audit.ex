@@ -0,0 +1,148 @@
+defmodule AgentlessMonitor.Monitoring.PortAudit do
+  @moduledoc """
+  External view of a server's TCP ports, from the monitor, set against the
+  listening ports its own port collector reported.
+
+  The scan only opens and closes plain TCP connections, with no banners
+  read and no SSH session, to the ports of `port_audit_ports` plus every TCP
+  port the latest sample shows listening. Each port is `open` (connected),
+  `closed` (refused) or `filtered` (no answer within the timeout), and is
+  classified as:
+
+    * `exposed` - listening and reachable
+    * `firewalled` - listening but not reachable from the monitor (a
+      firewall, or bound to loopback only)
+    * `unexpected` - reachable although the collector didn't report it
+      listening (port forwarding, a NAT in front, or a stale sample)
+    * `closed` - neither listening nor reachable
+  """
+
+  alias AgentlessMonitor.{Config, State}
+
+  @connect_timeout 2_000
+  @concurrency 50
+  @max_ports 1024
+
+  @doc """
+  Audits `server`, scanning `ports` (the configured list when nil) and the
+  ports its latest sample shows listening.
+  """
+  def run(server, ports \\ nil) do
+    ports = ports || Config.load().port_audit_ports
+    listening = listening(server)
+
+    results =
+      (ports ++ listening)
+      |> Enum.filter(&(is_integer(&1) and valid_port?(&1)))
+      |> Enum.uniq()
+      |> Enum.sort()
+      |> Task.async_stream(&{&1, scan(server.host, &1)},
+        max_concurrency: @concurrency,
+        timeout: @connect_timeout + 1_000,
+        on_timeout: :kill_task
+      )
+      |> Enum.flat_map(fn
+        {:ok, {port, reachability}} ->
+          [
+            %{
+              "port" => port,
+              "reachability" => reachability,
+              "listening" => port in listening,
+              "finding" => finding(port in listening, reachability)
+            }
+          ]
+
+        {:exit, _reason} ->
+          []
+      end)
+
+    %{
+      "server_id" => server.id,
+      "host" => server.host,
+      "scanned_at" => DateTime.utc_now() |> DateTime.to_iso8601(),
+      "listening_reported" => listening != [],
+      "ports" => results,
+      "discrepancies" => Enum.filter(results, &(&1["finding"] in ["firewalled", "unexpected"])),
+      "summary" => Enum.frequencies_by(results, & &1["finding"])
+    }
+  end
+
+  @doc """
+  Parses a comma-separated port list such as `22,80,8000-8010`. Returns
+  `{:ok, ports}` or `{:error, message}`.
+  """
+  def parse_ports(spec) do
+    ports =
+      spec
+      |> String.split(",", trim: true)
+      |> Enum.map(&String.trim/1)
+      |> Enum.flat_map(&parse_range/1)
+
+    cond do
+      :error in ports -> {:error, "ports must be numbers or ranges between 1 and 65535"}
+      length(Enum.uniq(ports)) > @max_ports -> {:error, "at most #{@max_ports} ports"}
+      true -> {:ok, Enum.uniq(ports)}
+    end
+  end
+
+  # ---- Private helpers ----
+
+  defp parse_range(part) do
+    case String.split(part, "-") do
+      [first, last] ->
+        with {first, ""} <- Integer.parse(first),
+             {last, ""} <- Integer.parse(last),
+             true <- valid_port?(first) and valid_port?(last) and first <= last do
+          Enum.to_list(first..last)
+        else
+          _ -> [:error]
+        end
+
+      [port] ->
+        case Integer.parse(port) do
+          {port, ""} -> if valid_port?(port), do: [port], else: [:error]
+          _ -> [:error]
+        end
+
+      _ ->
+        [:error]
+    end
+  end
+
+  defp valid_port?(port), do: port in 1..65_535
+
+  # TCP ports the latest sample shows listening.
+  defp listening(server) do
+    case State.get_latest_monitoring_data(server.id) do
+      %{ports: ports} when is_list(ports) ->
+        for %{protocol: "tcp" <> _, state: state, port: port} <- ports,
+            String.upcase(state) == "LISTEN",
+            uniq: true,
+            do: port
+
+      _ ->
+        []
+    end
+  end
+
+  defp scan(host, port) do
+    opts = [:binary, active: false]
+
+    case :gen_tcp.connect(String.to_charlist(host), port, opts, @connect_timeout) do
+      {:ok, socket} ->
+        :gen_tcp.close(socket)
+        "open"
+
+      {:error, :econnrefused} ->
+        "closed"
+
+      {:error, _reason} ->
+        "filtered"
+    end
+  end
+
+  defp finding(true, "open"), do: "exposed"
+  defp finding(true, _reachability), do: "firewalled"
+  defp finding(false, "open"), do: "unexpected"
+  defp finding(false, _reachability), do: "closed"
+end
@@ -2,29 +2,126 @@ defmodule AgentlessMonitor.Monitoring.Service do
   use GenServer
   require Logger
 
-  alias AgentlessMonitor.{State, SSH.Connection, SSH.Manager}
-  alias AgentlessMonitor.Monitoring.Parser
-  alias AgentlessMonitor.Models.{MonitoringData, MonitoringJob}
+  alias AgentlessMonitor.{PubSub, Privacy, ServerStatus, Settings, State, Storage}
+  alias AgentlessMonitor.SSH.{Connection, Manager, Restricted}
+  alias AgentlessMonitor.Alerting.{Canary, Events}
+  alias AgentlessMonitor.Monitoring.{
+    BSD,
+    CustomMetrics,
+    Fingerprint,
+    Hooks,
+    HttpChecks,
+    Neighbors,
+    Parser,
+    SNMP,
+    Watchdog,
+    Windows
+  }
+
+  alias AgentlessMonitor.Models.{
+    CpuInfo,
+    DiskInfo,
+    MemoryInfo,
+    MonitoringData,
+    MonitoringJob,
+    PingTest,
+    ServiceUnit,
+    SystemInfo
+  }
 
   @poll_interval 1_000
 
-  @mega_command """
-  cat /proc/stat | head -1; echo '---SEP---'; \
-  cat /proc/loadavg; echo '---SEP---'; \
-  nproc; echo '---SEP---'; \
-  cat /proc/cpuinfo | grep 'model name' | head -1 | cut -d: -f2; echo '---SEP---'; \
-  cat /proc/meminfo; echo '---SEP---'; \
-  df -h; echo '---SEP---'; \
-  cat /proc/net/dev; echo '---SEP---'; \
-  hostname; echo '---SEP---'; \
-  uname -s; echo '---SEP---'; \
-  uname -r; echo '---SEP---'; \
-  cat /proc/uptime; echo '---SEP---'; \
-  uname -m; echo '---SEP---'; \
-  (ss -tuln 2>/dev/null || netstat -tuln 2>/dev/null || echo 'no_port_info')
-  """
+  # The heaviest processes by CPU and by memory (procps `ps`; BusyBox's
+  # cannot sort, leaving the section empty).
+  @process_command "(ps -eo pid=,user=,pcpu=,pmem=,rss=,args= --sort=-pcpu 2>/dev/null | " <>
+                     "head -n 50; ps -eo pid=,user=,pcpu=,pmem=,rss=,args= --sort=-rss " <>
+                     "2>/dev/null | head -n 50)"
+
+  # Recent OOM-killer victims from the kernel log; reading it may need
+  # privileges (`kernel.dmesg_restrict`), otherwise the journal is tried.
+  @oom_command "((dmesg --ctime 2>/dev/null || journalctl -k -o short-iso --no-pager " <>
+                 "-n 5000 2>/dev/null) | grep 'Killed process' | tail -n 20)"
+
+  # Containers when the docker CLI is installed and usable by the SSH user;
+  # `docker stats` only reports running ones.
+  @docker_command "(command -v docker >/dev/null 2>&1 && " <>
+                    "docker ps -a --no-trunc --format " <>
+                    "'{{.ID}}\t{{.Names}}\t{{.Image}}\t{{.State}}\t{{.Status}}' " <>
+                    "2>/dev/null && " <>
+                    "echo '---STATS---' && docker stats --no-stream --no-trunc --format " <>
+                    "'{{.ID}}\t{{.CPUPerc}}\t{{.MemUsage}}\t{{.MemPerc}}' 2>/dev/null) || true"
+
+  # Running and failed systemd services. As the last section it must not fail
+  # the whole command on hosts without systemd.
+  @systemd_command "(systemctl list-units --type=service --state=failed,running " <>
+                     "--no-pager --plain --no-legend 2>/dev/null || true)"
+
+  # The sections of the mega command, in the order the parser reads them.
+  @sections [
+    "cat /proc/stat | head -1",
+    "cat /proc/loadavg",
+    "nproc",
+    "cat /proc/cpuinfo | grep 'model name' | head -1 | cut -d: -f2",
+    "cat /proc/meminfo; grep -E '^pswp(in|out) ' /proc/vmstat 2>/dev/null",
+    "df -h",
+    "{{net}}cat /proc/net/dev",
+    "hostname",
+    "uname -s",
+    "uname -r",
+    "cat /proc/uptime",
+    "uname -m",
+    "({{net}}ss -tuln 2>/dev/null || {{net}}netstat -tuln 2>/dev/null || echo 'no_port_info')",
+    ~S[(. /etc/os-release 2>/dev/null && printf '%s\n' "$PRETTY_NAME" "$ID" "$VERSION_ID")],
+    "(hostname -I 2>/dev/null || true)",
+    @process_command,
+    @oom_command,
+    @docker_command,
+    @systemd_command
+  ]
+
+  @mega_command Enum.join(@sections, "; echo '---SEP---'; ")
+
+  @ping_table :ping_results
+  @attempt_table :collection_attempts
+  # Background jobs in progress: `{job_id, task_pid, server, pid_file}`.
+  @job_table :running_jobs
+  # Collection hooks failing per server: `{server_id, MapSet of {phase, index}}`.
+  @hook_table :failing_hooks
+  # Consecutive collections over their `collection_budget`: `{server_id, count}`.
+  @slow_table :slow_collections
 
-  @ping_command "ping -c 1 -W 2 8.8.8.8 2>&1; echo '---SEP---'; ping -c 1 -W 2 1.1.1.1 2>&1"
+  # Detects the platform of a target on its first collection; Windows hosts
+  # lack uname and are recognized by `ver` instead.
+  @platform_command "uname -s"
+  @windows_command "cmd /c ver"
+
+  # What a degraded collection reads when the full one fails: uptime and
+  # load averages, from files any user can read.
+  @degraded_command "cat /proc/uptime /proc/loadavg"
+
+  @threshold_rules ["cpu_percent", "memory_percent", "disk_percent", "swap_out_rate"]
+
+  # Mega command output sections behind each metric, for `debug_collect/2`,
+  # per-collector commands and trimming the mega command.
+  @debug_sections %{
+    "cpu" => [{"proc_stat", 0}, {"loadavg", 1}, {"nproc", 2}, {"cpu_model", 3}],
+    "memory" => [{"meminfo", 4}, {"oom_kills", 16}],
+    "disks" => [{"df", 5}],
+    "network" => [{"net_dev", 6}],
+    "ports" => [{"ss", 12}],
+    "system" => [
+      {"hostname", 7},
+      {"uname_s", 8},
+      {"uname_r", 9},
+      {"uptime", 10},
+      {"uname_m", 11},
+      {"os_release", 13},
+      {"hostname_i", 14}
+    ],
+    "processes" => [{"ps", 15}],
+    "containers" => [{"docker", 17}],
+    "services" => [{"systemctl", 18}]
+  }
 
   # ---- Public API ----
 
@@ -36,32 +133,393 @@ defmodule AgentlessMonitor.Monitoring.Service do
     GenServer.cast(__MODULE__, {:collect, server_id})
   end
 
+  @doc """
+  Collects from `server_id` right away in the calling process, whatever its
+  schedule or pause state, and returns `{:ok, job}` with the finished
+  monitoring job.
+  """
+  def collect_now(server_id) do
+    do_collect(server_id)
+  end
+
+  @doc """
+  Runs an arbitrary shell command on a server, locally for servers with the
+  `local` transport (through their `command_prefix`, if any) and over SSH
+  otherwise; SNMP devices refuse. `opts` override the server's SSH options,
+  e.g. a longer `timeout`.
+  """
+  def run_command(server, command, opts \\ [])
+
+  def run_command(%{transport: "snmp"}, _command, _opts),
+    do: {:error, "SNMP devices don't run commands"}
+
+  def run_command(%{transport: "local"} = server, command, _opts) do
+    command =
+      case server.command_prefix do
+        prefix when is_binary(prefix) and prefix != "" ->
+          "#{prefix} sh -c #{shell_quote(command)}"
+
+        _ ->
+          command
+      end
+
+    case System.cmd("sh", ["-c", command], stderr_to_stdout: true) do
+      {output, 0} -> {:ok, output}
+      {output, _code} -> {:error, output}
+    end
+  end
+
+  def run_command(server, command, opts) do
+    opts = Keyword.merge(connection_opts(server), opts)
+    Connection.execute(server.host, server.port, server.username, command, opts)
+  end
+
+  @doc "SSH options collections use for `server`: timeout, ControlPersist, proxy, password"
+  def connection_opts(server) do
+    config = AgentlessMonitor.Config.load()
+    ssh_opts(server, Settings.for_server(server, config), config)
+  end
+
+  @doc """
+  The commands collections with `settings` send to a server, as `{name,
+  command}`: the collection itself (one command per collector with a
+  `collector_timeout`), its pings (part of the collection when
+  `single_shot`), target-side HTTP checks, the connectivity check, platform
+  detection, the degraded collection and the remote collection hooks.
+  """
+  def remote_commands(settings) do
+    config = AgentlessMonitor.Config.load()
+    embedded = single_shot_targets(settings, config)
+
+    targets = if embedded, do: [], else: settings["ping_targets"] || []
+
+    pings =
+      Enum.map(targets, fn target ->
+        {"ping #{target}", ping_command(target, config.ping_timeout)}
+      end)
+
+    collect =
+      if parallel_timeout(config) do
+        for {name, command} <- collector_commands(settings), do: {"collect #{name}", command}
+      else
+        [{"collect", mega_command(settings) <> ping_sections(embedded, config.ping_timeout)}]
+      end
+
+    custom =
+      case CustomMetrics.declarations(settings) do
+        [] -> []
+        declarations -> [{"custom metrics", CustomMetrics.command(declarations)}]
+      end
+
+    http =
+      case target_http_checks(settings) do
+        [] -> []
+        checks -> [{"http checks", HttpChecks.command(checks)}]
+      end
+
+    hooks = Hooks.remote_commands(settings)
+
+    checks = [
+      {"connectivity check", "true"},
+      {"platform detection", @platform_command},
+      {"degraded collection", @degraded_command}
+    ]
+
+    collect ++ custom ++ http ++ checks ++ pings ++ hooks
+  end
+
+  @doc """
+  Runs `fun` (returning `{:ok, output}` or `{:error, output}`) while tracking
+  it as a job of the given type, including its output.
+  """
+  def run_as_job(server, job_type, fun) do
+    server
+    |> add_job(job_type)
+    |> finish_job(fun)
+  end
+
+  @doc """
+  Like `run_as_job/3`, but runs `fun` in the background and returns the
+  job right away; its outcome is read from the job later.
+  """
+  def start_job(server, job_type, fun) do
+    spawn_job(add_job(server, job_type), server, nil, fun)
+  end
+
+  @doc """
+  Like `start_job/3` for a single remote `command`, run so that cancelling
+  the job also terminates the command (and its children) on the server.
+  """
+  def start_command_job(server, job_type, command, opts \\ []) do
+    job = add_job(server, job_type)
+    pid_file = "/tmp/agentless-monitor-job-#{job.id}.pid"
+    run = fn -> run_command(server, cancellable_command(command, pid_file), opts) end
+    spawn_job(job, server, pid_file, run)
+  end
+
+  @doc """
+  Cancels a running background job: its task is stopped and, for a
+  command job, the remote command is killed over a new session on the
+  shared connection. Returns `{:ok, job}`, `{:error, :finished}` or
+  `{:error, :not_found}`.
+  """
+  def cancel_job(job_id) do
+    with {:ok, job} <- State.get_job(job_id),
+         :ok <- cancellable(job) do
+      case :ets.lookup(@job_table, job_id) do
+        [{^job_id, task, server, pid_file}] ->
+          :ets.delete(@job_table, job_id)
+          Process.exit(task, :kill)
+          if pid_file, do: run_command(server, kill_command(pid_file))
+
+        [] ->
+          :ok
+      end
+
+      completed_at = DateTime.utc_now()
+
+      State.update_job(job_id, %{
+        status: "cancelled",
+        completed_at: DateTime.to_iso8601(completed_at),
+        duration_ms: job_duration(job, completed_at),
+        error: "cancelled"
+      })
+
+      State.get_job(job_id)
+    end
+  end
+
+  @doc """
+  Collects one sample from `server` with the given resolved settings,
+  without recording it. Used by the monitoring loop and by
+  `AgentlessMonitor.Collector`. Custom metrics and HTTP checks are read
+  alongside.
+  """
+  def collect(server, settings) do
+    custom =
+      case CustomMetrics.declarations(settings) do
+        [] -> nil
+        declarations -> Task.async(fn -> collect_custom(server, declarations) end)
+      end
+
+    http =
+      case HttpChecks.declarations(settings) do
+        [] -> nil
+        checks -> Task.async(fn -> run_http_checks(server, checks) end)
+      end
+
+    result =
+      case server.transport do
+        "local" -> collect_local(server, settings)
+        "snmp" -> collect_snmp(server, settings)
+        _ -> collect_remote(server, settings)
+      end
+
+    values = if custom, do: Task.await(custom, :infinity), else: %{}
+    http_checks = if http, do: Task.await(http, :infinity), else: []
+
+    case result do
+      {:ok, data} -> {:ok, %{data | custom: values, http_checks: http_checks}}
+      error -> error
+    end
+  end
+
+  @doc "Thresholds `data` exceeds, as a map of threshold key to message"
+  def threshold_breaches(data, thresholds), do: breaches(data, thresholds)
+
+  @doc """
+  Parses the output of the degraded collection, `/proc/uptime` (`12345.67
+  23456.78`) then `/proc/loadavg` (`0.12 0.34 0.56 1/234 5678`), into
+  `{:ok, uptime_seconds, load_average}`, or `:error`.
+  """
+  def parse_degraded(output) do
+    with [uptime, loadavg | _] <- String.split(output, "\n", trim: true),
+         {seconds, _} <- Float.parse(uptime),
+         [_, _, _] = loads <- loadavg |> String.split() |> Enum.take(3),
+         parsed = Enum.map(loads, &Float.parse/1),
+         true <- Enum.all?(parsed, &match?({_load, _rest}, &1)) do
+      {:ok, trunc(seconds), Enum.map(parsed, &elem(&1, 0))}
+    else
+      _ -> :error
+    end
+  end
+
+  @doc """
+  Runs a fresh collection of `metric` for debugging, without recording it.
+  Returns `{:ok, raw, data}` where `raw` maps each command behind the metric
+  (or each ping target) to its unparsed output.
+  """
+  def debug_collect(server, "ping") do
+    settings = Settings.for_server(server)
+    config = AgentlessMonitor.Config.load()
+    opts = ssh_opts(server, settings, config)
+
+    raw = Map.new(settings["ping_targets"], &{&1, ping(server, &1, config.ping_timeout, opts)})
+    tests = Enum.map(raw, fn {target, output} -> Parser.parse_single_ping(output, target) end)
+
+    {:ok, raw, %MonitoringData{server_id: server.id, ping_tests: tests}}
+  end
+
+  def debug_collect(server, metric) do
+    settings = Settings.for_server(server)
+
+    output =
+      if server.transport == "local" and server.command_prefix in [nil, ""] do
+        {:ok, build_local_mega_output(settings)}
+      else
+        run_command(server, mega_command(settings))
+      end
+
+    with {:ok, sections} <- Map.fetch(@debug_sections, metric),
+         {:ok, output} <- output,
+         {:ok, data} <- parse_output(output, [], server.id) do
+      parts = String.split(output, "---SEP---\n")
+      {:ok, Map.new(sections, fn {name, index} -> {name, Enum.at(parts, index, "")} end), data}
+    else
+      :error -> {:error, :unknown_metric}
+      {:error, reason} -> {:error, reason}
+    end
+  end
+
+  @doc """
+  Seconds since the last collection attempt for `server` (or since startup
+  when there was none) and whether that exceeds `stale_factor` times its
+  interval. Paused and retired servers are never stale.
+  """
+  def staleness(server) do
+    case :ets.whereis(@attempt_table) do
+      :undefined ->
+        %{"collection_age_seconds" => nil, "stale" => false}
+
+      _table ->
+        age = collection_age(server, System.monotonic_time(:second))
+        factor = AgentlessMonitor.Config.load().stale_factor
+        %{"collection_age_seconds" => age, "stale" => stale?(server, age, factor)}
+    end
+  end
+
+  @doc "Threshold keys that can be dry-run with `dry_run/3`, besides `custom.<name>`"
+  def threshold_rules, do: @threshold_rules
+
+  @doc """
+  Replays `samples` (newest first, as stored) against a single threshold
+  rule and returns, oldest first, every point at which it would have fired.
+  Uses the same edge-triggering as live monitoring.
+  """
+  def dry_run(rule, samples, threshold) do
+    thresholds = %{rule => threshold}
+
+    samples
+    |> Enum.reverse()
+    |> Enum.reduce({%{}, []}, fn data, {before, fired} ->
+      current = breaches(data, thresholds)
+
+      new =
+        current
+        |> Enum.reject(fn {key, _message} -> Map.has_key?(before, key) end)
+        |> Enum.map(fn {_key, message} ->
+          %{"timestamp" => data.timestamp, "message" => message}
+        end)
+
+      {current, fired ++ new}
+    end)
+    |> elem(1)
+  end
+
+  @doc """
+  Alert drill: raises `server_down` for `server` as a failed collection
+  would, whatever its current status. The event is marked as a drill, so
+  the server's status is left alone and no action or capture runs.
+  """
+  def simulate_down(server, reason) do
+    message = "#{server.name} monitoring failed: #{reason}"
+    emit_event(server, "server_down", "critical", message, drill: true)
+  end
+
+  @doc """
+  Alert drill: records a sample for `server`, its latest one with
+  `overrides` (`cpu_percent`, `memory_percent`, `disk_percent` for `/`)
+  applied, and runs the threshold checks on it as if it had been
+  collected; the events it raises are marked as drills. It isn't written
+  to the storage backend and the next collection supersedes it.
+  """
+  def inject_sample(server, overrides) do
+    previous = State.get_latest_monitoring_data(server.id)
+    timestamp = DateTime.utc_now() |> DateTime.to_iso8601()
+
+    data =
+      (previous || %MonitoringData{server_id: server.id})
+      |> Map.put(:timestamp, timestamp)
+      |> override_cpu(overrides["cpu_percent"])
+      |> override_memory(overrides["memory_percent"])
+      |> override_disk(overrides["disk_percent"])
+
+    State.add_monitoring_data(server.id, data)
+    PubSub.broadcast("sample", MonitoringData.to_map(data))
+    thresholds = Settings.for_server(server)["thresholds"]
+    check_thresholds(server, data, previous, thresholds, drill: true)
+    data
+  end
+
   # ---- Callbacks ----
 
   def init(_) do
-    schedule_poll()
-    {:ok, %{}}
+    :ets.new(@ping_table, [:named_table, :public, :set])
+    :ets.new(@attempt_table, [:named_table, :public, :set])
+    :ets.new(@job_table, [:named_table, :public, :set])
+    :ets.new(@hook_table, [:named_table, :public, :set])
+    :ets.new(@slow_table, [:named_table, :public, :set])
+    :ets.insert(@attempt_table, {:started, System.monotonic_time(:second)})
+
+    case AgentlessMonitor.Config.load().warmup do
+      %{} = warmup ->
+        parent = self()
+
+        # Polling starts once the warm-up is over, even if it crashed.
+        Task.Supervisor.start_child(AgentlessMonitor.TaskSupervisor, fn ->
+          try do
+            warm_up(warmup)
+          after
+            send(parent, :poll)
+          end
+        end)
+
+      nil ->
+        schedule_poll()
+    end
+
+    {:ok, %{stale: MapSet.new()}}
   end
 
   def handle_cast({:collect, server_id}, state) do
-    do_collect(server_id)
+    Task.Supervisor.start_child(AgentlessMonitor.TaskSupervisor, fn ->
+      do_collect(server_id)
+    end)
+
     {:noreply, state}
   end
 
   def handle_info(:poll, state) do
-    now = System.system_time(:second)
+    now = System.monotonic_time(:second)
+
+    servers =
+      State.get_servers()
+      |> Enum.reject(&(&1.status == "retired"))
+      |> Enum.reject(fn server -> State.is_paused?(server.id) end)
 
-    State.get_servers()
-    |> Enum.reject(fn server -> State.is_paused?(server.id) end)
-    |> Enum.filter(fn server -> server.next_monitoring <= now end)
+    servers
+    |> Enum.filter(fn server -> is_nil(server.due_at) or server.due_at <= now end)
     |> Enum.each(fn server ->
       Task.Supervisor.start_child(AgentlessMonitor.TaskSupervisor, fn ->
         do_collect(server.id)
       end)
     end)
 
+    config = AgentlessMonitor.Config.load()
+    Enum.each(servers, &maybe_ping(&1, config, now))
+
     schedule_poll()
-    {:noreply, state}
+    {:noreply, %{state | stale: check_staleness(servers, state.stale, now, config)}}
   end
 
   # ---- Private helpers ----
@@ -70,30 +528,132 @@ defmodule AgentlessMonitor.Monitoring.Service do
     Process.send_after(self(), :poll, @poll_interval)
   end
 
+  # Watchdog for collections that silently stopped (as opposed to failing,
+  # which `server_down` covers): alerts once when a server goes stale and
+  # once when collection resumes. `servers` are the polled ones, paused
+  # servers already left out.
+  defp check_staleness(servers, previous, now, config) do
+    factor = config.stale_factor
+
+    stale =
+      servers
+      |> Enum.filter(&stale?(&1, collection_age(&1, now), factor, config))
+      |> MapSet.new(& &1.id)
+
+    Enum.each(servers, fn server ->
+      cond do
+        MapSet.member?(stale, server.id) and not MapSet.member?(previous, server.id) ->
+          emit_event(
+            server,
+            "monitoring_stale",
+            "warning",
+            "#{server.name}: no collection for #{collection_age(server, now)}s " <>
+              "(interval #{interval(server, config)}s)"
+          )
+
+        MapSet.member?(previous, server.id) and not MapSet.member?(stale, server.id) ->
+          emit_event(server, "monitoring_resumed", "info", "#{server.name}: collection resumed")
+
+        true ->
+          :ok
+      end
+    end)
+
+    stale
+  end
+
+  defp collection_age(server, now) do
+    case :ets.lookup(@attempt_table, server.id) do
+      [{_, at}] -> now - at
+      [] -> now - :ets.lookup_element(@attempt_table, :started, 2)
+    end
+  end
+
+  defp stale?(%{status: "retired"}, _age, _factor, _config), do: false
+
+  defp stale?(server, age, factor, config), do: age > factor * interval(server, config)
+
+  defp interval(server, config), do: Settings.for_server(server, config)["monitoring_interval"]
+
+  # Opens ControlMaster connections to the servers of the configured groups
+  # (or names) before the first poll, so the first monitoring cycle reuses
+  # them instead of paying for connection setup.
+  defp warm_up(warmup) do
+    groups = Map.get(warmup, "groups", [])
+    names = Map.get(warmup, "servers", [])
+
+    servers =
+      State.get_servers()
+      |> Enum.filter(&(&1.transport == "ssh" and &1.status != "retired"))
+      |> Enum.filter(&(&1.name in names or Settings.group_for(&1) in groups))
+
+    concurrency =
+      case Map.get(warmup, "concurrency") do
+        n when is_integer(n) and n > 0 -> n
+        _ -> 8
+      end
+
+    started_at = System.monotonic_time(:millisecond)
+
+    warmed =
+      servers
+      |> Task.async_stream(
+        fn server ->
+          case run_command(server, "true") do
+            {:ok, _} ->
+              Manager.record_connection(server.id, server.host, server.port, server.username)
+              :ok
+
+            {:error, reason} ->
+              Logger.warning("Warm-up of #{server.name} failed: #{String.trim(reason)}")
+              :error
+          end
+        end,
+        max_concurrency: concurrency,
+        timeout: :infinity
+      )
+      |> Enum.count(&(&1 == {:ok, :ok}))
+
+    elapsed = System.monotonic_time(:millisecond) - started_at
+    Logger.info("Warmed up #{warmed}/#{length(servers)} connections in #{elapsed}ms")
+  end
+
   defp do_collect(server_id) do
     case State.get_server(server_id) do
       {:error, :not_found} ->
         Logger.warning("Server #{server_id} not found for monitoring")
+        {:error, :not_found}
 
       {:ok, server} ->
+        settings = Settings.for_server(server)
+        thresholds = settings["thresholds"]
         job = build_job(server)
         State.add_job(job)
         started_at = DateTime.utc_now()
         State.update_job(job.id, %{started_at: DateTime.to_iso8601(started_at)})
 
-        result =
-          if server_id == "local" do
-            collect_local(server)
-          else
-            collect_remote(server)
-          end
+        before_errors = Hooks.run(server, settings, "before")
+        collect_started = System.monotonic_time(:millisecond)
+        result = collect(server, settings)
+        collect_ms = System.monotonic_time(:millisecond) - collect_started
+        :ets.insert(@attempt_table, {server_id, System.monotonic_time(:second)})
+        outcome = if match?({:ok, _data}, result), do: "ok", else: "error"
+        hook_errors = before_errors ++ Hooks.run(server, settings, "after", outcome)
+        State.update_job(job.id, %{hook_errors: hook_errors})
+        check_hooks(server, hook_errors)
 
         completed_at = DateTime.utc_now()
         duration_ms = DateTime.diff(completed_at, started_at, :millisecond)
 
         case result do
           {:ok, data} ->
+            previous = State.get_latest_monitoring_data(server_id)
+            Neighbors.record_addresses(server_id, data)
+            data = data |> with_swap_rates(previous) |> Privacy.redact_data()
             State.add_monitoring_data(server_id, data)
+            sample = MonitoringData.to_map(data)
+            PubSub.broadcast("sample", sample)
+            Storage.write(:sample, sample)
 
             State.update_job(job.id, %{
               status: "completed",
@@ -102,15 +662,34 @@ defmodule AgentlessMonitor.Monitoring.Service do
               metrics_collected: 1
             })
 
-            next_monitoring = System.system_time(:second) + server.monitoring_interval
+            interval = settings["monitoring_interval"]
+            due_at = System.monotonic_time(:second) + interval
+            status = ServerStatus.collected(data.failed_collectors, breaches(data, thresholds))
 
             State.update_server(server_id, %{
-              status: "online",
+              status: status,
               last_seen: DateTime.to_iso8601(completed_at),
-              next_monitoring: next_monitoring
+              due_at: due_at
             })
 
-            Manager.record_connection(server_id, server.host, server.port, server.username)
+            if server.transport == "ssh" do
+              Manager.record_connection(server_id, server.host, server.port, server.username)
+            end
+
+            broadcast_status(server, status)
+
+            if server.status == "error" do
+              emit_event(server, "server_recovered", "info", "#{server.name} is reachable again")
+            end
+
+            check_thresholds(server, data, previous, thresholds)
+            Canary.evaluate(server, data, previous, thresholds)
+            check_budget(server, settings["collection_budget"], collect_ms)
+            check_fingerprint(server, data)
+            check_processes(server, settings["process_rules"])
+            check_oom_kills(server, data, previous)
+            check_http(server, data, previous)
+            check_degraded(server, data, previous)
 
           {:error, reason} ->
             Logger.warning("Monitoring failed for #{server.name}: #{reason}")
@@ -122,16 +701,447 @@ defmodule AgentlessMonitor.Monitoring.Service do
               error: to_string(reason)
             })
 
-            next_monitoring = System.system_time(:second) + server.monitoring_interval
+            interval = settings["monitoring_interval"]
+            due_at = System.monotonic_time(:second) + interval
 
-            State.update_server(server_id, %{
-              status: "error",
-              next_monitoring: next_monitoring
-            })
+            State.update_server(server_id, %{status: "error", due_at: due_at})
+
+            broadcast_status(server, "error")
+
+            if server.status != "error" do
+              emit_event(server, "server_down", "critical", "#{server.name} monitoring failed: #{reason}")
+            end
         end
+
+        State.get_job(job.id)
+    end
+  end
+
+  # Emits a warning for every collection hook that fails now but did not in
+  # the previous collection, so a hook that keeps failing is reported once.
+  defp check_hooks(server, errors) do
+    failing = MapSet.new(errors, &{&1["phase"], &1["index"]})
+
+    before =
+      case :ets.lookup(@hook_table, server.id) do
+        [{_server_id, before}] -> before
+        [] -> MapSet.new()
+      end
+
+    :ets.insert(@hook_table, {server.id, failing})
+
+    errors
+    |> Enum.reject(&MapSet.member?(before, {&1["phase"], &1["index"]}))
+    |> Enum.each(fn error ->
+      emit_event(
+        server,
+        "collection_hook_failed",
+        "warning",
+        "#{server.name}: #{error["phase"]} hook `#{error["command"]}` failed: #{error["error"]}"
+      )
+    end)
+  end
+
+  # A collection (connecting and running every collector, hooks aside) that
+  # takes longer than `collection_budget.seconds` for `consecutive`
+  # collections in a row (default 3) raises one `collection_slow` warning;
+  # the first one back within budget reports `collection_budget_met`.
+  defp check_budget(server, %{"seconds" => seconds} = budget, elapsed_ms)
+       when is_number(seconds) and seconds > 0 do
+    consecutive = budget["consecutive"] || 3
+
+    streak =
+      case :ets.lookup(@slow_table, server.id) do
+        [{_server_id, streak}] -> streak
+        [] -> 0
+      end
+
+    if elapsed_ms > seconds * 1000 do
+      :ets.insert(@slow_table, {server.id, streak + 1})
+
+      if streak + 1 == consecutive do
+        emit_event(
+          server,
+          "collection_slow",
+          "warning",
+          "#{server.name}: #{consecutive} collections in a row took longer than " <>
+            "#{seconds}s (last #{elapsed_ms}ms)"
+        )
+      end
+    else
+      :ets.insert(@slow_table, {server.id, 0})
+
+      if streak >= consecutive do
+        emit_event(
+          server,
+          "collection_budget_met",
+          "info",
+          "#{server.name}: collection is back within #{seconds}s (#{elapsed_ms}ms)"
+        )
+      end
+    end
+  end
+
+  defp check_budget(_server, _budget, _elapsed_ms), do: :ok
+
+  # Emits a warning for every threshold that is breached now but was not in
+  # the previous sample, so a sustained breach is reported once.
+  defp check_thresholds(server, data, previous, thresholds, opts \\ []) do
+    current = breaches(data, thresholds)
+    before = if previous, do: breaches(previous, thresholds), else: %{}
+
+    current
+    |> Enum.reject(fn {key, _message} -> Map.has_key?(before, key) end)
+    |> Enum.each(fn {_key, message} ->
+      emit_event(server, "threshold_exceeded", "warning", "#{server.name}: #{message}", opts)
+    end)
+  end
+
+  defp check_fingerprint(server, data) do
+    case Fingerprint.check(server.id, data) do
+      [] ->
+        :ok
+
+      changes ->
+        details =
+          Enum.map_join(changes, "; ", fn change ->
+            "#{change["field"]} changed from #{format_fingerprint(change["previous"])} " <>
+              "to #{format_fingerprint(change["current"])}"
+          end)
+
+        emit_event(server, "fingerprint_changed", "warning", "#{server.name}: #{details}")
+    end
+  end
+
+  defp check_processes(_server, rules) when rules in [nil, []], do: :ok
+
+  defp check_processes(server, rules) do
+    case Watchdog.check(server, rules) do
+      {:ok, violated, recovered} ->
+        Enum.each(violated, fn result ->
+          emit_event(
+            server,
+            "process_rule_violated",
+            result["severity"],
+            "#{server.name}: process rule #{result["rule"]} violated (#{describe_rule(result)})"
+          )
+        end)
+
+        Enum.each(recovered, fn result ->
+          emit_event(
+            server,
+            "process_rule_recovered",
+            "info",
+            "#{server.name}: process rule #{result["rule"]} satisfied again"
+          )
+        end)
+
+      {:error, reason} ->
+        Logger.warning("Process check failed for #{server.name}: #{inspect(reason)}")
     end
   end
 
+  defp describe_rule(%{"error" => message}), do: message
+
+  defp describe_rule(result) do
+    bounds =
+      if result["max"],
+        do: "#{result["min"]}-#{result["max"]}",
+        else: ">= #{result["min"]}"
+
+    "#{result["count"]} matching, expected #{bounds}"
+  end
+
+  defp format_fingerprint(value) when is_list(value), do: "[#{Enum.join(value, ", ")}]"
+  defp format_fingerprint(value), do: to_string(value)
+
+  defp breaches(data, thresholds) do
+    cpu =
+      if data.cpu && over?(data.cpu.usage_percent, thresholds["cpu_percent"]),
+        do: [{"cpu", "CPU usage #{data.cpu.usage_percent}% exceeds #{thresholds["cpu_percent"]}%"}],
+        else: []
+
+    memory =
+      case memory_percent(data.memory) do
+        nil ->
+          []
+
+        percent ->
+          if over?(percent, thresholds["memory_percent"]),
+            do: [{"memory", "memory usage #{percent}% exceeds #{thresholds["memory_percent"]}%"}],
+            else: []
+      end
+
+    disks =
+      for disk <- data.disks, over?(disk.usage_percent, thresholds["disk_percent"]) do
+        {"disk:#{disk.mount_point}",
+         "disk #{disk.mount_point} at #{disk.usage_percent}% exceeds #{thresholds["disk_percent"]}%"}
+      end
+
+    swap =
+      case data.memory && Map.get(data.memory, :swap_out_rate) do
+        rate when is_number(rate) ->
+          if over?(rate, thresholds["swap_out_rate"]),
+            do: [{"swap", "swapping out #{rate} pages/s exceeds #{thresholds["swap_out_rate"]}"}],
+            else: []
+
+        _ ->
+          []
+      end
+
+    custom =
+      Enum.flat_map(thresholds, fn
+        {"custom." <> name, threshold} ->
+          value = CustomMetrics.value(data, name)
+
+          if over?(value, threshold),
+            do: [{"custom:#{name}", "#{name} at #{value} exceeds #{threshold}"}],
+            else: []
+
+        _ ->
+          []
+      end)
+
+    Map.new(cpu ++ memory ++ disks ++ swap ++ custom)
+  end
+
+  # Pages swapped in/out per second since the previous sample; counters that
+  # went backwards (a reboot) give no rate.
+  defp with_swap_rates(
+         %{memory: %MemoryInfo{swap_in_pages: pages_in} = memory} = data,
+         %{memory: %MemoryInfo{} = before} = previous
+       )
+       when is_integer(pages_in) do
+    seconds = seconds_between(previous.timestamp, data.timestamp)
+
+    %{
+      data
+      | memory: %{
+          memory
+          | swap_in_rate: rate(pages_in, Map.get(before, :swap_in_pages), seconds),
+            swap_out_rate: rate(memory.swap_out_pages, Map.get(before, :swap_out_pages), seconds)
+        }
+    }
+  end
+
+  defp with_swap_rates(data, _previous), do: data
+
+  defp rate(current, previous, seconds)
+       when is_integer(current) and is_integer(previous) and current >= previous and seconds > 0,
+       do: Float.round((current - previous) / seconds, 1)
+
+  defp rate(_current, _previous, _seconds), do: nil
+
+  defp seconds_between(from, to) do
+    with {:ok, from, _} <- DateTime.from_iso8601(from || ""),
+         {:ok, to, _} <- DateTime.from_iso8601(to || "") do
+      DateTime.diff(to, from)
+    else
+      _ -> 0
+    end
+  end
+
+  # OOM kills in the kernel log that the previous sample did not show. The
+  # first sample after startup only sets the baseline.
+  defp check_oom_kills(_server, _data, nil), do: :ok
+
+  defp check_oom_kills(server, data, previous) do
+    seen = previous |> Map.get(:oom_kills, []) |> MapSet.new(& &1.message)
+
+    data
+    |> Map.get(:oom_kills, [])
+    |> Enum.reject(&MapSet.member?(seen, &1.message))
+    |> Enum.each(fn kill ->
+      emit_event(
+        server,
+        "oom_kill",
+        "warning",
+        "#{server.name}: OOM killer terminated #{kill.process} (pid #{kill.pid})"
+      )
+    end)
+  end
+
+  # A degraded collection after a full one raises `collection_degraded`; the
+  # next full collection reports `collection_restored`.
+  defp check_degraded(server, data, previous) do
+    before = previous != nil and Map.get(previous, :degraded, false)
+
+    cond do
+      data.degraded and not before ->
+        emit_event(
+          server,
+          "collection_degraded",
+          "warning",
+          "#{server.name}: full collection failed, only uptime and load were read"
+        )
+
+      before and not data.degraded ->
+        message = "#{server.name}: full collection is back"
+        emit_event(server, "collection_restored", "info", message)
+
+      true ->
+        :ok
+    end
+  end
+
+  # Emits a warning for every HTTP check failing now but not in the previous
+  # sample, and an info event for every one passing again.
+  defp check_http(server, data, previous) do
+    failing = fn sample ->
+      for check <- Map.get(sample, :http_checks, []),
+          not check.success,
+          into: %{},
+          do: {check.name, check}
+    end
+
+    now = failing.(data)
+    before = if previous, do: failing.(previous), else: %{}
+
+    for {name, check} <- now, not Map.has_key?(before, name) do
+      emit_event(
+        server,
+        "http_check_failed",
+        "warning",
+        "#{server.name}: HTTP check #{name} (#{check.url}) failed: #{check.error}"
+      )
+    end
+
+    for {name, check} <- before, not Map.has_key?(now, name),
+        Enum.any?(data.http_checks, &(&1.name == name)) do
+      emit_event(
+        server,
+        "http_check_recovered",
+        "info",
+        "#{server.name}: HTTP check #{name} (#{check.url}) passes again"
+      )
+    end
+
+    :ok
+  end
+
+  defp override_cpu(data, nil), do: data
+
+  defp override_cpu(data, percent) do
+    %{data | cpu: %{data.cpu || %CpuInfo{} | usage_percent: percent}}
+  end
+
+  defp override_memory(data, nil), do: data
+
+  defp override_memory(data, percent) do
+    memory = data.memory || %MemoryInfo{}
+    total = if memory.total > 0, do: memory.total, else: 1024 * 1024 * 1024
+    used = round(total * percent / 100)
+    free = total - used
+
+    %{data | memory: %{memory | total: total, used: used, free: free, available: free}}
+  end
+
+  defp override_disk(data, nil), do: data
+
+  defp override_disk(data, percent) do
+    {root, others} = Enum.split_with(data.disks, &(&1.mount_point == "/"))
+    root = List.first(root) || %DiskInfo{device: "drill", mount_point: "/"}
+    %{data | disks: [%{root | usage_percent: percent} | others]}
+  end
+
+  defp over?(value, threshold) when is_number(value) and is_number(threshold), do: value >= threshold
+  defp over?(_value, _threshold), do: false
+
+  defp memory_percent(%{total: total, used: used}) when total > 0, do: Float.round(used / total * 100, 1)
+  defp memory_percent(_), do: nil
+
+  defp broadcast_status(%{status: status}, status), do: :ok
+
+  defp broadcast_status(server, status) do
+    PubSub.broadcast("status", %{
+      "server_id" => server.id,
+      "server_name" => server.name,
+      "previous_status" => server.status,
+      "status" => status
+    })
+  end
+
+  defp emit_event(server, kind, severity, message, opts \\ []) do
+    Events.emit(
+      server_id: server.id,
+      server_name: server.name,
+      group: Settings.group_for(server),
+      kind: kind,
+      severity: severity,
+      message: message,
+      drill: Keyword.get(opts, :drill, false)
+    )
+  end
+
+  defp add_job(server, job_type) do
+    now = DateTime.utc_now() |> DateTime.to_iso8601()
+    job = %{build_job(server) | job_type: job_type, started_at: now}
+    State.add_job(job)
+    job
+  end
+
+  # The task registers itself so `cancel_job/1` can find it; a cancelled
+  # task is killed and its entry removed by the canceller.
+  defp spawn_job(job, server, pid_file, fun) do
+    Task.Supervisor.start_child(AgentlessMonitor.TaskSupervisor, fn ->
+      :ets.insert(@job_table, {job.id, self(), server, pid_file})
+
+      try do
+        finish_job(job, fun)
+      after
+        :ets.delete(@job_table, job.id)
+      end
+    end)
+
+    job
+  end
+
+  defp cancellable(%{status: "running"}), do: :ok
+  defp cancellable(_job), do: {:error, :finished}
+
+  defp job_duration(%{started_at: started_at}, completed_at) when is_binary(started_at) do
+    case DateTime.from_iso8601(started_at) do
+      {:ok, started_at, _} -> DateTime.diff(completed_at, started_at, :millisecond)
+      _ -> nil
+    end
+  end
+
+  defp job_duration(_job, _completed_at), do: nil
+
+  # The command runs in the background of a shell that records its pid and
+  # passes on its exit status, so it can be signalled from another session.
+  defp cancellable_command(command, pid_file) do
+    "sh -c #{shell_quote(command)} & echo $! > #{pid_file}; wait $!; status=$?; " <>
+      "rm -f #{pid_file}; exit $status"
+  end
+
+  defp kill_command(pid_file) do
+    "pid=$(cat #{pid_file} 2>/dev/null) && " <>
+      "{ pkill -TERM -P \"$pid\"; kill -TERM \"$pid\"; rm -f #{pid_file}; }; true"
+  end
+
+  defp finish_job(job, fun) do
+    {:ok, started_at, _} = DateTime.from_iso8601(job.started_at)
+    result = fun.()
+    completed_at = DateTime.utc_now()
+
+    {status, output} =
+      case result do
+        {:ok, output} -> {"completed", output}
+        {:error, output} -> {"failed", output}
+      end
+
+    State.update_job(job.id, %{
+      status: status,
+      completed_at: DateTime.to_iso8601(completed_at),
+      duration_ms: DateTime.diff(completed_at, started_at, :millisecond),
+      output: output,
+      error: if(status == "failed", do: "#{job.job_type} failed", else: nil)
+    })
+
+    result
+  end
+
   defp build_job(server) do
     now = DateTime.utc_now() |> DateTime.to_iso8601()
 
@@ -152,37 +1162,494 @@ defmodule AgentlessMonitor.Monitoring.Service do
     }
   end
 
-  defp collect_local(_server) do
+  # Without a command prefix the host is read directly; with one (a chroot,
+  # network namespace, ...) the mega command runs through it instead.
+  defp collect_local(server, settings) do
     try do
-      mega_output = build_local_mega_output()
-      ping_output = run_ping()
-      parse_output(mega_output, ping_output, "local")
+      mega_output =
+        if server.command_prefix in [nil, ""] do
+          {:ok, build_local_mega_output(settings)}
+        else
+          run_command(server, mega_command(settings))
+        end
+
+      with {:ok, output} <- mega_output,
+           {:ok, data} <- parse_output(output, ping_tests(server, settings), server.id) do
+        {:ok, data |> watch_units(server) |> apply_collectors(settings["collectors"])}
+      end
     rescue
       e ->
         {:error, Exception.message(e)}
     end
   end
 
-  defp collect_remote(server) do
+  defp collect_snmp(server, settings) do
+    case SNMP.collect(server) do
+      {:ok, data} -> {:ok, apply_collectors(data, settings["collectors"])}
+      error -> error
+    end
+  end
+
+  # Pings run alongside the collection rather than after it, or within it
+  # in single-shot mode. Windows, macOS and BSD hosts take their own
+  # collection.
+  defp collect_remote(server, settings) do
     config = AgentlessMonitor.Config.load()
-    timeout = config.ssh_timeout
-    password = config.fallback_password
+    opts = ssh_opts(server, settings, config)
+
+    with {:ok, platform} <- platform(server, opts) do
+      cond do
+        platform == "windows" ->
+          collect_windows(server, settings, opts)
+
+        platform in BSD.platforms() ->
+          collect_bsd(server, settings, platform, opts)
+
+        true ->
+          case collect_posix(server, settings, config, opts) do
+            {:error, reason} -> collect_degraded(server, settings, opts, reason)
+            result -> result
+          end
+      end
+    end
+  end
+
+  # When the full collection fails but the host still answers on its SSH
+  # port, a sample with just uptime and load averages, marked `degraded`
+  # and with every collector failed, keeps charts from showing a gap. The
+  # original error stands when that fails too.
+  defp collect_degraded(server, settings, opts, reason) do
+    execute = &Connection.execute(server.host, server.port, server.username, &1, opts)
+
+    with :ok <- reachable(server, opts),
+         {:ok, output} <- execute.(@degraded_command),
+         {:ok, uptime, load_average} <- parse_degraded(output) do
+      Logger.info("Degraded collection for #{server.name}: #{reason}")
+
+      {:ok,
+       %MonitoringData{
+         server_id: server.id,
+         timestamp: DateTime.utc_now() |> DateTime.to_iso8601(),
+         cpu: %CpuInfo{usage_percent: nil, load_average: load_average, cores: nil},
+         system_info: %SystemInfo{uptime: uptime},
+         failed_collectors: settings["collectors"],
+         degraded: true
+       }}
+    else
+      _ -> {:error, reason}
+    end
+  end
+
+  # Behind a proxy the TCP probe can't tell, so the command decides.
+  defp reachable(server, opts) do
+    if Keyword.get(opts, :proxy) do
+      :ok
+    else
+      case Connection.probe(server.host, server.port, Keyword.get(opts, :timeout, 10)) do
+        {:ok, %{"banner" => "SSH-" <> _}} -> :ok
+        _ -> {:error, :unreachable}
+      end
+    end
+  end
+
+  defp collect_posix(server, settings, config, opts) do
+    embedded = single_shot_targets(settings, config)
+    pings = Task.async(fn -> if embedded, do: [], else: ping_tests(server, settings) end)
+
+    {output, failed} =
+      case parallel_timeout(config) do
+        nil ->
+          command = mega_command(settings) <> ping_sections(embedded, config.ping_timeout)
+          {Connection.execute(server.host, server.port, server.username, command, opts), []}
+
+        timeout ->
+          collect_parallel(server, settings, Keyword.put(opts, :timeout, timeout))
+      end
 
-    opts = [timeout: timeout, password: password]
+    ping_tests = Task.await(pings, :infinity)
 
-    with {:ok, mega_output} <-
-           Connection.execute(server.host, server.port, server.username, @mega_command, opts),
-         {:ok, ping_output} <-
-           Connection.execute(server.host, server.port, server.username, @ping_command, opts) do
-      parse_output(mega_output, ping_output, server.id)
+    with {:ok, output} <- output,
+         {mega_output, ping_tests} = split_pings(output, embedded, ping_tests),
+         {:ok, data} <- parse_output(mega_output, ping_tests, server.id) do
+      data = %{data | failed_collectors: failed}
+      {:ok, data |> watch_units(server) |> apply_collectors(settings["collectors"])}
     else
       {:error, reason} -> {:error, reason}
     end
   end
 
-  defp parse_output(mega_output, ping_output, server_id) do
+  # `opts` lack the platform on the collection that detects it.
+  defp collect_windows(server, settings, opts) do
+    command = Windows.command()
+    opts = Keyword.put(opts, :platform, "windows")
+
+    with {:ok, output} <-
+           Connection.execute(server.host, server.port, server.username, command, opts),
+         {:ok, data} <- Windows.parse(output, server.id) do
+      {:ok, apply_collectors(data, settings["collectors"])}
+    end
+  end
+
+  defp collect_bsd(server, settings, platform, opts) do
+    command = BSD.command(platform)
+
+    with {:ok, output} <-
+           Connection.execute(server.host, server.port, server.username, command, opts),
+         {:ok, data} <- BSD.parse(output, platform, server.id) do
+      {:ok, apply_collectors(data, settings["collectors"])}
+    end
+  end
+
+  # The lowercased `uname -s` of the target (`linux`, `freebsd`, ...), or
+  # `windows` when uname is missing and `ver` names Windows. Detected once
+  # and kept on the server; nil, and retried next time, when neither answers.
+  # When `uname -s` fails without a session to the host, the host wasn't
+  # reached and its error is returned so nothing else is attempted.
+  defp platform(%{platform: platform}, _opts) when is_binary(platform), do: {:ok, platform}
+
+  defp platform(server, opts) do
+    execute = &Connection.execute(server.host, server.port, server.username, &1, &2)
+
+    detected =
+      case execute.(@platform_command, opts) do
+        {:ok, output} ->
+          {:ok, output |> String.trim() |> String.downcase()}
+
+        {:error, reason} ->
+          if Connection.alive?(server.host, server.port, server.username, opts) do
+            case execute.(@windows_command, Keyword.put(opts, :platform, "windows")) do
+              {:ok, output} -> {:ok, if(output =~ "Windows", do: "windows")}
+              {:error, _reason} -> {:ok, nil}
+            end
+          else
+            {:error, reason}
+          end
+      end
+
+    # Embedded (see AgentlessMonitor.Collector) there is no State to cache it in.
+    with {:ok, platform} when is_binary(platform) <- detected,
+         pid when is_pid(pid) <- Process.whereis(State),
+         do: State.update_server(server.id, %{platform: platform})
+
+    detected
+  end
+
+  # With a `collector_timeout` each collector runs as its own command,
+  # concurrently over the shared connection and bounded by that timeout, so
+  # a section that hangs (`df` on a stuck NFS mount, a wedged docker daemon)
+  # only leaves its collector empty. The outputs are reassembled into mega
+  # command output, returned with the names of the collectors that failed;
+  # the collection fails only when every collector does.
+  defp collect_parallel(server, settings, opts) do
+    commands = collector_commands(settings)
+
+    results =
+      commands
+      |> Task.async_stream(
+        fn {_name, command} ->
+          Connection.execute(server.host, server.port, server.username, command, opts)
+        end,
+        timeout: (3 * opts[:timeout] + 10) * 1000,
+        on_timeout: :kill_task
+      )
+      |> Enum.zip(commands)
+      |> Enum.map(fn
+        {{:ok, result}, {name, _command}} -> {name, result}
+        {{:exit, _reason}, {name, _command}} -> {name, {:error, "timeout"}}
+      end)
+
+    outputs = for {name, {:ok, output}} <- results, into: %{}, do: {name, output}
+
+    Enum.each(results, fn
+      {name, {:error, reason}} ->
+        Logger.debug("Collector #{name} failed for #{server.name}: #{inspect(reason)}")
+
+      _ ->
+        :ok
+    end)
+
+    failed = for {name, {:error, _reason}} <- results, do: name
+
+    if outputs == %{} and results != [] do
+      {{:error, Enum.find_value(results, fn {_name, {:error, reason}} -> to_string(reason) end)},
+       failed}
+    else
+      sections =
+        for {name, output} <- outputs,
+            {{_label, index}, part} <-
+              Enum.zip(@debug_sections[name], String.split(output, "---SEP---\n")),
+            into: %{},
+            do: {index, part}
+
+      output =
+        Enum.map_join(0..(length(@sections) - 1), "---SEP---\n", &Map.get(sections, &1, ""))
+
+      {{:ok, output}, failed}
+    end
+  end
+
+  defp parallel_timeout(%{collector_timeout: timeout}) when is_integer(timeout) and timeout > 0,
+    do: timeout
+
+  defp parallel_timeout(_config), do: nil
+
+  # With `single_shot` the ping targets are pinged by the mega command itself,
+  # so a collection is one SSH execution. Pings on their own `ping_interval`
+  # and per-collector commands keep their separate executions.
+  defp single_shot_targets(settings, config) do
+    scheduled = is_integer(settings["ping_interval"]) and settings["ping_interval"] > 0
+
+    if config.single_shot == true and is_nil(parallel_timeout(config)) and not scheduled and
+         "ping" in settings["collectors"] do
+      settings["ping_targets"] || []
+    end
+  end
+
+  # Appended to the mega command: one ping per target after a `---PINGS---`
+  # marker, separated like mega command sections.
+  defp ping_sections(targets, _wait) when targets in [nil, []], do: ""
+
+  defp ping_sections(targets, wait) do
+    "; echo '---PINGS---'; " <>
+      Enum.map_join(targets, "; echo '---SEP---'; ", &ping_command(&1, wait))
+  end
+
+  defp split_pings(output, targets, ping_tests) when targets in [nil, []],
+    do: {output, ping_tests}
+
+  defp split_pings(output, targets, _ping_tests) do
+    case String.split(output, "---PINGS---\n", parts: 2) do
+      [mega_output, pings] ->
+        {mega_output, Parser.parse_ping_output(pings, targets)}
+
+      [mega_output] ->
+        failed = %PingTest{latency_ms: nil, success: false, error: "no ping output"}
+        {mega_output, Enum.map(targets, &%{failed | target: &1})}
+    end
+  end
+
+  # `{collector, command}` for the enabled collectors, each running its
+  # mega command sections. They end in `true` so, as in the mega command, a
+  # failing section doesn't fail the collector.
+  defp collector_commands(settings) do
+    for name <- settings["collectors"], Map.has_key?(@debug_sections, name) do
+      command =
+        @debug_sections
+        |> Map.fetch!(name)
+        |> Enum.map_join("; echo '---SEP---'; ", fn {_label, i} -> Enum.at(@sections, i) end)
+        |> String.replace("{{net}}", network_prefix(settings))
+
+      {name, command <> "; true"}
+    end
+  end
+
+  # A failed run leaves every custom metric out of the sample.
+  defp collect_custom(server, declarations) do
+    case run_command(server, CustomMetrics.command(declarations)) do
+      {:ok, output} ->
+        CustomMetrics.parse(output, declarations)
+
+      {:error, reason} ->
+        Logger.debug("Custom metrics failed for #{server.name}: #{inspect(reason)}")
+        %{}
+    end
+  end
+
+  # Monitor-side checks run concurrently; target-side ones share one remote
+  # command, which SNMP devices can't run.
+  defp run_http_checks(server, checks) do
+    {target, monitor} = Enum.split_with(checks, &(&1["from"] == "target"))
+
+    remote =
+      Task.async(fn ->
+        cond do
+          target == [] ->
+            []
+
+          server.transport == "snmp" ->
+            HttpChecks.failed(target, "not supported over SNMP")
+
+          true ->
+            case run_command(server, HttpChecks.command(target)) do
+              {:ok, output} -> HttpChecks.parse(output, target)
+              {:error, reason} -> HttpChecks.failed(target, to_string(reason))
+            end
+        end
+      end)
+
+    local =
+      monitor
+      |> Task.async_stream(&HttpChecks.run/1,
+        timeout: :infinity,
+        max_concurrency: 10
+      )
+      |> Enum.map(fn {:ok, result} -> result end)
+
+    results = local ++ Task.await(remote, :infinity)
+    Enum.map(checks, fn check -> Enum.find(results, &(&1.name == check["name"])) end)
+  end
+
+  defp target_http_checks(settings) do
+    settings |> HttpChecks.declarations() |> Enum.filter(&(&1["from"] == "target"))
+  end
+
+  # Servers with a `ping_interval` have their ping tests run on that schedule
+  # and collections attach the latest results; for the others the setting is
+  # re-checked every minute, without starting anything.
+  defp maybe_ping(server, config, now) do
+    case :ets.lookup(@ping_table, server.id) do
+      [{_, _tests, next_at}] when next_at > now ->
+        :ok
+
+      entry ->
+        previous =
+          case entry do
+            [{_, tests, _}] -> tests
+            [] -> nil
+          end
+
+        settings = Settings.for_server(server, config)
+
+        case settings["ping_interval"] do
+          interval when is_integer(interval) and interval > 0 ->
+            if "ping" in settings["collectors"] do
+              # Pushed back before the run so the next poll doesn't start another.
+              :ets.insert(@ping_table, {server.id, previous, now + interval})
+
+              Task.Supervisor.start_child(AgentlessMonitor.TaskSupervisor, fn ->
+                tests = run_pings(server, settings)
+                :ets.insert(@ping_table, {server.id, tests, now + interval})
+              end)
+            else
+              :ets.insert(@ping_table, {server.id, [], now + interval})
+            end
+
+          _ ->
+            :ets.insert(@ping_table, {server.id, previous, now + 60})
+        end
+    end
+  end
+
+  defp ping_tests(server, settings) do
+    cond do
+      "ping" not in settings["collectors"] ->
+        []
+
+      # Without the monitoring loop (embedded use) there is no scheduled
+      # ping table and pings run inline.
+      is_integer(settings["ping_interval"]) and settings["ping_interval"] > 0 and
+          :ets.whereis(@ping_table) != :undefined ->
+        case :ets.lookup(@ping_table, server.id) do
+          [{_, tests, _}] when is_list(tests) -> tests
+          _ -> []
+        end
+
+      true ->
+        run_pings(server, settings)
+    end
+  end
+
+  # Targets are pinged concurrently, each bounded by its own timeout, so an
+  # unreachable target no longer holds up the others.
+  defp run_pings(%{transport: "snmp"}, _settings), do: []
+
+  defp run_pings(server, settings) do
+    config = AgentlessMonitor.Config.load()
+    opts = ssh_opts(server, settings, config)
+    targets = settings["ping_targets"]
+
+    targets
+    |> Task.async_stream(&ping(server, &1, config.ping_timeout, opts),
+      timeout: (config.ping_timeout + opts[:timeout]) * 1000,
+      on_timeout: :kill_task
+    )
+    |> Enum.zip(targets)
+    |> Enum.map(fn
+      {{:ok, output}, target} ->
+        Parser.parse_single_ping(output, target)
+
+      {{:exit, _reason}, target} ->
+        %PingTest{target: target, latency_ms: nil, success: false, error: "timed out"}
+    end)
+  end
+
+  defp ping(server, target, wait, opts) do
+    command = ping_command(target, wait)
+
+    result =
+      if server.transport == "local" do
+        run_command(server, command)
+      else
+        Connection.execute(server.host, server.port, server.username, command, opts)
+      end
+
+    case result do
+      {:ok, output} -> output
+      {:error, reason} -> to_string(reason)
+    end
+  end
+
+  defp ping_command(target, wait), do: "ping -c 1 -W #{wait} #{shell_quote(target)} 2>&1"
+
+  defp shell_quote(value) do
+    "'" <> String.replace(value, "'", "'\\''") <> "'"
+  end
+
+  defp ssh_opts(server, settings, config) do
+    ssh_options = settings["ssh_options"]
+
+    [
+      timeout: Map.get(ssh_options, "timeout", config.ssh_timeout),
+      control_persist: Map.get(ssh_options, "control_persist", 60),
+      password: if(server.auth_method != "key_only", do: config.fallback_password),
+      proxy: server.proxy_config,
+      identity_files: Map.get(server, :identity_files) || [],
+      identification: config.identification,
+      platform: Map.get(server, :platform),
+      backend: Map.get(ssh_options, "backend", config.ssh_backend),
+      user_dir: ssh_options["user_dir"],
+      restricted: if(config.restricted_mode, do: Restricted.allowed(settings, config))
+    ]
+  end
+
+  # Drops the sections of a sample whose collectors are disabled for the server.
+  defp apply_collectors(data, collectors) do
+    enabled? = &(&1 in collectors)
+
+    %{
+      data
+      | cpu: if(enabled?.("cpu"), do: data.cpu),
+        memory: if(enabled?.("memory"), do: data.memory),
+        oom_kills: if(enabled?.("memory"), do: data.oom_kills, else: []),
+        disks: if(enabled?.("disks"), do: data.disks, else: []),
+        network: if(enabled?.("network"), do: data.network, else: []),
+        ports: if(enabled?.("ports"), do: data.ports, else: []),
+        processes: if(enabled?.("processes"), do: data.processes, else: []),
+        containers: if(enabled?.("containers"), do: data.containers, else: []),
+        services: if(enabled?.("services"), do: data.services, else: []),
+        ping_tests: if(enabled?.("ping"), do: data.ping_tests, else: []),
+        system_info: if(enabled?.("system"), do: data.system_info)
+    }
+  end
+
+  # With `watched_units` only those services are kept; a watched unit that is
+  # neither running nor failed is reported as inactive.
+  defp watch_units(data, %{watched_units: [_ | _] = watched}) do
+    units = Map.new(data.services, &{&1.name, &1})
+
+    services =
+      Enum.map(watched, fn name ->
+        name = if String.contains?(name, "."), do: name, else: name <> ".service"
+        Map.get(units, name, %ServiceUnit{name: name, active: "inactive", sub: "dead"})
+      end)
+
+    %{data | services: services}
+  end
+
+  defp watch_units(data, _server), do: data
+
+  defp parse_output(mega_output, ping_tests, server_id) do
     metrics = Parser.parse_mega_output(mega_output)
-    ping_tests = Parser.parse_ping_output(ping_output)
 
     data = %MonitoringData{
       server_id: server_id,
@@ -192,6 +1659,10 @@ defmodule AgentlessMonitor.Monitoring.Service do
       disks: metrics.disks,
       network: metrics.network,
       ports: metrics.ports,
+      processes: metrics.processes,
+      oom_kills: metrics.oom_kills,
+      containers: metrics.containers,
+      services: metrics.services,
       ping_tests: ping_tests,
       system_info: metrics.system_info
     }
@@ -199,7 +1670,40 @@ defmodule AgentlessMonitor.Monitoring.Service do
     {:ok, data}
   end
 
-  defp build_local_mega_output do
+  # The network and port collectors run behind the server's `network_prefix`
+  # (e.g. `ip netns exec blue` or `ip vrf exec mgmt`), if any, so they report
+  # that namespace or VRF rather than the default one. Sections of collectors
+  # the server doesn't use are left empty rather than run.
+  defp mega_command(settings) do
+    case skipped_sections(settings["collectors"]) do
+      [] ->
+        String.replace(@mega_command, "{{net}}", network_prefix(settings))
+
+      skipped ->
+        @sections
+        |> Enum.with_index()
+        |> Enum.map_join("; echo '---SEP---'; ", fn {section, index} ->
+          if index in skipped, do: "true", else: section
+        end)
+        |> String.replace("{{net}}", network_prefix(settings))
+    end
+  end
+
+  defp skipped_sections(collectors) do
+    for {name, sections} <- @debug_sections,
+        name not in collectors,
+        {_label, index} <- sections,
+        do: index
+  end
+
+  defp network_prefix(settings) do
+    case settings["network_prefix"] do
+      prefix when is_binary(prefix) and prefix != "" -> prefix <> " "
+      _ -> ""
+    end
+  end
+
+  defp build_local_mega_output(settings) do
     stat =
       case File.read("/proc/stat") do
         {:ok, content} -> content |> String.split("\n") |> hd()
@@ -235,12 +1739,28 @@ defmodule AgentlessMonitor.Monitoring.Service do
         _ -> ""
       end
 
+    vmstat =
+      case File.read("/proc/vmstat") do
+        {:ok, content} ->
+          content |> String.split("\n") |> Enum.filter(&(&1 =~ ~r/^pswp(in|out) /))
+
+        _ ->
+          []
+      end
+
     {df, _} = System.cmd("df", ["-h"], stderr_to_stdout: true)
 
+    net = network_prefix(settings)
+
     netdev =
-      case File.read("/proc/net/dev") do
-        {:ok, content} -> content
-        _ -> ""
+      if net == "" do
+        case File.read("/proc/net/dev") do
+          {:ok, content} -> content
+          _ -> ""
+        end
+      else
+        {out, _} = System.cmd("sh", ["-c", net <> "cat /proc/net/dev"], stderr_to_stdout: true)
+        out
       end
 
     {hostname, _} = System.cmd("hostname", [], stderr_to_stdout: true)
@@ -262,39 +1782,50 @@ defmodule AgentlessMonitor.Monitoring.Service do
     arch = String.trim(arch)
 
     ports =
-      case System.cmd("ss", ["-tuln"], stderr_to_stdout: true) do
+      case System.cmd("sh", ["-c", net <> "ss -tuln"], stderr_to_stdout: true) do
         {out, 0} ->
           out
 
         _ ->
-          case System.cmd("netstat", ["-tuln"], stderr_to_stdout: true) do
+          case System.cmd("sh", ["-c", net <> "netstat -tuln"], stderr_to_stdout: true) do
             {out, 0} -> out
             _ -> "no_port_info"
           end
       end
 
+    os_release =
+      case File.read("/etc/os-release") do
+        {:ok, content} ->
+          Enum.map_join(["PRETTY_NAME", "ID", "VERSION_ID"], "\n", fn key ->
+            case Regex.run(~r/^#{key}="?([^"\n]*)"?$/m, content) do
+              [_, value] -> value
+              _ -> ""
+            end
+          end)
+
+        _ ->
+          ""
+      end
+
+    ip_addresses =
+      case System.cmd("hostname", ["-I"], stderr_to_stdout: true) do
+        {out, 0} -> out
+        _ -> ""
+      end
+
+    {processes, _} = System.cmd("sh", ["-c", @process_command], stderr_to_stdout: true)
+    {oom_kills, _} = System.cmd("sh", ["-c", @oom_command], stderr_to_stdout: true)
+    {containers, _} = System.cmd("sh", ["-c", @docker_command])
+    {services, _} = System.cmd("sh", ["-c", @systemd_command])
+    meminfo = Enum.join([meminfo | vmstat], "\n")
+
     Enum.join(
-      [stat, loadavg, nproc, model, meminfo, df, netdev, hostname, os, kernel, uptime, arch, ports],
+      [stat, loadavg, nproc, model, meminfo, df, netdev, hostname, os, kernel, uptime, arch, ports] ++
+        [os_release, ip_addresses, processes, oom_kills, containers, services],
       "---SEP---\n"
     )
   end
 
-  defp run_ping do
-    task =
-      Task.async(fn ->
-        {r1, _} = System.cmd("ping", ["-c", "1", "-W", "2", "8.8.8.8"], stderr_to_stdout: true)
-        {r2, _} = System.cmd("ping", ["-c", "1", "-W", "2", "1.1.1.1"], stderr_to_stdout: true)
-        r1 <> "---SEP---\n" <> r2
-      end)
-
-    case Task.yield(task, 15_000) do
-      {:ok, result} -> result
-      nil ->
-        Task.shutdown(task, :brutal_kill)
-        "---SEP---\n"
-    end
-  end
-
   defp generate_id do
     :crypto.strong_rand_bytes(16)
     |> Base.encode16(case: :lower)
@@ -0,0 +1,252 @@
+defmodule AgentlessMonitor.Monitoring.SNMP do
+  @moduledoc """
+  Collection from network devices that speak SNMP but not SSH, for servers
+  with `"transport": "snmp"`. Credentials go in the server's `snmp` entry:
+
+      {"name": "core-sw-01", "host": "10.0.0.2", "transport": "snmp",
+       "snmp": {"version": "2c", "community": "monitoring"}}
+
+      "snmp": {"version": "3", "username": "monitor", "security_level": "authPriv",
+               "auth_protocol": "SHA", "auth_password": "...",
+               "priv_protocol": "AES", "priv_password": "..."}
+
+  The device is walked from the monitor with the net-snmp tools
+  (`snmpbulkwalk`, or `snmpwalk` for version 1) on the server's port
+  (161 by default), each walk bounded by the entry's `timeout` (default 5
+  seconds) with one retry. A collection reads:
+
+    * the system group (`sysDescr`, `sysName`, `sysUpTime`), which must
+      answer for the collection to succeed
+    * interfaces from `ifTable`, with names and 64-bit counters from
+      `ifXTable` where the device has them
+    * CPU from `hrProcessorLoad`, else Cisco's `cpmCPUTotal1minRev`
+    * memory from the RAM entry of `hrStorageTable`, else Cisco's memory
+      pools
+
+  CPU and memory stay empty on devices offering neither. Load averages,
+  disks, ports, processes, containers, services and pings aren't collected.
+  """
+
+  alias AgentlessMonitor.Models.{CpuInfo, MemoryInfo, MonitoringData, NetworkInfo, SystemInfo}
+
+  @default_timeout 5
+
+  @system "1.3.6.1.2.1.1"
+  @if_table "1.3.6.1.2.1.2.2.1"
+  @if_x_table "1.3.6.1.2.1.31.1.1.1"
+  @hr_processor_load "1.3.6.1.2.1.25.3.3.1.2"
+  @hr_storage "1.3.6.1.2.1.25.2.3.1"
+  @hr_storage_ram ".1.3.6.1.2.1.25.2.1.2"
+  @cisco_cpu "1.3.6.1.4.1.9.9.109.1.1.1.1.7"
+  @cisco_memory "1.3.6.1.4.1.9.9.48.1.1.1"
+
+  @walks [@system, @if_table, @if_x_table, @hr_processor_load, @hr_storage] ++
+           [@cisco_cpu, @cisco_memory]
+
+  @doc "Collects one sample from `server`; `{:ok, data}` or `{:error, reason}`"
+  def collect(server) do
+    # `"version": 3` is as good as `"3"`.
+    snmp = Map.update(server.snmp || %{}, "version", "2c", &to_string/1)
+    timeout = snmp["timeout"] || @default_timeout
+
+    with {:ok, args} <- credentials(snmp) do
+      target = "#{server.host}:#{server.port || 161}"
+
+      walks =
+        @walks
+        |> Task.async_stream(&walk(snmp["version"], args, timeout, target, &1),
+          timeout: (2 * timeout + 5) * 1000,
+          on_timeout: :kill_task
+        )
+        |> Enum.zip(@walks)
+        |> Map.new(fn
+          {{:ok, result}, oid} -> {oid, result}
+          {{:exit, _reason}, oid} -> {oid, {:error, "timeout"}}
+        end)
+
+      case walks[@system] do
+        {:ok, system} when map_size(system) > 0 ->
+          walked = for {oid, {:ok, values}} <- walks, into: %{}, do: {oid, values}
+          {:ok, sample(server.id, walked)}
+
+        {:ok, _empty} ->
+          {:error, "#{target} returned no system information"}
+
+        {:error, reason} ->
+          {:error, reason}
+      end
+    end
+  end
+
+  # ---- Private helpers ----
+
+  defp credentials(%{"version" => "3"} = snmp) do
+    level = snmp["security_level"] || "authPriv"
+
+    auth =
+      if level in ["authNoPriv", "authPriv"],
+        do: ["-a", snmp["auth_protocol"] || "SHA", "-A", to_string(snmp["auth_password"])],
+        else: []
+
+    priv =
+      if level == "authPriv",
+        do: ["-x", snmp["priv_protocol"] || "AES", "-X", to_string(snmp["priv_password"])],
+        else: []
+
+    if is_binary(snmp["username"]),
+      do: {:ok, ["-v3", "-u", snmp["username"], "-l", level] ++ auth ++ priv},
+      else: {:error, "snmp.username is required for SNMPv3"}
+  end
+
+  defp credentials(%{"community" => community} = snmp) when is_binary(community) do
+    version = if snmp["version"] == "1", do: "-v1", else: "-v2c"
+    {:ok, [version, "-c", community]}
+  end
+
+  defp credentials(_snmp), do: {:error, "snmp.community (or SNMPv3 credentials) is required"}
+
+  defp walk(version, args, timeout, target, oid) do
+    tool = if version == "1", do: "snmpwalk", else: "snmpbulkwalk"
+    options = ["-On", "-Oq", "-Ot", "-Oe", "-t", to_string(timeout), "-r", "1"]
+
+    if System.find_executable(tool) do
+      case System.cmd(tool, args ++ options ++ [target, oid], stderr_to_stdout: true) do
+        {output, 0} -> {:ok, parse_walk(output)}
+        {output, _code} -> {:error, String.trim(output)}
+      end
+    else
+      {:error, "#{tool} not found; install the net-snmp tools on the monitor"}
+    end
+  end
+
+  # `.1.3.6.1.2.1.1.5.0 core-sw-01` lines; a quoted string may run over
+  # several lines. Varbinds the agent doesn't have are left out.
+  defp parse_walk(output) do
+    output
+    |> String.split("\n")
+    |> Enum.reduce([], fn line, acc ->
+      case {Regex.run(~r/^\.([\d.]+) ?(.*)$/, line), acc} do
+        {[_, oid, value], _} -> [{oid, value} | acc]
+        {nil, [{oid, value} | rest]} -> [{oid, value <> "\n" <> line} | rest]
+        {nil, []} -> []
+      end
+    end)
+    |> Enum.reject(fn {_oid, value} -> String.starts_with?(value, "No ") end)
+    |> Map.new(fn {oid, value} -> {oid, value |> String.trim() |> String.trim("\"")} end)
+  end
+
+  # Values of one table column by row index.
+  defp column(values, table, field) do
+    prefix = "#{table}.#{field}."
+
+    for {oid, value} <- Map.get(values, table, %{}),
+        String.starts_with?(oid, prefix),
+        into: %{},
+        do: {String.replace_prefix(oid, prefix, ""), value}
+  end
+
+  defp sample(server_id, values) do
+    system = values[@system]
+    description = system |> Map.get("#{@system}.1.0", "") |> String.split("\n") |> hd()
+
+    %MonitoringData{
+      server_id: server_id,
+      timestamp: DateTime.utc_now() |> DateTime.to_iso8601(),
+      cpu: cpu(values, description),
+      memory: memory(values),
+      network: network(values),
+      system_info: %SystemInfo{
+        hostname: Map.get(system, "#{@system}.5.0", ""),
+        os: description,
+        os_release: description,
+        uptime: div(integer(system["#{@system}.3.0"]), 100)
+      }
+    }
+  end
+
+  defp cpu(values, description) do
+    loads =
+      case values |> Map.get(@hr_processor_load, %{}) |> Map.values() do
+        [] -> values |> Map.get(@cisco_cpu, %{}) |> Map.values()
+        loads -> loads
+      end
+
+    case Enum.map(loads, &integer/1) do
+      [] ->
+        nil
+
+      loads ->
+        %CpuInfo{
+          usage_percent: Float.round(Enum.sum(loads) / length(loads), 1),
+          cores: length(loads),
+          model: description
+        }
+    end
+  end
+
+  defp memory(values) do
+    ram =
+      values
+      |> column(@hr_storage, 2)
+      |> Enum.find_value(fn {index, type} -> if type == @hr_storage_ram, do: index end)
+
+    pools_used = column(values, @cisco_memory, 5)
+
+    cond do
+      ram ->
+        unit = integer(column(values, @hr_storage, 4)[ram])
+        total = integer(column(values, @hr_storage, 5)[ram]) * unit
+        used = integer(column(values, @hr_storage, 6)[ram]) * unit
+        memory_info(total, used)
+
+      pools_used != %{} ->
+        used = sum(pools_used)
+        memory_info(used + sum(column(values, @cisco_memory, 6)), used)
+
+      true ->
+        nil
+    end
+  end
+
+  defp sum(column), do: column |> Map.values() |> Enum.map(&integer/1) |> Enum.sum()
+
+  defp memory_info(total, used) do
+    free = max(0, total - used)
+    %MemoryInfo{total: total, used: used, free: free, available: free}
+  end
+
+  # 64-bit counters from `ifXTable` win over the 32-bit ones of `ifTable`,
+  # which wrap within minutes on fast links.
+  defp network(values) do
+    names = column(values, @if_x_table, 1)
+    hc_in = column(values, @if_x_table, 6)
+    hc_out = column(values, @if_x_table, 10)
+
+    [in_octets, in_packets, in_errors, out_octets, out_packets, out_errors] =
+      Enum.map([10, 11, 14, 16, 17, 20], &column(values, @if_table, &1))
+
+    values
+    |> column(@if_table, 2)
+    |> Enum.sort_by(fn {index, _description} -> integer(index) end)
+    |> Enum.map(fn {index, description} ->
+      %NetworkInfo{
+        interface: Map.get(names, index, description),
+        rx_bytes: integer(hc_in[index] || in_octets[index]),
+        tx_bytes: integer(hc_out[index] || out_octets[index]),
+        rx_packets: integer(in_packets[index]),
+        tx_packets: integer(out_packets[index]),
+        rx_errors: integer(in_errors[index]),
+        tx_errors: integer(out_errors[index])
+      }
+    end)
+  end
+
+  defp integer(nil), do: 0
+
+  defp integer(value) do
+    case Integer.parse(value) do
+      {n, _} -> n
+      :error -> 0
+    end
+  end
+end
@@ -0,0 +1,128 @@
+defmodule AgentlessMonitor.Monitoring.Watchdog do
+  @moduledoc """
+  Process expectations, a lighter alternative to service checks: each rule
+  counts the processes whose command line matches and requires at least
+  `min` (default 1) and at most `max` (optional) of them.
+
+      "process_rules": [
+        {"match": "postgres: checkpointer"},
+        {"name": "gunicorn", "match": "gunicorn: worker", "min": 4},
+        {"match": "^/usr/sbin/sshd", "regex": true, "severity": "critical"}
+      ]
+
+  `match` is a substring of the command line unless `regex` is set. Rules
+  are a setting like `thresholds`, so groups and `server_overrides` can
+  replace them. When a server has rules, its process list is read after
+  each collection; a rule that starts failing emits a
+  `process_rule_violated` event (severity `warning` unless set) and one
+  that passes again a `process_rule_recovered` event.
+  """
+
+  use GenServer
+
+  alias AgentlessMonitor.Monitoring.Service
+
+  @command "ps -eo args= 2>/dev/null || ps -o args"
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, nil, name: __MODULE__)
+  end
+
+  def command, do: @command
+
+  @doc """
+  Reads the process list of `server` and evaluates `rules` against it.
+  Returns `{:ok, violated, recovered}` with the rule results that changed
+  state since the previous check.
+  """
+  def check(server, rules) do
+    with {:ok, output} <- Service.run_command(server, @command) do
+      results = evaluate(rules, parse(output))
+      checked_at = DateTime.utc_now() |> DateTime.to_iso8601()
+      GenServer.call(__MODULE__, {:record, server.id, results, checked_at})
+    end
+  end
+
+  @doc "Latest rule results of a server, or nil when it has not been checked"
+  def status(server_id) do
+    GenServer.call(__MODULE__, {:status, server_id})
+  end
+
+  @doc "Command lines from `ps` output, without the header BusyBox prints"
+  def parse(output) do
+    output
+    |> String.split("\n", trim: true)
+    |> Enum.map(&String.trim/1)
+    |> Enum.reject(&(&1 in ["", "COMMAND"]))
+  end
+
+  @doc """
+  One result per rule: `%{"rule", "count", "min", "max", "ok", "severity"}`,
+  or `"error"` instead of a count for an invalid regex.
+  """
+  def evaluate(rules, processes) do
+    Enum.map(rules, fn rule ->
+      min = Map.get(rule, "min", 1)
+      max = Map.get(rule, "max")
+
+      base = %{
+        "rule" => Map.get(rule, "name") || rule["match"],
+        "match" => rule["match"],
+        "min" => min,
+        "max" => max,
+        "severity" => Map.get(rule, "severity", "warning")
+      }
+
+      case matcher(rule) do
+        {:ok, matches?} ->
+          count = Enum.count(processes, matches?)
+          ok = count >= min and (is_nil(max) or count <= max)
+          Map.merge(base, %{"count" => count, "ok" => ok})
+
+        {:error, message} ->
+          Map.merge(base, %{"error" => message, "ok" => false})
+      end
+    end)
+  end
+
+  # ---- Callbacks ----
+
+  def init(_) do
+    {:ok, %{results: %{}}}
+  end
+
+  def handle_call({:record, server_id, results, checked_at}, _from, state) do
+    previous =
+      state.results
+      |> Map.get(server_id, %{"rules" => []})
+      |> Map.fetch!("rules")
+      |> Map.new(&{&1["rule"], &1["ok"]})
+
+    violated = Enum.filter(results, &(not &1["ok"] and Map.get(previous, &1["rule"], true)))
+    recovered = Enum.filter(results, &(&1["ok"] and Map.get(previous, &1["rule"]) == false))
+
+    entry = %{"checked_at" => checked_at, "rules" => results}
+    {:reply, {:ok, violated, recovered}, put_in(state.results[server_id], entry)}
+  end
+
+  def handle_call({:status, server_id}, _from, state) do
+    {:reply, Map.get(state.results, server_id), state}
+  end
+
+  # ---- Private helpers ----
+
+  defp matcher(%{"match" => pattern, "regex" => true}) when is_binary(pattern) do
+    case Regex.compile(pattern) do
+      {:ok, regex} -> {:ok, &Regex.match?(regex, &1)}
+      {:error, {reason, _at}} -> {:error, "invalid regex: #{reason}"}
+    end
+  end
+
+  defp matcher(%{"match" => pattern}) when is_binary(pattern) and pattern != "" do
+    {:ok, &String.contains?(&1, pattern)}
+  end
+
+  defp matcher(_rule), do: {:error, "match is required"}
+end
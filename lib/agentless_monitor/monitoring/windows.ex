@@ -0,0 +1,175 @@
+defmodule AgentlessMonitor.Monitoring.Windows do
+  @moduledoc """
+  Collection from Windows hosts running OpenSSH, where neither /proc nor the
+  Linux tools exist.
+
+  A collection is one PowerShell script, sent as `-EncodedCommand` so it
+  survives both cmd.exe and PowerShell as the default SSH shell. It reads
+  CPU usage with `Get-Counter` (falling back to `Win32_Processor`'s load),
+  memory and the page file from `Win32_OperatingSystem` and
+  `Win32_PageFileUsage`, fixed disks from `Win32_LogicalDisk`, interfaces
+  from `Get-NetAdapterStatistics` and `Get-NetIPAddress`, and prints them as
+  one JSON document.
+
+  Load averages don't exist on Windows and stay at zero; ports, processes,
+  containers, services, OOM kills and pings aren't collected.
+  """
+
+  alias AgentlessMonitor.Models.{
+    CpuInfo,
+    DiskInfo,
+    MemoryInfo,
+    MonitoringData,
+    NetworkInfo,
+    SystemInfo
+  }
+
+  @script ~S"""
+  $ErrorActionPreference = 'SilentlyContinue'
+  $os = Get-CimInstance Win32_OperatingSystem
+  $cpu = @(Get-CimInstance Win32_Processor)
+  $counter = Get-Counter '\Processor(_Total)\% Processor Time' -SampleInterval 1 -MaxSamples 1
+  $load = $counter.CounterSamples[0].CookedValue
+  if ($null -eq $load) { $load = ($cpu | Measure-Object LoadPercentage -Average).Average }
+  $pagefile = @(Get-CimInstance Win32_PageFileUsage)
+  $disks = @(Get-CimInstance Win32_LogicalDisk -Filter 'DriveType=3' | ForEach-Object {
+    @{
+      device = $_.DeviceID; filesystem = $_.FileSystem
+      size = [int64]$_.Size; free = [int64]$_.FreeSpace
+    }
+  })
+  $addresses = @(Get-NetIPAddress |
+    Where-Object { $_.IPAddress -notlike '127.*' -and $_.IPAddress -ne '::1' })
+  $network = @(Get-NetAdapterStatistics | ForEach-Object {
+    $name = $_.Name
+    @{
+      name = $name
+      rx_bytes = [int64]$_.ReceivedBytes; tx_bytes = [int64]$_.SentBytes
+      rx_packets = [int64]$_.ReceivedUnicastPackets; tx_packets = [int64]$_.SentUnicastPackets
+      rx_errors = [int64]$_.ReceivedPacketErrors; tx_errors = [int64]$_.OutboundPacketErrors
+      addresses = @($addresses | Where-Object { $_.InterfaceAlias -eq $name } |
+        ForEach-Object { $_.IPAddress })
+    }
+  })
+  @{
+    cpu_percent = $load
+    cores = ($cpu | Measure-Object NumberOfLogicalProcessors -Sum).Sum
+    cpu_model = $cpu[0].Name
+    memory_total_kb = $os.TotalVisibleMemorySize
+    memory_free_kb = $os.FreePhysicalMemory
+    swap_total_mb = ($pagefile | Measure-Object AllocatedBaseSize -Sum).Sum
+    swap_used_mb = ($pagefile | Measure-Object CurrentUsage -Sum).Sum
+    hostname = $env:COMPUTERNAME
+    os = $os.Caption
+    version = $os.Version
+    architecture = $os.OSArchitecture
+    uptime = [int64]((Get-Date) - $os.LastBootUpTime).TotalSeconds
+    disks = $disks
+    network = $network
+  } | ConvertTo-Json -Depth 4 -Compress
+  """
+
+  @doc "The collection command for Windows hosts"
+  def command do
+    encoded =
+      @script
+      |> :unicode.characters_to_binary(:utf8, {:utf16, :little})
+      |> Base.encode64()
+
+    "powershell -NoProfile -NonInteractive -EncodedCommand #{encoded}"
+  end
+
+  @doc "Parses the output of `command/0` into a sample of `server_id`"
+  def parse(output, server_id) do
+    case Jason.decode(String.trim(output)) do
+      {:ok, %{} = json} ->
+        {:ok,
+         %MonitoringData{
+           server_id: server_id,
+           timestamp: DateTime.utc_now() |> DateTime.to_iso8601(),
+           cpu: cpu(json),
+           memory: memory(json),
+           disks: json["disks"] |> List.wrap() |> Enum.map(&disk/1),
+           network: json["network"] |> List.wrap() |> Enum.map(&interface/1),
+           system_info: system_info(json)
+         }}
+
+      _ ->
+        {:error, "unexpected output from Windows collection: #{String.slice(output, 0, 200)}"}
+    end
+  end
+
+  # ---- Private helpers ----
+
+  defp cpu(json) do
+    %CpuInfo{
+      usage_percent: Float.round(number(json["cpu_percent"]) / 1, 1),
+      cores: max(1, number(json["cores"])),
+      model: String.trim(json["cpu_model"] || "")
+    }
+  end
+
+  defp memory(json) do
+    total = number(json["memory_total_kb"]) * 1024
+    free = number(json["memory_free_kb"]) * 1024
+    swap_total = number(json["swap_total_mb"]) * 1024 * 1024
+    swap_used = number(json["swap_used_mb"]) * 1024 * 1024
+
+    %MemoryInfo{
+      total: total,
+      used: max(0, total - free),
+      free: free,
+      available: free,
+      swap_total: swap_total,
+      swap_used: swap_used,
+      swap_free: max(0, swap_total - swap_used)
+    }
+  end
+
+  defp disk(disk) do
+    total = number(disk["size"])
+    free = number(disk["free"])
+    used = max(0, total - free)
+
+    %DiskInfo{
+      device: disk["device"] || "",
+      mount_point: disk["device"] || "",
+      filesystem: disk["filesystem"] || "",
+      total: total,
+      used: used,
+      free: free,
+      usage_percent: if(total > 0, do: Float.round(used / total * 100, 1), else: 0.0)
+    }
+  end
+
+  defp interface(interface) do
+    %NetworkInfo{
+      interface: interface["name"] || "",
+      rx_bytes: number(interface["rx_bytes"]),
+      tx_bytes: number(interface["tx_bytes"]),
+      rx_packets: number(interface["rx_packets"]),
+      tx_packets: number(interface["tx_packets"]),
+      rx_errors: number(interface["rx_errors"]),
+      tx_errors: number(interface["tx_errors"]),
+      ip_addresses: List.wrap(interface["addresses"])
+    }
+  end
+
+  defp system_info(json) do
+    %SystemInfo{
+      hostname: json["hostname"] || "",
+      os: "Windows",
+      os_release: json["os"] || "",
+      os_id: "windows",
+      os_version: json["version"] || "",
+      kernel: json["version"] || "",
+      architecture: json["architecture"] || "",
+      uptime: number(json["uptime"]),
+      ip_addresses:
+        json["network"] |> List.wrap() |> Enum.flat_map(&List.wrap(&1["addresses"]))
+    }
+  end
+
+  defp number(value) when is_number(value), do: value
+  defp number(_value), do: 0
+end
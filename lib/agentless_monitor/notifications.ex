@@ -0,0 +1,237 @@
+defmodule AgentlessMonitor.Notifications do
+  @moduledoc """
+  Fans events out to the notification channels configured in `notifiers`:
+
+      "notifiers": [
+        {"type": "slack", "url": "https://hooks.slack.com/services/...",
+         "min_severity": "warning"},
+        {"type": "command", "command": "/usr/local/bin/page-oncall",
+         "min_severity": "critical", "kinds": ["server_down"], "groups": ["prod"]}
+      ]
+
+  Each channel's `type` is looked up in the registry of channel types (see
+  `AgentlessMonitor.Notifier`); a `module` key can name a custom
+  implementation instead. `"enabled": false` turns a channel off without
+  removing it. Channels filter on `min_severity` (default
+  `warning`), `kinds` and `groups`. Deliveries run concurrently and failures
+  are logged without affecting other channels; a failed delivery is retried
+  `retries` times (default 2). Every delivery is recorded in
+  `AgentlessMonitor.Alerting.History`.
+
+  Channels are identified by their optional `id` key, falling back to their
+  position in the list (`"0"`, `"1"`, ...).
+  """
+
+  use GenServer
+  require Logger
+
+  alias AgentlessMonitor.{Config, Features}
+  alias AgentlessMonitor.Models.Event
+  alias AgentlessMonitor.Alerting.History
+
+  @builtin_types %{
+    "webhook" => AgentlessMonitor.Notifiers.Webhook,
+    "slack" => AgentlessMonitor.Notifiers.Slack,
+    "telegram" => AgentlessMonitor.Notifiers.Telegram,
+    "email" => AgentlessMonitor.Notifiers.Email,
+    "command" => AgentlessMonitor.Notifiers.Command
+  }
+
+  @severities ["info", "warning", "critical"]
+  @default_retries 2
+  @retry_delay 1_000
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, nil, name: __MODULE__)
+  end
+
+  def dispatch(%Event{} = event) do
+    if Features.enabled?(:notifications) do
+      GenServer.cast(__MODULE__, {:dispatch, Event.to_map(event)})
+    end
+
+    :ok
+  end
+
+  @doc "Registers (or replaces) a channel type implemented by `module`"
+  def register_type(type, module) do
+    GenServer.call(__MODULE__, {:register_type, type, module})
+  end
+
+  def types do
+    GenServer.call(__MODULE__, :types)
+  end
+
+  @doc "Configured channels without their credentials"
+  def channels do
+    Config.load().notifiers
+    |> with_ids()
+    |> Enum.map(fn {id, channel} ->
+      channel
+      |> Map.take(["type", "module", "format", "min_severity", "kinds", "groups", "retries"])
+      |> Map.put("id", id)
+      |> Map.put("enabled", Map.get(channel, "enabled", true) != false)
+    end)
+  end
+
+  @doc """
+  Sends a test message through one channel, bypassing its filters, and
+  returns the delivery result.
+  """
+  def test_channel(id) do
+    with {:ok, channel} <- find_channel(id),
+         {:ok, module} <- GenServer.call(__MODULE__, {:resolve, channel}) do
+      event = %{
+        "id" => "test",
+        "kind" => "test",
+        "severity" => "info",
+        "message" => "Test notification from agentless-monitor",
+        "timestamp" => DateTime.utc_now() |> DateTime.to_iso8601()
+      }
+
+      deliver(module, event, channel, 0)
+    end
+  end
+
+  # ---- Callbacks ----
+
+  def init(_) do
+    {:ok, %{types: @builtin_types}}
+  end
+
+  def handle_call({:register_type, type, module}, _from, state) do
+    {:reply, :ok, %{state | types: Map.put(state.types, type, module)}}
+  end
+
+  def handle_call(:types, _from, state) do
+    {:reply, Map.keys(state.types), state}
+  end
+
+  def handle_call({:resolve, channel}, _from, state) do
+    case resolve(channel, state.types) do
+      {:ok, module} -> {:reply, {:ok, module}, state}
+      :error -> {:reply, {:error, :unknown_type}, state}
+    end
+  end
+
+  def handle_cast({:dispatch, event}, state) do
+    Config.load().notifiers
+    |> Enum.filter(&wants?(&1, event))
+    |> Enum.each(fn channel ->
+      case resolve(channel, state.types) do
+        {:ok, module} ->
+          Task.Supervisor.start_child(AgentlessMonitor.TaskSupervisor, fn ->
+            deliver(module, event, channel)
+          end)
+
+        :error ->
+          Logger.warning("Unknown notifier type #{inspect(channel["type"])}")
+      end
+    end)
+
+    {:noreply, state}
+  end
+
+  # ---- Private helpers ----
+
+  defp with_ids(channels) do
+    channels
+    |> Enum.with_index()
+    |> Enum.map(fn {channel, index} -> {Map.get(channel, "id", to_string(index)), channel} end)
+  end
+
+  defp find_channel(id) do
+    case List.keyfind(with_ids(Config.load().notifiers), id, 0) do
+      {_id, channel} -> {:ok, channel}
+      nil -> {:error, :not_found}
+    end
+  end
+
+  defp resolve(%{"module" => module}, _types) when is_binary(module) do
+    module = Module.concat([module])
+    if Code.ensure_loaded?(module), do: {:ok, module}, else: :error
+  end
+
+  defp resolve(channel, types), do: Map.fetch(types, channel["type"])
+
+  defp wants?(channel, event) do
+    min_severity = Map.get(channel, "min_severity", "warning")
+
+    Map.get(channel, "enabled", true) != false and
+      severity_rank(event["severity"]) >= severity_rank(min_severity) and
+      (is_nil(channel["kinds"]) or event["kind"] in channel["kinds"]) and
+      (is_nil(channel["groups"]) or event["group"] in channel["groups"])
+  end
+
+  defp severity_rank(severity) do
+    Enum.find_index(@severities, &(&1 == severity)) || 0
+  end
+
+  # Failed deliveries are retried `retries` times (default 2) with a growing
+  # pause; the outcome is recorded in the alert history either way.
+  defp deliver(module, event, channel, retries \\ nil) do
+    retries = retries || Map.get(channel, "retries", @default_retries)
+    {result, attempts} = attempt(module, event, channel, retries, 1)
+
+    History.record_delivery(%{
+      "channel" => channel_id(channel),
+      "type" => channel["type"] || channel["module"],
+      "event_id" => event["id"],
+      "kind" => event["kind"],
+      "severity" => event["severity"],
+      "status" => if(result == :ok, do: "delivered", else: "failed"),
+      "status_code" => status_code(result),
+      "attempts" => attempts,
+      "error" => error_message(result)
+    })
+
+    result
+  end
+
+  defp attempt(module, event, channel, retries, n) do
+    case deliver_once(module, event, channel) do
+      {:error, _reason} when n <= retries ->
+        Process.sleep(@retry_delay * n)
+        attempt(module, event, channel, retries, n + 1)
+
+      result ->
+        {result, n}
+    end
+  end
+
+  defp deliver_once(module, event, channel) do
+    case module.deliver(event, channel) do
+      :ok ->
+        :ok
+
+      {:error, reason} ->
+        Logger.error("Notification via #{channel["type"]} failed: #{inspect(reason)}")
+        {:error, reason}
+    end
+  rescue
+    e ->
+      Logger.error("Notification via #{channel["type"]} crashed: #{Exception.message(e)}")
+      {:error, Exception.message(e)}
+  end
+
+  defp channel_id(channel) do
+    case Enum.find(with_ids(Config.load().notifiers), fn {_id, c} -> c == channel end) do
+      {id, _channel} -> id
+      nil -> nil
+    end
+  end
+
+  defp status_code({:error, {:http_status, status, _body}}), do: status
+  defp status_code({:error, {:exit_status, code, _output}}), do: code
+  defp status_code({:error, {:smtp_status, code, _reply}}), do: code
+  defp status_code(_result), do: nil
+
+  defp error_message(:ok), do: nil
+  defp error_message({:error, {:http_status, _status, body}}), do: String.slice(body, 0, 200)
+  defp error_message({:error, {:exit_status, _code, output}}), do: String.slice(output, 0, 200)
+  defp error_message({:error, {:smtp_status, _code, reply}}), do: String.slice(reply, 0, 200)
+  defp error_message({:error, reason}) when is_binary(reason), do: reason
+  defp error_message({:error, reason}), do: inspect(reason)
+end
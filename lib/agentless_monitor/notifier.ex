@@ -0,0 +1,114 @@
+defmodule AgentlessMonitor.Notifier do
+  @moduledoc """
+  Behaviour for notification channels.
+
+  A channel type is a module implementing `deliver/2`, which receives the
+  event (as a string-keyed map) and the channel's config entry. Types are
+  registered with `AgentlessMonitor.Notifications.register_type/2`; the
+  built-in ones are `webhook`, `slack`, `telegram`, `email` and `command`.
+  """
+
+  @callback deliver(event :: map(), channel :: map()) :: :ok | {:error, term()}
+
+  @doc "One-line summary of an event, used by the chat-style channels"
+  def summary(event) do
+    server = if event["server_name"], do: "[#{event["server_name"]}] ", else: ""
+    "#{String.upcase(event["severity"] || "info")} #{server}#{event["message"]}"
+  end
+
+  @doc """
+  POSTs a JSON payload, treating any 2xx response as delivered.
+
+  `destination` is the channel entry (or a list of headers). These keys of
+  it shape the request:
+
+    * `headers` - extra request headers
+    * `bearer_token` - sent as `Authorization: Bearer <token>`
+    * `tls` - `cert_file` and `key_file` for a client certificate (mTLS),
+      `ca_file` to trust a private CA instead of the system store, and
+      `"verify": false` to skip server certificate checks
+    * `proxy` - an HTTP proxy URL such as `http://proxy.internal:3128`
+  """
+  def post_json(url, payload, destination \\ %{})
+
+  def post_json(url, payload, headers) when is_list(headers) do
+    post_json(url, payload, %{"headers" => Map.new(headers)})
+  end
+
+  def post_json(url, payload, destination) do
+    bearer =
+      case destination["bearer_token"] do
+        nil -> []
+        token -> [{"Authorization", "Bearer #{token}"}]
+      end
+
+    headers =
+      (bearer ++ Map.to_list(destination["headers"] || %{}))
+      |> Enum.map(fn {key, value} -> {to_charlist(key), to_charlist(value)} end)
+
+    request = {
+      String.to_charlist(url),
+      headers,
+      ~c"application/json",
+      Jason.encode!(payload)
+    }
+
+    http_options = [timeout: 10_000, ssl: ssl_options(destination["tls"] || %{})]
+
+    with {:ok, profile} <- http_profile(destination["proxy"]) do
+      case :httpc.request(:post, request, http_options, [], profile) do
+        {:ok, {{_, status, _}, _headers, _body}} when status in 200..299 ->
+          :ok
+
+        {:ok, {{_, status, _}, _headers, body}} ->
+          {:error, {:http_status, status, to_string(body)}}
+
+        {:error, reason} ->
+          {:error, reason}
+      end
+    end
+  end
+
+  @doc "`:ssl` options for a `tls` entry as described in `post_json/3`"
+  def ssl_options(%{"verify" => false} = tls) do
+    [verify: :verify_none] ++ client_certificate(tls)
+  end
+
+  def ssl_options(tls) do
+    trust =
+      case tls["ca_file"] do
+        nil -> [cacerts: :public_key.cacerts_get()]
+        ca_file -> [cacertfile: to_charlist(ca_file)]
+      end
+
+    [
+      verify: :verify_peer,
+      customize_hostname_check: [match_fun: :public_key.pkix_verify_hostname_match_fun(:https)]
+    ] ++ trust ++ client_certificate(tls)
+  end
+
+  defp client_certificate(%{"cert_file" => cert_file} = tls) when is_binary(cert_file) do
+    [certfile: to_charlist(cert_file), keyfile: to_charlist(tls["key_file"] || cert_file)]
+  end
+
+  defp client_certificate(_tls), do: []
+
+  # httpc only takes proxies per profile, so each proxy gets its own profile.
+  defp http_profile(nil), do: {:ok, :httpc_manager}
+
+  defp http_profile(proxy) do
+    with %URI{host: host, port: port} when is_binary(host) <- URI.parse(proxy) do
+      profile = :"notifier_proxy_#{:erlang.phash2(proxy)}"
+      address = {{to_charlist(host), port || 3128}, []}
+
+      case :inets.start(:httpc, profile: profile) do
+        {:ok, _pid} -> :httpc.set_options([proxy: address, https_proxy: address], profile)
+        {:error, {:already_started, _pid}} -> :ok
+      end
+
+      {:ok, profile}
+    else
+      _ -> {:error, "invalid proxy URL: #{proxy}"}
+    end
+  end
+end
@@ -0,0 +1,28 @@
+defmodule AgentlessMonitor.Notifiers.Command do
+  @moduledoc """
+  Runs a local script with the event in `MONITOR_*` environment variables
+  and as JSON on the `MONITOR_EVENT` variable.
+
+      {"type": "command", "command": "/usr/local/bin/page-oncall"}
+  """
+
+  @behaviour AgentlessMonitor.Notifier
+
+  @impl true
+  def deliver(event, channel) do
+    env =
+      [
+        {"MONITOR_EVENT", Jason.encode!(event)},
+        {"MONITOR_KIND", event["kind"]},
+        {"MONITOR_SEVERITY", event["severity"]},
+        {"MONITOR_SERVER", event["server_name"]},
+        {"MONITOR_MESSAGE", event["message"]}
+      ]
+      |> Enum.map(fn {key, value} -> {key, to_string(value)} end)
+
+    case System.cmd("sh", ["-c", channel["command"]], env: env, stderr_to_stdout: true) do
+      {_output, 0} -> :ok
+      {output, code} -> {:error, {:exit_status, code, output}}
+    end
+  end
+end
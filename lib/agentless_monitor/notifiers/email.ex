@@ -0,0 +1,55 @@
+defmodule AgentlessMonitor.Notifiers.Email do
+  @moduledoc """
+  Sends an email through the local `sendmail` binary (or `sendmail_path`),
+  or directly to an SMTP server when the channel has an `smtp` entry (see
+  `AgentlessMonitor.Notifiers.SMTP`).
+
+      {"type": "email", "to": ["oncall@example.com"], "from": "monitor@example.com"}
+  """
+
+  @behaviour AgentlessMonitor.Notifier
+
+  alias AgentlessMonitor.Notifier
+  alias AgentlessMonitor.Notifiers.SMTP
+
+  @impl true
+  def deliver(event, channel) do
+    from = channel["from"] || "agentless-monitor"
+    recipients = List.wrap(channel["to"])
+
+    message = """
+    From: #{from}
+    To: #{Enum.join(recipients, ", ")}
+    Date: #{Calendar.strftime(DateTime.utc_now(), "%a, %d %b %Y %H:%M:%S +0000")}
+    Subject: #{Notifier.summary(event)}
+
+    #{event["message"]}
+
+    Server: #{event["server_name"] || "-"}
+    Kind: #{event["kind"]}
+    Severity: #{event["severity"]}
+    Time: #{event["timestamp"]}
+    """
+
+    case channel["smtp"] do
+      %{} = smtp -> SMTP.send_message(smtp, from, recipients, message)
+      _ -> sendmail(channel, event, message)
+    end
+  end
+
+  defp sendmail(channel, event, message) do
+    sendmail = channel["sendmail_path"] || "sendmail"
+    path = Path.join(System.tmp_dir!(), "notification-#{event["id"]}.eml")
+
+    try do
+      File.write!(path, message)
+
+      case System.cmd("sh", ["-c", "#{sendmail} -t < #{path}"], stderr_to_stdout: true) do
+        {_output, 0} -> :ok
+        {output, code} -> {:error, {:exit_status, code, output}}
+      end
+    after
+      File.rm(path)
+    end
+  end
+end
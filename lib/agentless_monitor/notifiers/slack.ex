@@ -0,0 +1,16 @@
+defmodule AgentlessMonitor.Notifiers.Slack do
+  @moduledoc """
+  Posts to a Slack (or Mattermost) incoming webhook.
+
+      {"type": "slack", "url": "https://hooks.slack.com/services/..."}
+  """
+
+  @behaviour AgentlessMonitor.Notifier
+
+  alias AgentlessMonitor.Notifier
+
+  @impl true
+  def deliver(event, channel) do
+    Notifier.post_json(channel["url"], %{"text" => Notifier.summary(event)}, channel)
+  end
+end
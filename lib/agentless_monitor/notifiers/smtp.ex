@@ -0,0 +1,160 @@
+defmodule AgentlessMonitor.Notifiers.SMTP do
+  @moduledoc """
+  Minimal SMTP client used by the email channel when it has an `smtp` entry:
+
+      "smtp": {"host": "smtp.example.com", "port": 587, "tls": "starttls",
+               "username": "monitor", "password": "..."}
+
+  `tls` is `starttls` (default, port 587), `ssl` (implicit TLS, port 465) or
+  `none` (port 25). Certificates are verified against the system CA store.
+  Authentication uses AUTH PLAIN and is skipped without a `username`.
+  A rejected command is reported as `{:smtp_status, code, reply}`.
+  """
+
+  @timeout 15_000
+
+  @doc "Sends `message` (headers and body) from `from` to `recipients`"
+  def send_message(config, from, recipients, message) do
+    tls = Map.get(config, "tls", "starttls")
+    host = config["host"]
+
+    with {:ok, socket} <- connect(host, Map.get(config, "port", default_port(tls)), tls) do
+      try do
+        with {:ok, _} <- expect(socket, 220),
+             {:ok, socket} <- hello(socket, host, tls),
+             :ok <- authenticate(socket, config),
+             {:ok, _} <- command(socket, "MAIL FROM:<#{address(from)}>", 250),
+             :ok <- add_recipients(socket, recipients),
+             {:ok, _} <- command(socket, "DATA", 354),
+             {:ok, _} <- command(socket, encode_data(message), 250) do
+          command(socket, "QUIT", 221)
+          :ok
+        end
+      after
+        close(socket)
+      end
+    end
+  end
+
+  # ---- Private helpers ----
+
+  defp default_port("ssl"), do: 465
+  defp default_port("none"), do: 25
+  defp default_port(_tls), do: 587
+
+  defp connect(host, port, "ssl") do
+    with {:ok, socket} <- :ssl.connect(to_charlist(host), port, ssl_opts(host), @timeout) do
+      {:ok, {:ssl, socket}}
+    end
+  end
+
+  defp connect(host, port, _tls) do
+    with {:ok, socket} <- :gen_tcp.connect(to_charlist(host), port, socket_opts(), @timeout) do
+      {:ok, {:tcp, socket}}
+    end
+  end
+
+  defp hello({:tcp, plain} = socket, host, "starttls") do
+    with {:ok, _} <- command(socket, "EHLO #{local_name()}", 250),
+         {:ok, _} <- command(socket, "STARTTLS", 220),
+         {:ok, secure} <- :ssl.connect(plain, ssl_opts(host), @timeout),
+         {:ok, _} <- command({:ssl, secure}, "EHLO #{local_name()}", 250) do
+      {:ok, {:ssl, secure}}
+    end
+  end
+
+  defp hello(socket, _host, _tls) do
+    with {:ok, _} <- command(socket, "EHLO #{local_name()}", 250), do: {:ok, socket}
+  end
+
+  defp authenticate(socket, %{"username" => username} = config) when is_binary(username) do
+    token = Base.encode64(<<0>> <> username <> <<0>> <> (config["password"] || ""))
+
+    case command(socket, "AUTH PLAIN #{token}", 235) do
+      {:ok, _} -> :ok
+      error -> error
+    end
+  end
+
+  defp authenticate(_socket, _config), do: :ok
+
+  defp add_recipients(socket, recipients) do
+    Enum.reduce_while(recipients, :ok, fn recipient, :ok ->
+      case command(socket, "RCPT TO:<#{address(recipient)}>", [250, 251]) do
+        {:ok, _} -> {:cont, :ok}
+        error -> {:halt, error}
+      end
+    end)
+  end
+
+  defp command(socket, line, expected) do
+    with :ok <- send_line(socket, line), do: expect(socket, expected)
+  end
+
+  # Replies may span lines: `250-first`, `250-second`, `250 last`.
+  defp expect(socket, expected, acc \\ []) do
+    with {:ok, line} <- recv(socket) do
+      line = String.trim_trailing(line)
+
+      case Regex.run(~r/^(\d{3})([ -])(.*)$/, line) do
+        [_, _code, "-", text] ->
+          expect(socket, expected, [text | acc])
+
+        [_, code, " ", text] ->
+          code = String.to_integer(code)
+          reply = [text | acc] |> Enum.reverse() |> Enum.join("\n")
+
+          if code in List.wrap(expected),
+            do: {:ok, reply},
+            else: {:error, {:smtp_status, code, reply}}
+
+        _ ->
+          {:error, {:smtp_status, nil, line}}
+      end
+    end
+  end
+
+  # CRLF line endings, leading dots doubled, terminated by a lone dot.
+  defp encode_data(message) do
+    message
+    |> String.split(~r/\r?\n/)
+    |> Enum.map_join("\r\n", fn
+      "." <> _ = line -> "." <> line
+      line -> line
+    end)
+    |> Kernel.<>("\r\n.")
+  end
+
+  defp address(value) do
+    case Regex.run(~r/<([^>]+)>/, value) do
+      [_, address] -> address
+      nil -> String.trim(value)
+    end
+  end
+
+  defp local_name do
+    {:ok, name} = :inet.gethostname()
+    to_string(name)
+  end
+
+  defp send_line({:tcp, socket}, line), do: :gen_tcp.send(socket, line <> "\r\n")
+  defp send_line({:ssl, socket}, line), do: :ssl.send(socket, line <> "\r\n")
+
+  defp recv({:tcp, socket}), do: :gen_tcp.recv(socket, 0, @timeout)
+  defp recv({:ssl, socket}), do: :ssl.recv(socket, 0, @timeout)
+
+  defp close({:tcp, socket}), do: :gen_tcp.close(socket)
+  defp close({:ssl, socket}), do: :ssl.close(socket)
+
+  defp socket_opts, do: [:binary, active: false, packet: :line]
+
+  defp ssl_opts(host) do
+    socket_opts() ++
+      [
+        verify: :verify_peer,
+        cacerts: :public_key.cacerts_get(),
+        server_name_indication: to_charlist(host),
+        customize_hostname_check: [match_fun: :public_key.pkix_verify_hostname_match_fun(:https)]
+      ]
+  end
+end
@@ -0,0 +1,18 @@
+defmodule AgentlessMonitor.Notifiers.Telegram do
+  @moduledoc """
+  Sends a message through the Telegram Bot API.
+
+      {"type": "telegram", "bot_token": "123:ABC", "chat_id": "-100123"}
+  """
+
+  @behaviour AgentlessMonitor.Notifier
+
+  alias AgentlessMonitor.Notifier
+
+  @impl true
+  def deliver(event, channel) do
+    url = "https://api.telegram.org/bot#{channel["bot_token"]}/sendMessage"
+    payload = %{"chat_id" => channel["chat_id"], "text" => Notifier.summary(event)}
+    Notifier.post_json(url, payload, Map.take(channel, ["headers", "tls", "proxy"]))
+  end
+end
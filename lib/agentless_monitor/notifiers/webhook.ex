@@ -0,0 +1,51 @@
+defmodule AgentlessMonitor.Notifiers.Webhook do
+  @moduledoc """
+  POSTs the event as JSON to `url`, with optional extra `headers`, a
+  `bearer_token`, a client certificate under `tls` and a `proxy` (see
+  `AgentlessMonitor.Notifier.post_json/3`).
+
+      {"type": "webhook", "url": "https://hooks.internal/monitor",
+       "headers": {"X-Team": "infra"}, "bearer_token": "...",
+       "tls": {"cert_file": "/etc/monitor/client.pem", "key_file": "/etc/monitor/client.key",
+               "ca_file": "/etc/monitor/internal-ca.pem"}}
+
+  `format` shapes the payload for chat incoming webhooks instead: `slack`
+  (also Mattermost and Rocket.Chat), `discord` or `teams` (a MessageCard).
+  """
+
+  @behaviour AgentlessMonitor.Notifier
+
+  alias AgentlessMonitor.Notifier
+
+  @colors %{"critical" => "D32F2F", "warning" => "F9A825", "info" => "1976D2"}
+
+  @impl true
+  def deliver(event, channel) do
+    payload = payload(Map.get(channel, "format", "json"), event)
+    Notifier.post_json(channel["url"], payload, channel)
+  end
+
+  defp payload("slack", event), do: %{"text" => Notifier.summary(event)}
+  defp payload("discord", event), do: %{"content" => Notifier.summary(event)}
+
+  defp payload("teams", event) do
+    %{
+      "@type" => "MessageCard",
+      "@context" => "https://schema.org/extensions",
+      "summary" => Notifier.summary(event),
+      "themeColor" => Map.get(@colors, event["severity"], "757575"),
+      "title" => Notifier.summary(event),
+      "sections" => [
+        %{
+          "facts" => [
+            %{"name" => "Server", "value" => event["server_name"] || "-"},
+            %{"name" => "Kind", "value" => event["kind"]},
+            %{"name" => "Time", "value" => event["timestamp"]}
+          ]
+        }
+      ]
+    }
+  end
+
+  defp payload(_format, event), do: event
+end
@@ -0,0 +1,170 @@
+defmodule AgentlessMonitor.Privacy do
+  @moduledoc """
+  Privacy mode: removes sensitive fields from samples before they are
+  stored, published or exported, and from API responses.
+
+      "privacy_redact": ["process", "username", "ip_address"]
+
+    * `process` - process names/command lines and PIDs of listening ports,
+      the command lines of collected processes and OOM-killed process names
+    * `username` - SSH usernames of servers and their proxies, and the
+      owners of collected processes
+    * `ip_address` - interface and host addresses, IP-literal server hosts
+      and ping targets, and IP and MAC addresses in event messages, mesh
+      errors and discovered neighbors
+
+  Samples collected before privacy mode was enabled are redacted when they
+  are served.
+  """
+
+  alias AgentlessMonitor.Config
+  alias AgentlessMonitor.Models.{
+    MonitoringData,
+    NetworkInfo,
+    OomKill,
+    PingTest,
+    PortInfo,
+    ProcessInfo,
+    SystemInfo
+  }
+
+  @fields ["process", "username", "ip_address"]
+  @mask "redacted"
+
+  def fields, do: @fields
+
+  @doc "Redaction fields currently configured"
+  def enabled do
+    case Config.load().privacy_redact do
+      fields when is_list(fields) -> Enum.filter(fields, &(&1 in @fields))
+      _ -> []
+    end
+  end
+
+  @doc "Redacts a sample (`%MonitoringData{}`)"
+  def redact_data(data, fields \\ enabled())
+
+  def redact_data(data, []), do: data
+
+  def redact_data(%MonitoringData{} = data, fields) do
+    Enum.reduce(fields, data, &redact_sample/2)
+  end
+
+  def redact_data(data, _fields), do: data
+
+  @doc "Redacts a server map as returned by `Server.to_map/1`"
+  def redact_server(server, fields \\ enabled())
+
+  def redact_server(server, []), do: server
+
+  def redact_server(server, fields) do
+    Enum.reduce(fields, server, &redact_server_field/2)
+  end
+
+  @doc "Redacts an event map as returned by `Event.to_map/1`"
+  def redact_event(event, fields \\ enabled()) do
+    Map.update(event, "message", nil, &redact_text(&1, fields))
+  end
+
+  @doc "Redacts the mesh matrix returned by `Mesh.matrix/0`"
+  def redact_mesh(mesh, fields \\ enabled()) do
+    redact_cell = &Map.update!(&1, "error", fn error -> redact_text(error, fields) end)
+
+    matrix =
+      Map.new(mesh["matrix"], fn {from, row} ->
+        {from, Map.new(row, fn {to, cell} -> {to, redact_cell.(cell)} end)}
+      end)
+
+    %{mesh | "matrix" => matrix}
+  end
+
+  @doc "Redacts the adjacency returned by `Neighbors.discovered/0`"
+  def redact_neighbors(discovered, fields \\ enabled()) do
+    edges =
+      Enum.map(discovered["edges"], fn edge ->
+        Map.update!(edge, "to", &redact_text(&1, fields))
+      end)
+
+    %{discovered | "edges" => edges}
+  end
+
+  @doc "Masks IP and MAC addresses in free text"
+  def redact_text(text, fields \\ enabled())
+
+  def redact_text(text, fields) when is_binary(text) do
+    if "ip_address" in fields do
+      text
+      |> String.replace(~r/\b(?:[0-9A-Fa-f]{2}[:-]){5}[0-9A-Fa-f]{2}\b/, @mask)
+      |> String.replace(~r/\b\d{1,3}(?:\.\d{1,3}){3}\b/, &mask_address/1)
+      |> String.replace(~r/[0-9A-Fa-f]{0,4}(?::[0-9A-Fa-f]{0,4}){2,7}/, &mask_address/1)
+    else
+      text
+    end
+  end
+
+  def redact_text(text, _fields), do: text
+
+  # ---- Private helpers ----
+
+  defp redact_sample("process", data) do
+    %{data | ports: Enum.map(data.ports, &%PortInfo{&1 | process: "", pid: nil})}
+    |> update_list(:processes, &%ProcessInfo{&1 | command: @mask})
+    |> update_list(:oom_kills, fn kill ->
+      message = String.replace(kill.message, "(#{kill.process})", "(#{@mask})")
+      %OomKill{kill | process: @mask, message: message}
+    end)
+  end
+
+  defp redact_sample("username", data) do
+    update_list(data, :processes, &%ProcessInfo{&1 | user: @mask})
+  end
+
+  defp redact_sample("ip_address", data) do
+    %{
+      data
+      | network: Enum.map(data.network, &%NetworkInfo{&1 | ip_addresses: []}),
+        ping_tests: Enum.map(data.ping_tests, &%PingTest{&1 | target: mask_ip(&1.target)}),
+        system_info: data.system_info && %SystemInfo{data.system_info | ip_addresses: []}
+    }
+  end
+
+  defp redact_sample(_field, data), do: data
+
+  # Samples recorded before a collector existed lack its key.
+  defp update_list(data, key, fun) do
+    Map.put(data, key, data |> Map.get(key, []) |> Enum.map(fun))
+  end
+
+  defp redact_server_field("username", server) do
+    proxy =
+      case server["proxy_config"] do
+        %{"username" => _} = proxy -> Map.put(proxy, "username", @mask)
+        proxy -> proxy
+      end
+
+    %{server | "username" => @mask, "proxy_config" => proxy}
+  end
+
+  defp redact_server_field("ip_address", server) do
+    Map.update!(server, "host", &mask_ip/1)
+  end
+
+  defp redact_server_field(_field, server), do: server
+
+  defp mask_ip(value) when is_binary(value) do
+    case :inet.parse_address(String.to_charlist(value)) do
+      {:ok, _address} -> @mask
+      {:error, _} -> value
+    end
+  end
+
+  defp mask_ip(value), do: value
+
+  # Candidates matched in text, kept unless they really are an address.
+  defp mask_address(candidate) do
+    case :inet.parse_strict_address(String.to_charlist(candidate)) do
+      {:ok, _address} -> @mask
+      {:error, _} -> candidate
+    end
+  end
+end
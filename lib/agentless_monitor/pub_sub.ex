@@ -0,0 +1,25 @@
+defmodule AgentlessMonitor.PubSub do
+  @moduledoc """
+  Local broadcast of live updates (status changes, samples and events),
+  backed by a duplicate-key `Registry`. Subscribers receive
+  `{:live_update, type, payload}` messages.
+  """
+
+  @registry __MODULE__
+  @topic :live_updates
+
+  def child_spec(_) do
+    Registry.child_spec(keys: :duplicate, name: @registry)
+  end
+
+  def subscribe do
+    {:ok, _} = Registry.register(@registry, @topic, nil)
+    :ok
+  end
+
+  def broadcast(type, payload) do
+    Registry.dispatch(@registry, @topic, fn subscribers ->
+      for {pid, _} <- subscribers, do: send(pid, {:live_update, type, payload})
+    end)
+  end
+end
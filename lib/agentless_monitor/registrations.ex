@@ -0,0 +1,200 @@
+defmodule AgentlessMonitor.Registrations do
+  @moduledoc """
+  Self-registration of newly provisioned machines.
+
+  Machines post their name, address, tags and SSH host key fingerprint to
+  `POST /api/register` (authenticated with `registration_token`, typically
+  from cloud-init or a Terraform provisioner). Registrations wait here until
+  an operator approves them, at which point the machine is added as a
+  server; re-registering a pending name replaces the earlier request. A
+  registration can't take the name of an existing server: it's refused when
+  filed, and approving it fails with `{:error, :name_taken}` if a server took
+  the name in the meantime.
+  """
+
+  use GenServer
+  require Logger
+
+  alias AgentlessMonitor.{Models, State, Storage}
+  alias AgentlessMonitor.Alerting.Events
+
+  @max_history 200
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, %{registrations: []}, name: __MODULE__)
+  end
+
+  @doc """
+  Files a registration from `%{"name", "host", "port", "username", "group",
+  "tags", "host_key_fingerprint"}`; only `name` and `host` are required.
+  """
+  def register(attrs) do
+    GenServer.call(__MODULE__, {:register, attrs})
+  end
+
+  def list(opts \\ []) do
+    GenServer.call(__MODULE__, {:list, opts})
+  end
+
+  def approve(id, operator) do
+    GenServer.call(__MODULE__, {:decide, id, operator, "approved"})
+  end
+
+  def reject(id, operator) do
+    GenServer.call(__MODULE__, {:decide, id, operator, "rejected"})
+  end
+
+  @doc "Renders a `#cloud-config` snippet that registers the machine on first boot"
+  def cloud_init(base_url, token, opts \\ []) do
+    host = "hostname -I | awk '{print $1}'"
+    fingerprint = "ssh-keygen -lf /etc/ssh/ssh_host_ed25519_key.pub | awk '{print $2}'"
+
+    # The JSON body sits in single quotes; the shell substitutions step out of them.
+    payload =
+      [
+        ~s("name": "'"$(hostname)"'"),
+        ~s("host": "'"$(#{host})"'"),
+        ~s("host_key_fingerprint": "'"$(#{fingerprint})"'")
+      ] ++
+        Enum.flat_map(opts, fn {key, value} ->
+          if is_nil(value),
+            do: [],
+            else: [~s("#{key}": #{value |> Jason.encode!() |> String.replace("'", "'\\''")})]
+        end)
+
+    """
+    #cloud-config
+    runcmd:
+      - >-
+        curl -fsS -X POST '#{base_url}/api/register'
+        -H 'Authorization: Bearer #{token}'
+        -H 'Content-Type: application/json'
+        -d '{#{Enum.join(payload, ", ")}}'
+    """
+  end
+
+  # ---- Callbacks ----
+
+  def init(state) do
+    {:ok, state}
+  end
+
+  def handle_call({:register, attrs}, _from, state) do
+    attrs = Map.put_new(attrs, "host", attrs["ip"])
+
+    cond do
+      blank?(attrs["name"]) or blank?(attrs["host"]) ->
+        {:reply, {:error, :invalid}, state}
+
+      server_named?(attrs["name"]) ->
+        {:reply, {:error, :name_taken}, state}
+
+      true ->
+        file(attrs, state)
+    end
+  end
+
+  def handle_call({:list, opts}, _from, state) do
+    status = Keyword.get(opts, :status)
+    registrations =
+      Enum.filter(state.registrations, &(is_nil(status) or &1["status"] == status))
+
+    {:reply, registrations, state}
+  end
+
+  def handle_call({:decide, id, operator, decision}, _from, state) do
+    case Enum.find(state.registrations, &(&1["id"] == id)) do
+      nil ->
+        {:reply, {:error, :not_found}, state}
+
+      %{"status" => "pending"} = registration ->
+        case add_server(registration, decision) do
+          :ok ->
+            decided = %{
+              registration
+              | "status" => decision,
+                "decided_by" => operator,
+                "decided_at" => DateTime.utc_now() |> DateTime.to_iso8601()
+            }
+
+            Logger.info("Registration of #{registration["name"]} #{decision} by #{operator}")
+            details = Map.take(registration, ["id", "name"])
+            Storage.audit("registration_#{decision}", operator, details)
+
+            registrations =
+              Enum.map(state.registrations, fn existing ->
+                if existing["id"] == id, do: decided, else: existing
+              end)
+
+            {:reply, {:ok, decided}, %{state | registrations: registrations}}
+
+          {:error, reason} ->
+            {:reply, {:error, reason}, state}
+        end
+
+      _registration ->
+        {:reply, {:error, :not_pending}, state}
+    end
+  end
+
+  # ---- Private helpers ----
+
+  defp file(attrs, state) do
+    registration = %{
+      "id" => Models.generate_id(),
+      "name" => attrs["name"],
+      "host" => attrs["host"],
+      "port" => attrs["port"],
+      "username" => attrs["username"],
+      "group" => attrs["group"],
+      "tags" => List.wrap(attrs["tags"]),
+      "host_key_fingerprint" => attrs["host_key_fingerprint"],
+      "status" => "pending",
+      "created_at" => DateTime.utc_now() |> DateTime.to_iso8601(),
+      "decided_by" => nil,
+      "decided_at" => nil
+    }
+
+    Events.emit(
+      kind: "registration_requested",
+      severity: "info",
+      group: registration["group"],
+      message:
+        "#{registration["name"]} (#{registration["host"]}) requested registration " <>
+          "- approve via POST /api/registrations/#{registration["id"]}/approve"
+    )
+
+    registrations =
+      state.registrations
+      |> Enum.reject(&(&1["name"] == registration["name"] and &1["status"] == "pending"))
+      |> then(&Enum.take([registration | &1], @max_history))
+
+    {:reply, {:ok, registration}, %{state | registrations: registrations}}
+  end
+
+  # Approving adds the machine as a server, unless one took its name since it
+  # registered; the registration then stays pending.
+  defp add_server(_registration, "rejected"), do: :ok
+
+  defp add_server(registration, "approved") do
+    entry =
+      registration
+      |> Map.take(["name", "host", "port", "username", "group"])
+      |> Map.reject(fn {_key, value} -> is_nil(value) end)
+
+    if server_named?(registration["name"]) do
+      {:error, :name_taken}
+    else
+      case State.import_servers([entry]) do
+        {:ok, _summary} -> :ok
+        {:error, {:name_taken, _names}} -> {:error, :name_taken}
+      end
+    end
+  end
+
+  defp server_named?(name), do: Enum.any?(State.get_servers(), &(&1.name == name))
+
+  defp blank?(value), do: not is_binary(value) or String.trim(value) == ""
+end
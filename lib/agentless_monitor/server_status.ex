@@ -0,0 +1,54 @@
+defmodule AgentlessMonitor.ServerStatus do
+  @moduledoc """
+  The status of a server as reported by the API, the UI and ChatOps.
+
+  Collections set the stored status:
+
+    * `unknown` - not collected yet (since it was added or un-retired)
+    * `online` - the last collection succeeded and is clean
+    * `degraded` - the last collection succeeded, but some collectors came
+      back empty or a threshold is breached
+    * `error` - the last collection failed
+
+  Operator and schedule states take precedence over it, in this order:
+
+    * `retired` - removed from monitoring
+    * `paused` - monitoring paused for the server (or for all of them)
+    * `maintenance` - a maintenance window of its site is active
+
+  A paused server keeps its last stored status, so resuming it shows
+  where it left off until the next collection.
+  """
+
+  alias AgentlessMonitor.{Sites, State}
+
+  @doc """
+  The status to report for `server`. `maintenance` says whether its site is
+  in a maintenance window; it is looked up when not given.
+  """
+  def effective(server, maintenance \\ nil)
+
+  def effective(%{status: "retired"}, _maintenance), do: "retired"
+
+  def effective(server, maintenance) do
+    cond do
+      State.is_paused?(server.id) -> "paused"
+      in_maintenance?(server, maintenance) -> "maintenance"
+      # Servers added before `unknown` existed start out as `offline`.
+      server.status == "offline" -> "unknown"
+      true -> server.status
+    end
+  end
+
+  @doc """
+  Stored status after a successful collection: `degraded` when any of
+  `failed_collectors` came back empty or any threshold is breached
+  """
+  def collected([], breaches) when map_size(breaches) == 0, do: "online"
+  def collected(_failed_collectors, _breaches), do: "degraded"
+
+  # ---- Private helpers ----
+
+  defp in_maintenance?(server, nil), do: Sites.in_maintenance(server) != nil
+  defp in_maintenance?(_server, maintenance), do: maintenance
+end
@@ -0,0 +1,184 @@
+defmodule AgentlessMonitor.Settings do
+  @moduledoc """
+  Resolves the effective monitoring settings of a server.
+
+  Settings are layered: global defaults from the top-level config, then the
+  server's monitoring profile, then the server's group entry in `groups`,
+  then the server itself: its entry in `server_overrides` (keyed by server
+  name) and the `monitoring_interval` of its own entry or set through the
+  API, when it has one. Map-valued settings (`thresholds`, `ssh_options`)
+  are merged key by key; everything else is replaced by the most specific
+  layer. A `ping_interval` moves ping tests
+  onto their own schedule instead of running them with every collection, and
+  a `network_prefix` such as `ip netns exec blue` scopes the network and
+  port collectors to a namespace or VRF. `process_rules` are evaluated by
+  `AgentlessMonitor.Monitoring.Watchdog`, `mesh_peers` by
+  `AgentlessMonitor.Monitoring.Mesh`, `custom_metrics` by
+  `AgentlessMonitor.Monitoring.CustomMetrics`, `collection_hooks` by
+  `AgentlessMonitor.Monitoring.Hooks` and `http_checks` by
+  `AgentlessMonitor.Monitoring.HttpChecks`. `retention_days` bounds the age of the
+  server's history, in memory and in storage, and `collection_budget` how
+  long its collections may take.
+
+      "groups": {
+        "prod": {"servers": ["web-*"], "monitoring_interval": 15,
+                 "thresholds": {"cpu_percent": 80}}
+      },
+      "server_overrides": {"web-01": {"collectors": ["cpu", "memory", "ping"]}}
+
+  A profile is a named bundle of settings, chosen with `profile` in the
+  server's override, else its group, else the top level. `light`,
+  `standard` and `deep` are built in; `profiles` adds others or replaces
+  them:
+
+      "profile": "standard",
+      "groups": {"edge": {"servers": ["edge-*"], "profile": "light"}},
+      "profiles": {"light": {"collectors": ["cpu", "ping"], "monitoring_interval": 600}}
+
+  Collectors left out of a server's `collectors` are left out of the
+  command sent to it too, so a light profile is also light on the target.
+  """
+
+  alias AgentlessMonitor.Config
+
+  @collectors [
+    "cpu",
+    "memory",
+    "disks",
+    "network",
+    "ports",
+    "processes",
+    "containers",
+    "services",
+    "system",
+    "ping"
+  ]
+  @keys [
+    "monitoring_interval",
+    "collectors",
+    "thresholds",
+    "ssh_options",
+    "ping_targets",
+    "ping_interval",
+    "network_prefix",
+    "process_rules",
+    "mesh_peers",
+    "custom_metrics",
+    "collection_hooks",
+    "retention_days",
+    "collection_budget",
+    "http_checks"
+  ]
+
+  @profiles %{
+    "light" => %{"collectors" => ["cpu", "ping"], "monitoring_interval" => 300},
+    "standard" => %{
+      "collectors" => ["cpu", "memory", "disks", "network", "system", "ping"],
+      "monitoring_interval" => 60
+    },
+    "deep" => %{"collectors" => @collectors, "monitoring_interval" => 30}
+  }
+
+  def collectors, do: @collectors
+
+  @doc "Every profile by name: the built-in ones with those of `profiles` over them"
+  def profiles(config \\ Config.load()) do
+    Map.merge(@profiles, config.profiles || %{})
+  end
+
+  @doc """
+  Returns `%{"group" => ..., "profile" => ..., "settings" => ...,
+  "sources" => ...}` where `sources` names the layer (`global`, `profile`,
+  `group` or `server`) each setting was last taken from. An unknown profile
+  contributes nothing.
+  """
+  def effective(server, config \\ Config.load()) do
+    group = group_for(server, config)
+    group_entry = Map.get(config.groups, group, %{})
+    server_entry = Map.get(config.server_overrides, server.name, %{})
+    profile = server_entry["profile"] || group_entry["profile"] || config.profile
+
+    layers = [
+      {"global", global(config)},
+      {"profile", config |> profiles() |> Map.get(profile, %{}) |> Map.take(@keys)},
+      {"group", Map.take(group_entry, @keys)},
+      {"server", server_entry |> Map.take(@keys) |> Map.merge(own_settings(server))}
+    ]
+
+    {settings, sources} =
+      Enum.reduce(layers, {%{}, %{}}, fn {source, layer}, acc ->
+        Enum.reduce(layer, acc, fn {key, value}, {settings, sources} ->
+          merged =
+            case {Map.get(settings, key), value} do
+              {%{} = current, %{} = override} -> Map.merge(current, override)
+              _ -> value
+            end
+
+          {Map.put(settings, key, merged), Map.put(sources, key, source)}
+        end)
+      end)
+
+    %{"group" => group, "profile" => profile, "settings" => settings, "sources" => sources}
+  end
+
+  @doc "Convenience accessor for the resolved settings map only"
+  def for_server(server, config \\ Config.load()) do
+    effective(server, config)["settings"]
+  end
+
+  @doc """
+  The server's group: its explicit `group`, or the first configured group
+  whose `servers` patterns match the server name.
+  """
+  def group_for(server, config \\ Config.load())
+
+  def group_for(%{group: group}, _config) when is_binary(group) and group != "", do: group
+
+  def group_for(server, config) do
+    config.groups
+    |> Enum.sort()
+    |> Enum.find_value(fn {name, group} ->
+      if Enum.any?(Map.get(group, "servers", []), &glob_match?(&1, server.name)), do: name
+    end)
+  end
+
+  @doc "Matches a name against a pattern where `*` matches any run of characters"
+  def glob_match?(pattern, value) do
+    regex =
+      pattern
+      |> Regex.escape()
+      |> String.replace("\\*", ".*")
+
+    Regex.match?(~r/^#{regex}$/, value)
+  end
+
+  # Settings carried by the server itself; a server without an interval of
+  # its own inherits one.
+  defp own_settings(%{monitoring_interval: interval}) when is_integer(interval),
+    do: %{"monitoring_interval" => interval}
+
+  defp own_settings(_server), do: %{}
+
+  defp global(config) do
+    %{
+      "monitoring_interval" => config.monitoring_interval,
+      "collectors" => @collectors,
+      "thresholds" => config.thresholds,
+      "ssh_options" => %{
+        "timeout" => config.ssh_timeout,
+        "control_persist" => 60,
+        "backend" => config.ssh_backend
+      },
+      "ping_targets" => config.ping_targets,
+      "ping_interval" => config.ping_interval,
+      "network_prefix" => nil,
+      "process_rules" => config.process_rules,
+      "mesh_peers" => [],
+      "custom_metrics" => config.custom_metrics,
+      "collection_hooks" => config.collection_hooks,
+      "retention_days" => config.retention_days,
+      "collection_budget" => config.collection_budget,
+      "http_checks" => config.http_checks
+    }
+  end
+end
@@ -0,0 +1,196 @@
+defmodule AgentlessMonitor.ShareLinks do
+  @moduledoc """
+  Expiring, signed links granting read-only access to one server or to the
+  fleet dashboard, for people without an account such as a vendor joining
+  an incident.
+
+  A link's token is `<id>.<signature>`, the signature being an HMAC-SHA256
+  of its id, view and expiry under `share_link_secret` (or a random key kept
+  in `<data_dir>/share_links.json` when unset). The token is returned once,
+  on creation, and is accepted in an `X-Share-Token` header or a `share`
+  query parameter. Links expire after `expires_in` seconds (default a day,
+  at most a week) and revoking one invalidates its token at once.
+
+  A `server` link allows the server's own read endpoints (status, history,
+  details, processes, process rules, fingerprint, annotations, bursts and
+  snapshot) and a server list narrowed to it. A `dashboard` link allows the
+  fleet overview, the server list, alerts and every server's status,
+  history and details. Debug details and everything else are refused.
+  """
+
+  use GenServer
+  require Logger
+
+  alias AgentlessMonitor.{Config, DataFile, Models, State}
+
+  @default_expiry 24 * 3600
+  @max_expiry 7 * 24 * 3600
+  @server_views [[], ["status"], ["history"], ["processes"], ["process-rules"]] ++
+                  [["fingerprint"], ["annotations"], ["bursts"], ["snapshot"]]
+  @dashboard_views [[], ["status"], ["history"]]
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, nil, name: __MODULE__)
+  end
+
+  def list do
+    GenServer.call(__MODULE__, :list)
+  end
+
+  @doc """
+  Creates a link from `%{"server_id" => ...}` (a server link) or
+  `%{"view" => "dashboard"}`, with an optional `label` and `expires_in`
+  seconds. Returns `{:ok, link, token}`.
+  """
+  def create(attrs) do
+    GenServer.call(__MODULE__, {:create, attrs})
+  end
+
+  def revoke(id) do
+    GenServer.call(__MODULE__, {:revoke, id})
+  end
+
+  @doc "The link a token belongs to: `{:ok, link}` or `{:error, :invalid | :expired}`"
+  def verify(token) do
+    GenServer.call(__MODULE__, {:verify, token})
+  end
+
+  @doc "Whether `link` allows a request with `method`, `path_info` and query params"
+  def permits?(link, method, path_info, query_params)
+
+  def permits?(_link, method, _path, _query) when method not in ["GET", "HEAD"], do: false
+
+  def permits?(_link, _method, ["api", "servers", _id, "details", _metric], %{"debug" => "true"}),
+    do: false
+
+  def permits?(%{"view" => "server", "server_id" => id}, _method, path, _query) do
+    case path do
+      ["api", "servers"] -> true
+      ["api", "servers", ^id, "details", _metric] -> true
+      ["api", "servers", ^id | rest] -> rest in @server_views
+      _ -> false
+    end
+  end
+
+  def permits?(%{"view" => "dashboard"}, _method, path, _query) do
+    case path do
+      ["api", view] when view in ["servers", "overview", "alerts"] -> true
+      ["api", "servers", _id, "details", _metric] -> true
+      ["api", "servers", _id | rest] -> rest in @dashboard_views
+      _ -> false
+    end
+  end
+
+  # ---- Callbacks ----
+
+  def init(_) do
+    {key, links} = load()
+    {:ok, %{key: key, links: links}}
+  end
+
+  def handle_call(:list, _from, state) do
+    {:reply, Enum.reject(state.links, &expired?/1), state}
+  end
+
+  def handle_call({:create, attrs}, _from, state) do
+    with {:ok, view} <- view(attrs),
+         {:ok, expires_in} <- expires_in(attrs["expires_in"]) do
+      now = DateTime.utc_now()
+
+      link =
+        Map.merge(view, %{
+          "id" => Models.generate_id(),
+          "label" => attrs["label"],
+          "created_at" => DateTime.to_iso8601(now),
+          "expires_at" => now |> DateTime.add(expires_in, :second) |> DateTime.to_iso8601()
+        })
+
+      links = [link | Enum.reject(state.links, &expired?/1)]
+      persist(state.key, links)
+      {:reply, {:ok, link, token(link, key(state))}, %{state | links: links}}
+    else
+      error -> {:reply, error, state}
+    end
+  end
+
+  def handle_call({:revoke, id}, _from, state) do
+    case Enum.split_with(state.links, &(&1["id"] == id)) do
+      {[], _} ->
+        {:reply, {:error, :not_found}, state}
+
+      {_revoked, remaining} ->
+        persist(state.key, remaining)
+        {:reply, :ok, %{state | links: remaining}}
+    end
+  end
+
+  def handle_call({:verify, token}, _from, state) do
+    with [id, _signature] <- String.split(to_string(token), ".", parts: 2),
+         %{} = link <- Enum.find(state.links, &(&1["id"] == id)),
+         true <- Plug.Crypto.secure_compare(token(link, key(state)), token) do
+      if expired?(link),
+        do: {:reply, {:error, :expired}, state},
+        else: {:reply, {:ok, link}, state}
+    else
+      _ -> {:reply, {:error, :invalid}, state}
+    end
+  end
+
+  # ---- Private helpers ----
+
+  defp view(%{"server_id" => id}) when is_binary(id) do
+    case State.get_server(id) do
+      {:ok, _server} -> {:ok, %{"view" => "server", "server_id" => id}}
+      {:error, :not_found} -> {:error, :unknown_server}
+    end
+  end
+
+  defp view(%{"view" => "dashboard"}), do: {:ok, %{"view" => "dashboard", "server_id" => nil}}
+  defp view(_attrs), do: {:error, :invalid_view}
+
+  defp expires_in(nil), do: {:ok, @default_expiry}
+
+  defp expires_in(seconds) when is_integer(seconds) and seconds > 0 and seconds <= @max_expiry,
+    do: {:ok, seconds}
+
+  defp expires_in(_seconds), do: {:error, :invalid_expiry}
+
+  defp token(link, key) do
+    payload = "#{link["id"]}.#{link["view"]}.#{link["server_id"]}.#{link["expires_at"]}"
+    signature = :crypto.mac(:hmac, :sha256, key, payload) |> Base.url_encode64(padding: false)
+    "#{link["id"]}.#{signature}"
+  end
+
+  defp key(state), do: Config.load().share_link_secret || state.key
+
+  defp expired?(%{"expires_at" => expires_at}) do
+    case DateTime.from_iso8601(expires_at) do
+      {:ok, dt, _} -> DateTime.compare(DateTime.utc_now(), dt) == :gt
+      _ -> true
+    end
+  end
+
+  defp path do
+    Path.join(Config.load().data_dir, "share_links.json")
+  end
+
+  defp load do
+    with {:ok, content} <- File.read(path()),
+         {:ok, %{"key" => key, "links" => links}} when is_list(links) <- Jason.decode(content) do
+      {key, Enum.reject(links, &expired?/1)}
+    else
+      _ -> {Base.encode64(:crypto.strong_rand_bytes(32)), []}
+    end
+  end
+
+  defp persist(key, links) do
+    content = Jason.encode!(%{"key" => key, "links" => links}, pretty: true)
+
+    case DataFile.write_atomic(path(), content) do
+      :ok -> :ok
+      {:error, reason} -> Logger.error("Failed to persist share links: #{inspect(reason)}")
+    end
+  end
+end
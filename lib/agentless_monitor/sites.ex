@@ -0,0 +1,325 @@
+defmodule AgentlessMonitor.Sites do
+  @moduledoc """
+  Physical locations servers belong to, with per-site roll-ups and
+  maintenance windows.
+
+  A server's site is its own `site` attribute or, failing that, the first
+  site in `sites` whose `servers` patterns match its name:
+
+      "sites": {
+        "ams1": {"name": "Amsterdam DC", "servers": ["ams-*"],
+                 "maintenance": [{"start": "2026-11-02T22:00:00Z",
+                                  "end": "2026-11-03T02:00:00Z",
+                                  "reason": "UPS replacement"}]}
+      }
+
+  Windows can also be scheduled through the API; those are kept in
+  `<data_dir>/maintenance.json`. While a window is active, events of the
+  site's servers are recorded as suppressed and not forwarded.
+
+  Window times without an offset are local to the window's `timezone`, or
+  else the site's (an IANA name such as `"Europe/Amsterdam"`), or else UTC.
+  Windows are reported in UTC unless a display zone is asked for.
+  """
+
+  use GenServer
+  require Logger
+
+  alias AgentlessMonitor.{Config, DataFile, Models, ServerStatus, Settings, State, TimeZones}
+  alias AgentlessMonitor.Monitoring.Matrix
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, nil, name: __MODULE__)
+  end
+
+  @doc "Site id of `server`, or nil"
+  def site_for(server, config \\ Config.load())
+
+  def site_for(%{site: site}, _config) when is_binary(site) and site != "", do: site
+
+  def site_for(server, config) do
+    config.sites
+    |> Enum.sort()
+    |> Enum.find_value(fn {id, site} ->
+      if Enum.any?(Map.get(site, "servers", []), &Settings.glob_match?(&1, server.name)), do: id
+    end)
+  end
+
+  @doc "Every site with a roll-up of its servers"
+  def list do
+    config = Config.load()
+    windows = windows()
+    by_site = servers_by_site(config)
+
+    config.sites
+    |> Map.keys()
+    |> Enum.concat(Map.keys(by_site))
+    |> Enum.uniq()
+    |> Enum.sort()
+    |> Enum.map(&rollup(&1, Map.get(by_site, &1, []), config, windows))
+  end
+
+  @doc """
+  Roll-up of one site including its servers and maintenance windows, with
+  window times shown in `zone`
+  """
+  def summary(site_id, zone \\ "Etc/UTC") do
+    config = Config.load()
+    servers = Map.get(servers_by_site(config), site_id, [])
+
+    if servers == [] and not Map.has_key?(config.sites, site_id) do
+      {:error, :not_found}
+    else
+      windows = windows()
+      rollup = rollup(site_id, servers, config, windows)
+      maintenance = rollup["in_maintenance"]
+
+      {:ok,
+       rollup
+       |> Map.put("maintenance", site_id |> site_windows(config, windows) |> display(zone))
+       |> Map.put("servers", Enum.map(servers, &server_summary(&1, maintenance)))}
+    end
+  end
+
+  @doc """
+  Schedules a maintenance window. `attrs` has `"end"` and optionally
+  `"start"` (default now), `"timezone"` (default the site's) and `"reason"`.
+  """
+  def add_window(site_id, attrs, actor) do
+    zone = Map.get(attrs, "timezone") || site_zone(site_id, Config.load())
+
+    with :ok <- TimeZones.validate(zone),
+         {:ok, starts_at} <- parse_time(Map.get(attrs, "start"), DateTime.utc_now(), zone),
+         {:ok, ends_at} <- parse_time(Map.get(attrs, "end"), nil, zone),
+         :ok <- validate_range(starts_at, ends_at) do
+      window = %{
+        "id" => Models.generate_id(),
+        "site" => site_id,
+        "start" => DateTime.to_iso8601(starts_at),
+        "end" => DateTime.to_iso8601(ends_at),
+        "timezone" => zone,
+        "reason" => Map.get(attrs, "reason"),
+        "created_by" => actor,
+        "source" => "api"
+      }
+
+      GenServer.call(__MODULE__, {:add_window, window})
+    end
+  end
+
+  def remove_window(site_id, window_id) do
+    GenServer.call(__MODULE__, {:remove_window, site_id, window_id})
+  end
+
+  @doc "The active maintenance window of `server`'s site, or nil"
+  def in_maintenance(server) do
+    config = Config.load()
+
+    case site_for(server, config) do
+      nil -> nil
+      site_id -> Enum.find(site_windows(site_id, config, windows()), &active?/1)
+    end
+  end
+
+  # ---- Callbacks ----
+
+  def init(_) do
+    {:ok, %{windows: load()}}
+  end
+
+  def handle_call(:windows, _from, state) do
+    {:reply, state.windows, state}
+  end
+
+  def handle_call({:add_window, window}, _from, state) do
+    windows = [window | prune(state.windows)]
+    persist(windows)
+    {:reply, {:ok, window}, %{state | windows: windows}}
+  end
+
+  def handle_call({:remove_window, site_id, window_id}, _from, state) do
+    case Enum.split_with(state.windows, &(&1["id"] == window_id and &1["site"] == site_id)) do
+      {[], _windows} ->
+        {:reply, {:error, :not_found}, state}
+
+      {_removed, windows} ->
+        persist(windows)
+        {:reply, :ok, %{state | windows: windows}}
+    end
+  end
+
+  # ---- Private helpers ----
+
+  # Runtime windows, or none when the server is not running (e.g. embedded).
+  defp windows do
+    if Process.whereis(__MODULE__), do: GenServer.call(__MODULE__, :windows), else: []
+  end
+
+  defp servers_by_site(config) do
+    State.get_servers()
+    |> Enum.reject(&(&1.status == "retired"))
+    |> Enum.group_by(&site_for(&1, config))
+    |> Map.delete(nil)
+  end
+
+  defp rollup(site_id, servers, config, windows) do
+    latest = Enum.map(servers, &State.get_latest_monitoring_data(&1.id))
+    active = Enum.find(site_windows(site_id, config, windows), &active?/1)
+    statuses = Enum.map(servers, &ServerStatus.effective(&1, active != nil))
+
+    %{
+      "id" => site_id,
+      "name" => get_in(config.sites, [site_id, "name"]) || site_id,
+      "server_count" => length(servers),
+      "status_counts" => Enum.frequencies(statuses),
+      "status" => site_status(statuses, active),
+      "in_maintenance" => active != nil,
+      "metrics" => %{
+        "cpu_usage_percent" => average(latest, &Matrix.cpu_usage/1),
+        "memory_usage_percent" => average(latest, &Matrix.memory_usage/1),
+        "max_disk_usage_percent" => maximum(latest, &Matrix.disk_usage/1),
+        "ping_success_percent" => average(latest, &Matrix.ping_success/1)
+      }
+    }
+  end
+
+  defp site_status(_statuses, active) when active != nil, do: "maintenance"
+  defp site_status([], _active), do: "empty"
+
+  # Paused servers count neither way; a site of only paused servers is ok.
+  defp site_status(statuses, _active) do
+    monitored = Enum.reject(statuses, &(&1 == "paused"))
+
+    cond do
+      monitored != [] and Enum.all?(monitored, &(&1 == "error")) -> "down"
+      Enum.any?(monitored, &(&1 in ["error", "degraded"])) -> "degraded"
+      true -> "ok"
+    end
+  end
+
+  defp server_summary(server, maintenance) do
+    status = ServerStatus.effective(server, maintenance)
+    %{"id" => server.id, "name" => server.name, "status" => status}
+  end
+
+  defp average(samples, extract) do
+    case values(samples, extract) do
+      [] -> nil
+      values -> Float.round(Enum.sum(values) / length(values), 1)
+    end
+  end
+
+  defp maximum(samples, extract) do
+    case values(samples, extract) do
+      [] -> nil
+      values -> Enum.max(values)
+    end
+  end
+
+  defp values(samples, extract) do
+    samples
+    |> Enum.reject(&is_nil/1)
+    |> Enum.map(extract)
+    |> Enum.filter(&is_number/1)
+  end
+
+  # Declared windows have their local times resolved to UTC here, so every
+  # window carries UTC `start` and `end` from this point on.
+  defp site_windows(site_id, config, windows) do
+    default_zone = site_zone(site_id, config)
+
+    configured =
+      config.sites
+      |> get_in([site_id, "maintenance"])
+      |> List.wrap()
+      |> Enum.map(fn window ->
+        zone = window["timezone"] || default_zone
+
+        window
+        |> Map.merge(%{"site" => site_id, "source" => "config", "timezone" => zone})
+        |> Map.update("start", nil, &utc_time(&1, zone))
+        |> Map.update("end", nil, &utc_time(&1, zone))
+      end)
+
+    runtime = for window <- windows, window["site"] == site_id, do: window
+
+    Enum.sort_by(configured ++ runtime, & &1["start"])
+  end
+
+  defp active?(window) do
+    now = DateTime.utc_now()
+
+    with {:ok, starts_at} <- parse_time(window["start"], nil),
+         {:ok, ends_at} <- parse_time(window["end"], nil) do
+      DateTime.compare(starts_at, now) != :gt and DateTime.compare(ends_at, now) == :gt
+    else
+      _ -> false
+    end
+  end
+
+  defp site_zone(site_id, config) do
+    get_in(config.sites, [site_id, "timezone"]) || "Etc/UTC"
+  end
+
+  # Left as is when it can't be read, so the window is never active.
+  defp utc_time(value, zone) do
+    case TimeZones.parse(value, zone) do
+      {:ok, datetime} -> DateTime.to_iso8601(datetime)
+      {:error, _} -> value
+    end
+  end
+
+  defp display(windows, zone) do
+    Enum.map(windows, fn window ->
+      window
+      |> Map.update("start", nil, &TimeZones.display(&1, zone))
+      |> Map.update("end", nil, &TimeZones.display(&1, zone))
+    end)
+  end
+
+  defp parse_time(value, default, zone \\ "Etc/UTC")
+  defp parse_time(nil, nil, _zone), do: {:error, "end is required"}
+  defp parse_time(nil, default, _zone), do: {:ok, default}
+  defp parse_time(value, _default, zone), do: TimeZones.parse(value, zone)
+
+  defp validate_range(starts_at, ends_at) do
+    if DateTime.compare(ends_at, starts_at) == :gt,
+      do: :ok,
+      else: {:error, "end must be after start"}
+  end
+
+  # Drops windows that ended more than a day ago.
+  defp prune(windows) do
+    cutoff = DateTime.add(DateTime.utc_now(), -86_400, :second)
+
+    Enum.filter(windows, fn window ->
+      case parse_time(window["end"], nil) do
+        {:ok, ends_at} -> DateTime.compare(ends_at, cutoff) == :gt
+        {:error, _} -> false
+      end
+    end)
+  end
+
+  defp path do
+    Path.join(Config.load().data_dir, "maintenance.json")
+  end
+
+  defp load do
+    with {:ok, content} <- File.read(path()),
+         {:ok, windows} when is_list(windows) <- Jason.decode(content) do
+      windows
+    else
+      _ -> []
+    end
+  end
+
+  defp persist(windows) do
+    case DataFile.write_atomic(path(), Jason.encode!(windows, pretty: true)) do
+      :ok -> :ok
+      {:error, reason} ->
+        Logger.error("Failed to persist maintenance windows: #{inspect(reason)}")
+    end
+  end
+end
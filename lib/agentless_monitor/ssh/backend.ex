@@ -0,0 +1,39 @@
+defmodule AgentlessMonitor.SSH.Backend do
+  @moduledoc """
+  Behaviour for the ways a command reaches a server.
+
+    * `"openssh"` (default) - `AgentlessMonitor.SSH.Connection` runs the
+      system `ssh` binary, multiplexed through ControlMaster sockets, with
+      `sshpass` for password logins
+    * `"native"` - `AgentlessMonitor.SSH.Native` keeps in-process sessions
+      with OTP's `:ssh` application, so neither binary is needed and the
+      password never appears in a process's arguments
+
+  The backend is chosen with `ssh_backend` in the config or per server with
+  `ssh_options.backend`. Servers behind a `proxy_config` or with
+  `identity_files` always use `openssh`, which implements the jump host and
+  proxy command support and takes the keys as `-i` arguments.
+  """
+
+  @callback run(
+              host :: String.t(),
+              port :: integer(),
+              username :: String.t(),
+              command :: String.t(),
+              opts :: keyword()
+            ) :: {:ok, String.t()} | {:error, String.t()}
+
+  @doc "Whether an established session to the destination can be reused"
+  @callback connected?(host :: String.t(), port :: integer(), username :: String.t()) ::
+              boolean()
+
+  @doc "The backend module for a set of connection options"
+  def module(opts) do
+    identity_files = Keyword.get(opts, :identity_files) || []
+
+    case {Keyword.get(opts, :backend), Keyword.get(opts, :proxy), identity_files} do
+      {"native", nil, []} -> AgentlessMonitor.SSH.Native
+      _ -> AgentlessMonitor.SSH.Connection
+    end
+  end
+end
@@ -0,0 +1,99 @@
+defmodule AgentlessMonitor.SSH.Coalescer do
+  @moduledoc """
+  Shares one execution between concurrent identical commands.
+
+  When a command is requested while the same one (same key, e.g. server and
+  command line) is still running, the caller waits for that run's result
+  instead of starting another, so an API-triggered collection racing the
+  scheduled cycle runs the SSH command once. Results are not cached: a
+  request arriving after the run finished starts a new one.
+  """
+
+  use GenServer
+  require Logger
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(
+      __MODULE__,
+      %{inflight: %{}, tasks: %{}, executions: 0, coalesced: 0},
+      name: __MODULE__
+    )
+  end
+
+  @doc """
+  Runs `fun` for `key`, or joins the run already in flight for it. Falls
+  back to calling `fun` directly when the coalescer is not running.
+  """
+  def run(key, fun, timeout) when is_function(fun, 0) do
+    if Process.whereis(__MODULE__) do
+      GenServer.call(__MODULE__, {:run, key, fun}, timeout)
+    else
+      fun.()
+    end
+  end
+
+  def stats do
+    GenServer.call(__MODULE__, :stats)
+  end
+
+  # ---- Callbacks ----
+
+  def init(state) do
+    {:ok, state}
+  end
+
+  def handle_call({:run, key, fun}, from, state) do
+    case Map.fetch(state.inflight, key) do
+      {:ok, waiters} ->
+        inflight = Map.put(state.inflight, key, [from | waiters])
+        {:noreply, %{state | inflight: inflight, coalesced: state.coalesced + 1}}
+
+      :error ->
+        task = Task.Supervisor.async_nolink(AgentlessMonitor.TaskSupervisor, fun)
+
+        {:noreply,
+         %{
+           state
+           | inflight: Map.put(state.inflight, key, [from]),
+             tasks: Map.put(state.tasks, task.ref, key),
+             executions: state.executions + 1
+         }}
+    end
+  end
+
+  def handle_call(:stats, _from, state) do
+    stats = %{
+      "in_flight" => map_size(state.inflight),
+      "executions" => state.executions,
+      "coalesced" => state.coalesced
+    }
+
+    {:reply, stats, state}
+  end
+
+  def handle_info({ref, result}, state) when is_map_key(state.tasks, ref) do
+    Process.demonitor(ref, [:flush])
+    {:noreply, finish(state, ref, result)}
+  end
+
+  def handle_info({:DOWN, ref, :process, _pid, reason}, state)
+      when is_map_key(state.tasks, ref) do
+    Logger.warning("Coalesced command crashed: #{inspect(reason)}")
+    {:noreply, finish(state, ref, {:error, "command crashed"})}
+  end
+
+  def handle_info(_message, state) do
+    {:noreply, state}
+  end
+
+  # ---- Private helpers ----
+
+  defp finish(state, ref, result) do
+    {key, tasks} = Map.pop(state.tasks, ref)
+    {waiters, inflight} = Map.pop(state.inflight, key, [])
+    Enum.each(waiters, &GenServer.reply(&1, result))
+    %{state | tasks: tasks, inflight: inflight}
+  end
+end
@@ -1,20 +1,203 @@
 defmodule AgentlessMonitor.SSH.Connection do
-  @moduledoc "Wraps the ssh CLI for executing remote commands"
+  @moduledoc """
+  Entry point for executing remote commands, and the default `openssh`
+  backend (see `AgentlessMonitor.SSH.Backend`), which wraps the ssh CLI.
+
+  The `:proxy` option takes a server's `proxy_config`:
+
+    * `%{"type" => "jump", "host" => "bastion", "port" => 22, "username" => "ops"}` -
+      a jump host (`ssh -J`); `host` may also be a full `user@host:port` spec,
+      or several of them separated by commas for a chain of jumps
+    * `%{"type" => "command", "command" => "corkscrew proxy 3128 %h %p"}` -
+      any `ProxyCommand`
+    * `%{"type" => "socks5", "host" => "10.0.0.1", "port" => 1080}` - a SOCKS5
+      proxy, through `ncat` (which also takes `username`/`password`) or
+      OpenBSD `nc`
+
+  Before a new ControlMaster is spawned for a directly reachable host, a
+  plain TCP connect reads the SSH banner (see `probe/3`); when that fails the
+  ssh process is not started at all. The latency and banner are recorded in
+  `AgentlessMonitor.SSH.Manager`.
+
+  Concurrent calls with the same host, user, command and options share one
+  execution through `AgentlessMonitor.SSH.Coalescer`.
+
+  `:identity_files` (a server's `identity_files`) are passed as `-i`
+  arguments, for hosts that need a particular key.
+
+  With `:restricted` (a list of commands, see `AgentlessMonitor.SSH.Restricted`)
+  any other command is refused before a connection is made.
+
+  The `:identification` option (the `identification` config map) lets
+  target-side auditing attribute activity to the monitor: every command
+  starts with a `# <tag>` comment line, `set_env` passes
+  `AGENTLESS_MONITOR=<tag>` (the target's sshd must `AcceptEnv` it) and
+  `log_marker` writes a syslog entry through `logger` whenever a new
+  session is opened. `tag` defaults to `agentless-monitor@<hostname>`.
+  Commands for Windows hosts (`platform: "windows"`) are sent without the
+  comment line and marker, which cmd.exe would try to run.
+  """
+
+  @behaviour AgentlessMonitor.SSH.Backend
+
+  alias AgentlessMonitor.SSH.{Backend, Coalescer, Manager, Restricted}
 
   @control_path_dir "/tmp/ssh_cm"
 
   def execute(host, port, username, command, opts \\ []) do
     timeout = Keyword.get(opts, :timeout, 10)
+    key = {host, port, username, command, :erlang.phash2(opts)}
+    run = fn -> execute_once(host, port, username, command, opts) end
+
+    # Bounded by the TCP probe (connect and banner) plus the ssh run.
+    Coalescer.run(key, run, (3 * timeout + 10) * 1000)
+  end
+
+  @doc """
+  Opens a TCP connection and reads the server's identification line.
+  Returns `{:ok, %{"tcp_latency_ms" => ms, "banner" => "SSH-2.0-OpenSSH_9.6"}}`.
+  """
+  def probe(host, port, timeout) do
+    started_at = System.monotonic_time(:microsecond)
+    tcp_opts = [:binary, active: false, packet: :line]
+
+    case :gen_tcp.connect(String.to_charlist(host), port, tcp_opts, timeout * 1000) do
+      {:ok, socket} ->
+        latency = (System.monotonic_time(:microsecond) - started_at) / 1000
+
+        banner =
+          case :gen_tcp.recv(socket, 0, timeout * 1000) do
+            {:ok, line} -> String.trim(line)
+            {:error, _} -> nil
+          end
+
+        :gen_tcp.close(socket)
+        {:ok, %{"tcp_latency_ms" => Float.round(latency, 2), "banner" => banner}}
+
+      {:error, reason} ->
+        {:error, reason}
+    end
+  end
+
+  @doc "Whether the destination has a session to reuse, on the backend `opts` select"
+  def alive?(host, port, username, opts) do
+    Backend.module(opts).connected?(host, port, username)
+  end
+
+  @impl Backend
+  def run(host, port, username, command, opts) do
+    run(host, port, username, command, Keyword.get(opts, :timeout, 10), opts)
+  end
+
+  @impl Backend
+  def connected?(host, port, username), do: master_alive?(host, port, username)
+
+  defp execute_once(host, port, username, command, opts) do
+    timeout = Keyword.get(opts, :timeout, 10)
+    backend = Backend.module(opts)
+    identification = Keyword.get(opts, :identification)
+    new_session = not session?(backend, host, port, username)
+    command = prepare_command(command, opts, new_session)
+    opts = Keyword.put(opts, :env, identification_env(identification))
+
+    with :ok <- Restricted.check(command, Keyword.get(opts, :restricted)),
+         :ok <- precheck(host, port, timeout, new_session, Keyword.get(opts, :proxy)) do
+      backend.run(host, port, username, command, opts)
+    end
+  end
+
+  @doc """
+  The command as sent to the host: prefixed with the identification tag
+  unless `opts` select restricted mode or a Windows host.
+  """
+  def prepare_command(command, opts, new_session) do
+    # A forced command on the target only recognizes commands sent verbatim,
+    # and cmd.exe has no comment line.
+    if Keyword.get(opts, :restricted) != nil or Keyword.get(opts, :platform) == "windows",
+      do: command,
+      else: identify(command, Keyword.get(opts, :identification), new_session)
+  end
+
+  # The control socket is a cheaper check than `ssh -O check`.
+  defp session?(__MODULE__, host, port, username),
+    do: File.exists?(control_path(host, port, username))
+
+  defp session?(backend, host, port, username), do: backend.connected?(host, port, username)
+
+  defp identify(command, nil, _new_session), do: command
+
+  defp identify(command, identification, new_session) do
+    tag = identification_tag(identification)
+
+    marker =
+      if new_session and Map.get(identification, "log_marker", false) do
+        message = shell_quote("connected: " <> tag)
+        "logger -p auth.info -t agentless-monitor #{message} 2>/dev/null; "
+      else
+        ""
+      end
+
+    "# #{tag}\n" <> marker <> command
+  end
+
+  defp identification_args(identification) do
+    Enum.flat_map(identification_env(identification), fn {name, value} ->
+      ["-o", "SetEnv=#{name}=#{value}"]
+    end)
+  end
+
+  defp identification_env(%{"set_env" => true} = identification) do
+    [{"AGENTLESS_MONITOR", identification |> identification_tag() |> String.replace(" ", "_")}]
+  end
+
+  defp identification_env(_identification), do: []
+
+  defp identification_tag(identification) do
+    tag =
+      case Map.get(identification, "tag") do
+        tag when is_binary(tag) and tag != "" -> tag
+        _ -> "agentless-monitor@#{hostname()}"
+      end
+
+    String.replace(tag, ["\r", "\n"], " ")
+  end
+
+  defp hostname do
+    {:ok, name} = :inet.gethostname()
+    to_string(name)
+  end
+
+  defp shell_quote(value) do
+    "'" <> String.replace(value, "'", "'\\''") <> "'"
+  end
+
+  # Only needed when there is no session to reuse and the host is reached
+  # directly rather than through a proxy.
+  defp precheck(host, port, timeout, true, nil) do
+    case probe(host, port, timeout) do
+      {:ok, result} ->
+        checked_at = DateTime.utc_now() |> DateTime.to_iso8601()
+        Manager.record_probe(host, port, Map.put(result, "checked_at", checked_at))
+        :ok
+
+      {:error, reason} ->
+        {:error, "TCP connect to #{host}:#{port} failed: #{:inet.format_error(reason)}"}
+    end
+  end
+
+  defp precheck(_host, _port, _timeout, _new_session, _proxy), do: :ok
+
+  defp run(host, port, username, command, timeout, opts) do
     password = Keyword.get(opts, :password, nil)
 
     ssh_args = [
       "-o", "StrictHostKeyChecking=no",
       "-o", "UserKnownHostsFile=/dev/null",
       "-o", "ConnectTimeout=#{timeout}",
-      "-o", "BatchMode=#{if password, do: "no", else: "yes"}",
-      "-o", "ControlMaster=auto",
-      "-o", "ControlPath=#{control_path(host, port, username)}",
-      "-o", "ControlPersist=60",
+      "-o", "BatchMode=#{if password, do: "no", else: "yes"}"
+    ] ++ multiplex_args(host, port, username, opts) ++ proxy_args(Keyword.get(opts, :proxy)) ++
+      identity_args(Keyword.get(opts, :identity_files, [])) ++
+      identification_args(Keyword.get(opts, :identification)) ++ [
       "-p", "#{port}",
       "#{username}@#{host}",
       command
@@ -30,11 +213,67 @@ defmodule AgentlessMonitor.SSH.Connection do
     end
   end
 
-  defp control_path(host, port, username) do
+  # `multiplex: false` authenticates a one-off connection instead of reusing
+  # or leaving behind a ControlMaster, e.g. to test a particular credential.
+  defp multiplex_args(host, port, username, opts) do
+    if Keyword.get(opts, :multiplex, true) do
+      [
+        "-o", "ControlMaster=auto",
+        "-o", "ControlPath=#{control_path(host, port, username)}",
+        "-o", "ControlPersist=#{Keyword.get(opts, :control_persist, 60)}"
+      ]
+    else
+      ["-o", "ControlMaster=no", "-o", "ControlPath=none"]
+    end
+  end
+
+  defp proxy_args(%{"type" => "jump", "host" => host} = proxy) do
+    user = if proxy["username"], do: "#{proxy["username"]}@", else: ""
+    port = if proxy["port"], do: ":#{proxy["port"]}", else: ""
+    ["-J", "#{user}#{host}#{port}"]
+  end
+
+  defp proxy_args(%{"type" => "command", "command" => command}) do
+    ["-o", "ProxyCommand=#{command}"]
+  end
+
+  defp proxy_args(%{"type" => "socks5", "host" => host} = proxy) do
+    address = "#{host}:#{Map.get(proxy, "port", 1080)}"
+
+    command =
+      if System.find_executable("ncat") do
+        auth =
+          if proxy["username"],
+            do: " --proxy-auth #{proxy["username"]}:#{proxy["password"]}",
+            else: ""
+
+        "ncat --proxy #{address} --proxy-type socks5#{auth} %h %p"
+      else
+        "nc -X 5 -x #{address} %h %p"
+      end
+
+    ["-o", "ProxyCommand=#{command}"]
+  end
+
+  defp proxy_args(_proxy), do: []
+
+  # Tried in order, before ssh's defaults and any agent keys.
+  defp identity_args(paths), do: Enum.flat_map(paths || [], &["-i", &1])
+
+  @doc "The ControlMaster socket path of a destination"
+  def control_path(host, port, username) do
     File.mkdir_p!(@control_path_dir)
     "#{@control_path_dir}/#{username}_#{host}_#{port}"
   end
 
+  @doc "Paths of the ControlMaster sockets present, including stale ones"
+  def control_sockets do
+    case File.ls(@control_path_dir) do
+      {:ok, names} -> names |> Enum.sort() |> Enum.map(&Path.join(@control_path_dir, &1))
+      {:error, _reason} -> []
+    end
+  end
+
   defp run_ssh(args, timeout) do
     task =
       Task.async(fn ->
@@ -83,12 +322,40 @@ defmodule AgentlessMonitor.SSH.Connection do
     execute(host, port, username, "echo ok", opts)
   end
 
+  @doc "Whether a ControlMaster for this destination is running"
+  def master_alive?(host, port, username) do
+    socket_alive?(control_path(host, port, username), "#{username}@#{host}", port)
+  end
+
+  @doc """
+  Whether a ControlMaster answers on the socket at `path`. The destination
+  only has to be well-formed: the socket alone decides.
+  """
+  def socket_alive?(path, destination \\ "localhost", port \\ 22) do
+    args = [
+      "-O", "check",
+      "-o", "ControlPath=#{path}",
+      "-p", "#{port}",
+      destination
+    ]
+
+    case System.cmd("ssh", args, stderr_to_stdout: true) do
+      {_output, 0} -> true
+      _ -> false
+    end
+  rescue
+    _ -> false
+  end
+
   def close_control_master(host, port, username) do
-    cp = control_path(host, port, username)
+    close_socket(control_path(host, port, username), "#{username}@#{host}")
+  end
 
+  @doc "Asks the ControlMaster on the socket at `path` to exit"
+  def close_socket(path, destination \\ "localhost") do
     System.cmd(
       "ssh",
-      ["-O", "exit", "-o", "ControlPath=#{cp}", "#{username}@#{host}"],
+      ["-O", "exit", "-o", "ControlPath=#{path}", destination],
       stderr_to_stdout: true
     )
   end
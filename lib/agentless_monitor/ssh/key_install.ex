@@ -0,0 +1,97 @@
+defmodule AgentlessMonitor.SSH.KeyInstall do
+  @moduledoc """
+  Onboarding of hosts that only accept passwords, the equivalent of
+  `ssh-copy-id`: a password supplied once is used to append the monitor's
+  public key to the target user's `~/.ssh/authorized_keys`.
+
+  The key is the first of `id_ed25519.pub`, `id_ecdsa.pub` and `id_rsa.pub`
+  in `ssh_options.user_dir` (default `~/.ssh`). After installing it, a
+  key-only login is attempted; only when that succeeds is the server
+  switched to `auth_method: "key_only"`, which stops the `fallback_password`
+  (and `sshpass`) from being offered to it. Both steps use one-off sessions
+  rather than a shared ControlMaster, so the check really exercises the key.
+  The password is not stored.
+  """
+
+  alias AgentlessMonitor.State
+  alias AgentlessMonitor.SSH.Connection
+
+  @key_files ["id_ed25519.pub", "id_ecdsa.pub", "id_rsa.pub"]
+
+  @doc """
+  Installs the monitor's key on `server` with `password`. `opts` are the
+  server's connection options. Returns `{:ok, output}` describing the
+  installed key or `{:error, output}`.
+  """
+  def install(server, password, opts) do
+    with {:ok, key} <- public_key(Keyword.get(opts, :user_dir)),
+         {:ok, _output} <- copy(server, key, password, opts),
+         {:ok, _output} <- verify(server, opts) do
+      State.update_server(server.id, %{auth_method: "key_only"})
+      {:ok, "installed #{describe(key)} for #{server.username}@#{server.host}"}
+    end
+  end
+
+  @doc "The monitor's public key, as one authorized_keys line"
+  def public_key(user_dir) do
+    dir = Path.expand(user_dir || "~/.ssh")
+
+    @key_files
+    |> Enum.map(&Path.join(dir, &1))
+    |> Enum.find_value(fn path ->
+      case File.read(path) do
+        {:ok, content} -> content |> String.trim() |> valid_key()
+        {:error, _} -> nil
+      end
+    end)
+    |> case do
+      nil -> {:error, "no public key found in #{dir}; generate one with ssh-keygen"}
+      key -> {:ok, key}
+    end
+  end
+
+  @doc "Shell command appending `key` to authorized_keys unless already present"
+  def command(key) do
+    quoted = shell_quote(key)
+
+    "umask 077 && mkdir -p ~/.ssh && touch ~/.ssh/authorized_keys && " <>
+      "(grep -qxF #{quoted} ~/.ssh/authorized_keys || " <>
+      "echo #{quoted} >> ~/.ssh/authorized_keys) && " <>
+      "(command -v restorecon >/dev/null 2>&1 && restorecon -R ~/.ssh || true)"
+  end
+
+  # ---- Private helpers ----
+
+  defp copy(server, key, password, opts) do
+    opts = Keyword.merge(opts, password: password, multiplex: false)
+
+    case Connection.execute(server.host, server.port, server.username, command(key), opts) do
+      {:ok, output} -> {:ok, output}
+      {:error, output} -> {:error, "installing the key failed: #{output}"}
+    end
+  end
+
+  defp verify(server, opts) do
+    opts = Keyword.merge(opts, password: nil, multiplex: false)
+
+    case Connection.execute(server.host, server.port, server.username, "true", opts) do
+      {:ok, output} -> {:ok, output}
+      {:error, output} -> {:error, "key installed but logging in with it failed: #{output}"}
+    end
+  end
+
+  defp valid_key(line) do
+    if Regex.match?(~r/^(ssh-|ecdsa-)\S+ [A-Za-z0-9+\/=]+( .*)?$/, line), do: line
+  end
+
+  defp describe(key) do
+    case String.split(key, " ", parts: 3) do
+      [type, _data, comment] -> "#{type} key #{comment}"
+      [type | _] -> "#{type} key"
+    end
+  end
+
+  defp shell_quote(value) do
+    "'" <> String.replace(value, "'", "'\\''") <> "'"
+  end
+end
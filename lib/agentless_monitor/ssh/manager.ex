@@ -1,18 +1,84 @@
 defmodule AgentlessMonitor.SSH.Manager do
+  @moduledoc """
+  The pool of connections the monitor keeps to its servers, one entry per
+  server naming its ControlMaster (or native session), with the latest
+  TCP probe of each host.
+
+  Entries are evicted, and their session closed, under the `ssh_pool`
+  config map:
+
+      "ssh_pool": {"idle_timeout": 1800, "max_age": 86400, "max_connections": 50}
+
+  `idle_timeout` (default 1800 seconds) closes connections unused for that
+  long and `max_age` (default none) recycles connections older than that,
+  both checked every minute. With `max_connections` (default none) a new
+  connection beyond the limit evicts the least recently used one. A
+  session shared by several servers is only closed with its last entry.
+
+  On startup the ControlMaster sockets left by a previous run are checked
+  with `ssh -O check`: live masters of configured servers are adopted into
+  the pool, live ones no server uses are closed and dead sockets, which
+  would otherwise stop ssh from multiplexing to their destination, are
+  removed.
+  """
+
   use GenServer
+  require Logger
+
+  alias AgentlessMonitor.{Config, State}
+  alias AgentlessMonitor.SSH.{Connection, Native}
+  alias AgentlessMonitor.Monitoring.Service
+
+  @evict_interval :timer.seconds(60)
+  @default_idle_timeout 1800
 
   def start_link(_) do
-    GenServer.start_link(__MODULE__, %{connections: %{}}, name: __MODULE__)
+    state = %{connections: %{}, probes: %{}, evictions: %{idle: 0, max_age: 0, lru: 0}}
+    GenServer.start_link(__MODULE__, state, name: __MODULE__)
   end
 
   def record_connection(server_id, host, port, username) do
     GenServer.cast(__MODULE__, {:record, server_id, host, port, username})
   end
 
+  @doc """
+  Connects to `server` with the same options as collections, opening (or
+  reusing) its pooled ControlMaster, and records the connection. `opts`
+  override those options, e.g. `password:`. Returns `{:ok, connection}`
+  with the connection id and its health.
+  """
+  def connect(server, opts \\ []) do
+    opts = Keyword.merge(Service.connection_opts(server), opts)
+    started_at = System.monotonic_time(:millisecond)
+
+    case Connection.test_connection(server.host, server.port, server.username, opts) do
+      {:ok, _output} ->
+        health = %{
+          latency_ms: System.monotonic_time(:millisecond) - started_at,
+          master_alive: Connection.alive?(server.host, server.port, server.username, opts)
+        }
+
+        record = {:record, server.id, server.host, server.port, server.username, health}
+        {:ok, GenServer.call(__MODULE__, record)}
+
+      {:error, reason} ->
+        {:error, reason}
+    end
+  end
+
   def remove_connection(server_id) do
     GenServer.cast(__MODULE__, {:remove, server_id})
   end
 
+  def record_probe(host, port, result) do
+    GenServer.cast(__MODULE__, {:record_probe, host, port, result})
+  end
+
+  @doc "Latest TCP latency and SSH banner seen for a host, or nil"
+  def get_probe(host, port) do
+    GenServer.call(__MODULE__, {:get_probe, host, port})
+  end
+
   def get_connections do
     GenServer.call(__MODULE__, :get_connections)
   end
@@ -24,24 +90,37 @@ defmodule AgentlessMonitor.SSH.Manager do
   # ---- Callbacks ----
 
   def init(state) do
-    {:ok, state}
+    Process.send_after(self(), :evict, @evict_interval)
+    {:ok, state, {:continue, :adopt}}
   end
 
-  def handle_cast({:record, server_id, host, port, username}, state) do
-    conn = %{
-      connected_at: DateTime.utc_now() |> DateTime.to_iso8601(),
-      host: host,
-      port: port,
-      username: username
-    }
+  def handle_continue(:adopt, state) do
+    Task.Supervisor.start_child(AgentlessMonitor.TaskSupervisor, &adopt_control_masters/0)
+    {:noreply, state}
+  end
 
-    {:noreply, %{state | connections: Map.put(state.connections, server_id, conn)}}
+  def handle_cast({:record, server_id, host, port, username}, state) do
+    {_conn, state} = record(state, server_id, host, port, username, %{})
+    {:noreply, state}
   end
 
   def handle_cast({:remove, server_id}, state) do
     {:noreply, %{state | connections: Map.delete(state.connections, server_id)}}
   end
 
+  def handle_cast({:record_probe, host, port, result}, state) do
+    {:noreply, %{state | probes: Map.put(state.probes, {host, port}, result)}}
+  end
+
+  def handle_call({:record, server_id, host, port, username, health}, _from, state) do
+    {conn, state} = record(state, server_id, host, port, username, health)
+    {:reply, conn, state}
+  end
+
+  def handle_call({:get_probe, host, port}, _from, state) do
+    {:reply, Map.get(state.probes, {host, port}), state}
+  end
+
   def handle_call(:get_connections, _from, state) do
     {:reply, state.connections, state}
   end
@@ -49,9 +128,145 @@ defmodule AgentlessMonitor.SSH.Manager do
   def handle_call(:get_stats, _from, state) do
     stats = %{
       total_connections: map_size(state.connections),
-      connections: state.connections
+      connections: state.connections,
+      evictions: state.evictions
     }
 
     {:reply, stats, state}
   end
+
+  def handle_info(:evict, state) do
+    Process.send_after(self(), :evict, @evict_interval)
+    pool = pool_config()
+    now = DateTime.utc_now()
+
+    state =
+      Enum.reduce(state.connections, state, fn {server_id, conn}, acc ->
+        case expiry(conn, pool, now) do
+          nil -> acc
+          reason -> evict(acc, server_id, reason)
+        end
+      end)
+
+    {:noreply, state}
+  end
+
+  # ---- Private helpers ----
+
+  # Entries are keyed by server; the id names the pooled ControlMaster.
+  defp record(state, server_id, host, port, username, health) do
+    now = DateTime.utc_now() |> DateTime.to_iso8601()
+    state = make_room(state, server_id, pool_config()["max_connections"])
+
+    conn =
+      state.connections
+      |> Map.get(server_id, %{connected_at: now})
+      |> Map.merge(health)
+      |> Map.merge(%{
+        id: "#{username}@#{host}:#{port}",
+        host: host,
+        port: port,
+        username: username,
+        last_checked_at: now,
+        last_used_at: now
+      })
+
+    {conn, %{state | connections: Map.put(state.connections, server_id, conn)}}
+  end
+
+  # Runs in a task so startup doesn't wait on one `ssh -O check` per socket;
+  # adopted masters are recorded like new connections.
+  defp adopt_control_masters do
+    servers =
+      State.get_servers()
+      |> Enum.group_by(&Connection.control_path(&1.host, &1.port, &1.username))
+
+    results =
+      Connection.control_sockets()
+      |> Task.async_stream(&{&1, Connection.socket_alive?(&1)}, timeout: 30_000)
+      |> Enum.map(fn
+        {:ok, {path, true}} -> adopt_socket(path, Map.get(servers, path, []))
+        {:ok, {path, false}} -> remove_socket(path)
+        {:exit, _reason} -> :failed
+      end)
+      |> Enum.frequencies()
+
+    unless results == %{} do
+      Logger.info(
+        "SSH pool: #{Map.get(results, :adopted, 0)} ControlMasters adopted, " <>
+          "#{Map.get(results, :closed, 0)} orphaned closed, " <>
+          "#{Map.get(results, :removed, 0)} stale sockets removed"
+      )
+    end
+  end
+
+  defp adopt_socket(path, []) do
+    Connection.close_socket(path)
+    :closed
+  end
+
+  defp adopt_socket(_path, servers) do
+    Enum.each(servers, fn server ->
+      health = %{master_alive: true, adopted: true}
+      record = {:record, server.id, server.host, server.port, server.username, health}
+      GenServer.call(__MODULE__, record)
+    end)
+
+    :adopted
+  end
+
+  defp remove_socket(path) do
+    case File.rm(path) do
+      :ok -> :removed
+      {:error, _reason} -> :failed
+    end
+  end
+
+  defp pool_config, do: Config.load().ssh_pool || %{}
+
+  defp expiry(conn, pool, now) do
+    idle_timeout = Map.get(pool, "idle_timeout", @default_idle_timeout)
+    max_age = pool["max_age"]
+
+    cond do
+      is_number(max_age) and seconds_since(conn.connected_at, now) > max_age -> :max_age
+      is_number(idle_timeout) and seconds_since(conn.last_used_at, now) > idle_timeout -> :idle
+      true -> nil
+    end
+  end
+
+  # A new entry beyond `max_connections` evicts the least recently used.
+  defp make_room(state, server_id, max) when is_integer(max) and max > 0 do
+    if Map.has_key?(state.connections, server_id) or map_size(state.connections) < max do
+      state
+    else
+      {lru, _conn} = Enum.min_by(state.connections, fn {_id, conn} -> conn.last_used_at end)
+      state |> evict(lru, :lru) |> make_room(server_id, max)
+    end
+  end
+
+  defp make_room(state, _server_id, _max), do: state
+
+  defp evict(state, server_id, reason) do
+    {conn, connections} = Map.pop(state.connections, server_id)
+
+    unless Enum.any?(connections, fn {_id, other} -> other.id == conn.id end) do
+      Logger.info("SSH pool: closing #{conn.id} (#{reason})")
+
+      Task.Supervisor.start_child(AgentlessMonitor.TaskSupervisor, fn ->
+        Connection.close_control_master(conn.host, conn.port, conn.username)
+        Native.close(conn.host, conn.port, conn.username)
+      end)
+    end
+
+    evictions = Map.update(state.evictions, reason, 1, &(&1 + 1))
+    %{state | connections: connections, evictions: evictions}
+  end
+
+  defp seconds_since(timestamp, now) do
+    case DateTime.from_iso8601(to_string(timestamp)) do
+      {:ok, dt, _} -> DateTime.diff(now, dt)
+      _ -> 0
+    end
+  end
 end
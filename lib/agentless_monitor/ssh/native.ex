@@ -0,0 +1,202 @@
+defmodule AgentlessMonitor.SSH.Native do
+  @moduledoc """
+  SSH backend on OTP's `:ssh` application: no `ssh` or `sshpass` binary is
+  spawned and credentials stay inside the VM.
+
+  Sessions are opened on first use and kept per destination, so later
+  commands only open a new channel, much like a ControlMaster. A session is
+  closed after `control_persist` seconds without channels. Keys are read
+  from `ssh_options.user_dir` (default `~/.ssh`: `id_ed25519`, `id_ecdsa`,
+  `id_rsa`); with a `password` (the `fallback_password`), password
+  authentication is offered as well. Like the openssh backend, host keys
+  are accepted without checking.
+
+  Standard error is interleaved with standard output, and a non-zero exit
+  status is reported as an error carrying the output.
+  """
+
+  @behaviour AgentlessMonitor.SSH.Backend
+
+  use GenServer
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, nil, name: __MODULE__)
+  end
+
+  @impl AgentlessMonitor.SSH.Backend
+  def run(host, port, username, command, opts) do
+    timeout = Keyword.get(opts, :timeout, 10) * 1000
+
+    case session(host, port, username, opts) do
+      {:ok, conn, :cached} ->
+        exec(conn, command, timeout, opts)
+
+      {:ok, conn, :transient} ->
+        try do
+          exec(conn, command, timeout, opts)
+        after
+          :ssh.close(conn)
+        end
+
+      {:error, reason} ->
+        {:error, "SSH connection to #{host}:#{port} failed: #{format_reason(reason)}"}
+    end
+  end
+
+  @impl AgentlessMonitor.SSH.Backend
+  def connected?(host, port, username) do
+    running?() and GenServer.call(__MODULE__, {:lookup, {host, port, username}}) != nil
+  end
+
+  @doc "Closes the session to a destination, if any"
+  def close(host, port, username) do
+    if running?(), do: GenServer.call(__MODULE__, {:close, {host, port, username}}), else: :ok
+  end
+
+  # ---- Callbacks ----
+
+  @impl GenServer
+  def init(_) do
+    {:ok, %{sessions: %{}}}
+  end
+
+  @impl GenServer
+  def handle_call({:lookup, key}, _from, state) do
+    {:reply, Map.get(state.sessions, key), state}
+  end
+
+  # Two callers may connect concurrently; the first session registered wins.
+  def handle_call({:register, key, conn}, _from, state) do
+    case Map.get(state.sessions, key) do
+      nil ->
+        Process.monitor(conn)
+        {:reply, conn, %{state | sessions: Map.put(state.sessions, key, conn)}}
+
+      existing ->
+        :ssh.close(conn)
+        {:reply, existing, state}
+    end
+  end
+
+  def handle_call({:close, key}, _from, state) do
+    {conn, sessions} = Map.pop(state.sessions, key)
+    if conn, do: :ssh.close(conn)
+    {:reply, :ok, %{state | sessions: sessions}}
+  end
+
+  @impl GenServer
+  def handle_info({:DOWN, _ref, :process, conn, _reason}, state) do
+    sessions = state.sessions |> Enum.reject(fn {_key, pid} -> pid == conn end) |> Map.new()
+    {:noreply, %{state | sessions: sessions}}
+  end
+
+  # ---- Private helpers ----
+
+  defp running?, do: Process.whereis(__MODULE__) != nil
+
+  # Without the registry (embedded use) or with `multiplex: false` every
+  # command gets its own session.
+  defp session(host, port, username, opts) do
+    key = {host, port, username}
+    shared = running?() and Keyword.get(opts, :multiplex, true)
+    cached = if shared, do: GenServer.call(__MODULE__, {:lookup, key}), else: :none
+
+    case cached do
+      :none ->
+        with {:ok, conn} <- connect(host, port, username, opts), do: {:ok, conn, :transient}
+
+      nil ->
+        with {:ok, conn} <- connect(host, port, username, opts) do
+          {:ok, GenServer.call(__MODULE__, {:register, key, conn}), :cached}
+        end
+
+      conn ->
+        {:ok, conn, :cached}
+    end
+  end
+
+  defp connect(host, port, username, opts) do
+    timeout = Keyword.get(opts, :timeout, 10) * 1000
+
+    ssh_opts =
+      [
+        user: String.to_charlist(username),
+        silently_accept_hosts: true,
+        save_accepted_host: false,
+        user_interaction: false,
+        connect_timeout: timeout,
+        idle_time: Keyword.get(opts, :control_persist, 60) * 1000
+      ] ++ password_opts(Keyword.get(opts, :password)) ++ user_dir_opts(opts)
+
+    :ssh.connect(String.to_charlist(host), port, ssh_opts, timeout)
+  end
+
+  defp password_opts(nil), do: [auth_methods: ~c"publickey"]
+  defp password_opts(password), do: [password: String.to_charlist(password)]
+
+  defp user_dir_opts(opts) do
+    case Keyword.get(opts, :user_dir) do
+      dir when is_binary(dir) -> [user_dir: String.to_charlist(Path.expand(dir))]
+      _ -> []
+    end
+  end
+
+  defp exec(conn, command, timeout, opts) do
+    deadline = System.monotonic_time(:millisecond) + timeout
+
+    with {:ok, channel} <- :ssh_connection.session_channel(conn, timeout) do
+      set_env(conn, channel, Keyword.get(opts, :env, []), timeout)
+
+      case :ssh_connection.exec(conn, channel, String.to_charlist(command), timeout) do
+        :success ->
+          collect(conn, channel, [], nil, deadline)
+
+        :failure ->
+          :ssh_connection.close(conn, channel)
+          {:error, "exec request refused"}
+
+        {:error, reason} ->
+          :ssh_connection.close(conn, channel)
+          {:error, format_reason(reason)}
+      end
+    else
+      {:error, reason} -> {:error, format_reason(reason)}
+    end
+  end
+
+  defp set_env(conn, channel, env, timeout) do
+    Enum.each(env, fn {name, value} ->
+      :ssh_connection.setenv(conn, channel, ~c"#{name}", ~c"#{value}", timeout)
+    end)
+  end
+
+  defp collect(conn, channel, acc, status, deadline) do
+    remaining = max(deadline - System.monotonic_time(:millisecond), 0)
+
+    receive do
+      {:ssh_cm, ^conn, {:data, ^channel, _type, data}} ->
+        :ssh_connection.adjust_window(conn, channel, byte_size(data))
+        collect(conn, channel, [acc | data], status, deadline)
+
+      {:ssh_cm, ^conn, {:exit_status, ^channel, code}} ->
+        collect(conn, channel, acc, code, deadline)
+
+      {:ssh_cm, ^conn, {:eof, ^channel}} ->
+        collect(conn, channel, acc, status, deadline)
+
+      {:ssh_cm, ^conn, {:closed, ^channel}} ->
+        output = IO.iodata_to_binary(acc)
+        if status == 0, do: {:ok, output}, else: {:error, output}
+    after
+      remaining ->
+        :ssh_connection.close(conn, channel)
+        {:error, "timeout"}
+    end
+  end
+
+  defp format_reason(reason) when is_binary(reason), do: reason
+  defp format_reason(reason) when is_list(reason), do: to_string(reason)
+  defp format_reason(reason), do: inspect(reason)
+end
@@ -0,0 +1,109 @@
+defmodule AgentlessMonitor.SSH.Restricted do
+  @moduledoc """
+  Restricted mode, for targets where the monitor's user may only run an
+  agreed set of commands.
+
+  With `"restricted_mode": true` every remote command must appear verbatim
+  in the server's allowlist: the collection command, its pings and the
+  connectivity check for the server's settings, the process and neighbor
+  listings, its mesh pings and the alert capture, plus any commands listed in
+  `restricted_commands` (for example those of `actions`). Anything else,
+  such as chatops `exec`, disk usage scans, MTU probes or key installation,
+  is refused before connecting, and commands are sent without the
+  identification comment so they arrive unchanged.
+
+  `manifest/2` lists the allowed commands with their SHA-256 digests and
+  `generate/3` renders the matching target-side setup: a forced-command gate
+  that runs a command only when its digest is on the list, the
+  `authorized_keys` entry pinning the monitor's key to that gate and a
+  sudoers rule denying the user any privilege escalation.
+  """
+
+  alias AgentlessMonitor.Alerting.Capture
+  alias AgentlessMonitor.Monitoring.{Mesh, Neighbors, Service, Watchdog}
+  alias AgentlessMonitor.SSH.KeyInstall
+
+  @gate_path "/usr/local/bin/agentless-monitor-gate"
+
+  @doc "Allowed commands for servers with `settings`"
+  def allowed(settings, config) do
+    settings |> manifest(config) |> Enum.map(& &1["command"])
+  end
+
+  @doc "`:ok` when `command` may run, always for a nil allowlist"
+  def check(_command, nil), do: :ok
+
+  def check(command, allowed) do
+    if command in allowed,
+      do: :ok,
+      else: {:error, "command not allowed in restricted mode"}
+  end
+
+  @doc "Allowed commands as `%{\"name\", \"command\", \"sha256\"}` maps"
+  def manifest(settings, config) do
+    process_check =
+      if settings["process_rules"] in [nil, []], do: [], else: [{"processes", Watchdog.command()}]
+
+    capture = if config.alert_capture, do: [{"alert capture", Capture.command()}], else: []
+    extra = Enum.map(config.restricted_commands, &{"configured", &1})
+
+    (Service.remote_commands(settings) ++
+       process_check ++
+       [{"neighbors", Neighbors.command()}] ++
+       Mesh.commands(settings, config) ++ capture ++ extra)
+    |> Enum.uniq_by(&elem(&1, 1))
+    |> Enum.map(fn {name, command} ->
+      %{"name" => name, "command" => command, "sha256" => digest(command)}
+    end)
+  end
+
+  @doc """
+  Target-side restrictions for `server` as `%{"gate", "gate_path",
+  "authorized_keys", "sudoers"}`.
+  """
+  def generate(server, settings, config) do
+    commands = manifest(settings, config)
+
+    key =
+      case KeyInstall.public_key(get_in(settings, ["ssh_options", "user_dir"])) do
+        {:ok, key} -> key
+        {:error, _} -> "<monitor public key>"
+      end
+
+    %{
+      "gate_path" => @gate_path,
+      "gate" => gate(server, commands),
+      "authorized_keys" => ~s(restrict,command="#{@gate_path}" #{key}),
+      "sudoers" =>
+        "# #{server.username} runs the monitor's commands and needs no privileges\n" <>
+          "#{server.username} ALL=(ALL:ALL) !ALL\n"
+    }
+  end
+
+  # ---- Private helpers ----
+
+  defp gate(server, commands) do
+    branches =
+      Enum.map_join(commands, "", fn command ->
+        "  #{command["sha256"]}) ;; # #{String.replace(command["name"], "\n", " ")}\n"
+      end)
+
+    """
+    #!/bin/sh
+    # agentless-monitor forced command for #{server.username}@#{server.host}.
+    # Runs the requested command only when it is on the monitor's manifest.
+    digest=$(printf '%s' "$SSH_ORIGINAL_COMMAND" | sha256sum | cut -d' ' -f1)
+    case "$digest" in
+    #{branches}  *)
+        echo "agentless-monitor: command not allowed" >&2
+        exit 126
+        ;;
+    esac
+    exec /bin/sh -c "$SSH_ORIGINAL_COMMAND"
+    """
+  end
+
+  defp digest(command) do
+    :crypto.hash(:sha256, command) |> Base.encode16(case: :lower)
+  end
+end
@@ -2,9 +2,63 @@ defmodule AgentlessMonitor.State do
   use GenServer
   require Logger
 
+  alias AgentlessMonitor.{Config, DataFile, Settings, Storage}
   alias AgentlessMonitor.Models.Server
+  alias AgentlessMonitor.SSH.Manager
 
   @max_history 1000
+  @purge_interval :timer.hours(1)
+  @archive_batch 100
+  @budget_interval :timer.seconds(30)
+  @retention_interval :timer.minutes(10)
+  # Samples per server kept at full resolution when downsampling.
+  @full_resolution 100
+  # Samples measured to estimate the size of the history.
+  @size_probe 32
+  # Server fields taken from config.json entries when they change.
+  @config_fields [
+    :host,
+    :port,
+    :username,
+    :auth_method,
+    :group,
+    :site,
+    :monitoring_interval,
+    :transport,
+    :command_prefix,
+    :snmp,
+    :proxy_config,
+    :depends_on,
+    :watched_units,
+    :identity_files
+  ]
+  # Nesting limit for SSH config `Include` directives, as in ssh.
+  @max_include_depth 16
+  # SSH config keywords read into a Host block (Port is parsed separately).
+  @ssh_options %{
+    "hostname" => :host_name,
+    "user" => :user,
+    "proxyjump" => :proxy_jump,
+    "proxycommand" => :proxy_command
+  }
+  # Server fields set through the API and saved for manually added servers.
+  @entry_fields [
+    :name,
+    :host,
+    :port,
+    :username,
+    :auth_method,
+    :group,
+    :site,
+    :proxy_config,
+    :monitoring_interval,
+    :transport,
+    :command_prefix,
+    :snmp,
+    :depends_on,
+    :watched_units,
+    :identity_files
+  ]
 
   # ---- Public API ----
 
@@ -80,19 +134,217 @@ defmodule AgentlessMonitor.State do
     GenServer.call(__MODULE__, :get_paused_servers)
   end
 
+  @doc """
+  Re-reads the SSH config and the servers declared in config.json and
+  reconciles them with the known servers. Hosts and entries that
+  disappeared are retired rather than dropped, so their history is kept.
+  """
+  def reload_config do
+    GenServer.call(__MODULE__, :reload_config)
+  end
+
+  def unretire_server(id) do
+    GenServer.call(__MODULE__, {:unretire_server, id})
+  end
+
+  @doc """
+  Soft-deletes a server: it is retired with its history archived, and can
+  be restored with `unretire_server/1` until `retired_retention_days` pass.
+  """
+  def delete_server(id) do
+    GenServer.call(__MODULE__, {:delete_server, id})
+  end
+
+  @doc "Permanently removes a retired server and its archived history"
+  def purge_server(id) do
+    GenServer.call(__MODULE__, {:purge_server, id})
+  end
+
+  @doc """
+  Reconciles servers with the entries of a desired-state manifest. Every
+  non-local server that is not declared is retired.
+  """
+  def apply_manifest(entries) do
+    GenServer.call(__MODULE__, {:apply_manifest, entries})
+  end
+
+  @doc """
+  Servers changed since `cursor` (a revision returned by a previous call),
+  along with the IDs of servers purged since then. Returns
+  `{changed, removed_ids, new_cursor}`. Every change to a server, its pause
+  state or its samples bumps its revision.
+  """
+  def changes_since(cursor) do
+    GenServer.call(__MODULE__, {:changes_since, cursor})
+  end
+
+  @doc """
+  Adds or updates servers from imported entries (config.json shape), matched
+  by name; an entry updates only the fields it has. Unlike
+  `apply_manifest/1`, servers missing from the import are left alone. An
+  import naming a server that didn't come from an import (config.json, the
+  SSH config, the API) is refused with `{:error, {:name_taken, names}}`.
+  """
+  def import_servers(entries) do
+    GenServer.call(__MODULE__, {:import_servers, entries})
+  end
+
+  @doc """
+  Adds a server from an API request (config.json shape). Manually added
+  servers are saved to `<data_dir>/servers.json` and come back on restart.
+  """
+  def create_server(entry) do
+    GenServer.call(__MODULE__, {:create_server, entry})
+  end
+
+  @doc """
+  Changes a server's settings from an API request (config.json shape).
+  The local server and SSH config hosts can't be edited.
+  """
+  def edit_server(id, entry) do
+    GenServer.call(__MODULE__, {:edit_server, id, entry})
+  end
+
+  @doc "Size of the in-memory history and what the memory budget evicted"
+  def memory_stats do
+    GenServer.call(__MODULE__, :memory_stats)
+  end
+
+  @doc "Per-server history in memory and the samples `retention_days` pruned"
+  def retention_stats do
+    GenServer.call(__MODULE__, :retention_stats)
+  end
+
+  @doc """
+  Servers known at startup: the local machine, hosts from the SSH config,
+  servers declared in config.json and those added through the API. Config
+  entries win over the others with the same name. In manifest mode the SSH
+  config is ignored, since the manifest is reconciled in right after startup.
+  """
+  def initial_servers do
+    config = Config.load()
+    config_servers = Enum.map(config.servers, &build_config_server/1)
+    config_names = MapSet.new(config_servers, & &1.name)
+    manual = Enum.reject(load_manual_servers(), &MapSet.member?(config_names, &1.name))
+    declared = MapSet.union(config_names, MapSet.new(manual, & &1.name))
+
+    ssh_servers =
+      if config.manifest_path,
+        do: [],
+        else: Enum.reject(read_ssh_config(), &MapSet.member?(declared, &1.name))
+
+    [local_server() | ssh_servers ++ config_servers ++ manual]
+  end
+
+  @doc """
+  Every entry of the SSH config as `initial_servers/0` reads it: the server
+  each `Host` name becomes (`status` `ok`, or `warning` with its
+  `problems`), the names and `Match` blocks that are `skipped` and why, and
+  the wildcard patterns that only supply defaults (`pattern`).
+  """
+  def ssh_config_report do
+    config = Config.load()
+    path = ssh_config_path()
+    blocks = ssh_config_blocks(ssh_config_source())
+    names = ssh_host_names(blocks)
+    resolved = Enum.map(names, &resolve_ssh_host(blocks, &1))
+    aliases = Map.new(resolved, &{&1.name, &1})
+    manual = Enum.map(load_manual_servers(), & &1.name)
+    declared = MapSet.new(Enum.map(config.servers, & &1["name"]) ++ manual)
+
+    hosts =
+      Enum.map(resolved, fn cfg ->
+        server = build_ssh_server(cfg, aliases)
+
+        skipped =
+          cond do
+            config.manifest_path -> "the SSH config is ignored in manifest mode"
+            cfg.name == "localhost" -> "localhost is monitored as the local machine"
+            MapSet.member?(declared, cfg.name) -> "a server of this name is declared elsewhere"
+            true -> nil
+          end
+
+        problems =
+          Enum.reject(
+            [
+              if(is_nil(cfg[:host_name]), do: "no HostName; the alias is connected to as is"),
+              if(cfg[:invalid_port], do: "Port #{cfg.invalid_port} is not a number, ignored")
+            ],
+            &is_nil/1
+          )
+
+        status =
+          cond do
+            skipped -> "skipped"
+            problems != [] -> "warning"
+            true -> "ok"
+          end
+
+        %{
+          "name" => cfg.name,
+          "host" => server.host,
+          "port" => server.port,
+          "user" => server.username,
+          "proxy" => server.proxy_config,
+          "identity_files" => server.identity_files,
+          "status" => status,
+          "problems" => List.wrap(skipped) ++ problems
+        }
+      end)
+
+    # The first block holds the options before any Host line.
+    others =
+      blocks
+      |> Enum.drop(1)
+      |> Enum.flat_map(fn
+        %{match: criteria} ->
+          [skipped_entry("Match #{criteria}", "Match blocks are ignored")]
+
+        block ->
+          {patterns, concrete} = Enum.split_with(block.patterns, &ssh_pattern?/1)
+          first_only = "only the first name of a Host line becomes a server"
+
+          Enum.map(patterns, &%{"name" => &1, "status" => "pattern", "problems" => []}) ++
+            for name <- Enum.drop(concrete, 1),
+                name not in names,
+                do: skipped_entry(name, first_only)
+      end)
+
+    %{"path" => path, "exists" => File.exists?(path), "hosts" => hosts ++ others}
+  end
+
   # ---- GenServer callbacks ----
 
   def init(_) do
+    config = Config.load()
+    servers = initial_servers()
+    paused_names = paused_names(config)
+
+    paused =
+      for server <- servers,
+          MapSet.member?(paused_names, server.name),
+          into: MapSet.new(),
+          do: server.id
+
     state = %{
-      servers: %{},
+      servers: Map.new(servers, &{&1.id, &1}),
       monitoring_data: %{},
       jobs: [],
-      paused_servers: MapSet.new()
+      paused_servers: paused,
+      revision: 0,
+      revisions: %{},
+      removed: %{},
+      evicted_samples: 0,
+      downsample_passes: 0,
+      retention_pruned: %{},
+      retention_run_at: nil,
+      config_fingerprint: config_fingerprint(config)
     }
 
-    state = add_local_server(state)
-    state = load_ssh_config(state)
-
+    schedule_retired_purge()
+    schedule_budget_check()
+    schedule_retention()
+    schedule_config_check(config.config_reload_interval)
     {:ok, state}
   end
 
@@ -109,7 +361,7 @@ defmodule AgentlessMonitor.State do
 
   def handle_call({:add_server, server}, _from, state) do
     new_state = %{state | servers: Map.put(state.servers, server.id, server)}
-    {:reply, :ok, new_state}
+    {:reply, :ok, touch(new_state, server.id)}
   end
 
   def handle_call({:update_server, id, attrs}, _from, state) do
@@ -117,7 +369,47 @@ defmodule AgentlessMonitor.State do
       {:ok, server} ->
         updated = apply_attrs(server, attrs)
         new_state = %{state | servers: Map.put(state.servers, id, updated)}
-        {:reply, {:ok, updated}, new_state}
+
+        if Enum.any?(Map.keys(attrs), &(&1 in @entry_fields)),
+          do: persist_manual(new_state, server)
+
+        {:reply, {:ok, updated}, touch(new_state, id)}
+
+      :error ->
+        {:reply, {:error, :not_found}, state}
+    end
+  end
+
+  def handle_call({:delete_server, id}, _from, state) do
+    case Map.fetch(state.servers, id) do
+      {:ok, %Server{source: "local"}} ->
+        {:reply, {:error, :local}, state}
+
+      {:ok, %Server{source: "ssh_config"}} ->
+        {:reply, {:error, :read_only}, state}
+
+      {:ok, %Server{status: "retired"}} ->
+        {:reply, {:error, :already_retired}, state}
+
+      {:ok, server} ->
+        new_state = retire(state, id, "deleted")
+        persist_manual(new_state, server)
+        {:reply, {:ok, new_state.servers[id]}, new_state}
+
+      :error ->
+        {:reply, {:error, :not_found}, state}
+    end
+  end
+
+  def handle_call({:purge_server, id}, _from, state) do
+    case Map.fetch(state.servers, id) do
+      {:ok, %Server{status: "retired"} = server} ->
+        new_state = purge(state, server)
+        persist_manual(new_state, server)
+        {:reply, :ok, new_state}
+
+      {:ok, _server} ->
+        {:reply, {:error, :not_retired}, state}
 
       :error ->
         {:reply, {:error, :not_found}, state}
@@ -161,11 +453,157 @@ defmodule AgentlessMonitor.State do
     {:reply, MapSet.to_list(state.paused_servers), state}
   end
 
+  def handle_call(:reload_config, _from, state) do
+    {new_state, summary} = reload(state, Config.load())
+    {:reply, {:ok, summary}, new_state}
+  end
+
+  def handle_call({:unretire_server, id}, _from, state) do
+    case Map.fetch(state.servers, id) do
+      {:ok, %Server{status: "retired"} = server} ->
+        new_state = unretire(state, id)
+        persist_manual(new_state, server)
+        {:reply, {:ok, new_state.servers[id]}, new_state}
+
+      {:ok, _server} ->
+        {:reply, {:error, :not_retired}, state}
+
+      :error ->
+        {:reply, {:error, :not_found}, state}
+    end
+  end
+
+  def handle_call({:apply_manifest, entries}, _from, state) do
+    desired = Enum.map(entries, &%{build_config_server(&1) | source: "manifest"})
+    existing = state.servers |> Map.values() |> Enum.reject(&(&1.source == "local"))
+    # Config servers the manifest takes over change source as well.
+    fields = [:source | @config_fields]
+
+    {new_state, summary} = sync_servers(state, desired, existing, fields, "not declared in manifest")
+    {:reply, {:ok, summary}, new_state}
+  end
+
+  def handle_call({:changes_since, cursor}, _from, state) do
+    changed =
+      state.revisions
+      |> Enum.filter(fn {_id, revision} -> revision > cursor end)
+      |> Enum.flat_map(fn {id, _revision} -> List.wrap(state.servers[id]) end)
+
+    removed = for {id, revision} <- state.removed, revision > cursor, do: id
+
+    {:reply, {changed, removed, state.revision}, state}
+  end
+
+  def handle_call(:memory_stats, _from, state) do
+    budget_mb = Config.load().memory_budget_mb
+
+    stats = %{
+      "history_samples" => sample_count(state.monitoring_data),
+      "history_bytes" => history_bytes(state.monitoring_data),
+      "budget_bytes" => if(budget_mb, do: budget_mb * 1024 * 1024),
+      "evicted_samples" => state.evicted_samples,
+      "downsample_passes" => state.downsample_passes
+    }
+
+    {:reply, stats, state}
+  end
+
+  def handle_call(:retention_stats, _from, state) do
+    servers =
+      Map.new(state.servers, fn {id, _server} ->
+        samples = Map.get(state.monitoring_data, id, [])
+
+        {id,
+         %{
+           "samples" => length(samples),
+           "oldest_sample" => if(samples != [], do: List.last(samples).timestamp),
+           "pruned_samples" => Map.get(state.retention_pruned, id, 0)
+         }}
+      end)
+
+    {:reply, %{"last_run" => state.retention_run_at, "servers" => servers}, state}
+  end
+
+  def handle_call({:import_servers, entries}, _from, state) do
+    by_name = state.servers |> Map.values() |> Map.new(&{&1.name, &1})
+
+    taken =
+      entries
+      |> Enum.map(& &1["name"])
+      |> Enum.filter(&match?(%Server{source: source} when source != "import", by_name[&1]))
+
+    if taken == [] do
+      # An entry updates only the fields it names; the others keep the
+      # imported server's current values.
+      desired =
+        Enum.map(entries, fn entry ->
+          entry =
+            case Map.fetch(by_name, entry["name"]) do
+              {:ok, current} -> Map.merge(server_entry(current), entry)
+              :error -> entry
+            end
+
+          %{build_config_server(entry) | source: "import"}
+        end)
+
+      existing = by_name |> Map.values() |> Enum.filter(&(&1.source == "import"))
+      fields = [:host, :port, :username, :group, :site, :transport, :command_prefix]
+
+      {new_state, summary} = sync_servers(state, desired, existing, fields, nil)
+      {:reply, {:ok, Map.delete(summary, "retired")}, new_state}
+    else
+      {:reply, {:error, {:name_taken, taken}}, state}
+    end
+  end
+
+  def handle_call({:create_server, entry}, _from, state) do
+    if name_taken?(state, entry["name"], nil) do
+      {:reply, {:error, :name_taken}, state}
+    else
+      server = %{build_config_server(Map.delete(entry, "id")) | source: "manual"}
+      new_state = touch(%{state | servers: Map.put(state.servers, server.id, server)}, server.id)
+      persist_manual(new_state, server)
+      {:reply, {:ok, server}, new_state}
+    end
+  end
+
+  def handle_call({:edit_server, id, entry}, _from, state) do
+    case Map.fetch(state.servers, id) do
+      {:ok, %Server{source: "local"}} ->
+        {:reply, {:error, :local}, state}
+
+      {:ok, %Server{source: "ssh_config"}} ->
+        {:reply, {:error, :read_only}, state}
+
+      {:ok, server} ->
+        if name_taken?(state, entry["name"], id) do
+          {:reply, {:error, :name_taken}, state}
+        else
+          rebuilt = build_config_server(Map.merge(server_entry(server), entry))
+          now = DateTime.utc_now() |> DateTime.to_iso8601()
+
+          updated =
+            server
+            |> Map.merge(Map.take(rebuilt, @entry_fields))
+            |> Map.put(:updated_at, now)
+
+          # The pooled connection may point at the old host or user.
+          Manager.remove_connection(id)
+          new_state = touch(%{state | servers: Map.put(state.servers, id, updated)}, id)
+          persist_manual(new_state, server)
+          {:reply, {:ok, updated}, new_state}
+        end
+
+      :error ->
+        {:reply, {:error, :not_found}, state}
+    end
+  end
+
   def handle_cast({:add_monitoring_data, server_id, data}, state) do
     existing = Map.get(state.monitoring_data, server_id, [])
-    updated = [data | existing] |> Enum.take(@max_history)
+    updated = existing |> insert_sample(data) |> Enum.take(@max_history)
     new_state = %{state | monitoring_data: Map.put(state.monitoring_data, server_id, updated)}
-    {:noreply, new_state}
+    {:noreply, touch(new_state, server_id)}
   end
 
   def handle_cast({:add_job, job}, state) do
@@ -182,136 +620,843 @@ defmodule AgentlessMonitor.State do
   end
 
   def handle_cast({:pause_server, server_id}, state) do
-    {:noreply, %{state | paused_servers: MapSet.put(state.paused_servers, server_id)}}
+    new_state = %{state | paused_servers: MapSet.put(state.paused_servers, server_id)}
+    {:noreply, touch(new_state, server_id)}
   end
 
   def handle_cast({:resume_server, server_id}, state) do
-    {:noreply, %{state | paused_servers: MapSet.delete(state.paused_servers, server_id)}}
+    new_state = %{state | paused_servers: MapSet.delete(state.paused_servers, server_id)}
+    {:noreply, touch(new_state, server_id)}
   end
 
   def handle_cast(:pause_all, state) do
     all_ids = Map.keys(state.servers)
     new_paused = Enum.reduce(all_ids, state.paused_servers, &MapSet.put(&2, &1))
-    {:noreply, %{state | paused_servers: new_paused}}
+    {:noreply, touch(%{state | paused_servers: new_paused}, all_ids)}
   end
 
   def handle_cast(:resume_all, state) do
-    {:noreply, %{state | paused_servers: MapSet.new()}}
+    resumed = MapSet.to_list(state.paused_servers)
+    {:noreply, touch(%{state | paused_servers: MapSet.new()}, resumed)}
+  end
+
+  def handle_info(:check_config, state) do
+    config = Config.load()
+    interval = config.config_reload_interval
+
+    state =
+      if config_reload?(interval) and config_fingerprint(config) != state.config_fingerprint do
+        {state, summary} = reload(state, config)
+        Logger.info("Configuration changed, servers reloaded: #{inspect(summary)}")
+        state
+      else
+        state
+      end
+
+    schedule_config_check(interval)
+    {:noreply, state}
+  end
+
+  def handle_info(:purge_retired, state) do
+    days = Config.load().retired_retention_days
+    cutoff = DateTime.add(DateTime.utc_now(), -days * 86_400, :second)
+
+    expired =
+      state.servers
+      |> Map.values()
+      |> Enum.filter(&retired_before?(&1, cutoff))
+
+    new_state = Enum.reduce(expired, state, &purge(&2, &1))
+    Enum.each(expired, &persist_manual(new_state, &1))
+
+    schedule_retired_purge()
+    {:noreply, new_state}
+  end
+
+  def handle_info(:enforce_memory_budget, state) do
+    new_state =
+      case Config.load().memory_budget_mb do
+        budget_mb when is_number(budget_mb) and budget_mb > 0 ->
+          enforce_budget(state, budget_mb * 1024 * 1024, 0)
+
+        _ ->
+          state
+      end
+
+    schedule_budget_check()
+    {:noreply, new_state}
+  end
+
+  # Samples older than a server's `retention_days` (a setting, so groups and
+  # servers can override it) are dropped from memory and, with a storage
+  # backend, from the database along with the server's events.
+  def handle_info(:enforce_retention, state) do
+    config = Config.load()
+    now = DateTime.utc_now()
+
+    new_state =
+      state.servers
+      |> Map.values()
+      |> Enum.reduce(state, fn server, acc ->
+        case Settings.for_server(server, config)["retention_days"] do
+          days when is_number(days) and days > 0 ->
+            cutoff = DateTime.add(now, -round(days * 86_400), :second)
+            Storage.prune(server.id, DateTime.to_unix(cutoff))
+            expire_samples(acc, server.id, DateTime.to_unix(cutoff, :microsecond))
+
+          _ ->
+            acc
+        end
+      end)
+
+    schedule_retention()
+    {:noreply, %{new_state | retention_run_at: DateTime.to_iso8601(now)}}
   end
 
   # ---- Private helpers ----
 
-  defp add_local_server(state) do
+  defp local_server do
     now = DateTime.utc_now() |> DateTime.to_iso8601()
 
-    local = %Server{
+    %Server{
       id: "local",
       name: "Local Machine",
       host: "localhost",
-      port: 22,
-      username: System.get_env("USER", "root"),
-      auth_method: "key",
+      port: nil,
+      username: nil,
+      auth_method: nil,
       proxy_config: nil,
       created_at: now,
       updated_at: now,
       last_seen: now,
       status: "online",
-      monitoring_interval: 30,
-      next_monitoring: 0
+      monitoring_interval: nil,
+      due_at: nil,
+      source: "local",
+      transport: "local"
     }
+  end
 
-    %{state | servers: Map.put(state.servers, "local", local)}
+  # Entries with `"transport": "local"` are collected on this machine, through
+  # an optional `command_prefix` such as `chroot /srv/jail` or
+  # `ip netns exec blue`; port and username don't apply to them.
+  defp build_config_server(%{"transport" => "local"} = entry) do
+    %{
+      build_config_server(Map.delete(entry, "transport"))
+      | host: entry["host"] || "localhost",
+        port: nil,
+        username: nil,
+        auth_method: nil,
+        transport: "local",
+        command_prefix: entry["command_prefix"]
+    }
   end
 
-  defp load_ssh_config(state) do
-    config_path =
-      System.get_env("SSH_CONFIG_PATH") ||
-        Application.get_env(:agentless_monitor, :ssh_config_path) ||
-        Path.expand("~/.ssh/config")
+  # Entries with `"transport": "snmp"` are network devices polled over SNMP
+  # with the credentials in `snmp`; port defaults to 161.
+  defp build_config_server(%{"transport" => "snmp"} = entry) do
+    %{
+      build_config_server(Map.delete(entry, "transport"))
+      | port: entry["port"] || 161,
+        username: nil,
+        auth_method: nil,
+        transport: "snmp",
+        snmp: entry["snmp"] || %{}
+    }
+  end
 
-    case File.read(config_path) do
-      {:ok, content} ->
-        servers = parse_ssh_config(content)
+  defp build_config_server(entry) do
+    now = DateTime.utc_now() |> DateTime.to_iso8601()
 
-        Enum.reduce(servers, state, fn server, acc ->
-          %{acc | servers: Map.put(acc.servers, server.id, server)}
-        end)
+    %Server{
+      id: entry["id"] || generate_id(),
+      name: entry["name"],
+      host: entry["host"] || entry["name"],
+      port: entry["port"] || 22,
+      username: entry["username"] || System.get_env("USER", "root"),
+      auth_method: entry["auth_method"] || "key",
+      group: entry["group"],
+      site: entry["site"],
+      proxy_config: entry["proxy"],
+      created_at: now,
+      updated_at: now,
+      last_seen: nil,
+      status: "unknown",
+      monitoring_interval: entry["monitoring_interval"],
+      due_at: nil,
+      source: "config",
+      depends_on: entry["depends_on"] || [],
+      watched_units: entry["watched_units"] || [],
+      identity_files: entry["identity_files"] || []
+    }
+  end
 
-      {:error, _} ->
-        state
+  # History is newest first. A sample older than the latest one (a late
+  # retry) is inserted at its place by timestamp, and one identical to a
+  # sample already held is dropped.
+  defp insert_sample([latest | _] = history, data) do
+    cond do
+      sample_time(data) >= sample_time(latest) and data != latest ->
+        [data | history]
+
+      data in history ->
+        history
+
+      true ->
+        {newer, older} = Enum.split_while(history, &(sample_time(&1) > sample_time(data)))
+        newer ++ [data | older]
+    end
+  end
+
+  defp insert_sample([], data), do: [data]
+
+  defp sample_time(%{timestamp: timestamp}) do
+    case DateTime.from_iso8601(timestamp || "") do
+      {:ok, datetime, _offset} -> DateTime.to_unix(datetime, :microsecond)
+      {:error, _} -> 0
+    end
+  end
+
+  defp name_taken?(_state, nil, _id), do: false
+
+  defp name_taken?(state, name, id) do
+    Enum.any?(Map.values(state.servers), &(&1.name == name and &1.id != id))
+  end
+
+  # A server in config.json shape, as accepted by `build_config_server/1`.
+  defp server_entry(server) do
+    %{
+      "id" => server.id,
+      "name" => server.name,
+      "host" => server.host,
+      "port" => server.port,
+      "username" => server.username,
+      "auth_method" => server.auth_method,
+      "group" => server.group,
+      "site" => server.site,
+      "proxy" => server.proxy_config,
+      "monitoring_interval" => server.monitoring_interval,
+      "transport" => server.transport,
+      "command_prefix" => server.command_prefix,
+      "snmp" => server.snmp,
+      "depends_on" => server.depends_on,
+      "watched_units" => server.watched_units,
+      "identity_files" => server.identity_files
+    }
+  end
+
+  defp manual_path do
+    Path.join(Config.load().data_dir, "servers.json")
+  end
+
+  # Retired manual servers are kept in the file too, so they can still be
+  # restored (or purged once `retired_retention_days` pass) after a restart.
+  defp load_manual_servers do
+    with {:ok, content} <- File.read(manual_path()),
+         {:ok, entries} when is_list(entries) <- Jason.decode(content) do
+      Enum.map(entries, fn entry ->
+        server = build_config_server(entry)
+
+        %{
+          server
+          | source: "manual",
+            created_at: entry["created_at"] || server.created_at,
+            retired_at: entry["retired_at"],
+            status: if(entry["retired_at"], do: "retired", else: server.status)
+        }
+      end)
+    else
+      _ -> []
+    end
+  end
+
+  # Rewrites servers.json when `changed` is a manually added server.
+  defp persist_manual(state, %Server{source: "manual"}) do
+    entries =
+      for {_id, %Server{source: "manual"} = server} <- state.servers do
+        server
+        |> server_entry()
+        |> Map.merge(%{"created_at" => server.created_at, "retired_at" => server.retired_at})
+      end
+
+    case DataFile.write_atomic(manual_path(), Jason.encode!(entries, pretty: true)) do
+      :ok -> :ok
+      {:error, reason} -> Logger.error("Failed to persist servers: #{inspect(reason)}")
+    end
+  end
+
+  defp persist_manual(_state, _changed), do: :ok
+
+  defp read_ssh_config do
+    parse_ssh_config(ssh_config_source())
+  end
+
+  defp ssh_config_path do
+    System.get_env("SSH_CONFIG_PATH") || Config.load().ssh_config_path ||
+      Path.expand("~/.ssh/config")
+  end
+
+  # The SSH config's lines with its includes expanded.
+  defp ssh_config_source do
+    config_path = ssh_config_path()
+
+    case File.read(config_path) do
+      {:ok, content} -> ssh_config_lines(content, Path.dirname(config_path), 0)
+      {:error, _} -> []
     end
   end
 
-  defp parse_ssh_config(content) do
-    lines = String.split(content, "\n")
+  # What the watcher compares to notice edits, so an unchanged file costs a
+  # read rather than a reconciliation.
+  defp config_fingerprint(config) do
+    ssh_config = if config.manifest_path, do: [], else: ssh_config_source()
+    :erlang.phash2({ssh_config, File.read("config.json")})
+  end
+
+  defp reload(state, config) do
+    {state, declared} = sync_config_servers(state, config)
+
+    {state, from_ssh_config} =
+      if config.manifest_path,
+        do: {state, %{}},
+        else: sync_ssh_config(state, read_ssh_config())
+
+    summary = Map.merge(declared, from_ssh_config, fn _key, first, second -> first ++ second end)
+    {%{state | config_fingerprint: config_fingerprint(config)}, summary}
+  end
 
-    {servers, current} =
-      Enum.reduce(lines, {[], nil}, fn line, {servers, current} ->
-        line = String.trim(line)
+  # Entries of config.json are matched by name as well. A name taken by a
+  # server added through the API (or registered, or imported) stays with
+  # that server until the next restart, when the config entry wins.
+  defp sync_config_servers(state, config) do
+    taken =
+      for {_id, %Server{source: source, name: name, status: status}} <- state.servers,
+          source not in ["config", "ssh_config"] and status != "retired",
+          into: MapSet.new(),
+          do: name
+
+    desired =
+      config.servers
+      |> Enum.map(&build_config_server/1)
+      |> Enum.reject(&MapSet.member?(taken, &1.name))
+
+    existing = state.servers |> Map.values() |> Enum.filter(&(&1.source == "config"))
+    reason = "no longer declared in config.json"
+    {state, summary} = sync_servers(state, desired, existing, @config_fields, reason)
+
+    # New entries start out paused like they would at startup.
+    paused = paused_names(config)
+
+    added =
+      for {id, %Server{source: "config", name: name}} <- state.servers,
+          name in summary["added"] and MapSet.member?(paused, name),
+          into: MapSet.new(),
+          do: id
+
+    {%{state | paused_servers: MapSet.union(state.paused_servers, added)}, summary}
+  end
+
+  defp paused_names(config) do
+    for entry <- config.servers,
+        entry["paused"] || entry["enabled"] == false,
+        into: MapSet.new(),
+        do: entry["name"]
+  end
 
-        cond do
-          String.starts_with?(line, "Host ") ->
-            host_val = String.trim(String.replace_prefix(line, "Host ", ""))
+  # Servers from the SSH config are matched by Host alias, since their IDs
+  # are generated fresh on every parse.
+  defp sync_ssh_config(state, parsed) do
+    declared =
+      for {_id, %Server{source: source, name: name}} <- state.servers,
+          source != "ssh_config",
+          into: MapSet.new(),
+          do: name
 
-            # flush previous block
-            servers =
-              if current && current[:host_name] && current[:name] not in ["*", "localhost"] do
-                [build_ssh_server(current) | servers]
-              else
-                servers
-              end
+    parsed = Enum.reject(parsed, &MapSet.member?(declared, &1.name))
+    existing = state.servers |> Map.values() |> Enum.filter(&(&1.source == "ssh_config"))
+
+    fields = [:host, :port, :username, :proxy_config, :identity_files]
+    sync_servers(state, parsed, existing, fields, "no longer present in SSH config")
+  end
 
-            if host_val in ["*", "localhost"] do
-              {servers, nil}
+  # Makes the servers in `existing` match `desired` by name: new names are
+  # added, matching ones get `fields` copied over (un-retiring them if needed)
+  # and the rest are retired, unless `retire_reason` is nil.
+  defp sync_servers(state, desired, existing, fields, retire_reason) do
+    now = DateTime.utc_now() |> DateTime.to_iso8601()
+    existing = Map.new(existing, &{&1.name, &1})
+
+    {state, added, updated} =
+      Enum.reduce(desired, {state, [], []}, fn server, {acc, added, updated} ->
+        case Map.fetch(existing, server.name) do
+          {:ok, current} ->
+            was_retired = current.status == "retired"
+            acc = if was_retired, do: unretire(acc, current.id), else: acc
+            changed = Map.take(current, fields) != Map.take(server, fields)
+
+            if changed or was_retired do
+              refreshed =
+                acc.servers[current.id]
+                |> Map.merge(Map.take(server, fields))
+                |> Map.put(:updated_at, now)
+
+              acc = touch(%{acc | servers: Map.put(acc.servers, current.id, refreshed)}, current.id)
+              {acc, added, [server.name | updated]}
             else
-              {servers, %{name: host_val, host_name: host_val, port: 22, user: System.get_env("USER", "root")}}
+              {acc, added, updated}
             end
 
-          current != nil && String.starts_with?(line, "HostName ") ->
-            val = String.trim(String.replace_prefix(line, "HostName ", ""))
-            {servers, Map.put(current, :host_name, val)}
+          :error ->
+            acc = touch(%{acc | servers: Map.put(acc.servers, server.id, server)}, server.id)
+            {acc, [server.name | added], updated}
+        end
+      end)
+
+    desired_names = MapSet.new(desired, & &1.name)
+
+    {state, retired} =
+      existing
+      |> Map.values()
+      |> Enum.reject(&(is_nil(retire_reason) or &1.status == "retired"))
+      |> Enum.reject(&MapSet.member?(desired_names, &1.name))
+      |> Enum.reduce({state, []}, fn server, {acc, retired} ->
+        {retire(acc, server.id, retire_reason), [server.name | retired]}
+      end)
+
+    summary = %{
+      "added" => Enum.reverse(added),
+      "updated" => Enum.reverse(updated),
+      "retired" => Enum.reverse(retired)
+    }
+
+    {state, summary}
+  end
+
+  defp retire(state, id, reason) do
+    server = Map.fetch!(state.servers, id)
+    now = DateTime.utc_now() |> DateTime.to_iso8601()
+
+    archive_history(id, Map.get(state.monitoring_data, id, []))
+    Manager.remove_connection(id)
+    Logger.info("Retiring server #{server.name}: #{reason}")
+
+    retired = %{server | status: "retired", retired_at: now, updated_at: now}
+
+    %{
+      state
+      | servers: Map.put(state.servers, id, retired),
+        monitoring_data: Map.delete(state.monitoring_data, id),
+        paused_servers: MapSet.delete(state.paused_servers, id)
+    }
+    |> touch(id)
+  end
+
+  defp unretire(state, id) do
+    server = Map.fetch!(state.servers, id)
+    now = DateTime.utc_now() |> DateTime.to_iso8601()
+
+    restored = %{
+      server
+      | status: "unknown",
+        retired_at: nil,
+        due_at: nil,
+        updated_at: now
+    }
+
+    %{
+      state
+      | servers: Map.put(state.servers, id, restored),
+        monitoring_data: Map.put(state.monitoring_data, id, restore_history(id))
+    }
+    |> touch(id)
+  end
+
+  defp purge(state, server) do
+    Logger.info("Purging retired server #{server.name} (retired at #{server.retired_at})")
+    File.rm(archive_path(server.id))
+    revision = state.revision + 1
+
+    %{
+      state
+      | servers: Map.delete(state.servers, server.id),
+        revision: revision,
+        revisions: Map.delete(state.revisions, server.id),
+        removed: Map.put(state.removed, server.id, revision)
+    }
+  end
+
+  # When disabled, check again later in case the configuration is reloaded.
+  defp schedule_config_check(interval) do
+    delay = if config_reload?(interval), do: :timer.seconds(interval), else: :timer.minutes(5)
+    Process.send_after(self(), :check_config, delay)
+  end
+
+  defp config_reload?(interval), do: is_integer(interval) and interval > 0
 
-          current != nil && String.starts_with?(line, "Port ") ->
-            val = line |> String.replace_prefix("Port ", "") |> String.trim() |> String.to_integer()
-            {servers, Map.put(current, :port, val)}
+  defp schedule_budget_check do
+    Process.send_after(self(), :enforce_memory_budget, @budget_interval)
+  end
+
+  # Over budget, older samples are thinned out (every other one dropped,
+  # keeping the newest `@full_resolution` per server untouched) until the
+  # history fits; as a last resort the oldest samples go. With a storage
+  # backend the evicted samples remain available from the database.
+  defp enforce_budget(state, budget, passes) do
+    bytes = history_bytes(state.monitoring_data)
+
+    cond do
+      bytes <= budget ->
+        state
+
+      passes < 8 and downsampleable?(state.monitoring_data) ->
+        downsampled =
+          Map.new(state.monitoring_data, fn {id, samples} -> {id, downsample(samples)} end)
+        evicted = sample_count(state.monitoring_data) - sample_count(downsampled)
+        Logger.warning("History over memory budget (#{bytes} bytes); downsampled #{evicted}")
+
+        enforce_budget(
+          %{
+            state
+            | monitoring_data: downsampled,
+              evicted_samples: state.evicted_samples + evicted,
+              downsample_passes: state.downsample_passes + 1
+          },
+          budget,
+          passes + 1
+        )
+
+      true ->
+        keep = max(div(sample_count(state.monitoring_data) * budget, bytes), 1)
+        per_server = max(div(keep, max(map_size(state.monitoring_data), 1)), 1)
+        trimmed =
+          Map.new(state.monitoring_data, fn {id, samples} ->
+            {id, Enum.take(samples, per_server)}
+          end)
+        evicted = sample_count(state.monitoring_data) - sample_count(trimmed)
+        Logger.warning("History over memory budget (#{bytes} bytes); evicted #{evicted} samples")
+
+        %{
+          state
+          | monitoring_data: trimmed,
+            evicted_samples: state.evicted_samples + evicted
+        }
+    end
+  end
+
+  defp schedule_retention do
+    Process.send_after(self(), :enforce_retention, @retention_interval)
+  end
+
+  # History is newest first, so everything after the first expired sample goes.
+  defp expire_samples(state, server_id, cutoff) do
+    samples = Map.get(state.monitoring_data, server_id, [])
 
-          current != nil && String.starts_with?(line, "User ") ->
-            val = String.trim(String.replace_prefix(line, "User ", ""))
-            {servers, Map.put(current, :user, val)}
+    case Enum.split_while(samples, &(sample_time(&1) >= cutoff)) do
+      {_kept, []} ->
+        state
+
+      {kept, expired} ->
+        pruned = Map.get(state.retention_pruned, server_id, 0) + length(expired)
+
+        %{
+          state
+          | monitoring_data: Map.put(state.monitoring_data, server_id, kept),
+            retention_pruned: Map.put(state.retention_pruned, server_id, pruned)
+        }
+        |> touch(server_id)
+    end
+  end
 
-          true ->
-            {servers, current}
+  defp downsampleable?(monitoring_data) do
+    Enum.any?(monitoring_data, fn {_id, samples} -> length(samples) > @full_resolution end)
+  end
+
+  defp downsample(samples) do
+    {recent, older} = Enum.split(samples, @full_resolution)
+    recent ++ Enum.take_every(older, 2)
+  end
+
+  defp sample_count(monitoring_data) do
+    monitoring_data |> Map.values() |> Enum.map(&length/1) |> Enum.sum()
+  end
+
+  # Estimated from the size of the latest samples of up to `@size_probe`
+  # servers rather than measured, which would walk the whole history.
+  defp history_bytes(monitoring_data) do
+    probe =
+      monitoring_data
+      |> Map.values()
+      |> Enum.flat_map(&Enum.take(&1, 1))
+      |> Enum.take(@size_probe)
+
+    case probe do
+      [] ->
+        0
+
+      probe ->
+        sample_bytes = :erts_debug.flat_size(probe) * :erlang.system_info(:wordsize)
+        div(sample_bytes, length(probe)) * sample_count(monitoring_data)
+    end
+  end
+
+  defp touch(state, ids) do
+    Enum.reduce(List.wrap(ids), state, fn id, acc ->
+      revision = acc.revision + 1
+      %{acc | revision: revision, revisions: Map.put(acc.revisions, id, revision)}
+    end)
+  end
+
+  defp retired_before?(%Server{status: "retired", retired_at: retired_at}, cutoff)
+       when is_binary(retired_at) do
+    case DateTime.from_iso8601(retired_at) do
+      {:ok, dt, _} -> DateTime.compare(dt, cutoff) != :gt
+      _ -> false
+    end
+  end
+
+  defp retired_before?(_server, _cutoff), do: false
+
+  defp schedule_retired_purge do
+    Process.send_after(self(), :purge_retired, @purge_interval)
+  end
+
+  # History archives are stored as checksummed batches of Erlang terms so
+  # they can be restored verbatim when a server is un-retired.
+  defp archive_path(server_id) do
+    Path.join([Config.load().data_dir, "archive", "#{server_id}.etf"])
+  end
+
+  defp archive_history(_server_id, []), do: :ok
+
+  defp archive_history(server_id, history) do
+    path = archive_path(server_id)
+
+    case DataFile.write_segments(path, Enum.chunk_every(history, @archive_batch)) do
+      :ok ->
+        :ok
+
+      {:error, reason} ->
+        Logger.warning("Failed to archive history for #{server_id}: #{inspect(reason)}")
+    end
+  end
+
+  defp restore_history(server_id) do
+    path = archive_path(server_id)
+
+    case DataFile.read_segments(path) do
+      {:ok, batches, bad} ->
+        if bad > 0 do
+          Logger.warning("Dropped #{bad} corrupt history segment(s) for #{server_id}")
         end
-      end)
 
-    # flush last block
-    servers =
-      if current && current[:host_name] && current[:name] not in ["*", "localhost"] do
-        [build_ssh_server(current) | servers]
-      else
-        servers
+        File.rm(path)
+        Enum.concat(batches)
+
+      {:error, _} ->
+        []
+    end
+  end
+
+  # The config's lines with `Include` directives replaced by the lines of
+  # the files they name. Relative paths are taken from the main config's
+  # directory, as ssh takes them from ~/.ssh, and globs are expanded.
+  defp ssh_config_lines(content, dir, depth) do
+    content
+    |> String.split("\n")
+    |> Enum.flat_map(fn line ->
+      case ssh_config_option(line) do
+        {"include", paths} when depth < @max_include_depth ->
+          paths
+          |> String.split()
+          |> Enum.flat_map(&(&1 |> Path.expand(dir) |> Path.wildcard() |> Enum.sort()))
+          |> Enum.flat_map(fn path ->
+            case File.read(path) do
+              {:ok, included} -> ssh_config_lines(included, dir, depth + 1)
+              {:error, _} -> []
+            end
+          end)
+
+        _ ->
+          [line]
       end
+    end)
+  end
 
-    servers
+  # Keywords are case-insensitive and may be followed by `=`. Every host
+  # named by a concrete pattern becomes a server whose options, as in ssh,
+  # are the first value found in the blocks matching it, in file order, so
+  # `Host *` and other wildcard blocks supply defaults. Options before the
+  # first `Host` apply to all hosts; `Match` blocks are skipped.
+  defp parse_ssh_config(lines) do
+    blocks = ssh_config_blocks(lines)
+
+    resolved =
+      blocks
+      |> ssh_host_names()
+      |> Enum.reject(&(&1 == "localhost"))
+      |> Enum.map(&resolve_ssh_host(blocks, &1))
+
+    aliases = Map.new(resolved, &{&1.name, &1})
+    Enum.map(resolved, &build_ssh_server(&1, aliases))
   end
 
-  defp build_ssh_server(cfg) do
+  defp ssh_config_blocks(lines) do
+    lines
+    |> Enum.reduce([%{patterns: ["*"], options: []}], fn line, blocks ->
+      case {ssh_config_option(line), blocks} do
+        {{"host", patterns}, _} ->
+          [%{patterns: String.split(patterns), options: []} | blocks]
+
+        {{"match", criteria}, _} ->
+          [%{patterns: [], options: [], match: criteria} | blocks]
+
+        {{key, value}, [current | rest]} ->
+          [%{current | options: [{key, value} | current.options]} | rest]
+
+        _ ->
+          blocks
+      end
+    end)
+    |> Enum.map(&%{&1 | options: Enum.reverse(&1.options)})
+    |> Enum.reverse()
+  end
+
+  # The first concrete name of every Host line, in file order.
+  defp ssh_host_names(blocks) do
+    blocks
+    |> Enum.flat_map(fn block ->
+      block.patterns |> Enum.reject(&ssh_pattern?/1) |> Enum.take(1)
+    end)
+    |> Enum.uniq()
+  end
+
+  defp skipped_entry(name, reason),
+    do: %{"name" => name, "status" => "skipped", "problems" => [reason]}
+
+  defp ssh_pattern?(pattern), do: String.contains?(pattern, ["*", "?", "!"])
+
+  defp resolve_ssh_host(blocks, name) do
+    blocks
+    |> Enum.filter(&ssh_host_match?(&1.patterns, name))
+    |> Enum.flat_map(& &1.options)
+    |> Enum.reduce(%{name: name}, fn {key, value}, acc -> put_ssh_option(acc, key, value) end)
+  end
+
+  # A block applies when one of its patterns matches and no negated one does.
+  defp ssh_host_match?(patterns, name) do
+    {negated, positive} = Enum.split_with(patterns, &String.starts_with?(&1, "!"))
+
+    Enum.any?(positive, &ssh_glob?(&1, name)) and
+      not Enum.any?(negated, &ssh_glob?(String.trim_leading(&1, "!"), name))
+  end
+
+  defp ssh_glob?(pattern, name) do
+    regex =
+      pattern
+      |> Regex.escape()
+      |> String.replace("\\*", ".*")
+      |> String.replace("\\?", ".")
+
+    Regex.match?(~r/^#{regex}$/, name)
+  end
+
+  defp ssh_config_option(line) do
+    case Regex.run(~r/^\s*([A-Za-z]+)(?:\s*=\s*|\s+)(.+?)\s*$/, line) do
+      [_, key, value] -> {String.downcase(key), value}
+      nil -> nil
+    end
+  end
+
+  # A Port that isn't a number is left out (22 is used) and remembered for
+  # `ssh_config_report/0`.
+  defp put_ssh_option(block, "port", value) do
+    case Integer.parse(value) do
+      {port, ""} -> Map.put_new(block, :port, port)
+      _ -> if block[:port], do: block, else: Map.put_new(block, :invalid_port, value)
+    end
+  end
+
+  # Unlike other options, every IdentityFile line adds a key to try.
+  defp put_ssh_option(block, "identityfile", value) do
+    Map.update(block, :identity_files, [value], &(&1 ++ [value]))
+  end
+
+  defp put_ssh_option(block, key, value) do
+    case Map.fetch(@ssh_options, key) do
+      {:ok, field} -> Map.put_new(block, field, value)
+      :error -> block
+    end
+  end
+
+  # ProxyJump takes precedence over ProxyCommand; `none` turns either off.
+  # Jump hosts naming another block are resolved to its HostName, User and
+  # Port, since the ssh process may not read the same config file.
+  defp ssh_proxy(block, aliases) do
+    cond do
+      block[:proxy_jump] not in [nil, "none"] ->
+        hops =
+          block.proxy_jump
+          |> String.split(",", trim: true)
+          |> Enum.map_join(",", &jump_spec(String.trim(&1), aliases))
+
+        %{"type" => "jump", "host" => hops}
+
+      block[:proxy_command] not in [nil, "none"] ->
+        %{"type" => "command", "command" => block.proxy_command}
+
+      true ->
+        nil
+    end
+  end
+
+  # `~` is the home of the user the monitor runs as; `%d`, `%u`, `%h` and
+  # `%r` are expanded as ssh does, other tokens are left to ssh.
+  defp expand_identity_file(path, cfg) do
+    home = System.user_home() || "~"
+
+    path
+    |> String.trim(~s("))
+    |> String.replace(~r/^~(?=\/|$)/, home)
+    |> String.replace("%d", home)
+    |> String.replace("%u", System.get_env("USER", "root"))
+    |> String.replace("%h", cfg[:host_name] || cfg.name)
+    |> String.replace("%r", cfg[:user] || System.get_env("USER", "root"))
+  end
+
+  defp jump_spec(hop, aliases) do
+    case Regex.run(~r{^(?:ssh://)?(?:([^@]+)@)?([^:@/]+)(?::(\d+))?$}, hop) do
+      nil ->
+        hop
+
+      [_ | parts] ->
+        [user, host, port] = parts ++ List.duplicate("", 3 - length(parts))
+        block = Map.get(aliases, host, %{})
+        user = if user == "", do: block[:user], else: user
+        port = if port == "", do: block[:port], else: port
+
+        "#{if user, do: "#{user}@"}#{block[:host_name] || host}#{if port, do: ":#{port}"}"
+    end
+  end
+
+  defp build_ssh_server(cfg, aliases) do
     now = DateTime.utc_now() |> DateTime.to_iso8601()
 
     %Server{
       id: generate_id(),
       name: cfg.name,
-      host: cfg.host_name,
-      port: cfg.port,
-      username: cfg.user,
+      host: cfg[:host_name] || cfg.name,
+      port: cfg[:port] || 22,
+      username: cfg[:user] || System.get_env("USER", "root"),
       auth_method: "key",
-      proxy_config: nil,
+      proxy_config: ssh_proxy(cfg, aliases),
+      identity_files: Enum.map(cfg[:identity_files] || [], &expand_identity_file(&1, cfg)),
       created_at: now,
       updated_at: now,
       last_seen: nil,
-      status: "offline",
-      monitoring_interval: 30,
-      next_monitoring: 0
+      status: "unknown",
+      monitoring_interval: nil,
+      due_at: nil,
+      source: "ssh_config"
     }
   end
 
@@ -0,0 +1,117 @@
+defmodule AgentlessMonitor.Storage do
+  @moduledoc """
+  Durable storage for monitoring history, events and the audit trail.
+
+  Samples and events are always kept in memory for the dashboard; a storage
+  backend additionally persists them. The backend is chosen by the `storage`
+  config map's `type`:
+
+    * `"none"` (default) - nothing is persisted (`AgentlessMonitor.Storage.Null`)
+    * `"sqlite"` - a local SQLite file (`AgentlessMonitor.Storage.SQLite`)
+    * `"postgres"` - PostgreSQL or TimescaleDB (`AgentlessMonitor.Storage.Postgres`)
+
+  Both persistent backends are available in builds with the `storage` feature.
+
+  Writes are asynchronous and never block monitoring; failures are logged.
+  """
+
+  require Logger
+
+  alias AgentlessMonitor.{Config, Features, Syslog}
+
+  @type kind :: :sample | :event | :audit
+
+  @doc "Child spec for the backend's processes, or nil when it needs none"
+  @callback child_spec(config :: map()) :: Supervisor.child_spec() | nil
+  @callback write(kind(), record :: map()) :: :ok | {:error, term()}
+  @doc "`opts` may bound the time range with `:from` and `:to` (unix seconds)"
+  @callback history(server_id :: String.t(), limit :: pos_integer(), opts :: keyword()) ::
+              {:ok, [map()]} | {:error, term()}
+  @doc "Deletes a server's samples and events recorded before `before` (unix seconds)"
+  @callback prune(server_id :: String.t(), before :: integer()) :: :ok | {:error, term()}
+
+  @backends %{"none" => AgentlessMonitor.Storage.Null}
+
+  if Features.enabled?(:storage) and Code.ensure_loaded?(Postgrex) do
+    @backends Map.put(@backends, "postgres", AgentlessMonitor.Storage.Postgres)
+  end
+
+  if Features.enabled?(:storage) and Code.ensure_loaded?(Exqlite) do
+    @backends Map.put(@backends, "sqlite", AgentlessMonitor.Storage.SQLite)
+  end
+
+  def backend do
+    config = Config.load().storage || %{}
+    Map.get(@backends, Map.get(config, "type", "none"), AgentlessMonitor.Storage.Null)
+  end
+
+  @doc "Supervision children needed by the configured backend"
+  def children do
+    List.wrap(backend().child_spec(Config.load().storage || %{}))
+  end
+
+  @doc "Persists a record in the background"
+  def write(kind, record) do
+    module = backend()
+
+    unless module == AgentlessMonitor.Storage.Null do
+      Task.Supervisor.start_child(AgentlessMonitor.TaskSupervisor, fn ->
+        case module.write(kind, record) do
+          :ok -> :ok
+          {:error, reason} -> Logger.error("Failed to store #{kind}: #{inspect(reason)}")
+        end
+      end)
+    end
+
+    :ok
+  end
+
+  @doc "Deletes a server's records older than `before` (unix seconds) in the background"
+  def prune(server_id, before) do
+    module = backend()
+
+    unless module == AgentlessMonitor.Storage.Null do
+      Task.Supervisor.start_child(AgentlessMonitor.TaskSupervisor, fn ->
+        case module.prune(server_id, before) do
+          :ok -> :ok
+          {:error, reason} -> Logger.error("Failed to prune #{server_id}: #{inspect(reason)}")
+        end
+      end)
+    end
+
+    :ok
+  end
+
+  @doc "Whether a backend that keeps records is configured"
+  def persistent?, do: backend() != AgentlessMonitor.Storage.Null
+
+  @doc "Persists records in the caller, stopping at the first failure"
+  def write_all(kind, records) do
+    module = backend()
+
+    Enum.reduce_while(records, :ok, fn record, :ok ->
+      case module.write(kind, record) do
+        :ok -> {:cont, :ok}
+        {:error, reason} -> {:halt, {:error, "failed to store #{kind}: #{inspect(reason)}"}}
+      end
+    end)
+  end
+
+  @doc "Records an operator action in the audit trail, also forwarded to syslog"
+  def audit(action, actor, details \\ %{}) do
+    record = %{
+      "timestamp" => DateTime.utc_now() |> DateTime.to_iso8601(),
+      "action" => action,
+      "actor" => actor,
+      "details" => details
+    }
+
+    Syslog.forward(:audit, record)
+    write(:audit, record)
+  end
+
+  @doc "Stored history for a server, newest first, optionally within `from`/`to`"
+  def history(server_id, limit, opts \\ []) do
+    backend().history(server_id, limit, opts)
+  end
+end
@@ -0,0 +1,17 @@
+defmodule AgentlessMonitor.Storage.Null do
+  @moduledoc "Storage backend that persists nothing"
+
+  @behaviour AgentlessMonitor.Storage
+
+  @impl true
+  def child_spec(_config), do: nil
+
+  @impl true
+  def write(_kind, _record), do: :ok
+
+  @impl true
+  def history(_server_id, _limit, _opts), do: {:error, :unsupported}
+
+  @impl true
+  def prune(_server_id, _before), do: :ok
+end
@@ -0,0 +1,173 @@
+# Only compiled when the `storage` feature pulls in Postgrex.
+if AgentlessMonitor.Features.enabled?(:storage) and Code.ensure_loaded?(Postgrex) do
+  defmodule AgentlessMonitor.Storage.Postgres do
+    @moduledoc """
+    PostgreSQL / TimescaleDB storage backend.
+
+        "storage": {"type": "postgres", "url": "postgres://monitor:secret@db/monitor",
+                    "timescale": true}
+
+    Records are stored as JSONB alongside their server and timestamp, in the
+    `samples`, `events` and `audit_log` tables, which are created on startup.
+    With `timescale` set, `samples` and `events` become hypertables.
+    """
+
+    @behaviour AgentlessMonitor.Storage
+
+    require Logger
+
+    @conn __MODULE__
+
+    @schema [
+      """
+      CREATE TABLE IF NOT EXISTS samples (
+        server_id text NOT NULL,
+        recorded_at timestamptz NOT NULL,
+        data jsonb NOT NULL
+      )
+      """,
+      "CREATE INDEX IF NOT EXISTS samples_server_time ON samples (server_id, recorded_at DESC)",
+      """
+      CREATE TABLE IF NOT EXISTS events (
+        id text NOT NULL,
+        server_id text,
+        recorded_at timestamptz NOT NULL,
+        data jsonb NOT NULL
+      )
+      """,
+      """
+      CREATE TABLE IF NOT EXISTS audit_log (
+        recorded_at timestamptz NOT NULL,
+        data jsonb NOT NULL
+      )
+      """
+    ]
+
+    @impl true
+    def child_spec(config) do
+      opts =
+        config
+        |> Map.fetch!("url")
+        |> parse_url()
+        |> Keyword.merge(
+          name: @conn,
+          pool_size: Map.get(config, "pool_size", 5),
+          after_connect: fn conn -> migrate(conn, Map.get(config, "timescale", false)) end
+        )
+
+      Postgrex.child_spec(opts)
+    end
+
+    @impl true
+    def write(:sample, record) do
+      query(
+        "INSERT INTO samples (server_id, recorded_at, data) VALUES ($1, $2, $3)",
+        [record["server_id"], timestamp(record["timestamp"]), record]
+      )
+    end
+
+    def write(:event, record) do
+      query(
+        "INSERT INTO events (id, server_id, recorded_at, data) VALUES ($1, $2, $3, $4)",
+        [record["id"], record["server_id"], timestamp(record["timestamp"]), record]
+      )
+    end
+
+    def write(:audit, record) do
+      query(
+        "INSERT INTO audit_log (recorded_at, data) VALUES ($1, $2)",
+        [timestamp(record["timestamp"]), record]
+      )
+    end
+
+    @impl true
+    def history(server_id, limit, opts) do
+      sql = """
+      SELECT data FROM samples
+      WHERE server_id = $1
+        AND ($3::timestamptz IS NULL OR recorded_at >= $3)
+        AND ($4::timestamptz IS NULL OR recorded_at <= $4)
+      ORDER BY recorded_at DESC LIMIT $2
+      """
+
+      params = [server_id, limit, from_unix(opts[:from]), from_unix(opts[:to])]
+
+      case Postgrex.query(@conn, sql, params) do
+        {:ok, %Postgrex.Result{rows: rows}} -> {:ok, Enum.map(rows, &hd/1)}
+        {:error, error} -> {:error, error}
+      end
+    end
+
+    @impl true
+    def prune(server_id, before) do
+      params = [server_id, DateTime.from_unix!(before)]
+
+      Enum.reduce_while(["samples", "events"], :ok, fn table, :ok ->
+        case query("DELETE FROM #{table} WHERE server_id = $1 AND recorded_at < $2", params) do
+          :ok -> {:cont, :ok}
+          {:error, error} -> {:halt, {:error, error}}
+        end
+      end)
+    end
+
+    # ---- Private helpers ----
+
+    defp query(sql, params) do
+      case Postgrex.query(@conn, sql, params) do
+        {:ok, _result} -> :ok
+        {:error, error} -> {:error, error}
+      end
+    end
+
+    defp migrate(conn, timescale) do
+      Enum.each(@schema, &Postgrex.query!(conn, &1, []))
+
+      if timescale do
+        for table <- ["samples", "events"] do
+          sql =
+            "SELECT create_hypertable('#{table}', 'recorded_at', " <>
+              "if_not_exists => TRUE, migrate_data => TRUE)"
+
+          Postgrex.query!(conn, sql, [])
+        end
+      end
+    rescue
+      error -> Logger.error("Storage migration failed: #{Exception.message(error)}")
+    end
+
+    defp from_unix(nil), do: nil
+    defp from_unix(unix), do: DateTime.from_unix!(unix)
+
+    defp timestamp(nil), do: DateTime.utc_now()
+
+    defp timestamp(iso8601) do
+      case DateTime.from_iso8601(iso8601) do
+        {:ok, dt, _} -> dt
+        _ -> DateTime.utc_now()
+      end
+    end
+
+    defp parse_url(url) do
+      uri = URI.parse(url)
+      {username, password} = parse_userinfo(uri.userinfo)
+
+      [
+        hostname: uri.host,
+        port: uri.port || 5432,
+        database: String.trim_leading(uri.path || "", "/"),
+        username: username,
+        password: password
+      ]
+      |> Enum.reject(fn {_key, value} -> is_nil(value) end)
+    end
+
+    defp parse_userinfo(nil), do: {nil, nil}
+
+    defp parse_userinfo(userinfo) do
+      case String.split(userinfo, ":", parts: 2) do
+        [username, password] -> {URI.decode(username), URI.decode(password)}
+        [username] -> {URI.decode(username), nil}
+      end
+    end
+  end
+end
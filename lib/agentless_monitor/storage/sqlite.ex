@@ -0,0 +1,181 @@
+# Only compiled when the `storage` feature pulls in Exqlite.
+if AgentlessMonitor.Features.enabled?(:storage) and Code.ensure_loaded?(Exqlite) do
+  defmodule AgentlessMonitor.Storage.SQLite do
+    @moduledoc """
+    SQLite storage backend, for single-node installs without a database
+    server.
+
+        "storage": {"type": "sqlite", "path": "data/history.db"}
+
+    `path` defaults to `history.db` in the data directory. Records are
+    stored as JSON with their server and unix timestamp in the `samples`,
+    `events` and `audit_log` tables, created on startup. One process owns
+    the connection, so writes are serialized; the database runs in WAL
+    mode so history reads don't wait for them.
+    """
+
+    @behaviour AgentlessMonitor.Storage
+
+    use GenServer
+    require Logger
+
+    alias AgentlessMonitor.Config
+    alias Exqlite.Sqlite3
+
+    @schema [
+      "PRAGMA journal_mode = WAL",
+      """
+      CREATE TABLE IF NOT EXISTS samples (
+        server_id TEXT NOT NULL,
+        recorded_at INTEGER NOT NULL,
+        data TEXT NOT NULL
+      )
+      """,
+      "CREATE INDEX IF NOT EXISTS samples_server_time ON samples (server_id, recorded_at DESC)",
+      """
+      CREATE TABLE IF NOT EXISTS events (
+        id TEXT NOT NULL,
+        server_id TEXT,
+        recorded_at INTEGER NOT NULL,
+        data TEXT NOT NULL
+      )
+      """,
+      """
+      CREATE TABLE IF NOT EXISTS audit_log (
+        recorded_at INTEGER NOT NULL,
+        data TEXT NOT NULL
+      )
+      """
+    ]
+
+    @impl AgentlessMonitor.Storage
+    def child_spec(config) do
+      %{id: __MODULE__, start: {__MODULE__, :start_link, [config]}}
+    end
+
+    def start_link(config) do
+      GenServer.start_link(__MODULE__, config, name: __MODULE__)
+    end
+
+    @impl AgentlessMonitor.Storage
+    def write(:sample, record) do
+      insert(
+        "INSERT INTO samples (server_id, recorded_at, data) VALUES (?1, ?2, ?3)",
+        [record["server_id"], timestamp(record["timestamp"]), Jason.encode!(record)]
+      )
+    end
+
+    def write(:event, record) do
+      insert(
+        "INSERT INTO events (id, server_id, recorded_at, data) VALUES (?1, ?2, ?3, ?4)",
+        [
+          record["id"],
+          record["server_id"],
+          timestamp(record["timestamp"]),
+          Jason.encode!(record)
+        ]
+      )
+    end
+
+    def write(:audit, record) do
+      insert(
+        "INSERT INTO audit_log (recorded_at, data) VALUES (?1, ?2)",
+        [timestamp(record["timestamp"]), Jason.encode!(record)]
+      )
+    end
+
+    @impl AgentlessMonitor.Storage
+    def history(server_id, limit, opts) do
+      sql = """
+      SELECT data FROM samples
+      WHERE server_id = ?1
+        AND (?3 IS NULL OR recorded_at >= ?3)
+        AND (?4 IS NULL OR recorded_at <= ?4)
+      ORDER BY recorded_at DESC LIMIT ?2
+      """
+
+      with {:ok, rows} <- query(sql, [server_id, limit, opts[:from], opts[:to]]) do
+        {:ok, Enum.map(rows, fn [data] -> Jason.decode!(data) end)}
+      end
+    end
+
+    @impl AgentlessMonitor.Storage
+    def prune(server_id, before) do
+      Enum.reduce_while(["samples", "events"], :ok, fn table, :ok ->
+        sql = "DELETE FROM #{table} WHERE server_id = ?1 AND recorded_at < ?2"
+
+        case insert(sql, [server_id, before]) do
+          :ok -> {:cont, :ok}
+          {:error, reason} -> {:halt, {:error, reason}}
+        end
+      end)
+    end
+
+    # ---- Callbacks ----
+
+    @impl GenServer
+    def init(config) do
+      path = Map.get(config, "path") || Path.join(Config.load().data_dir, "history.db")
+      File.mkdir_p!(Path.dirname(path))
+
+      with {:ok, db} <- Sqlite3.open(path),
+           :ok <- migrate(db) do
+        {:ok, %{db: db}}
+      else
+        {:error, reason} -> {:stop, reason}
+      end
+    end
+
+    @impl GenServer
+    def handle_call({:query, sql, params}, _from, state) do
+      {:reply, run(state.db, sql, params), state}
+    end
+
+    # ---- Private helpers ----
+
+    defp insert(sql, params) do
+      case query(sql, params) do
+        {:ok, _rows} -> :ok
+        {:error, reason} -> {:error, reason}
+      end
+    end
+
+    defp query(sql, params) do
+      GenServer.call(__MODULE__, {:query, sql, params})
+    end
+
+    defp run(db, sql, params) do
+      with {:ok, statement} <- Sqlite3.prepare(db, sql) do
+        try do
+          with :ok <- Sqlite3.bind(db, statement, params) do
+            Sqlite3.fetch_all(db, statement)
+          end
+        after
+          Sqlite3.release(db, statement)
+        end
+      end
+    end
+
+    defp migrate(db) do
+      Enum.reduce_while(@schema, :ok, fn sql, :ok ->
+        case Sqlite3.execute(db, sql) do
+          :ok ->
+            {:cont, :ok}
+
+          {:error, reason} ->
+            Logger.error("Storage migration failed: #{inspect(reason)}")
+            {:halt, {:error, reason}}
+        end
+      end)
+    end
+
+    defp timestamp(iso8601) when is_binary(iso8601) do
+      case DateTime.from_iso8601(iso8601) do
+        {:ok, dt, _} -> DateTime.to_unix(dt)
+        _ -> System.system_time(:second)
+      end
+    end
+
+    defp timestamp(_iso8601), do: System.system_time(:second)
+  end
+end
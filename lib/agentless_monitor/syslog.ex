@@ -0,0 +1,275 @@
+defmodule AgentlessMonitor.Syslog do
+  @moduledoc """
+  Forwards events, alerts and audit entries to a remote syslog receiver,
+  typically a SIEM, configured in `syslog`:
+
+      "syslog": {"host": "siem.example.com", "port": 6514, "transport": "tls",
+                 "format": "cef", "tls": {"ca_file": "/etc/monitor/siem-ca.pem"}}
+
+  `transport` is `tcp` (default, port 514) or `tls` (port 6514), which takes
+  the `tls` entry of the HTTP notification channels (client certificate,
+  private CA, `"verify": false`; see `AgentlessMonitor.Notifier`). Messages
+  carry an RFC 5424 header with facility `facility` (default 16, local0)
+  and are framed by octet counting (RFC 6587). The message is the record as
+  JSON with `format` `rfc5424` (the default), or an ArcSight CEF line with
+  `cef`.
+
+  Every event is forwarded, suppressed ones included, as are alerts when
+  they open or change status (`alert_open`, `alert_mitigated`,
+  `alert_resolved`) and every audit entry. While the receiver is
+  unreachable records are queued, up to 10,000 with the oldest dropped
+  first, and the connection is retried every 10 seconds.
+  """
+
+  use GenServer
+  require Logger
+
+  alias AgentlessMonitor.{Config, Notifier}
+
+  @max_queued 10_000
+  @retry_delay :timer.seconds(10)
+  @timeout 5_000
+  @app_name "agentless-monitor"
+  @severities %{"critical" => {2, 10}, "warning" => {4, 6}, "info" => {6, 3}}
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, nil, name: __MODULE__)
+  end
+
+  @doc """
+  Forwards a record of `type` (`:event`, `:alert` or `:audit`), as the
+  string-keyed map the rest of the monitor uses, when `syslog` is set.
+  """
+  def forward(type, record) do
+    if is_map(Config.load().syslog) and Process.whereis(__MODULE__) do
+      GenServer.cast(__MODULE__, {:forward, type, record})
+    end
+
+    :ok
+  end
+
+  # ---- Callbacks ----
+
+  def init(_) do
+    {:ok, %{socket: nil, target: nil, queue: :queue.new(), queued: 0, retry: nil}}
+  end
+
+  def handle_cast({:forward, type, record}, state) do
+    config = Config.load().syslog
+
+    if is_map(config) do
+      state = enqueue(state, message(type, record, config))
+      {:noreply, flush(state, config)}
+    else
+      {:noreply, state}
+    end
+  end
+
+  def handle_info(:retry, state) do
+    state = %{state | retry: nil}
+
+    case Config.load().syslog do
+      config when is_map(config) -> {:noreply, flush(state, config)}
+      _ -> {:noreply, %{disconnect(state) | queue: :queue.new(), queued: 0}}
+    end
+  end
+
+  # ---- Private helpers ----
+
+  defp enqueue(state, message) do
+    if state.queued >= @max_queued do
+      {_dropped, queue} = :queue.out(state.queue)
+      %{state | queue: :queue.in(message, queue)}
+    else
+      %{state | queue: :queue.in(message, state.queue), queued: state.queued + 1}
+    end
+  end
+
+  # Sends the queue in order, reconnecting when `syslog` changed; on a
+  # failure the unsent records wait for the next retry.
+  defp flush(%{retry: retry} = state, _config) when retry != nil, do: state
+
+  defp flush(state, config) do
+    state = if state.target != config, do: disconnect(state), else: state
+
+    case ensure_connected(state, config) do
+      {:ok, state} ->
+        send_queued(state)
+
+      {:error, reason} ->
+        Logger.warning("Syslog: cannot connect to #{config["host"]}: #{inspect(reason)}")
+        schedule_retry(state)
+    end
+  end
+
+  defp send_queued(state) do
+    case :queue.out(state.queue) do
+      {:empty, _queue} ->
+        state
+
+      {{:value, message}, rest} ->
+        case send_frame(state.socket, message) do
+          :ok ->
+            send_queued(%{state | queue: rest, queued: state.queued - 1})
+
+          {:error, reason} ->
+            Logger.warning("Syslog: send failed: #{inspect(reason)}")
+            state |> disconnect() |> schedule_retry()
+        end
+    end
+  end
+
+  defp schedule_retry(state) do
+    %{state | retry: Process.send_after(self(), :retry, @retry_delay)}
+  end
+
+  defp ensure_connected(%{socket: nil} = state, config) do
+    host = to_charlist(config["host"] || "localhost")
+    options = [:binary, active: false, send_timeout: @timeout]
+
+    result =
+      if config["transport"] == "tls" do
+        options = options ++ Notifier.ssl_options(config["tls"] || %{})
+
+        with {:ok, socket} <- :ssl.connect(host, config["port"] || 6514, options, @timeout),
+             do: {:ok, {:ssl, socket}}
+      else
+        with {:ok, socket} <- :gen_tcp.connect(host, config["port"] || 514, options, @timeout),
+             do: {:ok, {:tcp, socket}}
+      end
+
+    with {:ok, socket} <- result, do: {:ok, %{state | socket: socket, target: config}}
+  end
+
+  defp ensure_connected(state, _config), do: {:ok, state}
+
+  defp disconnect(%{socket: {:ssl, socket}} = state) do
+    :ssl.close(socket)
+    %{state | socket: nil, target: nil}
+  end
+
+  defp disconnect(%{socket: {:tcp, socket}} = state) do
+    :gen_tcp.close(socket)
+    %{state | socket: nil, target: nil}
+  end
+
+  defp disconnect(state), do: state
+
+  defp send_frame({:ssl, socket}, message), do: :ssl.send(socket, frame(message))
+  defp send_frame({:tcp, socket}, message), do: :gen_tcp.send(socket, frame(message))
+
+  defp frame(message), do: "#{byte_size(message)} #{message}"
+
+  # `<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG`
+  defp message(type, record, config) do
+    entry = entry(type, record)
+    {severity, _cef} = Map.get(@severities, entry.severity, {5, 3})
+    priority = (config["facility"] || 16) * 8 + severity
+
+    body =
+      if config["format"] == "cef",
+        do: cef(entry),
+        else: Jason.encode!(Map.put(entry.record, "type", to_string(type)))
+
+    timestamp = entry.timestamp || "-"
+    "<#{priority}>1 #{timestamp} #{hostname()} #{@app_name} - #{type} - #{body}"
+  end
+
+  # The fields both formats need, by record type.
+  defp entry(:event, event) do
+    %{
+      timestamp: event["timestamp"],
+      severity: event["severity"],
+      signature: event["kind"],
+      name: event["message"],
+      record: event,
+      extension: [
+        {"externalId", event["id"]},
+        {"dhost", event["server_name"]},
+        {"cs1Label", "serverId"},
+        {"cs1", event["server_id"]},
+        {"cs2Label", "group"},
+        {"cs2", event["group"]}
+      ]
+    }
+  end
+
+  defp entry(:alert, incident) do
+    record = Map.drop(incident, ["timeline", "captures"])
+
+    %{
+      timestamp: incident["updated_at"],
+      severity: incident["severity"],
+      signature: "alert_#{incident["status"]}",
+      name: incident["title"],
+      record: record,
+      extension: [
+        {"externalId", incident["id"]},
+        {"cs1Label", "serverId"},
+        {"cs1", incident["server_id"]},
+        {"cs2Label", "group"},
+        {"cs2", incident["group"]},
+        {"cs3Label", "status"},
+        {"cs3", incident["status"]}
+      ]
+    }
+  end
+
+  defp entry(:audit, audit) do
+    %{
+      timestamp: audit["timestamp"],
+      severity: "notice",
+      signature: "audit:#{audit["action"]}",
+      name: "#{audit["actor"]}: #{audit["action"]}",
+      record: audit,
+      extension: [
+        {"act", audit["action"]},
+        {"suser", audit["actor"]},
+        {"msg", Jason.encode!(audit["details"] || %{})}
+      ]
+    }
+  end
+
+  # `CEF:Version|Vendor|Product|Version|Signature ID|Name|Severity|Extension`
+  defp cef(entry) do
+    {_severity, cef_severity} = Map.get(@severities, entry.severity, {5, 3})
+    version = to_string(Application.spec(:agentless_monitor, :vsn) || "")
+
+    extension =
+      [{"rt", unix_ms(entry.timestamp)} | entry.extension]
+      |> Enum.reject(fn {_key, value} -> value in [nil, ""] end)
+      |> Enum.map_join(" ", fn {key, value} -> "#{key}=#{cef_value(value)}" end)
+
+    header =
+      ["Agentless Monitor", @app_name, version, entry.signature, entry.name || ""]
+      |> Enum.map_join("|", &cef_header/1)
+
+    "CEF:0|#{header}|#{cef_severity}|#{extension}"
+  end
+
+  defp cef_header(value) do
+    value |> to_string() |> String.replace("\\", "\\\\") |> String.replace("|", "\\|")
+  end
+
+  defp cef_value(value) do
+    value
+    |> to_string()
+    |> String.replace("\\", "\\\\")
+    |> String.replace("=", "\\=")
+    |> String.replace(["\r\n", "\n", "\r"], "\\n")
+  end
+
+  defp unix_ms(timestamp) do
+    case DateTime.from_iso8601(to_string(timestamp)) do
+      {:ok, dt, _} -> DateTime.to_unix(dt, :millisecond)
+      _ -> nil
+    end
+  end
+
+  defp hostname do
+    {:ok, name} = :inet.gethostname()
+    to_string(name)
+  end
+end
@@ -0,0 +1,85 @@
+defmodule AgentlessMonitor.TimeZones do
+  @moduledoc """
+  IANA time zones for schedules and displayed times.
+
+  Timestamps are stored and compared in UTC. A zone only decides how a
+  local time given without an offset (`2026-11-02T22:00:00`) is read and
+  how times are shown, so a window scheduled in `Europe/Amsterdam` follows
+  that zone's daylight saving changes.
+  """
+
+  @utc "Etc/UTC"
+
+  @doc "Checks that `zone` is a known IANA time zone name"
+  def validate(zone) when is_binary(zone) do
+    case DateTime.now(zone) do
+      {:ok, _now} -> :ok
+      {:error, _reason} -> {:error, "unknown time zone #{inspect(zone)}"}
+    end
+  end
+
+  def validate(zone), do: {:error, "unknown time zone #{inspect(zone)}"}
+
+  @doc """
+  Parses an ISO 8601 timestamp into a UTC `DateTime`. One with an offset
+  (`Z`, `+02:00`) is taken as is; a local one is read in `zone`. A local
+  time skipped by a daylight saving change moves past the gap and one that
+  occurs twice means its first occurrence.
+  """
+  def parse(value, zone \\ @utc)
+
+  def parse(value, zone) when is_binary(value) do
+    case DateTime.from_iso8601(value) do
+      {:ok, datetime, _offset} -> {:ok, datetime}
+      {:error, :missing_offset} -> parse_local(value, zone)
+      {:error, _reason} -> {:error, "invalid timestamp #{inspect(value)}"}
+    end
+  end
+
+  def parse(value, _zone), do: {:error, "invalid timestamp #{inspect(value)}"}
+
+  @doc """
+  `timestamp` (a `DateTime` or an ISO 8601 string) as ISO 8601 in `zone`.
+  Values that aren't timestamps are returned unchanged.
+  """
+  def display(%DateTime{} = datetime, zone) do
+    case DateTime.shift_zone(datetime, zone) do
+      {:ok, shifted} -> DateTime.to_iso8601(shifted)
+      {:error, _reason} -> DateTime.to_iso8601(datetime)
+    end
+  end
+
+  def display(timestamp, zone) when is_binary(timestamp) do
+    case DateTime.from_iso8601(timestamp) do
+      {:ok, datetime, _offset} -> display(datetime, zone)
+      {:error, _reason} -> timestamp
+    end
+  end
+
+  def display(value, _zone), do: value
+
+  @doc "The current date in `zone`"
+  def today(zone) do
+    case DateTime.now(zone) do
+      {:ok, now} -> DateTime.to_date(now)
+      {:error, _reason} -> Date.utc_today()
+    end
+  end
+
+  # ---- Private helpers ----
+
+  defp parse_local(value, zone) do
+    with {:ok, naive} <- NaiveDateTime.from_iso8601(value) do
+      case DateTime.from_naive(naive, zone) do
+        {:ok, datetime} -> {:ok, to_utc(datetime)}
+        {:ambiguous, first, _second} -> {:ok, to_utc(first)}
+        {:gap, _before, just_after} -> {:ok, to_utc(just_after)}
+        {:error, _reason} -> {:error, "unknown time zone #{inspect(zone)}"}
+      end
+    else
+      _ -> {:error, "invalid timestamp #{inspect(value)}"}
+    end
+  end
+
+  defp to_utc(datetime), do: DateTime.shift_zone!(datetime, @utc)
+end
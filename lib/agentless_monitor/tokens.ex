@@ -0,0 +1,193 @@
+defmodule AgentlessMonitor.Tokens do
+  @moduledoc """
+  Scoped API tokens.
+
+  Each token carries a set of scopes (`read:metrics`, `write:servers`,
+  `exec:commands`, `admin`; `admin` implies all others), an optional expiry
+  and last-used tracking. Only a SHA-256 hash of the secret is kept, in
+  `<data_dir>/tokens.json`; the secret itself is returned once on creation.
+
+  A static `admin_token` from config is also accepted, to bootstrap the
+  first real tokens.
+  """
+
+  use GenServer
+  require Logger
+
+  alias AgentlessMonitor.{Config, DataFile, Models, Storage}
+
+  @scopes ["read:metrics", "write:servers", "exec:commands", "admin"]
+  @flush_interval :timer.seconds(60)
+
+  # ---- Public API ----
+
+  def start_link(_) do
+    GenServer.start_link(__MODULE__, nil, name: __MODULE__)
+  end
+
+  def scopes, do: @scopes
+
+  def list do
+    GenServer.call(__MODULE__, :list)
+  end
+
+  @doc """
+  Creates a token from `%{"name" => ..., "scopes" => [...], "expires_in" => seconds}`.
+  Returns `{:ok, token, secret}`. `operator` is recorded in the audit log.
+  """
+  def create(attrs, operator) do
+    GenServer.call(__MODULE__, {:create, attrs, operator})
+  end
+
+  def revoke(id, operator) do
+    GenServer.call(__MODULE__, {:revoke, id, operator})
+  end
+
+  @doc "Looks up the token for a bearer secret, recording its use"
+  def authenticate(secret) do
+    GenServer.call(__MODULE__, {:authenticate, secret})
+  end
+
+  def authorized?(token, scope) do
+    "admin" in token["scopes"] or scope in token["scopes"]
+  end
+
+  # ---- Callbacks ----
+
+  def init(_) do
+    schedule_flush()
+    {:ok, %{tokens: load(), dirty: false}}
+  end
+
+  def handle_call(:list, _from, state) do
+    {:reply, Enum.map(state.tokens, &public/1), state}
+  end
+
+  def handle_call({:create, attrs, operator}, _from, state) do
+    scopes = Map.get(attrs, "scopes", [])
+
+    cond do
+      not is_binary(attrs["name"]) or attrs["name"] == "" ->
+        {:reply, {:error, :invalid_name}, state}
+
+      not is_list(scopes) or scopes == [] or Enum.any?(scopes, &(&1 not in @scopes)) ->
+        {:reply, {:error, :invalid_scopes}, state}
+
+      true ->
+        secret = "am_" <> Base.url_encode64(:crypto.strong_rand_bytes(24), padding: false)
+        now = DateTime.utc_now()
+
+        expires_at =
+          case attrs["expires_in"] do
+            seconds when is_integer(seconds) and seconds > 0 ->
+              now |> DateTime.add(seconds, :second) |> DateTime.to_iso8601()
+
+            _ ->
+              nil
+          end
+
+        token = %{
+          "id" => Models.generate_id(),
+          "name" => attrs["name"],
+          "scopes" => scopes,
+          "hash" => hash(secret),
+          "created_at" => DateTime.to_iso8601(now),
+          "expires_at" => expires_at,
+          "last_used_at" => nil
+        }
+
+        tokens = [token | state.tokens]
+        persist(tokens)
+        Storage.audit("token_created", operator, Map.take(token, ["id", "name", "scopes"]))
+        {:reply, {:ok, public(token), secret}, %{state | tokens: tokens, dirty: false}}
+    end
+  end
+
+  def handle_call({:revoke, id, operator}, _from, state) do
+    case Enum.split_with(state.tokens, &(&1["id"] == id)) do
+      {[], _} ->
+        {:reply, {:error, :not_found}, state}
+
+      {_revoked, remaining} ->
+        persist(remaining)
+        Storage.audit("token_revoked", operator, %{"id" => id})
+        {:reply, :ok, %{state | tokens: remaining, dirty: false}}
+    end
+  end
+
+  def handle_call({:authenticate, secret}, _from, state) do
+    admin_token = Config.load().admin_token
+
+    if admin_token && Plug.Crypto.secure_compare(admin_token, secret) do
+      {:reply, {:ok, %{"id" => "config", "name" => "admin_token", "scopes" => ["admin"]}}, state}
+    else
+      digest = hash(secret)
+
+      case Enum.find(state.tokens, &Plug.Crypto.secure_compare(&1["hash"], digest)) do
+        nil ->
+          {:reply, {:error, :invalid}, state}
+
+        token ->
+          if expired?(token) do
+            {:reply, {:error, :expired}, state}
+          else
+            used = %{token | "last_used_at" => DateTime.utc_now() |> DateTime.to_iso8601()}
+            tokens =
+              Enum.map(state.tokens, fn existing ->
+                if existing["id"] == used["id"], do: used, else: existing
+              end)
+            {:reply, {:ok, public(used)}, %{state | tokens: tokens, dirty: true}}
+          end
+      end
+    end
+  end
+
+  # last_used_at changes on every request, so it is written out periodically
+  # rather than on each use.
+  def handle_info(:flush, state) do
+    if state.dirty, do: persist(state.tokens)
+    schedule_flush()
+    {:noreply, %{state | dirty: false}}
+  end
+
+  # ---- Private helpers ----
+
+  defp public(token), do: Map.delete(token, "hash")
+
+  defp hash(secret) do
+    :crypto.hash(:sha256, secret) |> Base.encode16(case: :lower)
+  end
+
+  defp expired?(%{"expires_at" => nil}), do: false
+
+  defp expired?(%{"expires_at" => expires_at}) do
+    case DateTime.from_iso8601(expires_at) do
+      {:ok, dt, _} -> DateTime.compare(DateTime.utc_now(), dt) == :gt
+      _ -> true
+    end
+  end
+
+  defp path do
+    Path.join(Config.load().data_dir, "tokens.json")
+  end
+
+  defp load do
+    with {:ok, content} <- File.read(path()),
+         {:ok, tokens} when is_list(tokens) <- Jason.decode(content) do
+      tokens
+    else
+      _ -> []
+    end
+  end
+
+  defp persist(tokens) do
+    case DataFile.write_atomic(path(), Jason.encode!(tokens, pretty: true)) do
+      :ok -> :ok
+      {:error, reason} -> Logger.error("Failed to persist API tokens: #{inspect(reason)}")
+    end
+  end
+
+  defp schedule_flush do
+    Process.send_after(self(), :flush, @flush_interval)
+  end
+end
@@ -0,0 +1,96 @@
+defmodule AgentlessMonitor.Topology do
+  @moduledoc """
+  Dependency graph between servers, declared by name in each server's
+  `depends_on` list (e.g. app-01 depends on db-01 and nfs-01).
+
+  A server in `error` whose dependencies are all healthy is a root cause;
+  one with a failing (direct or transitive) dependency is a downstream
+  symptom, and its `server_down` alerts are suppressed.
+  """
+
+  alias AgentlessMonitor.{ServerStatus, State}
+
+  @doc "Nodes with their dependency role plus `from` → `to` dependency edges"
+  def graph(servers \\ State.get_servers()) do
+    servers = Enum.reject(servers, &(&1.status == "retired"))
+    by_name = Map.new(servers, &{&1.name, &1})
+
+    nodes =
+      Enum.map(servers, fn server ->
+        failing = failing_upstream(server, by_name)
+
+        %{
+          "id" => server.id,
+          "name" => server.name,
+          "status" => ServerStatus.effective(server),
+          "depends_on" => server.depends_on,
+          "role" => role(server, failing),
+          "failing_upstream" => failing
+        }
+      end)
+
+    edges =
+      for server <- servers, dependency <- server.depends_on do
+        %{"from" => server.name, "to" => dependency, "known" => Map.has_key?(by_name, dependency)}
+      end
+
+    %{"nodes" => Enum.sort_by(nodes, & &1["name"]), "edges" => edges}
+  end
+
+  @doc "Names of failing servers that `server` depends on, directly or transitively"
+  def failing_upstream(server, by_name \\ nil) do
+    by_name = by_name || Map.new(State.get_servers(), &{&1.name, &1})
+
+    server.name
+    |> upstream(by_name, MapSet.new())
+    |> Enum.filter(&(by_name[&1].status == "error"))
+    |> Enum.sort()
+  end
+
+  @doc """
+  Validates a dependency list for `server`: every name must be a known
+  server and the result must not introduce a cycle.
+  """
+  def validate(server, depends_on, servers \\ State.get_servers()) do
+    by_name = Map.new(servers, &{&1.name, &1})
+    unknown = Enum.reject(depends_on, &Map.has_key?(by_name, &1))
+
+    cond do
+      unknown != [] ->
+        {:error, "unknown servers: #{Enum.join(unknown, ", ")}"}
+
+      server.name in depends_on ->
+        {:error, "a server cannot depend on itself"}
+
+      true ->
+        by_name = Map.put(by_name, server.name, %{server | depends_on: depends_on})
+
+        if MapSet.member?(upstream(server.name, by_name, MapSet.new()), server.name),
+          do: {:error, "dependencies would form a cycle"},
+          else: :ok
+    end
+  end
+
+  # ---- Private helpers ----
+
+  defp role(%{status: "error"}, []), do: "root_cause"
+  defp role(%{status: "error"}, _failing), do: "symptom"
+  defp role(_server, []), do: "healthy"
+  defp role(_server, _failing), do: "at_risk"
+
+  defp upstream(name, by_name, seen) do
+    case by_name[name] do
+      nil ->
+        seen
+
+      server ->
+        server.depends_on
+        |> Enum.filter(&Map.has_key?(by_name, &1))
+        |> Enum.reduce(seen, fn dependency, acc ->
+          if MapSet.member?(acc, dependency),
+            do: acc,
+            else: upstream(dependency, by_name, MapSet.put(acc, dependency))
+        end)
+    end
+  end
+end
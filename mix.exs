@@ -14,7 +14,7 @@ defmodule AgentlessMonitor.MixProject do
 
   def application do
     [
-      extra_applications: [:logger, :crypto],
+      extra_applications: [:logger, :crypto, :inets, :ssl, :ssh, :eldap],
       mod: {AgentlessMonitor.Application, []}
     ]
   end
@@ -27,6 +27,11 @@ defmodule AgentlessMonitor.MixProject do
       agentless_monitor: [
         steps: [:assemble, &Burrito.wrap/1],
         burrito: [targets: burrito_targets()]
+      ],
+      # Plain release for the container image (see Dockerfile).
+      container: [
+        applications: [agentless_monitor: :permanent],
+        steps: [:assemble]
       ]
     ]
   end
@@ -44,7 +49,17 @@ defmodule AgentlessMonitor.MixProject do
       {:plug_cowboy, "~> 2.7"},
       {:jason, "~> 1.4"},
       {:plug, "~> 1.15"},
+      {:yaml_elixir, "~> 2.9"},
+      {:tz, "~> 0.28"},
       {:burrito, "~> 1.0"}
-    ]
+    ] ++ if(feature?("storage"), do: [{:postgrex, "~> 0.19"}, {:exqlite, "~> 0.23"}], else: [])
+  end
+
+  # Mirrors the AGENTLESS_FEATURES handling in config/config.exs.
+  defp feature?(name) do
+    case System.get_env("AGENTLESS_FEATURES") do
+      nil -> true
+      features -> name in (features |> String.split(",", trim: true) |> Enum.map(&String.trim/1))
+    end
   end
 end
@@ -1,4 +1,16 @@
 // Agentless Monitor - Frontend JavaScript
+
+// Opened through a share link (`/?share=<token>`): send the token with every API call.
+const shareToken = new URLSearchParams(window.location.search).get('share');
+if (shareToken) {
+    const originalFetch = window.fetch.bind(window);
+    window.fetch = (url, options = {}) => {
+        const headers = new Headers(options.headers || {});
+        headers.set('X-Share-Token', shareToken);
+        return originalFetch(url, { ...options, headers });
+    };
+}
+
 class MonitorApp {
     constructor() {
         this.servers = [];
@@ -70,16 +82,12 @@ class MonitorApp {
 
     async fetchLatestMonitoringData(serverId) {
         try {
-            // Fetch all monitoring data in parallel
-            const [cpuResponse, memoryResponse, diskResponse] = await Promise.all([
-                fetch(`/api/servers/${serverId}/details/cpu`),
-                fetch(`/api/servers/${serverId}/details/memory`),
-                fetch(`/api/servers/${serverId}/details/disks`)
-            ]);
+            const response = await fetch(`/api/servers/${serverId}/details/all?metrics=cpu,memory,disks`);
+            const details = response.ok ? await response.json() : {};
 
-            const cpuData = cpuResponse.ok ? await cpuResponse.json() : null;
-            const memoryData = memoryResponse.ok ? await memoryResponse.json() : null;
-            const diskData = diskResponse.ok ? await diskResponse.json() : null;
+            const cpuData = details.cpu || null;
+            const memoryData = details.memory || null;
+            const diskData = details.disks || null;
 
             // Only return data if we have at least CPU data
             if (cpuData && !cpuData.error) {
@@ -216,11 +224,11 @@ class MonitorApp {
             <div class="server-info">
                 <div class="server-info-item">
                     <span class="server-info-label">Host</span>
-                    <span class="server-info-value">${server.host}:${server.port}</span>
+                    <span class="server-info-value">${server.transport === 'local' ? server.host : `${server.host}:${server.port}`}</span>
                 </div>
                 <div class="server-info-item">
                     <span class="server-info-label">User</span>
-                    <span class="server-info-value">${server.username}</span>
+                    <span class="server-info-value">${server.username || 'local'}</span>
                 </div>
                 <div class="server-info-item">
                     <span class="server-info-label">Last Seen</span>
@@ -562,10 +570,15 @@ class MonitorApp {
 
     getStatusClass(status) {
         if (typeof status === 'string') {
-            switch (status) {
-                case 'Online': return 'status-online';
-                case 'Offline': return 'status-offline';
-                case 'Connecting': return 'status-connecting';
+            switch (status.toLowerCase()) {
+                case 'online': return 'status-online';
+                case 'degraded': return 'status-degraded';
+                case 'maintenance': return 'status-maintenance';
+                case 'paused': return 'status-paused';
+                case 'unknown': return 'status-unknown';
+                case 'retired': return 'status-retired';
+                case 'offline': return 'status-offline';
+                case 'connecting': return 'status-connecting';
                 default: return 'status-error';
             }
         }
@@ -577,7 +590,7 @@ class MonitorApp {
 
     getStatusText(status) {
         if (typeof status === 'string') {
-            return status;
+            return status.charAt(0).toUpperCase() + status.slice(1);
         }
         if (typeof status === 'object' && status.Online) return 'Online';
         if (typeof status === 'object' && status.Offline) return 'Offline';
@@ -630,11 +643,11 @@ class MonitorApp {
                 </div>
                 <div class="metric-card">
                     <div class="metric-title">Host</div>
-                    <div class="metric-value">${this.currentServer.host}:${this.currentServer.port}</div>
+                    <div class="metric-value">${this.currentServer.transport === 'local' ? this.currentServer.host : `${this.currentServer.host}:${this.currentServer.port}`}</div>
                 </div>
                 <div class="metric-card">
                     <div class="metric-title">Username</div>
-                    <div class="metric-value">${this.currentServer.username}</div>
+                    <div class="metric-value">${this.currentServer.username || 'local'}</div>
                 </div>
                 <div class="metric-card">
                     <div class="metric-title">Last Seen</div>
@@ -1477,7 +1490,7 @@ class MonitorApp {
                                     </div>
                                     <div class="detail-row">
                                         <span class="detail-label">Username:</span>
-                                        <span class="detail-value">${server.username}</span>
+                                        <span class="detail-value">${server.username || 'local'}</span>
                                     </div>
                                     <div class="detail-row">
                                         <span class="detail-label">Last Seen:</span>
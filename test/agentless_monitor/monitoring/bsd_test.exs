@@ -0,0 +1,271 @@
+defmodule AgentlessMonitor.Monitoring.BSDTest do
+  use ExUnit.Case, async: true
+
+  alias AgentlessMonitor.Monitoring.BSD
+
+  # Sections of `BSD.command/1` output in order, as captured on the hosts.
+  defp output(sections), do: Enum.join(sections, "\n---SEP---\n") <> "\n"
+
+  describe "parse/3 on FreeBSD" do
+    setup do
+      sections = [
+        ~S"""
+        { sec = 1700000000, usec = 123456 } Tue Nov 14 22:13:20 2023
+        1700086400
+        """,
+        "{ 0.52 0.41 0.38 }",
+        "8",
+        "Intel(R) Xeon(R) CPU E5-2680 v4 @ 2.40GHz",
+        ~S"""
+               tty             cpu
+         tin  tout  us ni sy in id
+           0     3   1  0  1  0 98
+           0   118   7  0  3  0 90
+        """,
+        ~S"""
+        hw.physmem: 8509194240
+        hw.pagesize: 4096
+        vm.stats.vm.v_free_count: 1048576
+        vm.stats.vm.v_inactive_count: 262144
+        swapinfo: /dev/ada0p3        2097152    524288  1572864    25%
+        """,
+        ~S"""
+        Filesystem   1024-blocks      Used     Avail Capacity  Mounted on
+        /dev/ada0p2    101445540  20401176  72928724    22%    /
+        devfs                  1         1         0   100%    /dev
+        fdescfs                1         1         0   100%    /dev/fd
+        """,
+        ~S"""
+        Name    Mtu Network       Address              Ipkts Ierrs Idrop     Ibytes    Opkts Oerrs     Obytes  Coll
+        em0    1500 <Link#1>      08:00:27:aa:bb:cc  1234567     0     0  987654321   765432     0  123456789     0
+        em0       - 192.168.1.0/24 192.168.1.10      1200000     -     -  980000000   760000     -  120000000     -
+        lo0   16384 <Link#2>      lo0                   4321     0     0     654321     4321     0     654321     0
+        lo0       - 127.0.0.0/8   127.0.0.1             4321     -     -     654321     4321     -     654321     -
+        """,
+        ~S"""
+        em0: flags=8843<UP,BROADCAST,RUNNING,SIMPLEX,MULTICAST> metric 0 mtu 1500
+                options=481009b<RXCSUM,TXCSUM,VLAN_MTU,VLAN_HWTAGGING>
+                ether 08:00:27:aa:bb:cc
+                inet 192.168.1.10 netmask 0xffffff00 broadcast 192.168.1.255
+                inet6 fe80::a00:27ff:feaa:bbcc%em0 prefixlen 64 scopeid 0x1
+                inet6 2001:db8::10 prefixlen 64
+        lo0: flags=8049<UP,LOOPBACK,RUNNING,MULTICAST> metric 0 mtu 16384
+                inet6 ::1 prefixlen 128
+                inet 127.0.0.1 netmask 0xff000000
+        """,
+        "freebsd-01.example.com",
+        "FreeBSD\n14.0-RELEASE\namd64",
+        "FreeBSD\n14.0-RELEASE-p3",
+        ~S"""
+        tcp4       0      0 *.22                   *.*                    LISTEN
+        tcp6       0      0 *.22                   *.*                    LISTEN
+        tcp4       0      0 127.0.0.1.25           *.*                    LISTEN
+        """,
+        ~S"""
+          812 www      12.5  2.1  180224 nginx: worker process (nginx)
+            1 root      0.0  0.0    1048 /sbin/init
+        """
+      ]
+
+      {:ok, parsed} = BSD.parse(output(sections), "freebsd", "bsd-1")
+      %{data: parsed}
+    end
+
+    test "reads CPU usage from iostat's idle column and load from sysctl", %{data: data} do
+      assert data.server_id == "bsd-1"
+      assert data.cpu.usage_percent == 10.0
+      assert data.cpu.load_average == [0.52, 0.41, 0.38]
+      assert data.cpu.cores == 8
+      assert data.cpu.model == "Intel(R) Xeon(R) CPU E5-2680 v4 @ 2.40GHz"
+    end
+
+    test "counts free and inactive pages as available, swap from swapinfo", %{data: data} do
+      assert data.memory.total == 8_509_194_240
+      assert data.memory.free == 4_294_967_296
+      assert data.memory.available == 5_368_709_120
+      assert data.memory.used == 3_140_485_120
+      assert data.memory.swap_total == 2_147_483_648
+      assert data.memory.swap_used == 536_870_912
+      assert data.memory.swap_free == 1_610_612_736
+    end
+
+    test "keeps only disks under /dev", %{data: data} do
+      assert [disk] = data.disks
+      assert disk.device == "/dev/ada0p2"
+      assert disk.mount_point == "/"
+      assert disk.total == 101_445_540 * 1024
+      assert disk.used == 20_401_176 * 1024
+      assert disk.free == 72_928_724 * 1024
+      assert disk.usage_percent == 22.0
+    end
+
+    test "reads link-level netstat rows with ifconfig addresses", %{data: data} do
+      assert [em0, lo0] = data.network
+      assert em0.interface == "em0"
+      assert em0.rx_bytes == 987_654_321
+      assert em0.tx_bytes == 123_456_789
+      assert em0.rx_packets == 1_234_567
+      assert em0.tx_packets == 765_432
+      assert em0.ip_addresses == ["192.168.1.10", "2001:db8::10"]
+      assert lo0.interface == "lo0"
+      assert lo0.rx_bytes == 654_321
+      assert lo0.ip_addresses == []
+    end
+
+    test "reads listening ports and processes", %{data: data} do
+      assert Enum.map(data.ports, &{&1.protocol, &1.port}) ==
+               [{"tcp4", 22}, {"tcp6", 22}, {"tcp4", 25}]
+
+      assert [nginx, init] = data.processes
+      assert nginx.pid == 812
+      assert nginx.user == "www"
+      assert nginx.cpu_percent == 12.5
+      assert nginx.command == "nginx: worker process (nginx)"
+      assert init.pid == 1
+    end
+
+    test "fills system info from uname, freebsd-version and kern.boottime", %{data: data} do
+      info = data.system_info
+      assert info.hostname == "freebsd-01.example.com"
+      assert info.os == "FreeBSD"
+      assert info.os_id == "freebsd"
+      assert info.os_release == "FreeBSD 14.0-RELEASE-p3"
+      assert info.os_version == "14.0-RELEASE-p3"
+      assert info.kernel == "14.0-RELEASE"
+      assert info.architecture == "amd64"
+      assert info.uptime == 86_400
+      assert info.ip_addresses == ["192.168.1.10", "2001:db8::10"]
+    end
+  end
+
+  describe "parse/3 on macOS" do
+    setup do
+      sections = [
+        ~S"""
+        { sec = 1700000000, usec = 0 } Tue Nov 14 22:13:20 2023
+        1700003600
+        """,
+        "{ 1.85 1.62 1.50 }",
+        "10",
+        "Apple M1 Pro",
+        ~S"""
+            cpu    load average
+         us sy id   1m   5m   15m
+          5  3 92  1.85 1.62 1.50
+         12  6 82  1.85 1.62 1.50
+        """,
+        ~S"""
+        hw.memsize: 17179869184
+        hw.pagesize: 16384
+        vm.swapusage: total = 2048.00M  used = 1047.50M  free = 1000.50M  (encrypted)
+        Mach Virtual Memory Statistics: (page size of 16384 bytes)
+        Pages free:                               12345.
+        Pages active:                            400000.
+        Pages inactive:                          300000.
+        Pages speculative:                         4000.
+        Pages wired down:                        150000.
+        """,
+        ~S"""
+        Filesystem     1024-blocks      Used Available Capacity  Mounted on
+        /dev/disk3s1s1   482797652  10008100 229468316     5%    /
+        devfs                  199       199         0   100%    /dev
+        /dev/disk3s6     482797652   2097172 229468316     1%    /System/Volumes/VM
+        /dev/disk3s5     482797652 239568924 229468316    52%    /System/Volumes/Data
+        map auto_home            0         0         0   100%    /System/Volumes/Data/home
+        """,
+        ~S"""
+        Name       Mtu   Network       Address            Ipkts Ierrs     Ibytes    Opkts Oerrs     Obytes  Coll
+        lo0        16384 <Link#1>                         52000     0    9000000    52000     0    9000000     0
+        lo0        16384 127           127.0.0.1          52000     -    9000000    52000     -    9000000     -
+        en0        1500  <Link#11>   a4:83:e7:12:34:56  8000000     0 9500000000  3000000     0  400000000     0
+        en0        1500  192.168.1     192.168.1.20     8000000     - 9500000000  3000000     -  400000000     -
+        """,
+        ~S"""
+        lo0: flags=8049<UP,LOOPBACK,RUNNING,MULTICAST> mtu 16384
+                inet 127.0.0.1 netmask 0xff000000
+                inet6 ::1 prefixlen 128
+                inet6 fe80::1%lo0 prefixlen 64 scopeid 0x1
+        en0: flags=8863<UP,BROADCAST,SMART,RUNNING,SIMPLEX,MULTICAST> mtu 1500
+                ether a4:83:e7:12:34:56
+                inet6 fe80::1c2b:3a4d:5e6f:7a8b%en0 prefixlen 64 secured scopeid 0xb
+                inet 192.168.1.20 netmask 0xffffff00 broadcast 192.168.1.255
+        """,
+        "mac-mini.local",
+        "Darwin\n23.4.0\narm64",
+        "macOS\n14.4.1",
+        ~S"""
+        tcp4       0      0  *.22                   *.*                    LISTEN
+        tcp4       0      0  127.0.0.1.631          *.*                    LISTEN
+        """,
+        ~S"""
+          612 _windowserver  23.5  1.2 201234 /System/Library/PrivateFrameworks/SkyLight.framework/Resources/WindowServer -daemon
+            1 root            0.1  0.1  12345 /sbin/launchd
+        """
+      ]
+
+      {:ok, parsed} = BSD.parse(output(sections), "darwin", "mac-1")
+      %{data: parsed}
+    end
+
+    test "reads CPU usage from iostat's idle column ahead of the load columns", %{data: data} do
+      assert data.cpu.usage_percent == 18.0
+      assert data.cpu.load_average == [1.85, 1.62, 1.5]
+      assert data.cpu.cores == 10
+      assert data.cpu.model == "Apple M1 Pro"
+    end
+
+    test "reads memory from vm_stat pages and swap from vm.swapusage", %{data: data} do
+      assert data.memory.total == 17_179_869_184
+      assert data.memory.free == 267_796_480
+      assert data.memory.available == 5_182_996_480
+      assert data.memory.used == 11_996_872_704
+      assert data.memory.swap_total == 2_147_483_648
+      assert data.memory.swap_used == 1_098_383_360
+      assert data.memory.swap_free == 1_049_100_288
+    end
+
+    test "leaves out APFS system volumes other than the data volume", %{data: data} do
+      assert Enum.map(data.disks, & &1.mount_point) == ["/", "/System/Volumes/Data"]
+      assert [root, volume] = data.disks
+      assert root.device == "/dev/disk3s1s1"
+      assert root.used == 10_008_100 * 1024
+      assert root.usage_percent == 5.0
+      assert volume.usage_percent == 52.0
+    end
+
+    test "aligns netstat columns from the right when the address is missing", %{data: data} do
+      assert [lo0, en0] = data.network
+      assert lo0.interface == "lo0"
+      assert lo0.rx_bytes == 9_000_000
+      assert lo0.rx_packets == 52_000
+      assert lo0.ip_addresses == []
+      assert en0.interface == "en0"
+      assert en0.rx_bytes == 9_500_000_000
+      assert en0.tx_bytes == 400_000_000
+      assert en0.ip_addresses == ["192.168.1.20"]
+    end
+
+    test "fills system info from uname and sw_vers", %{data: data} do
+      info = data.system_info
+      assert info.hostname == "mac-mini.local"
+      assert info.os == "Darwin"
+      assert info.os_id == "macos"
+      assert info.os_release == "macOS 14.4.1"
+      assert info.os_version == "14.4.1"
+      assert info.kernel == "23.4.0"
+      assert info.architecture == "arm64"
+      assert info.uptime == 3600
+      assert info.ip_addresses == ["192.168.1.20"]
+    end
+
+    test "reads listening ports and processes", %{data: data} do
+      assert Enum.map(data.ports, &{&1.protocol, &1.port}) == [{"tcp4", 22}, {"tcp4", 631}]
+      assert Enum.map(data.processes, & &1.pid) == [612, 1]
+    end
+  end
+
+  test "parse/3 rejects output with missing sections" do
+    assert {:error, "unexpected output from freebsd collection"} =
+             BSD.parse("sysctl: unknown oid 'kern.boottime'\n", "freebsd", "bsd-1")
+  end
+end
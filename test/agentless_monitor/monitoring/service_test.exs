@@ -0,0 +1,35 @@
+defmodule AgentlessMonitor.Monitoring.ServiceTest do
+  use ExUnit.Case, async: true
+
+  alias AgentlessMonitor.Monitoring.Service
+
+  describe "parse_degraded/1" do
+    test "reads the uptime and the three load averages" do
+      output = "3456789.12 13456789.01\n2.15 1.87 1.62 3/412 98765\n"
+      assert Service.parse_degraded(output) == {:ok, 3_456_789, [2.15, 1.87, 1.62]}
+    end
+
+    test "accepts a host under heavy load with integral averages" do
+      output = "60.00 55.00\n12 10 8 40/900 1234\n"
+      assert Service.parse_degraded(output) == {:ok, 60, [12.0, 10.0, 8.0]}
+    end
+
+    test "fails when /proc/loadavg couldn't be read" do
+      output = "3456789.12 13456789.01\ncat: /proc/loadavg: No such file or directory\n"
+      assert Service.parse_degraded(output) == :error
+    end
+
+    test "fails without /proc" do
+      output = "cat: /proc/uptime: No such file or directory\n" <>
+                 "cat: /proc/loadavg: No such file or directory\n"
+
+      assert Service.parse_degraded(output) == :error
+    end
+
+    test "fails on truncated or empty output" do
+      assert Service.parse_degraded("3456789.12 13456789.01\n") == :error
+      assert Service.parse_degraded("3456789.12 13456789.01\n0.50 0.40\n") == :error
+      assert Service.parse_degraded("") == :error
+    end
+  end
+end
@@ -0,0 +1,126 @@
+defmodule AgentlessMonitor.Monitoring.WindowsTest do
+  use ExUnit.Case, async: true
+
+  alias AgentlessMonitor.Monitoring.Windows
+
+  # `ConvertTo-Json -Compress` output of the collection script on Windows
+  # Server 2022, pretty-printed here.
+  @server_2022 ~S"""
+  {
+    "cpu_percent": 12.3456,
+    "cores": 8,
+    "cpu_model": "Intel(R) Xeon(R) Gold 6230 CPU @ 2.10GHz  ",
+    "memory_total_kb": 16776756,
+    "memory_free_kb": 8388378,
+    "swap_total_mb": 2432,
+    "swap_used_mb": 120,
+    "hostname": "WIN-SRV01",
+    "os": "Microsoft Windows Server 2022 Standard",
+    "version": "10.0.20348",
+    "architecture": "64-bit",
+    "uptime": 86400,
+    "disks": [
+      {"device": "C:", "filesystem": "NTFS", "size": 107374182400, "free": 53687091200},
+      {"device": "D:", "filesystem": "ReFS", "size": 0, "free": 0}
+    ],
+    "network": [
+      {
+        "name": "Ethernet",
+        "rx_bytes": 9876543210, "tx_bytes": 1234567890,
+        "rx_packets": 7654321, "tx_packets": 1234567,
+        "rx_errors": 0, "tx_errors": 2,
+        "addresses": ["10.0.0.5", "fe80::8d1c:2a3b:4c5d:6e7f%4"]
+      }
+    ]
+  }
+  """
+
+  # A single disk and a single address are serialized as a bare object and
+  # string, and a failed counter as null.
+  @single_values ~S"""
+  {"cpu_percent": null, "cores": 2, "cpu_model": null,
+   "memory_total_kb": 4193848, "memory_free_kb": 1048462,
+   "swap_total_mb": null, "swap_used_mb": null,
+   "hostname": "WIN-EDGE", "os": "Microsoft Windows 10 Pro", "version": "10.0.19045",
+   "architecture": "64-bit", "uptime": 600,
+   "disks": {"device": "C:", "filesystem": "NTFS", "size": 1000, "free": 250},
+   "network": [{"name": "Wi-Fi", "rx_bytes": 100, "tx_bytes": 50,
+                "rx_packets": 10, "tx_packets": 5, "rx_errors": 0, "tx_errors": 0,
+                "addresses": "192.168.56.1"}]}
+  """
+
+  describe "parse/2" do
+    test "reads CPU, memory and page file usage" do
+      assert {:ok, data} = Windows.parse(@server_2022, "win-1")
+      assert data.server_id == "win-1"
+      assert data.cpu.usage_percent == 12.3
+      assert data.cpu.cores == 8
+      assert data.cpu.model == "Intel(R) Xeon(R) Gold 6230 CPU @ 2.10GHz"
+      assert data.memory.total == 16_776_756 * 1024
+      assert data.memory.free == 8_388_378 * 1024
+      assert data.memory.used == (16_776_756 - 8_388_378) * 1024
+      assert data.memory.available == data.memory.free
+      assert data.memory.swap_total == 2432 * 1_048_576
+      assert data.memory.swap_used == 120 * 1_048_576
+      assert data.memory.swap_free == (2432 - 120) * 1_048_576
+    end
+
+    test "reads fixed disks and interfaces with their addresses" do
+      assert {:ok, data} = Windows.parse(@server_2022, "win-1")
+
+      assert [c, d] = data.disks
+      assert c.device == "C:"
+      assert c.mount_point == "C:"
+      assert c.filesystem == "NTFS"
+      assert c.total == 107_374_182_400
+      assert c.used == 53_687_091_200
+      assert c.usage_percent == 50.0
+      assert d.usage_percent == 0.0
+
+      assert [ethernet] = data.network
+      assert ethernet.interface == "Ethernet"
+      assert ethernet.rx_bytes == 9_876_543_210
+      assert ethernet.tx_bytes == 1_234_567_890
+      assert ethernet.rx_packets == 7_654_321
+      assert ethernet.tx_errors == 2
+      assert ethernet.ip_addresses == ["10.0.0.5", "fe80::8d1c:2a3b:4c5d:6e7f%4"]
+    end
+
+    test "fills system info, without load averages" do
+      assert {:ok, data} = Windows.parse(@server_2022, "win-1")
+
+      info = data.system_info
+      assert info.hostname == "WIN-SRV01"
+      assert info.os == "Windows"
+      assert info.os_id == "windows"
+      assert info.os_release == "Microsoft Windows Server 2022 Standard"
+      assert info.os_version == "10.0.20348"
+      assert info.kernel == "10.0.20348"
+      assert info.architecture == "64-bit"
+      assert info.uptime == 86_400
+      assert info.ip_addresses == ["10.0.0.5", "fe80::8d1c:2a3b:4c5d:6e7f%4"]
+      assert data.cpu.load_average == [0.0, 0.0, 0.0]
+    end
+
+    test "accepts single values where PowerShell drops the array, and nulls" do
+      assert {:ok, data} = Windows.parse(@single_values, "win-2")
+      assert data.cpu.usage_percent == 0.0
+      assert data.cpu.model == ""
+      assert data.memory.swap_total == 0
+      assert [%{device: "C:", used: 750, usage_percent: 75.0}] = data.disks
+      assert [%{interface: "Wi-Fi", ip_addresses: ["192.168.56.1"]}] = data.network
+      assert data.system_info.ip_addresses == ["192.168.56.1"]
+    end
+
+    test "ignores surrounding whitespace and CRLF line endings" do
+      output = "\r\n" <> String.replace(@single_values, "\n", "\r\n")
+      assert {:ok, %{system_info: %{hostname: "WIN-EDGE"}}} = Windows.parse(output, "win-2")
+    end
+
+    test "rejects output that isn't the script's JSON" do
+      output = "'powershell' is not recognized as an internal or external command,\r\n"
+      assert {:error, "unexpected output from Windows collection: " <> _} =
+               Windows.parse(output, "win-1")
+    end
+  end
+end
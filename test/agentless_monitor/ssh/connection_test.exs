@@ -0,0 +1,28 @@
+defmodule AgentlessMonitor.SSH.ConnectionTest do
+  use ExUnit.Case, async: true
+
+  alias AgentlessMonitor.SSH.Connection
+
+  @identification %{"tag" => "monitor@ops"}
+
+  describe "prepare_command/3" do
+    test "tags the command when restricted mode is off" do
+      opts = [restricted: nil, identification: @identification]
+      assert Connection.prepare_command("uptime", opts, false) == "# monitor@ops\nuptime"
+    end
+
+    test "sends the command as-is without identification" do
+      assert Connection.prepare_command("uptime", [restricted: nil], false) == "uptime"
+    end
+
+    test "sends the command verbatim in restricted mode" do
+      opts = [restricted: ["uptime"], identification: @identification]
+      assert Connection.prepare_command("uptime", opts, true) == "uptime"
+    end
+
+    test "sends the command verbatim to Windows hosts" do
+      opts = [restricted: nil, platform: "windows", identification: @identification]
+      assert Connection.prepare_command("ver", opts, false) == "ver"
+    end
+  end
+end